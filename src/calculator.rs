@@ -0,0 +1,1146 @@
+use anyhow::{anyhow, bail, Result};
+
+// Exponents of the base units kg, m, s and K
+type Dims = [i8; 4];
+
+const NONE: Dims = [0, 0, 0, 0];
+const MASS: Dims = [1, 0, 0, 0];
+const LENGTH: Dims = [0, 1, 0, 0];
+const AREA: Dims = [0, 2, 0, 0];
+const VOLUME: Dims = [0, 3, 0, 0];
+const TIME: Dims = [0, 0, 1, 0];
+const SPEED: Dims = [0, 1, -1, 0];
+const FORCE: Dims = [1, 1, -2, 0];
+const ENERGY: Dims = [1, 2, -2, 0];
+const POWER: Dims = [1, 2, -3, 0];
+const PRESSURE: Dims = [1, -1, -2, 0];
+const TEMPERATURE: Dims = [0, 0, 0, 1];
+
+const BASE_UNIT_NAMES: [&str; 4] = ["kg", "m", "s", "K"];
+
+// Significant digits shown when no fixed number of decimals is requested;
+// enough to hide binary floating point noise like 0.1 + 0.2
+const SIGNIFICANT_DIGITS: i32 = 12;
+// Most digits shown when a number of decimals is requested, as doubles
+// don't hold more than about 17 significant digits; past that the decimals
+// would be made up
+const MAX_DECIMALS: usize = 15;
+// Parentheses, signs and exponents nested deeper than this are an error
+// rather than a stack overflow
+const MAX_DEPTH: usize = 64;
+
+struct Unit {
+    names: &'static [&'static str],
+    scale: f64,
+    dims: Dims,
+    // Temperatures on the Celsius and Fahrenheit scales don't start at zero
+    offset: f64,
+}
+
+const fn unit(names: &'static [&'static str], scale: f64, dims: Dims) -> Unit {
+    Unit {
+        names,
+        scale,
+        dims,
+        offset: 0.0,
+    }
+}
+
+const UNITS: &[Unit] = &[
+    unit(&["m", "meter", "meters", "metre", "metres"], 1.0, LENGTH),
+    unit(&["mm"], 1e-3, LENGTH),
+    unit(&["cm"], 1e-2, LENGTH),
+    unit(&["km"], 1e3, LENGTH),
+    unit(&["in", "inch", "inches"], 0.0254, LENGTH),
+    unit(&["ft", "foot", "feet"], 0.3048, LENGTH),
+    unit(&["yd", "yard", "yards"], 0.9144, LENGTH),
+    unit(&["mi", "mile", "miles"], 1609.344, LENGTH),
+    unit(&["ha", "hectare", "hectares"], 1e4, AREA),
+    unit(&["acre", "acres"], 4_046.856_422_4, AREA),
+    unit(
+        &["l", "L", "liter", "liters", "litre", "litres"],
+        1e-3,
+        VOLUME,
+    ),
+    unit(&["ml", "mL"], 1e-6, VOLUME),
+    unit(&["gal", "gallon", "gallons"], 3.785_411_784e-3, VOLUME),
+    unit(&["qt", "quart", "quarts"], 9.463_529_46e-4, VOLUME),
+    unit(&["pt", "pint", "pints"], 4.731_764_73e-4, VOLUME),
+    unit(&["cup", "cups"], 2.365_882_365e-4, VOLUME),
+    unit(&["floz"], 2.957_352_956_25e-5, VOLUME),
+    unit(&["tbsp"], 1.478_676_478_125e-5, VOLUME),
+    unit(&["tsp"], 4.928_921_593_75e-6, VOLUME),
+    unit(&["g", "gram", "grams"], 1e-3, MASS),
+    unit(&["mg"], 1e-6, MASS),
+    unit(&["kg"], 1.0, MASS),
+    unit(&["t", "tonne", "tonnes"], 1e3, MASS),
+    unit(&["lb", "lbs", "pound", "pounds"], 0.453_592_37, MASS),
+    unit(&["oz", "ounce", "ounces"], 0.028_349_523_125, MASS),
+    unit(&["s", "sec", "second", "seconds"], 1.0, TIME),
+    unit(&["ms"], 1e-3, TIME),
+    unit(&["min", "minute", "minutes"], 60.0, TIME),
+    unit(&["h", "hr", "hour", "hours"], 3600.0, TIME),
+    unit(&["day", "days"], 86400.0, TIME),
+    unit(&["week", "weeks"], 604_800.0, TIME),
+    unit(&["year", "years", "yr"], 31_557_600.0, TIME),
+    unit(&["mph"], 0.44704, SPEED),
+    unit(&["knot", "knots"], 1852.0 / 3600.0, SPEED),
+    unit(&["N"], 1.0, FORCE),
+    unit(&["J"], 1.0, ENERGY),
+    unit(&["kJ"], 1e3, ENERGY),
+    unit(&["cal"], 4.184, ENERGY),
+    unit(&["kcal"], 4184.0, ENERGY),
+    unit(&["Wh"], 3600.0, ENERGY),
+    unit(&["kWh"], 3.6e6, ENERGY),
+    unit(&["W"], 1.0, POWER),
+    unit(&["kW"], 1e3, POWER),
+    unit(&["Pa"], 1.0, PRESSURE),
+    unit(&["kPa"], 1e3, PRESSURE),
+    unit(&["bar"], 1e5, PRESSURE),
+    unit(&["psi"], 6_894.757_293_168, PRESSURE),
+    unit(&["K", "kelvin"], 1.0, TEMPERATURE),
+    Unit {
+        names: &["degC", "°C", "celsius"],
+        scale: 1.0,
+        dims: TEMPERATURE,
+        offset: 273.15,
+    },
+    Unit {
+        names: &["degF", "°F", "fahrenheit"],
+        scale: 5.0 / 9.0,
+        dims: TEMPERATURE,
+        offset: 459.67,
+    },
+    // Angles are plain numbers in radians
+    unit(&["rad", "radian", "radians"], 1.0, NONE),
+    unit(
+        &["deg", "°", "degree", "degrees"],
+        std::f64::consts::PI / 180.0,
+        NONE,
+    ),
+];
+
+fn find_unit(name: &str) -> Option<&'static Unit> {
+    UNITS.iter().find(|unit| unit.names.contains(&name))
+}
+
+fn constant(name: &str) -> Option<f64> {
+    match name {
+        "pi" | "π" => Some(std::f64::consts::PI),
+        "tau" | "τ" => Some(std::f64::consts::TAU),
+        "e" => Some(std::f64::consts::E),
+        _ => None,
+    }
+}
+
+/// How a temperature in kelvin relates to the Celsius and Fahrenheit
+/// scales, which don't start at zero
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Temperature {
+    /// Anything that isn't a temperature, or one given in kelvin
+    Kelvin,
+    /// A reading on a scale with an offset, like "20 degC"
+    Reading,
+    /// The difference between two readings, shown without the offset
+    Difference,
+}
+
+/// A number with physical dimensions, stored in base SI units
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Quantity {
+    value: f64,
+    dims: Dims,
+    temperature: Temperature,
+}
+
+impl Quantity {
+    fn number(value: f64) -> Quantity {
+        Quantity::new(value, NONE)
+    }
+
+    fn new(value: f64, dims: Dims) -> Quantity {
+        Quantity {
+            value,
+            dims,
+            temperature: Temperature::Kelvin,
+        }
+    }
+
+    fn is_number(&self) -> bool {
+        self.dims == NONE
+    }
+
+    fn expect_number(&self, context: &str) -> Result<f64> {
+        if !self.is_number() {
+            bail!(
+                "{} needs a plain number, not {}",
+                context,
+                dims_name(self.dims)
+            );
+        }
+        Ok(self.value)
+    }
+
+    fn add(self, other: Quantity, sign: f64) -> Result<Quantity> {
+        if self.dims != other.dims {
+            bail!(
+                "Cannot add or subtract {} and {}",
+                dims_name(self.dims),
+                dims_name(other.dims)
+            );
+        }
+        // Readings are measured from absolute zero, so adding two counts
+        // it twice, while subtracting them leaves only a difference
+        let temperature = match (self.temperature, other.temperature, sign > 0.0) {
+            (Temperature::Reading, Temperature::Reading, true) => {
+                bail!("Cannot add two temperature readings; add a difference in K instead")
+            }
+            (Temperature::Reading, Temperature::Reading, false) => Temperature::Difference,
+            (Temperature::Reading, _, _) => Temperature::Reading,
+            (_, Temperature::Reading, true) => Temperature::Reading,
+            (_, Temperature::Reading, false) => Temperature::Difference,
+            (Temperature::Difference, Temperature::Difference, _) => Temperature::Difference,
+            (temperature, _, _) => temperature,
+        };
+        Ok(Quantity {
+            value: self.value + sign * other.value,
+            dims: self.dims,
+            temperature,
+        })
+    }
+
+    /// The value as shown in `unit`, with the offset taken off readings
+    fn in_unit(&self, unit: &Unit) -> f64 {
+        let scaled = self.value / unit.scale;
+        if self.temperature == Temperature::Difference || unit.offset == 0.0 {
+            return scaled;
+        }
+        // 32 degF is 0 degC, not the rounding error left after subtracting
+        let shifted = scaled - unit.offset;
+        if shifted.abs() < scaled.abs() * 1e-12 {
+            0.0
+        } else {
+            shifted
+        }
+    }
+
+    fn mul(self, other: Quantity, power: i8) -> Result<Quantity> {
+        let mut dims = self.dims;
+        for (dim, other_dim) in dims.iter_mut().zip(other.dims) {
+            *dim = power
+                .checked_mul(other_dim)
+                .and_then(|d| dim.checked_add(d))
+                .ok_or_else(too_big_power)?;
+        }
+        let value = if power > 0 {
+            self.value * other.value
+        } else {
+            self.value / other.value
+        };
+        Ok(Quantity::new(value, dims))
+    }
+
+    fn pow(self, exponent: Quantity) -> Result<Quantity> {
+        let exponent = exponent.expect_number("An exponent")?;
+        if self.is_number() {
+            return Ok(Quantity::number(self.value.powf(exponent)));
+        }
+        if exponent.fract() != 0.0 || exponent.abs() > 16.0 {
+            return Err(too_big_power());
+        }
+        let mut dims = self.dims;
+        for dim in dims.iter_mut() {
+            *dim = dim.checked_mul(exponent as i8).ok_or_else(too_big_power)?;
+        }
+        Ok(Quantity::new(self.value.powi(exponent as i32), dims))
+    }
+}
+
+fn too_big_power() -> anyhow::Error {
+    anyhow!("Units can only be raised to small whole powers")
+}
+
+/// Describe dimensions in base units, like "kg*m/s^2"
+fn dims_name(dims: Dims) -> String {
+    if dims == NONE {
+        return "a plain number".to_string();
+    }
+    let part = |name: &str, exponent: i8| {
+        if exponent == 1 {
+            name.to_string()
+        } else {
+            format!("{}^{}", name, exponent)
+        }
+    };
+    let numerator: Vec<String> = BASE_UNIT_NAMES
+        .iter()
+        .zip(dims)
+        .filter(|(_, d)| *d > 0)
+        .map(|(name, d)| part(name, d))
+        .collect();
+    let denominator: Vec<String> = BASE_UNIT_NAMES
+        .iter()
+        .zip(dims)
+        .filter(|(_, d)| *d < 0)
+        .map(|(name, d)| part(name, -d))
+        .collect();
+    let numerator = if numerator.is_empty() {
+        "1".to_string()
+    } else {
+        numerator.join("*")
+    };
+    match denominator.len() {
+        0 => numerator,
+        1 => format!("{}/{}", numerator, denominator[0]),
+        _ => format!("{}/({})", numerator, denominator.join("*")),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Op(char),
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_ascii_digit() || c == '.' {
+                    number.push(c);
+                    chars.next();
+                } else if c == '_' {
+                    // Digit separators, as in 1_000_000
+                    chars.next();
+                } else if c == 'e' || c == 'E' {
+                    // Only an exponent if digits follow, so "2e" stays 2 * e
+                    let mut lookahead = chars.clone();
+                    lookahead.next();
+                    let mut exponent = String::from("e");
+                    if let Some(&sign) = lookahead.peek().filter(|s| **s == '-' || **s == '+') {
+                        exponent.push(sign);
+                        lookahead.next();
+                    }
+                    if !lookahead.peek().is_some_and(|d| d.is_ascii_digit()) {
+                        break;
+                    }
+                    while let Some(&d) = lookahead.peek().filter(|d| d.is_ascii_digit()) {
+                        exponent.push(d);
+                        lookahead.next();
+                    }
+                    number.push_str(&exponent);
+                    chars = lookahead;
+                    break;
+                } else {
+                    break;
+                }
+            }
+            match number.parse() {
+                Ok(value) => tokens.push(Token::Number(value)),
+                Err(_) => bail!("Bad number '{}'", number),
+            }
+        } else if c.is_alphabetic() || c == '_' || c == '°' {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' || (name.is_empty() && c == '°') {
+                    name.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token::Name(name));
+        } else {
+            chars.next();
+            let op = match c {
+                '×' | '·' => '*',
+                '÷' => '/',
+                '−' => '-',
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    '^'
+                }
+                '+' | '-' | '*' | '/' | '^' | '(' | ')' | ',' | '!' | '%' => c,
+                _ => bail!("Unexpected character '{}'", c),
+            };
+            tokens.push(Token::Op(op));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    // Units as written in the expression, in order, for displaying the result
+    units_used: Vec<(String, &'static Unit)>,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_op(&self, op: char) -> bool {
+        self.peek() == Some(&Token::Op(op))
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect_op(&mut self, op: char) -> Result<()> {
+        match self.next() {
+            Some(Token::Op(c)) if c == op => Ok(()),
+            Some(token) => bail!("Expected '{}' but found {}", op, describe(&token)),
+            None => bail!("Expected '{}' but the expression ended", op),
+        }
+    }
+
+    // Everything that nests goes through here, so this is where the depth
+    // is counted
+    fn nested(&mut self, parse: impl FnOnce(&mut Parser) -> Result<Quantity>) -> Result<Quantity> {
+        if self.depth == MAX_DEPTH {
+            bail!("Expression is nested more than {} levels deep", MAX_DEPTH);
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn expr(&mut self) -> Result<Quantity> {
+        let mut value = self.term()?;
+        loop {
+            let sign = if self.peek_op('+') {
+                1.0
+            } else if self.peek_op('-') {
+                -1.0
+            } else {
+                return Ok(value);
+            };
+            self.next();
+            let start = self.pos;
+            let other = self.term()?;
+            // "200 + 10%" adds ten percent of 200, like on a calculator
+            if self.pos == start + 2 && self.tokens[start + 1] == Token::Op('%') {
+                value = value.add(value.mul(other, 1)?, sign)?;
+            } else {
+                value = value.add(other, sign)?;
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Quantity> {
+        let mut value = self.implicit_product()?;
+        loop {
+            if self.peek_op('*') {
+                self.next();
+                value = value.mul(self.implicit_product()?, 1)?;
+            } else if self.peek_op('/') {
+                self.next();
+                let divisor = self.implicit_product()?;
+                if divisor.value == 0.0 {
+                    bail!("Division by zero");
+                }
+                value = value.mul(divisor, -1)?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    // Juxtaposition binds tighter than * and /, so "10 km / 2 h" is a speed
+    fn implicit_product(&mut self) -> Result<Quantity> {
+        let mut value = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Name(name)) if name != "to" => {
+                    if let Some(temperature) = self.absolute_temperature(value) {
+                        value = temperature;
+                    } else {
+                        value = value.mul(self.power()?, 1)?;
+                    }
+                }
+                Some(Token::Op('(')) => value = value.mul(self.power()?, 1)?,
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    // A plain number followed by a bare degC or degF is a temperature reading
+    fn absolute_temperature(&mut self, value: Quantity) -> Option<Quantity> {
+        let Some(Token::Name(name)) = self.peek() else {
+            return None;
+        };
+        let unit = find_unit(name).filter(|unit| unit.offset != 0.0)?;
+        let followed_by_power = self.tokens.get(self.pos + 1) == Some(&Token::Op('^'));
+        if !value.is_number() || followed_by_power {
+            return None;
+        }
+        self.units_used.push((name.clone(), unit));
+        self.next();
+        Some(Quantity {
+            value: (value.value + unit.offset) * unit.scale,
+            dims: unit.dims,
+            temperature: Temperature::Reading,
+        })
+    }
+
+    fn unary(&mut self) -> Result<Quantity> {
+        if self.peek_op('-') {
+            self.next();
+            let value = self.nested(Parser::unary)?;
+            return Ok(Quantity {
+                value: -value.value,
+                ..value
+            });
+        }
+        if self.peek_op('+') {
+            self.next();
+            return self.nested(Parser::unary);
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Quantity> {
+        let base = self.postfix()?;
+        if self.peek_op('^') {
+            self.next();
+            let exponent = self.nested(Parser::unary)?;
+            return base.pow(exponent);
+        }
+        Ok(base)
+    }
+
+    fn postfix(&mut self) -> Result<Quantity> {
+        let mut value = self.primary()?;
+        loop {
+            if self.peek_op('!') {
+                self.next();
+                value = Quantity::number(factorial(value.expect_number("Factorial")?)?);
+            } else if self.peek_op('%') {
+                self.next();
+                value.value /= 100.0;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Quantity> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Quantity::number(value)),
+            Some(Token::Op('(')) => {
+                let value = self.nested(Parser::expr)?;
+                self.expect_op(')')?;
+                Ok(value)
+            }
+            Some(Token::Name(name)) => {
+                if self.peek_op('(') {
+                    self.next();
+                    let mut args = Vec::new();
+                    if !self.peek_op(')') {
+                        args.push(self.nested(Parser::expr)?);
+                        while self.peek_op(',') {
+                            self.next();
+                            args.push(self.nested(Parser::expr)?);
+                        }
+                    }
+                    self.expect_op(')')?;
+                    call_function(&name, &args)
+                } else if let Some(value) = constant(&name) {
+                    Ok(Quantity::number(value))
+                } else if let Some(unit) = find_unit(&name) {
+                    self.units_used.push((name, unit));
+                    Ok(Quantity::new(unit.scale, unit.dims))
+                } else {
+                    bail!("Unknown name '{}'", name)
+                }
+            }
+            Some(token) => bail!("Unexpected {}", describe(&token)),
+            None => bail!("The expression ended too early"),
+        }
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(value) => format!("number {}", value),
+        Token::Name(name) => format!("'{}'", name),
+        Token::Op(op) => format!("'{}'", op),
+    }
+}
+
+fn factorial(n: f64) -> Result<f64> {
+    if n < 0.0 || n.fract() != 0.0 || n > 170.0 {
+        bail!("Factorial needs a whole number from 0 to 170");
+    }
+    Ok((1..=n as u64).map(|i| i as f64).product())
+}
+
+fn call_function(name: &str, args: &[Quantity]) -> Result<Quantity> {
+    let arity = |count: usize| -> Result<()> {
+        if args.len() != count {
+            bail!("{}() takes {} argument(s), got {}", name, count, args.len());
+        }
+        Ok(())
+    };
+    // Functions of one plain number
+    let unary: Option<fn(f64) -> f64> = match name {
+        "exp" => Some(f64::exp),
+        "ln" => Some(f64::ln),
+        "log10" => Some(f64::log10),
+        "log2" => Some(f64::log2),
+        "sin" => Some(f64::sin),
+        "cos" => Some(f64::cos),
+        "tan" => Some(f64::tan),
+        "asin" => Some(f64::asin),
+        "acos" => Some(f64::acos),
+        "atan" => Some(f64::atan),
+        "cbrt" => Some(f64::cbrt),
+        _ => None,
+    };
+    if let Some(function) = unary {
+        arity(1)?;
+        let x = args[0].expect_number(&format!("{}()", name))?;
+        let value = function(x);
+        if !value.is_finite() {
+            bail!("{}() is not defined for {}", name, format_number(x, None));
+        }
+        return Ok(Quantity::number(value));
+    }
+
+    match name {
+        "sqrt" => {
+            arity(1)?;
+            if args[0].dims.iter().any(|d| d % 2 != 0) {
+                bail!("sqrt() of {} has no whole units", dims_name(args[0].dims));
+            }
+            if args[0].value < 0.0 {
+                bail!("sqrt() is not defined for negative numbers");
+            }
+            Ok(Quantity::new(
+                args[0].value.sqrt(),
+                args[0].dims.map(|d| d / 2),
+            ))
+        }
+        "abs" | "floor" | "ceil" => {
+            arity(1)?;
+            let value = args[0].value;
+            let value = match name {
+                "abs" => value.abs(),
+                "floor" => value.floor(),
+                _ => value.ceil(),
+            };
+            Ok(Quantity { value, ..args[0] })
+        }
+        "round" => {
+            if args.is_empty() || args.len() > 2 {
+                bail!("round() takes 1 or 2 arguments");
+            }
+            let places = match args.get(1) {
+                Some(places) => places.expect_number("round() places")?,
+                None => 0.0,
+            };
+            let factor = 10f64.powi(places as i32);
+            Ok(Quantity {
+                value: (args[0].value * factor).round() / factor,
+                ..args[0]
+            })
+        }
+        "log" => {
+            let (x, base) = match args {
+                [x] => (x.expect_number("log()")?, 10.0),
+                [x, base] => (x.expect_number("log()")?, base.expect_number("log() base")?),
+                _ => bail!("log() takes 1 or 2 arguments"),
+            };
+            let value = x.log(base);
+            if !value.is_finite() {
+                bail!(
+                    "log() is not defined for {} in base {}",
+                    format_number(x, None),
+                    format_number(base, None)
+                );
+            }
+            Ok(Quantity::number(value))
+        }
+        "atan2" => {
+            arity(2)?;
+            if args[0].dims != args[1].dims {
+                bail!("atan2() needs arguments with the same units");
+            }
+            Ok(Quantity::number(args[0].value.atan2(args[1].value)))
+        }
+        "mod" => {
+            arity(2)?;
+            if args[1].value == 0.0 {
+                bail!("Division by zero");
+            }
+            if args[0].dims != args[1].dims {
+                bail!("mod() needs arguments with the same units");
+            }
+            Ok(Quantity {
+                value: args[0].value.rem_euclid(args[1].value),
+                ..args[0]
+            })
+        }
+        "min" | "max" => {
+            let Some(first) = args.first() else {
+                bail!("{}() needs at least one argument", name);
+            };
+            if args.iter().any(|arg| arg.dims != first.dims) {
+                bail!("{}() needs arguments with the same units", name);
+            }
+            Ok(args.iter().skip(1).fold(*first, |best, arg| {
+                let better = if name == "min" {
+                    arg.value < best.value
+                } else {
+                    arg.value > best.value
+                };
+                if better {
+                    *arg
+                } else {
+                    best
+                }
+            }))
+        }
+        _ => bail!("Unknown function '{}'", name),
+    }
+}
+
+/// Format a number without floating point noise, or with `decimals` places
+/// after the point, as far as the number holds that many digits
+fn format_number(value: f64, decimals: Option<usize>) -> String {
+    if let Some(decimals) = decimals.filter(|_| value.is_finite()) {
+        let whole_digits = if value.abs() >= 1.0 {
+            value.abs().log10().floor() as usize + 1
+        } else {
+            0
+        };
+        if whole_digits <= MAX_DECIMALS {
+            return round_decimal(value, decimals.min(MAX_DECIMALS - whole_digits));
+        }
+    }
+    if !value.is_finite() {
+        return value.to_string();
+    }
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    let magnitude = value.abs().log10().floor() as i32;
+    let text = if (-6..15).contains(&magnitude) {
+        let places = (SIGNIFICANT_DIGITS - 1 - magnitude).max(0) as usize;
+        format!("{:.*}", places, value)
+    } else {
+        let text = format!("{:.*e}", SIGNIFICANT_DIGITS as usize - 1, value);
+        let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+        let mantissa = if mantissa.contains('.') {
+            mantissa.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            mantissa
+        };
+        return format!("{}e{}", mantissa, exponent);
+    };
+    let text = if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &text
+    };
+    // Avoid "-0" for tiny negative results
+    if text == "-0" {
+        "0".to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Round to `decimals` places the way it's done on paper, half away from
+/// zero, working on the shortest decimal that reads back as `value`. This
+/// gives 2.675 -> 2.68, where rounding the binary double (2.67499999...)
+/// would give 2.67.
+fn round_decimal(value: f64, decimals: usize) -> String {
+    if !value.is_finite() {
+        return value.to_string();
+    }
+    // Display for f64 never uses an exponent
+    let text = value.abs().to_string();
+    let (whole, fraction) = text.split_once('.').unwrap_or((&text, ""));
+    let mut digits: Vec<u8> = whole
+        .bytes()
+        .chain(fraction.bytes())
+        .map(|b| b - b'0')
+        .collect();
+    let kept = whole.len() + decimals;
+    let round_up = fraction.len() > decimals && digits[kept] >= 5;
+    digits.resize(kept, 0);
+    if round_up {
+        let mut i = kept;
+        loop {
+            if i == 0 {
+                digits.insert(0, 1);
+                break;
+            }
+            i -= 1;
+            if digits[i] == 9 {
+                digits[i] = 0;
+            } else {
+                digits[i] += 1;
+                break;
+            }
+        }
+    }
+    let split = digits.len() - decimals;
+    let whole: String = digits[..split].iter().map(|d| (b'0' + d) as char).collect();
+    let fraction: String = digits[split..].iter().map(|d| (b'0' + d) as char).collect();
+    let sign = if value < 0.0 && digits.iter().any(|&d| d != 0) {
+        "-"
+    } else {
+        ""
+    };
+    if decimals == 0 {
+        format!("{}{}", sign, whole)
+    } else {
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+}
+
+/// Evaluate one expression, like "2^10", "sqrt(2) * 3" or "5 km to mi",
+/// returning the result as text
+pub fn evaluate(expression: &str, decimals: Option<usize>) -> Result<String> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        pos: 0,
+        units_used: Vec::new(),
+        depth: 0,
+    };
+    if parser.tokens.is_empty() {
+        bail!("Empty expression");
+    }
+    let value = parser.expr()?;
+    if value.value.is_nan() {
+        bail!("The result is not a real number");
+    }
+    if value.value.is_infinite() {
+        bail!("The result is too large");
+    }
+    let units_used = std::mem::take(&mut parser.units_used);
+
+    let conversion = if parser.peek() == Some(&Token::Name("to".to_string())) {
+        parser.next();
+        let start = parser.pos;
+        let target = parser.expr()?;
+        let target_tokens = &parser.tokens[start..parser.pos];
+        Some((
+            target,
+            target_tokens.to_vec(),
+            std::mem::take(&mut parser.units_used),
+        ))
+    } else {
+        None
+    };
+    if let Some(token) = parser.peek() {
+        bail!("Unexpected {}", describe(token));
+    }
+
+    if let Some((target, target_tokens, target_units)) = conversion {
+        if target.dims != value.dims {
+            bail!(
+                "Cannot convert {} to {}",
+                dims_name(value.dims),
+                dims_name(target.dims)
+            );
+        }
+        let (converted, unit_name) = match (target_tokens.as_slice(), target_units.as_slice()) {
+            // Converting to a single unit, which may have an offset
+            ([Token::Name(_)], [(name, unit)]) => (value.in_unit(unit), name.clone()),
+            _ => (value.value / target.value, unit_text(&target_tokens)),
+        };
+        return Ok(format!(
+            "{} {}",
+            format_number(converted, decimals),
+            unit_name
+        ));
+    }
+
+    if value.is_number() {
+        return Ok(format_number(value.value, decimals));
+    }
+    // Show the result in the first unit from the expression that fits it
+    if let Some((name, unit)) = units_used.iter().find(|(_, unit)| unit.dims == value.dims) {
+        let converted = value.in_unit(unit);
+        return Ok(format!("{} {}", format_number(converted, decimals), name));
+    }
+    let base = dims_name(value.dims);
+    Ok(format!("{} {}", format_number(value.value, decimals), base))
+}
+
+fn unit_text(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|token| match token {
+            Token::Number(value) => format_number(*value, None),
+            Token::Name(name) => name.clone(),
+            Token::Op(op) => op.to_string(),
+        })
+        .collect()
+}
+
+/// Evaluate each line of `expressions`, answering every line with either
+/// "expression = result" or the error that stopped it
+pub fn calculate(expressions: &str, decimals: Option<usize>) -> Result<String> {
+    let results: Vec<String> = expressions
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match evaluate(line, decimals) {
+            Ok(result) => format!("{} = {}", line, result),
+            Err(e) => format!("{} = error: {}", line, e),
+        })
+        .collect();
+    if results.is_empty() {
+        bail!("No expression to calculate");
+    }
+    Ok(results.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_powers_that_overflow_are_errors() {
+        let error = evaluate("(m^12)^12", Some(2)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Units can only be raised to small whole powers"
+        );
+        let error = evaluate(
+            "m^16 * m^16 * m^16 * m^16 * m^16 * m^16 * m^16 * m^16",
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Units can only be raised to small whole powers"
+        );
+        assert_eq!(evaluate("(m^2)^3", None).unwrap(), "1 m^6");
+    }
+
+    #[test]
+    fn decimals_round_half_away_from_zero() {
+        assert_eq!(evaluate("2.675", Some(2)).unwrap(), "2.68");
+        assert_eq!(evaluate("1.005", Some(2)).unwrap(), "1.01");
+        assert_eq!(evaluate("9.995", Some(2)).unwrap(), "10.00");
+        assert_eq!(evaluate("-2.675", Some(2)).unwrap(), "-2.68");
+        assert_eq!(evaluate("-0.001", Some(2)).unwrap(), "0.00");
+        assert_eq!(evaluate("2.5", Some(0)).unwrap(), "3");
+        assert_eq!(evaluate("1/3", Some(4)).unwrap(), "0.3333");
+        assert_eq!(evaluate("12", Some(2)).unwrap(), "12.00");
+    }
+
+    #[test]
+    fn decimals_are_capped() {
+        let result = evaluate("1/3", Some(usize::MAX)).unwrap();
+        assert_eq!(result.len(), 2 + MAX_DECIMALS);
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(evaluate("1 + 2", None).unwrap(), "3");
+        assert_eq!(evaluate("0.1 + 0.2", None).unwrap(), "0.3");
+        assert_eq!(evaluate("7 - 10", None).unwrap(), "-3");
+        assert_eq!(evaluate("6 × 7", None).unwrap(), "42");
+        assert_eq!(evaluate("1 ÷ 8", None).unwrap(), "0.125");
+        assert_eq!(evaluate("2 ** 10", None).unwrap(), "1024");
+        assert_eq!(evaluate("1_000_000 * 3", None).unwrap(), "3000000");
+        assert_eq!(evaluate("1.5e3 + 2e-1", None).unwrap(), "1500.2");
+        assert_eq!(evaluate("2e", None).unwrap(), "5.43656365692");
+        assert_eq!(evaluate("5!", None).unwrap(), "120");
+        assert_eq!(
+            evaluate("1/0", None).unwrap_err().to_string(),
+            "Division by zero"
+        );
+        assert!(evaluate("", None).is_err());
+        assert!(evaluate("2 +", None).is_err());
+        assert!(evaluate("(1 + 2", None).is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(evaluate("2 + 3 * 4", None).unwrap(), "14");
+        assert_eq!(evaluate("(2 + 3) * 4", None).unwrap(), "20");
+        assert_eq!(evaluate("2 ^ 3 ^ 2", None).unwrap(), "512");
+        assert_eq!(evaluate("-2 ^ 2", None).unwrap(), "-4");
+        assert_eq!(evaluate("2 ^ -1", None).unwrap(), "0.5");
+        assert_eq!(evaluate("8 / 2 / 2", None).unwrap(), "2");
+        assert_eq!(evaluate("10 - 2 - 3", None).unwrap(), "5");
+        assert_eq!(evaluate("2(3 + 4)", None).unwrap(), "14");
+        assert_eq!(evaluate("10 km / 2 h", None).unwrap(), "1.38888888889 m/s");
+        assert_eq!(evaluate("10 km / 2 h to km/h", None).unwrap(), "5 km/h");
+    }
+
+    #[test]
+    fn percentages() {
+        assert_eq!(evaluate("200 + 10%", None).unwrap(), "220");
+        assert_eq!(evaluate("200 - 10%", None).unwrap(), "180");
+        assert_eq!(evaluate("15% * 80", None).unwrap(), "12");
+        assert_eq!(evaluate("200 * 10%", None).unwrap(), "20");
+        assert_eq!(evaluate("2 kg + 50%", None).unwrap(), "3 kg");
+    }
+
+    #[test]
+    fn unit_conversions() {
+        assert_eq!(evaluate("5 km to mi", Some(3)).unwrap(), "3.107 mi");
+        assert_eq!(evaluate("1 mi to km", None).unwrap(), "1.609344 km");
+        assert_eq!(evaluate("2 cups to ml", None).unwrap(), "473.176473 ml");
+        assert_eq!(evaluate("1 kWh to J", None).unwrap(), "3600000 J");
+        assert_eq!(evaluate("3 ft + 12 in", None).unwrap(), "4 ft");
+        assert_eq!(evaluate("60 mph to km/h", Some(1)).unwrap(), "96.6 km/h");
+        assert_eq!(evaluate("2 m * 3 m", None).unwrap(), "6 m^2");
+        assert_eq!(evaluate("1 ha to m^2", None).unwrap(), "10000 m^2");
+        assert_eq!(evaluate("180 deg", None).unwrap(), "3.14159265359");
+        assert_eq!(
+            evaluate("5 kg to m", None).unwrap_err().to_string(),
+            "Cannot convert kg to m"
+        );
+        assert_eq!(
+            evaluate("1 m + 1 s", None).unwrap_err().to_string(),
+            "Cannot add or subtract m and s"
+        );
+    }
+
+    #[test]
+    fn temperatures() {
+        assert_eq!(evaluate("100 degC to degF", None).unwrap(), "212 degF");
+        assert_eq!(evaluate("32 degF to degC", None).unwrap(), "0 degC");
+        assert_eq!(evaluate("0 degC to K", None).unwrap(), "273.15 K");
+        assert_eq!(evaluate("300 K to degC", None).unwrap(), "26.85 degC");
+        assert_eq!(evaluate("10 degC + 5 K", None).unwrap(), "15 degC");
+        assert_eq!(evaluate("30 degC - 10 degC", None).unwrap(), "20 degC");
+        assert_eq!(
+            evaluate("30 degC - 10 degC to degF", None).unwrap(),
+            "36 degF"
+        );
+        assert_eq!(
+            evaluate("10 degC + 5 degC", None).unwrap_err().to_string(),
+            "Cannot add two temperature readings; add a difference in K instead"
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let cases = [
+            ("exp(0)", "1"),
+            ("ln(e)", "1"),
+            ("log10(1000)", "3"),
+            ("log2(8)", "3"),
+            ("log(100)", "2"),
+            ("log(8, 2)", "3"),
+            ("sin(90 deg)", "1"),
+            ("cos(pi)", "-1"),
+            ("tan(45 deg)", "1"),
+            ("asin(1) to deg", "90 deg"),
+            ("acos(1)", "0"),
+            ("atan(1) to deg", "45 deg"),
+            ("cbrt(27)", "3"),
+            ("sqrt(16)", "4"),
+            ("sqrt(9 m^2)", "3 m"),
+            ("abs(-3 kg)", "3 kg"),
+            ("floor(2.7)", "2"),
+            ("ceil(2.1)", "3"),
+            ("round(2.5)", "3"),
+            ("round(3.14159, 2)", "3.14"),
+            ("atan2(1, 1) to deg", "45 deg"),
+            ("mod(-7, 3)", "2"),
+            ("min(3, 1, 2)", "1"),
+            ("max(1 m, 2 ft)", "1 m"),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(
+                evaluate(expression, None).unwrap(),
+                expected,
+                "{}",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn results_that_are_not_real_numbers_are_errors() {
+        assert_eq!(
+            evaluate("sqrt(-1)", None).unwrap_err().to_string(),
+            "sqrt() is not defined for negative numbers"
+        );
+        assert_eq!(
+            evaluate("ln(0)", None).unwrap_err().to_string(),
+            "ln() is not defined for 0"
+        );
+        assert_eq!(
+            evaluate("asin(2)", None).unwrap_err().to_string(),
+            "asin() is not defined for 2"
+        );
+        assert!(evaluate("log(-1, 10)", None).is_err());
+        assert_eq!(
+            evaluate("(-8)^(1/3)", None).unwrap_err().to_string(),
+            "The result is not a real number"
+        );
+        assert_eq!(
+            evaluate("10^400", None).unwrap_err().to_string(),
+            "The result is too large"
+        );
+        assert!(evaluate("foo(1)", None).is_err());
+        assert!(evaluate("min()", None).is_err());
+        assert!(evaluate("sqrt(1, 2)", None).is_err());
+    }
+
+    #[test]
+    fn decimals_only_show_digits_the_number_holds() {
+        assert_eq!(evaluate("1e20/3", Some(2)).unwrap(), "3.33333333333e19");
+        assert_eq!(
+            evaluate("12345678901234.5678", Some(4)).unwrap(),
+            "12345678901234.6"
+        );
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        let parens = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(evaluate(&parens(MAX_DEPTH), None).unwrap(), "1");
+        for expression in [
+            parens(MAX_DEPTH + 1),
+            parens(5_000),
+            "(".repeat(5_000),
+            format!("{}1", "-".repeat(5_000)),
+            format!("{}1", "+-".repeat(5_000)),
+            format!("2{}", "^2".repeat(5_000)),
+            format!("{}1{}", "sqrt(".repeat(5_000), ")".repeat(5_000)),
+            format!("{}1{}", "2(".repeat(5_000), ")".repeat(5_000)),
+        ] {
+            let error = evaluate(&expression, None).unwrap_err().to_string();
+            assert_eq!(error, "Expression is nested more than 64 levels deep");
+        }
+        assert_eq!(
+            evaluate(&format!("{}1", "-".repeat(MAX_DEPTH)), None).unwrap(),
+            "1"
+        );
+    }
+
+    #[test]
+    fn calculate_answers_every_line() {
+        assert_eq!(
+            calculate("1 + 1\n\nsqrt(-4)\n", None).unwrap(),
+            "1 + 1 = 2\nsqrt(-4) = error: sqrt() is not defined for negative numbers"
+        );
+        assert!(calculate("  \n", None).is_err());
+    }
+}
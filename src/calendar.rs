@@ -0,0 +1,1153 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::dates::{day_count, days_until, weekday};
+
+// Stop expanding a recurrence after this many periods (days, weeks, ...)
+const MAX_PERIODS: usize = 100_000;
+const FOLD_WIDTH: usize = 75;
+
+/// Outlook and Exchange write Windows zone names instead of IANA ones
+const WINDOWS_ZONES: &[(&str, &str)] = &[
+    ("Pacific Standard Time", "America/Los_Angeles"),
+    ("Mountain Standard Time", "America/Denver"),
+    ("Central Standard Time", "America/Chicago"),
+    ("Eastern Standard Time", "America/New_York"),
+    ("GMT Standard Time", "Europe/London"),
+    ("W. Europe Standard Time", "Europe/Berlin"),
+    ("Romance Standard Time", "Europe/Paris"),
+    ("Central Europe Standard Time", "Europe/Budapest"),
+    ("India Standard Time", "Asia/Kolkata"),
+    ("China Standard Time", "Asia/Shanghai"),
+    ("Tokyo Standard Time", "Asia/Tokyo"),
+    ("AUS Eastern Standard Time", "Australia/Sydney"),
+    ("UTC", "UTC"),
+];
+
+/// The zone to show times in: the given name, else the system zone, else UTC
+pub fn local_zone(name: Option<&str>) -> Result<Tz> {
+    match name {
+        Some(name) => name
+            .parse()
+            .map_err(|_| anyhow!("Unknown time zone '{}'", name)),
+        None => Ok(iana_time_zone::get_timezone()
+            .ok()
+            .and_then(|name| name.parse().ok())
+            .unwrap_or(Tz::UTC)),
+    }
+}
+
+fn parse_zone(tzid: &str) -> Option<Tz> {
+    let tzid = tzid.trim_matches('"');
+    if let Ok(tz) = tzid.parse() {
+        return Some(tz);
+    }
+    if let Some((_, iana)) = WINDOWS_ZONES.iter().find(|(windows, _)| *windows == tzid) {
+        return iana.parse().ok();
+    }
+    // Some clients prefix the IANA name, like /mozilla.org/20050126_1/America/New_York
+    tzid.match_indices('/')
+        .find_map(|(i, _)| tzid[i + 1..].parse().ok())
+}
+
+/// Wall-clock time in a zone; a time that falls in a DST gap moves forward
+fn in_zone(tz: &Tz, time: NaiveDateTime) -> DateTime<Tz> {
+    tz.from_local_datetime(&time)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(time + Duration::hours(1)))
+                .earliest()
+        })
+        .unwrap_or_else(|| tz.from_utc_datetime(&time))
+}
+
+/// When an event starts or ends, as written in the file
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Time {
+    Date(NaiveDate),
+    /// Local time in a zone; None is "floating" and means the viewer's zone
+    Local(NaiveDateTime, Option<Tz>),
+}
+
+impl Time {
+    fn parse(value: &str, tzid: Option<&str>) -> Result<Time> {
+        let value = value.trim();
+        if value.len() == 8 {
+            return Ok(Time::Date(NaiveDate::parse_from_str(value, "%Y%m%d")?));
+        }
+        if let Some(utc) = value.strip_suffix('Z') {
+            let time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")?;
+            return Ok(Time::Local(time, Some(Tz::UTC)));
+        }
+        let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")?;
+        Ok(Time::Local(time, tzid.and_then(parse_zone)))
+    }
+
+    fn naive(&self) -> NaiveDateTime {
+        match self {
+            Time::Date(date) => date.and_time(NaiveTime::MIN),
+            Time::Local(time, _) => *time,
+        }
+    }
+
+    fn zone(&self) -> Option<Tz> {
+        match self {
+            Time::Date(_) => None,
+            Time::Local(_, zone) => *zone,
+        }
+    }
+
+    /// The same kind of time at another wall-clock time
+    fn with_naive(&self, time: NaiveDateTime) -> Time {
+        match self {
+            Time::Date(_) => Time::Date(time.date()),
+            Time::Local(_, zone) => Time::Local(time, *zone),
+        }
+    }
+
+    /// Wall-clock time of this moment in the zone of `like`, so that
+    /// EXDATE and UNTIL values can be compared with recurrence instances
+    fn naive_like(&self, like: &Time, viewer: &Tz) -> NaiveDateTime {
+        match (self, like) {
+            (Time::Local(time, zone), Time::Local(_, like_zone)) if zone != like_zone => {
+                let moment = in_zone(&zone.unwrap_or(*viewer), *time);
+                moment
+                    .with_timezone(&like_zone.unwrap_or(*viewer))
+                    .naive_local()
+            }
+            _ => self.naive(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// The parts of an RFC 5545 RRULE that calendars commonly use
+#[derive(Clone, Debug)]
+struct Rule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<usize>,
+    until: Option<Time>,
+    by_day: Vec<(Option<i32>, Weekday)>,
+    by_month_day: Vec<i32>,
+    by_month: Vec<u32>,
+    by_set_pos: Vec<i32>,
+}
+
+fn parse_weekday(code: &str) -> Result<Weekday> {
+    Ok(match code {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => bail!("Unknown weekday '{}'", code),
+    })
+}
+
+fn parse_list<T: std::str::FromStr>(value: &str) -> Result<Vec<T>> {
+    value
+        .split(',')
+        .map(|item| {
+            item.trim_start_matches('+')
+                .parse()
+                .map_err(|_| anyhow!("Bad number '{}' in RRULE", item))
+        })
+        .collect()
+}
+
+impl Rule {
+    fn parse(value: &str) -> Result<Rule> {
+        let mut rule = Rule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+            by_set_pos: Vec::new(),
+        };
+        let mut frequency = None;
+        for part in value.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Bad RRULE part '{}'", part))?;
+            match key {
+                "FREQ" => {
+                    frequency = Some(match value {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => bail!("Unsupported recurrence frequency {}", value),
+                    })
+                }
+                "INTERVAL" => rule.interval = value.parse::<u32>()?.max(1),
+                "COUNT" => rule.count = Some(value.parse()?),
+                "UNTIL" => rule.until = Some(Time::parse(value, None)?),
+                "BYDAY" => {
+                    rule.by_day = value
+                        .split(',')
+                        .map(|day| {
+                            let (ordinal, code) = day.split_at(day.len().saturating_sub(2));
+                            let ordinal = match ordinal.trim_start_matches('+') {
+                                "" => None,
+                                n => Some(n.parse()?),
+                            };
+                            Ok((ordinal, parse_weekday(code)?))
+                        })
+                        .collect::<Result<_>>()?
+                }
+                "BYMONTHDAY" => rule.by_month_day = parse_list(value)?,
+                "BYMONTH" => rule.by_month = parse_list(value)?,
+                "BYSETPOS" => rule.by_set_pos = parse_list(value)?,
+                // WKST only matters for rare weekly rules; other BY parts
+                // (hours, minutes, week numbers) are not supported
+                _ => {}
+            }
+        }
+        rule.frequency = frequency.ok_or_else(|| anyhow!("RRULE without FREQ"))?;
+        Ok(rule)
+    }
+
+    /// All dates in the k-th period after the one containing `start`
+    fn period(&self, start: NaiveDate, k: usize) -> Option<Vec<NaiveDate>> {
+        let step = u32::try_from(k).ok()?.checked_mul(self.interval)?;
+        let dates = match self.frequency {
+            Frequency::Daily => vec![start.checked_add_signed(Duration::try_days(step as i64)?)?],
+            Frequency::Weekly => {
+                let monday = start
+                    .checked_sub_signed(Duration::days(
+                        start.weekday().num_days_from_monday() as i64
+                    ))?
+                    .checked_add_signed(Duration::try_weeks(step as i64)?)?;
+                (0..7)
+                    .map(|i| monday.checked_add_signed(Duration::days(i)))
+                    .collect::<Option<_>>()?
+            }
+            Frequency::Monthly => {
+                let months = start.month0().checked_add(step)?;
+                let year = start.year().checked_add(i32::try_from(months / 12).ok()?)?;
+                let first = NaiveDate::from_ymd_opt(year, months % 12 + 1, 1)?;
+                first
+                    .iter_days()
+                    .take_while(|d| d.month() == first.month())
+                    .collect()
+            }
+            Frequency::Yearly => {
+                let year = start.year().checked_add(i32::try_from(step).ok()?)?;
+                let first = NaiveDate::from_ymd_opt(year, 1, 1)?;
+                first
+                    .iter_days()
+                    .take_while(|d| d.year() == first.year())
+                    .collect()
+            }
+        };
+        Some(dates)
+    }
+
+    /// Pick the dates of one period the rule applies to
+    fn select(&self, dates: Vec<NaiveDate>, start: NaiveDate) -> Vec<NaiveDate> {
+        let days_in_month = |date: &NaiveDate| {
+            let next =
+                NaiveDate::from_ymd_opt(date.year(), date.month(), 28).unwrap() + Duration::days(4);
+            (next - Duration::days(next.day() as i64)).day() as i32
+        };
+        // Ordinals like 2MO count within the month, or within the year for
+        // a yearly rule without BYMONTH
+        let ordinal_in_month = self.frequency == Frequency::Monthly
+            || (self.frequency == Frequency::Yearly && !self.by_month.is_empty());
+
+        let mut selected: Vec<NaiveDate> = dates
+            .iter()
+            .copied()
+            .filter(|date| self.by_month.is_empty() || self.by_month.contains(&date.month()))
+            .filter(|date| {
+                self.by_month_day.is_empty()
+                    || self.by_month_day.iter().any(|&day| {
+                        let day = if day < 0 {
+                            days_in_month(date) + 1 + day
+                        } else {
+                            day
+                        };
+                        date.day() as i32 == day
+                    })
+            })
+            .filter(|date| {
+                self.by_day.is_empty()
+                    || self.by_day.iter().any(|&(ordinal, weekday)| {
+                        if date.weekday() != weekday {
+                            return false;
+                        }
+                        let Some(ordinal) = ordinal else {
+                            return true;
+                        };
+                        let same: Vec<&NaiveDate> = dates
+                            .iter()
+                            .filter(|other| {
+                                other.weekday() == weekday
+                                    && (!ordinal_in_month || other.month() == date.month())
+                            })
+                            .collect();
+                        let position = same.iter().position(|other| *other == date).unwrap() as i32;
+                        if ordinal > 0 {
+                            position + 1 == ordinal
+                        } else {
+                            position - same.len() as i32 == ordinal
+                        }
+                    })
+            })
+            .filter(|date| {
+                // Without BYDAY or BYMONTHDAY the start date sets the day
+                !self.by_day.is_empty()
+                    || !self.by_month_day.is_empty()
+                    || match self.frequency {
+                        Frequency::Daily => true,
+                        Frequency::Weekly => date.weekday() == start.weekday(),
+                        Frequency::Monthly => date.day() == start.day(),
+                        Frequency::Yearly => {
+                            date.day() == start.day()
+                                && (!self.by_month.is_empty() || date.month() == start.month())
+                        }
+                    }
+            })
+            .collect();
+
+        if !self.by_set_pos.is_empty() {
+            let count = selected.len() as i32;
+            selected = self
+                .by_set_pos
+                .iter()
+                .filter_map(|&pos| {
+                    let index = if pos > 0 { pos - 1 } else { count + pos };
+                    selected.get(usize::try_from(index).ok()?).copied()
+                })
+                .collect();
+            selected.sort();
+        }
+        selected
+    }
+
+    /// Start times of the instances up to `end`, all as wall-clock times in
+    /// the zone of the start
+    fn expand(
+        &self,
+        start: NaiveDateTime,
+        until: Option<NaiveDateTime>,
+        end: NaiveDateTime,
+    ) -> Vec<NaiveDateTime> {
+        let mut instances = Vec::new();
+        for k in 0..MAX_PERIODS {
+            let Some(dates) = self.period(start.date(), k) else {
+                break;
+            };
+            if dates
+                .first()
+                .is_some_and(|first| first.and_time(NaiveTime::MIN) > end)
+            {
+                break;
+            }
+            for date in self.select(dates, start.date()) {
+                let instance = date.and_time(start.time());
+                if instance < start {
+                    continue;
+                }
+                if until.is_some_and(|until| instance > until)
+                    || self.count.is_some_and(|count| instances.len() >= count)
+                {
+                    return instances;
+                }
+                instances.push(instance);
+            }
+        }
+        instances
+    }
+}
+
+/// A VEVENT, or a changed instance of a recurring one
+#[derive(Clone, Debug)]
+struct Event {
+    uid: String,
+    summary: String,
+    location: String,
+    start: Time,
+    end: Option<Time>,
+    duration: Option<Duration>,
+    rule: Option<Rule>,
+    extra_dates: Vec<Time>,
+    excluded: Vec<Time>,
+    recurrence_id: Option<Time>,
+    cancelled: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum When {
+    /// First day and the day after the last
+    AllDay(NaiveDate, NaiveDate),
+    Timed(DateTime<Tz>, DateTime<Tz>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Occurrence {
+    pub summary: String,
+    pub location: String,
+    pub when: When,
+}
+
+impl Event {
+    fn length(&self) -> Duration {
+        if let Some(end) = self.end {
+            return end.naive() - self.start.naive();
+        }
+        self.duration.unwrap_or(match self.start {
+            Time::Date(_) => Duration::days(1),
+            Time::Local(..) => Duration::zero(),
+        })
+    }
+
+    fn occurrence(&self, start: Time, viewer: &Tz) -> Occurrence {
+        let end = start
+            .naive()
+            .checked_add_signed(self.length())
+            .unwrap_or(NaiveDateTime::MAX);
+        let when = match start {
+            Time::Date(date) => When::AllDay(date, end.date().max(date + Duration::days(1))),
+            Time::Local(time, zone) => {
+                let zone = zone.unwrap_or(*viewer);
+                When::Timed(
+                    in_zone(&zone, time).with_timezone(viewer),
+                    in_zone(&zone, end).with_timezone(viewer),
+                )
+            }
+        };
+        Occurrence {
+            summary: self.summary.clone(),
+            location: self.location.clone(),
+            when,
+        }
+    }
+
+    /// Start times of every instance that may begin before `end`
+    fn instances(&self, end: &DateTime<Tz>, viewer: &Tz) -> Vec<Time> {
+        let Some(rule) = &self.rule else {
+            return vec![self.start];
+        };
+        let zone = self.start.zone().unwrap_or(*viewer);
+        let end = end.with_timezone(&zone).naive_local() + Duration::days(1);
+        let until = rule.until.map(|until| match until {
+            Time::Date(date) => {
+                date.and_time(NaiveTime::MIN) + Duration::days(1) - Duration::seconds(1)
+            }
+            until => until.naive_like(&self.start, viewer),
+        });
+        let mut instances: Vec<Time> = rule
+            .expand(self.start.naive(), until, end)
+            .into_iter()
+            .map(|time| self.start.with_naive(time))
+            .collect();
+        instances.extend(self.extra_dates.iter().copied());
+        let excluded: Vec<NaiveDateTime> = self
+            .excluded
+            .iter()
+            .map(|time| time.naive_like(&self.start, viewer))
+            .collect();
+        instances.retain(|time| !excluded.contains(&time.naive()));
+        instances
+    }
+}
+
+fn unescape(value: &str) -> String {
+    let mut text = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') | Some('N') => text.push('\n'),
+                Some(other) => text.push(other),
+                None => {}
+            }
+        } else {
+            text.push(c);
+        }
+    }
+    text
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+type Params = Vec<(String, String)>;
+
+/// Split a content line into name, parameters and value. Colons inside
+/// quoted parameter values don't end the name.
+fn parse_line(line: &str) -> Option<(String, Params, String)> {
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, c)| {
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => return Some(i),
+            _ => {}
+        }
+        None
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let mut parts = head.split(';');
+    let name = parts.next()?.to_uppercase();
+    let params = parts
+        .filter_map(|param| param.split_once('='))
+        .map(|(key, value)| (key.to_uppercase(), value.trim_matches('"').to_string()))
+        .collect();
+    Some((name, params, value.to_string()))
+}
+
+/// Set one property of an event from its content line
+fn set_property(event: &mut Event, name: &str, tzid: Option<&str>, value: &str) -> Result<()> {
+    let times =
+        || -> Result<Vec<Time>> { value.split(',').map(|v| Time::parse(v, tzid)).collect() };
+    let context = || format!("Bad {} '{}'", name, value);
+    match name {
+        "UID" => event.uid = value.to_string(),
+        "SUMMARY" => event.summary = unescape(value),
+        "LOCATION" => event.location = unescape(value),
+        "DTSTART" => event.start = Time::parse(value, tzid).with_context(context)?,
+        "DTEND" => event.end = Some(Time::parse(value, tzid).with_context(context)?),
+        "DURATION" => event.duration = Some(parse_duration(value).with_context(context)?),
+        "RRULE" => event.rule = Some(Rule::parse(value).with_context(context)?),
+        "RDATE" => event.extra_dates.extend(times().with_context(context)?),
+        "EXDATE" => event.excluded.extend(times().with_context(context)?),
+        "RECURRENCE-ID" => {
+            event.recurrence_id = Some(Time::parse(value, tzid).with_context(context)?)
+        }
+        "STATUS" => event.cancelled = value == "CANCELLED",
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The events of an iCalendar file. One that can't be understood, like one
+/// repeating hourly, is left out rather than losing the whole calendar.
+fn parse_events(data: &str) -> Vec<Event> {
+    // Lines starting with a space or tab continue the previous one
+    let mut lines: Vec<String> = Vec::new();
+    for line in data.lines() {
+        match line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')) {
+            Some(rest) if !lines.is_empty() => lines.last_mut().unwrap().push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut events = Vec::new();
+    let mut current: Option<Event> = None;
+    // Alarms and other components nested in an event have their own fields
+    let mut depth = 0;
+    for line in lines {
+        let Some((name, params, value)) = parse_line(&line) else {
+            continue;
+        };
+        let tzid = params
+            .iter()
+            .find(|(key, _)| key == "TZID")
+            .map(|(_, value)| value.as_str());
+        match (name.as_str(), value.as_str()) {
+            ("BEGIN", "VEVENT") => {
+                current = Some(Event {
+                    uid: String::new(),
+                    summary: String::new(),
+                    location: String::new(),
+                    start: Time::Date(NaiveDate::MIN),
+                    end: None,
+                    duration: None,
+                    rule: None,
+                    extra_dates: Vec::new(),
+                    excluded: Vec::new(),
+                    recurrence_id: None,
+                    cancelled: false,
+                });
+                depth = 0;
+            }
+            ("END", "VEVENT") => {
+                if let Some(event) = current.take() {
+                    if event.start != Time::Date(NaiveDate::MIN) {
+                        events.push(event);
+                    }
+                }
+            }
+            ("BEGIN", _) if current.is_some() => depth += 1,
+            ("END", _) if current.is_some() => depth -= 1,
+            _ => {
+                let Some(event) = current.as_mut().filter(|_| depth == 0) else {
+                    continue;
+                };
+                if let Err(e) = set_property(event, &name, tzid, &value) {
+                    // Later lines of the event are ignored until the next one
+                    println!("Skipping calendar event {}: {:#}", event.uid, e);
+                    current = None;
+                }
+            }
+        }
+    }
+    events
+}
+
+/// An RFC 5545 duration like PT1H30M or P2D
+fn parse_duration(value: &str) -> Result<Duration> {
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, value.trim_start_matches('+')),
+    };
+    let value = value
+        .strip_prefix('P')
+        .ok_or_else(|| anyhow!("Durations start with P"))?;
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let n: i64 = if c == 'T' { 0 } else { number.parse()? };
+        let part = match c {
+            'W' => Duration::try_weeks(n),
+            'D' => Duration::try_days(n),
+            'H' => Duration::try_hours(n),
+            'M' => Duration::try_minutes(n),
+            'S' => Duration::try_seconds(n),
+            'T' => Some(Duration::zero()),
+            _ => bail!("Unexpected '{}' in duration", c),
+        };
+        total = part
+            .and_then(|part| total.checked_add(&part))
+            .ok_or_else(|| anyhow!("Duration too long"))?;
+        number.clear();
+    }
+    Ok(total * sign)
+}
+
+/// Fold a content line at 75 octets as RFC 5545 asks
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > FOLD_WIDTH {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+/// An iCalendar file, which may be kept in sync by another program
+pub struct Calendar {
+    path: PathBuf,
+    zone: Tz,
+}
+
+impl Calendar {
+    /// Times are shown, and entered, in `zone`
+    pub fn new(path: impl AsRef<Path>, zone: Tz) -> Calendar {
+        Calendar {
+            path: path.as_ref().to_path_buf(),
+            zone,
+        }
+    }
+
+    pub fn zone(&self) -> Tz {
+        self.zone
+    }
+
+    pub fn today(&self) -> NaiveDate {
+        Utc::now().with_timezone(&self.zone).date_naive()
+    }
+
+    /// Every occurrence that overlaps the days from `first` to `last`,
+    /// sorted by start time
+    pub fn occurrences(&self, first: NaiveDate, last: NaiveDate) -> Result<Vec<Occurrence>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let events = parse_events(&fs::read_to_string(&self.path)?);
+        let start = in_zone(&self.zone, first.and_time(NaiveTime::MIN));
+        let end = in_zone(
+            &self.zone,
+            (last + Duration::days(1)).and_time(NaiveTime::MIN),
+        );
+
+        let mut occurrences = Vec::new();
+        for event in events.iter().filter(|event| event.recurrence_id.is_none()) {
+            // Changed instances replace the ones the rule would produce
+            let changes: Vec<&Event> = events
+                .iter()
+                .filter(|other| other.uid == event.uid && other.recurrence_id.is_some())
+                .collect();
+            let changed: Vec<NaiveDateTime> = changes
+                .iter()
+                .filter_map(|change| change.recurrence_id)
+                .map(|id| id.naive_like(&event.start, &self.zone))
+                .collect();
+            if !event.cancelled {
+                for instance in event.instances(&end, &self.zone) {
+                    if !changed.contains(&instance.naive()) {
+                        occurrences.push(event.occurrence(instance, &self.zone));
+                    }
+                }
+            }
+            for change in changes.iter().filter(|change| !change.cancelled) {
+                occurrences.push(change.occurrence(change.start, &self.zone));
+            }
+        }
+
+        occurrences.retain(|occurrence| match occurrence.when {
+            When::AllDay(from, to) => from <= last && to > first,
+            When::Timed(from, to) => from < end && (to > start || from >= start),
+        });
+        occurrences.sort_by_key(|occurrence| match occurrence.when {
+            When::AllDay(from, _) => (from, None),
+            When::Timed(from, _) => (from.date_naive(), Some(from)),
+        });
+        Ok(occurrences)
+    }
+
+    /// A day-by-day agenda for the model
+    pub fn agenda(&self, first: NaiveDate, days: u32, search: &str) -> Result<String> {
+        let last = first
+            .checked_add_signed(Duration::days(days.max(1) as i64 - 1))
+            .ok_or_else(|| anyhow!("{} days from {} is out of range", days, first))?;
+        let search = search.trim().to_lowercase();
+        let occurrences: Vec<Occurrence> = self
+            .occurrences(first, last)?
+            .into_iter()
+            .filter(|occurrence| {
+                search.is_empty()
+                    || occurrence.summary.to_lowercase().contains(&search)
+                    || occurrence.location.to_lowercase().contains(&search)
+            })
+            .collect();
+
+        let mut lines = vec![format!(
+            "Today is {}. Times are in {}.",
+            self.today().format("%A %Y-%m-%d"),
+            self.zone
+        )];
+        for day in first.iter_days().take_while(|day| *day <= last) {
+            let on_day: Vec<String> = occurrences
+                .iter()
+                .filter_map(|occurrence| {
+                    let what = if occurrence.location.is_empty() {
+                        occurrence.summary.clone()
+                    } else {
+                        format!("{} ({})", occurrence.summary, occurrence.location)
+                    };
+                    match occurrence.when {
+                        When::AllDay(from, to) if from <= day && day < to => {
+                            Some(format!("  all day: {}", what))
+                        }
+                        When::Timed(from, to)
+                            if from.date_naive() == day
+                                || (from.date_naive() < day
+                                    && to.date_naive() >= day
+                                    && day == first) =>
+                        {
+                            Some(format!(
+                                "  {}-{} {}",
+                                from.format(if from.date_naive() == day {
+                                    "%H:%M"
+                                } else {
+                                    "(%a) %H:%M"
+                                }),
+                                to.format(if to.date_naive() == from.date_naive() {
+                                    "%H:%M"
+                                } else {
+                                    "%a %H:%M"
+                                }),
+                                what
+                            ))
+                        }
+                        _ => None,
+                    }
+                })
+                .collect();
+            lines.push(format!("{}:", day.format("%A %Y-%m-%d")));
+            if on_day.is_empty() {
+                lines.push("  nothing scheduled".to_string());
+            } else {
+                lines.extend(on_day);
+            }
+        }
+        Ok(lines.join("\n"))
+    }
+
+    /// Add a single event, timed when `start` has a time and all-day
+    /// otherwise. Times are stored in UTC so the file needs no VTIMEZONE.
+    pub fn add_event(
+        &self,
+        summary: &str,
+        date: NaiveDate,
+        start: Option<NaiveTime>,
+        length: Duration,
+        location: &str,
+    ) -> Result<Occurrence> {
+        if summary.trim().is_empty() {
+            bail!("The event needs a summary");
+        }
+        let now = Utc::now();
+        let (dtstart, dtend, when) = match start {
+            Some(time) => {
+                let from = in_zone(&self.zone, date.and_time(time));
+                let to = from
+                    .checked_add_signed(length)
+                    .ok_or_else(|| anyhow!("The event is too long"))?;
+                let format = |time: DateTime<Tz>| {
+                    time.with_timezone(&Utc)
+                        .format("%Y%m%dT%H%M%SZ")
+                        .to_string()
+                };
+                (format(from), format(to), When::Timed(from, to))
+            }
+            None => {
+                let to = date
+                    .checked_add_signed(Duration::days(length.num_days().max(1)))
+                    .ok_or_else(|| anyhow!("The event is too long"))?;
+                (
+                    format!("VALUE=DATE:{}", date.format("%Y%m%d")),
+                    format!("VALUE=DATE:{}", to.format("%Y%m%d")),
+                    When::AllDay(date, to),
+                )
+            }
+        };
+        let separator = |value: &str| if value.starts_with("VALUE") { ";" } else { ":" };
+        let mut event = vec![
+            "BEGIN:VEVENT".to_string(),
+            format!(
+                "UID:{}@ghostwriter",
+                now.timestamp_nanos_opt().unwrap_or_default()
+            ),
+            format!("DTSTAMP:{}", now.format("%Y%m%dT%H%M%SZ")),
+            format!("DTSTART{}{}", separator(&dtstart), dtstart),
+            format!("DTEND{}{}", separator(&dtend), dtend),
+            format!("SUMMARY:{}", escape(summary.trim())),
+        ];
+        if !location.trim().is_empty() {
+            event.push(format!("LOCATION:{}", escape(location.trim())));
+        }
+        event.push("END:VEVENT".to_string());
+        let event: String = event.iter().map(|line| fold(line)).collect();
+
+        let existing = if self.path.exists() {
+            fs::read_to_string(&self.path)?
+        } else {
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//ghostwriter//EN\r\nEND:VCALENDAR\r\n"
+                .to_string()
+        };
+        let end = existing
+            .rfind("END:VCALENDAR")
+            .ok_or_else(|| anyhow!("{} has no END:VCALENDAR", self.path.display()))?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            &self.path,
+            format!("{}{}{}", &existing[..end], event, &existing[end..]),
+        )?;
+
+        Ok(Occurrence {
+            summary: summary.trim().to_string(),
+            location: location.trim().to_string(),
+            when,
+        })
+    }
+}
+
+/// A time of day like 12:30, 9am or 2:15 pm
+pub fn parse_time(value: &str) -> Result<NaiveTime> {
+    let value = value.trim().to_lowercase().replace([' ', '.'], "");
+    let (value, offset) = if let Some(rest) = value.strip_suffix("am") {
+        (rest.to_string(), Some(0))
+    } else if let Some(rest) = value.strip_suffix("pm") {
+        (rest.to_string(), Some(12))
+    } else {
+        (value, None)
+    };
+    let (hour, minute) = match value.split_once(':') {
+        Some((hour, minute)) => (hour.parse::<u32>()?, minute.parse::<u32>()?),
+        None => (value.parse::<u32>()?, 0),
+    };
+    let hour = match offset {
+        Some(_) if hour == 0 || hour > 12 => bail!("Bad time '{}'", value),
+        Some(offset) => hour % 12 + offset,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0).ok_or_else(|| anyhow!("Bad time '{}'", value))
+}
+
+/// A day the model or user wrote: 2024-10-23, today, yesterday, tomorrow,
+/// a weekday name (this week's, from today on), next or last and a weekday,
+/// this, next or last week (its Monday), "in 3 days" or "2 weeks ago".
+/// Unlike a todo due date this can be today or in the past.
+pub fn parse_day(value: &str, today: NaiveDate) -> Result<NaiveDate> {
+    let value = value.trim().to_lowercase();
+    if let Ok(date) = NaiveDate::parse_from_str(&value, "%Y-%m-%d") {
+        return Ok(date);
+    }
+    let monday = -(today.weekday().num_days_from_monday() as i64);
+    let unknown = || anyhow!("Can not understand the date '{}'; use YYYY-MM-DD", value);
+    let count = |count: &str, unit: &str| day_count(count, unit).ok_or_else(unknown);
+
+    let words: Vec<&str> = value.split_whitespace().collect();
+    let days = match words.as_slice() {
+        ["today"] => 0,
+        ["yesterday"] => -1,
+        ["tomorrow"] => 1,
+        ["this", "week"] => monday,
+        ["next", "week"] => monday + 7,
+        ["last", "week"] => monday - 7,
+        ["in", n, unit] => count(n, unit)?,
+        [n, unit, "ago"] => -count(n, unit)?,
+        [name] | ["this", name] | ["on", name] if weekday(name).is_some() => {
+            days_until(today, weekday(name).unwrap())
+        }
+        ["next", name] if weekday(name).is_some() => days_until(today, weekday(name).unwrap()) + 7,
+        ["last", name] if weekday(name).is_some() => days_until(today, weekday(name).unwrap()) - 7,
+        _ => return Err(unknown()),
+    };
+    Duration::try_days(days)
+        .and_then(|days| today.checked_add_signed(days))
+        .ok_or_else(|| anyhow!("'{}' is out of range", value))
+}
+
+impl std::fmt::Display for Occurrence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.when {
+            When::AllDay(from, to) if to - from == Duration::days(1) => write!(
+                f,
+                "'{}' all day on {}",
+                self.summary,
+                from.format("%A %Y-%m-%d")
+            )?,
+            When::AllDay(from, to) => write!(
+                f,
+                "'{}' from {} to {}",
+                self.summary,
+                from.format("%A %Y-%m-%d"),
+                (to - Duration::days(1)).format("%A %Y-%m-%d")
+            )?,
+            When::Timed(from, to) => write!(
+                f,
+                "'{}' on {} from {} to {}",
+                self.summary,
+                from.format("%A %Y-%m-%d"),
+                from.format("%H:%M"),
+                to.format("%H:%M")
+            )?,
+        }
+        if !self.location.is_empty() {
+            write!(f, " at {}", self.location)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn expand(rule: &str, start: &str, count: usize) -> Vec<NaiveDateTime> {
+        let start = NaiveDateTime::parse_from_str(start, "%Y%m%dT%H%M%S").unwrap();
+        let end = start + Duration::days(3650);
+        let mut instances = Rule::parse(rule).unwrap().expand(start, None, end);
+        instances.truncate(count);
+        instances
+    }
+
+    fn dates(instances: Vec<NaiveDateTime>) -> Vec<String> {
+        instances
+            .iter()
+            .map(|time| time.format("%Y-%m-%d").to_string())
+            .collect()
+    }
+
+    fn calendar(events: &str) -> String {
+        format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\n{}END:VCALENDAR\r\n",
+            events
+        )
+    }
+
+    #[test]
+    fn weekly_rule_on_several_days() {
+        assert_eq!(
+            dates(expand(
+                "FREQ=WEEKLY;BYDAY=MO,WE;COUNT=4",
+                "20241021T090000",
+                10
+            )),
+            ["2024-10-21", "2024-10-23", "2024-10-28", "2024-10-30"]
+        );
+    }
+
+    #[test]
+    fn monthly_rule_on_last_friday() {
+        assert_eq!(
+            dates(expand("FREQ=MONTHLY;BYDAY=-1FR", "20241025T120000", 3)),
+            ["2024-10-25", "2024-11-29", "2024-12-27"]
+        );
+    }
+
+    #[test]
+    fn monthly_rule_skips_short_months() {
+        assert_eq!(
+            dates(expand("FREQ=MONTHLY;INTERVAL=1", "20250131T120000", 3)),
+            ["2025-01-31", "2025-03-31", "2025-05-31"]
+        );
+    }
+
+    #[test]
+    fn yearly_rule_with_set_position() {
+        // Last weekday of March
+        assert_eq!(
+            dates(expand(
+                "FREQ=YEARLY;BYMONTH=3;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1",
+                "20240329T100000",
+                2
+            )),
+            ["2024-03-29", "2025-03-31"]
+        );
+    }
+
+    #[test]
+    fn huge_intervals_stop_instead_of_overflowing() {
+        for frequency in ["DAILY", "WEEKLY", "MONTHLY", "YEARLY"] {
+            let rule = format!("FREQ={};INTERVAL=4294967295", frequency);
+            assert_eq!(expand(&rule, "20241021T090000", 10).len(), 1, "{}", rule);
+        }
+    }
+
+    #[test]
+    fn unsupported_rules_are_errors() {
+        assert!(Rule::parse("FREQ=HOURLY").is_err());
+        assert!(Rule::parse("INTERVAL=2").is_err());
+    }
+
+    #[test]
+    fn bad_events_are_skipped() {
+        let data = calendar(
+            "BEGIN:VEVENT\r\nUID:a\r\nDTSTART:20241021T090000Z\r\nRRULE:FREQ=HOURLY\r\nSUMMARY:Hourly\r\nEND:VEVENT\r\n\
+             BEGIN:VEVENT\r\nUID:b\r\nDTSTART:20241021T090000Z\r\nSUMMARY:Kept\r\nEND:VEVENT\r\n",
+        );
+        let events = parse_events(&data);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].summary, "Kept");
+    }
+
+    #[test]
+    fn times_are_shown_in_the_viewers_zone() {
+        let dir = ScratchDir::new("calendar-zones");
+        let path = dir.join("calendar.ics");
+        fs::write(
+            &path,
+            calendar(
+                "BEGIN:VEVENT\r\nUID:a\r\nDTSTART;TZID=America/New_York:20241021T090000\r\n\
+                 DTEND;TZID=America/New_York:20241021T100000\r\nSUMMARY:Standup\r\nEND:VEVENT\r\n\
+                 BEGIN:VEVENT\r\nUID:b\r\nDTSTART;TZID=W. Europe Standard Time:20241022T090000\r\n\
+                 DURATION:PT30M\r\nSUMMARY:Call\r\nEND:VEVENT\r\n",
+            ),
+        )
+        .unwrap();
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        let occurrences = Calendar::new(&path, berlin)
+            .occurrences(date(2024, 10, 21), date(2024, 10, 22))
+            .unwrap();
+        let times: Vec<String> = occurrences
+            .iter()
+            .map(|occurrence| match occurrence.when {
+                When::Timed(from, to) => {
+                    format!("{} {}", from.format("%d %H:%M"), to.format("%H:%M"))
+                }
+                When::AllDay(..) => "all day".to_string(),
+            })
+            .collect();
+        assert_eq!(times, ["21 15:00 16:00", "22 09:00 09:30"]);
+    }
+
+    #[test]
+    fn recurrence_follows_the_event_zone_across_dst() {
+        // 9:00 in New York stays 9:00 there when Berlin changes its clocks
+        // a week earlier, so it moves an hour in Berlin
+        let data = calendar(
+            "BEGIN:VEVENT\r\nUID:a\r\nDTSTART;TZID=America/New_York:20241021T090000\r\n\
+             RRULE:FREQ=WEEKLY;COUNT=2\r\nSUMMARY:Standup\r\nEND:VEVENT\r\n",
+        );
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        let event = &parse_events(&data)[0];
+        let end = in_zone(&berlin, date(2024, 11, 1).and_time(NaiveTime::MIN));
+        let hours: Vec<String> = event
+            .instances(&end, &berlin)
+            .into_iter()
+            .map(|start| match event.occurrence(start, &berlin).when {
+                When::Timed(from, _) => from.format("%H:%M").to_string(),
+                When::AllDay(..) => String::new(),
+            })
+            .collect();
+        assert_eq!(hours, ["15:00", "14:00"]);
+    }
+
+    #[test]
+    fn long_lines_are_folded() {
+        let line = format!("SUMMARY:{}", "é".repeat(50));
+        let folded = fold(&line);
+        assert!(folded.split("\r\n").all(|part| part.len() <= FOLD_WIDTH));
+        let data = calendar(&format!(
+            "BEGIN:VEVENT\r\nDTSTART:20241021\r\n{}END:VEVENT\r\n",
+            folded
+        ));
+        assert_eq!(parse_events(&data)[0].summary, "é".repeat(50));
+    }
+
+    #[test]
+    fn days_include_today_and_the_past() {
+        let monday = date(2024, 10, 21);
+        assert_eq!(parse_day("monday", monday).unwrap(), monday);
+        assert_eq!(parse_day("friday", monday).unwrap(), date(2024, 10, 25));
+        assert_eq!(
+            parse_day("next monday", monday).unwrap(),
+            date(2024, 10, 28)
+        );
+        assert_eq!(
+            parse_day("last friday", monday).unwrap(),
+            date(2024, 10, 18)
+        );
+        assert_eq!(parse_day("yesterday", monday).unwrap(), date(2024, 10, 20));
+        assert_eq!(parse_day("2 weeks ago", monday).unwrap(), date(2024, 10, 7));
+        assert_eq!(
+            parse_day("last week", date(2024, 10, 23)).unwrap(),
+            date(2024, 10, 14)
+        );
+        assert!(parse_day("in 999999999999 days", monday).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+//! Letting the user stop a drawing or typing part way through by touching
+//! the screen, instead of waiting out a runaway answer
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The error drawing and typing stop with once the user cancels
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Stopped by the user")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Shared between the touch watcher, which raises it, and the pen and
+/// keyboard, which check it before each event. It only takes effect while
+/// armed, so touches between answers are ignored.
+#[derive(Clone, Default)]
+pub struct CancelFlag {
+    armed: Arc<AtomicBool>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelFlag {
+    pub fn new() -> CancelFlag {
+        CancelFlag::default()
+    }
+
+    pub fn arm(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+        self.armed.store(true, Ordering::SeqCst);
+    }
+
+    pub fn disarm(&self) {
+        self.armed.store(false, Ordering::SeqCst);
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+
+    pub fn cancel(&self) {
+        if self.armed.load(Ordering::SeqCst) {
+            self.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
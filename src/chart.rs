@@ -0,0 +1,802 @@
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+use crate::stroke_font::{StrokeFont, CAP_HEIGHT, UNITS_PER_EM};
+use crate::util::{strokes_to_path_data, Polyline};
+
+const TITLE_SIZE: f32 = 24.0;
+const LABEL_SIZE: f32 = 16.0;
+const MARKER_SIZE: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChartType {
+    Bar,
+    Line,
+    Scatter,
+    Pie,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Series {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// One value per label, for bar, line and pie charts
+    #[serde(default)]
+    pub values: Vec<f64>,
+    /// Explicit [x, y] pairs, for scatter charts and numeric line charts
+    #[serde(default)]
+    pub points: Vec<[f64; 2]>,
+}
+
+/// A chart as described by the model, plus the screen rectangle to draw it in
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChartSpec {
+    pub chart_type: ChartType,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    pub series: Vec<Series>,
+    #[serde(default)]
+    pub x_axis_title: Option<String>,
+    #[serde(default)]
+    pub y_axis_title: Option<String>,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+impl Rect {
+    fn right(&self) -> f32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> f32 {
+        self.y + self.height
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Anchor {
+    Start,
+    Middle,
+    End,
+}
+
+/// Collects chart strokes, grouped by the SVG style they are drawn with
+#[derive(Default)]
+struct Canvas {
+    solid: Vec<Polyline>,
+    dashed: Vec<Polyline>,
+    dotted: Vec<Polyline>,
+}
+
+impl Canvas {
+    fn line(&mut self, from: (f32, f32), to: (f32, f32)) {
+        self.solid.push(vec![from, to]);
+    }
+
+    fn rect(&mut self, r: Rect) {
+        self.solid.push(vec![
+            (r.x, r.y),
+            (r.right(), r.y),
+            (r.right(), r.bottom()),
+            (r.x, r.bottom()),
+            (r.x, r.y),
+        ]);
+    }
+
+    fn text(&mut self, text: &str, (x, y): (f32, f32), size: f32, anchor: Anchor) {
+        let font = StrokeFont::simplex();
+        let width = font.text_width(text, size);
+        let x = match anchor {
+            Anchor::Start => x,
+            Anchor::Middle => x - width / 2.0,
+            Anchor::End => x - width,
+        };
+        // (x, y) is the vertical middle of the capitals
+        let baseline = y + CAP_HEIGHT * size / UNITS_PER_EM / 2.0;
+        self.solid
+            .extend(font.text_strokes(text, (x, baseline), size));
+    }
+
+    // Text reading bottom-to-top, centered on (x, y), for the y axis title
+    fn vertical_text(&mut self, text: &str, (x, y): (f32, f32), size: f32) {
+        let font = StrokeFont::simplex();
+        let width = font.text_width(text, size);
+        let half_cap = CAP_HEIGHT * size / UNITS_PER_EM / 2.0;
+        for stroke in font.text_strokes(text, (0.0, half_cap), size) {
+            self.solid.push(
+                stroke
+                    .iter()
+                    .map(|(sx, sy)| (x + sy, y + width / 2.0 - sx))
+                    .collect(),
+            );
+        }
+    }
+
+    fn marker(&mut self, series: usize, (x, y): (f32, f32)) {
+        let s = MARKER_SIZE;
+        let marker = match series % 4 {
+            0 => (0..=12)
+                .map(|i| {
+                    let a = i as f32 / 12.0 * std::f32::consts::TAU;
+                    (x + s * a.cos(), y + s * a.sin())
+                })
+                .collect(),
+            1 => vec![
+                (x - s, y - s),
+                (x + s, y - s),
+                (x + s, y + s),
+                (x - s, y + s),
+                (x - s, y - s),
+            ],
+            2 => vec![
+                (x, y - s * 1.2),
+                (x + s, y + s * 0.8),
+                (x - s, y + s * 0.8),
+                (x, y - s * 1.2),
+            ],
+            _ => {
+                self.solid.push(vec![(x - s, y - s), (x + s, y + s)]);
+                vec![(x + s, y - s), (x - s, y + s)]
+            }
+        };
+        self.solid.push(marker);
+    }
+
+    fn series_line(&mut self, series: usize, points: Polyline) {
+        match series % 3 {
+            0 => self.solid.push(points),
+            1 => self.dashed.push(points),
+            _ => self.dotted.push(points),
+        }
+    }
+
+    // Bars are told apart by their shading, since the screen is monochrome
+    fn bar(&mut self, series: usize, r: Rect) {
+        self.rect(r);
+        match series % 4 {
+            0 => self.hatch(r, 8.0, 1.0),
+            1 => {}
+            2 => {
+                self.hatch(r, 8.0, 1.0);
+                self.hatch(r, 8.0, -1.0);
+            }
+            _ => {
+                let mut y = r.y + 6.0;
+                while y < r.bottom() {
+                    self.line((r.x, y), (r.right(), y));
+                    y += 6.0;
+                }
+            }
+        }
+    }
+
+    // Diagonal lines across a rectangle, going up-right for slope 1 and
+    // down-right for slope -1
+    fn hatch(&mut self, r: Rect, spacing: f32, slope: f32) {
+        let span = r.width + r.height;
+        let mut offset = spacing;
+        while offset < span {
+            // Walk a diagonal starting on the left or bottom/top edge
+            let (start, end) = if slope > 0.0 {
+                let start = (
+                    r.x + (offset - r.height).max(0.0),
+                    r.bottom() - offset.min(r.height),
+                );
+                let end = (
+                    r.x + offset.min(r.width),
+                    r.bottom() - (offset - r.width).max(0.0),
+                );
+                (start, end)
+            } else {
+                let start = (
+                    r.x + (offset - r.height).max(0.0),
+                    r.y + offset.min(r.height),
+                );
+                let end = (r.x + offset.min(r.width), r.y + (offset - r.width).max(0.0));
+                (start, end)
+            };
+            self.line(start, end);
+            offset += spacing;
+        }
+    }
+
+    fn to_svg(&self, width: u32, height: u32) -> String {
+        let mut paths = String::new();
+        for (strokes, dash) in [
+            (&self.solid, None),
+            (&self.dashed, Some("8 5")),
+            (&self.dotted, Some("2 5")),
+        ] {
+            if strokes.is_empty() {
+                continue;
+            }
+            let dash = dash
+                .map(|d| format!(r#" stroke-dasharray="{}""#, d))
+                .unwrap_or_default();
+            paths.push_str(&format!(
+                r#"<path d="{}" fill="none" stroke="black" stroke-width="2" stroke-linecap="round" stroke-linejoin="round"{}/>"#,
+                strokes_to_path_data(strokes),
+                dash
+            ));
+        }
+        format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}">{}</svg>"#,
+            width, height, paths
+        )
+    }
+}
+
+/// Round tick steps to 1, 2 or 5 times a power of ten
+fn nice_ticks(min: f64, max: f64, target_count: usize) -> Vec<f64> {
+    let (min, max) = if (max - min).abs() < f64::EPSILON {
+        (min - 1.0, max + 1.0)
+    } else {
+        (min, max)
+    };
+    let raw_step = (max - min) / target_count.max(1) as f64;
+    let magnitude = 10f64.powf(raw_step.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|step| *step >= raw_step)
+        .unwrap_or(10.0 * magnitude);
+    // Allow for rounding in the division, which would otherwise add a tick
+    // outside the range when it starts or ends on one
+    let first = (min / step + 1e-9).floor() as i64;
+    let last = ((max / step - 1e-9).ceil() as i64).max(first + 1);
+    (first..=last).map(|i| i as f64 * step).collect()
+}
+
+fn format_tick(value: f64, step: f64) -> String {
+    let decimals = if step >= 1.0 {
+        0
+    } else {
+        (-step.log10().floor()) as usize
+    };
+    let text = format!("{:.*}", decimals, value);
+    // Avoid printing "-0"
+    if text
+        .trim_start_matches('-')
+        .chars()
+        .all(|c| c == '0' || c == '.')
+    {
+        text.trim_start_matches('-').to_string()
+    } else {
+        text
+    }
+}
+
+struct Axis {
+    ticks: Vec<f64>,
+}
+
+impl Axis {
+    fn new(values: impl Iterator<Item = f64>, include_zero: bool) -> Axis {
+        let (mut min, mut max) =
+            values.fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)));
+        if min > max {
+            (min, max) = (0.0, 1.0);
+        }
+        if include_zero {
+            min = min.min(0.0);
+            max = max.max(0.0);
+        }
+        Axis {
+            ticks: nice_ticks(min, max, 5),
+        }
+    }
+
+    fn min(&self) -> f64 {
+        self.ticks[0]
+    }
+
+    fn max(&self) -> f64 {
+        self.ticks[self.ticks.len() - 1]
+    }
+
+    fn step(&self) -> f64 {
+        self.ticks[1] - self.ticks[0]
+    }
+
+    // Position of a value between `start` and `end` screen coordinates
+    fn map(&self, value: f64, start: f32, end: f32) -> f32 {
+        let t = (value - self.min()) / (self.max() - self.min());
+        start + (end - start) * t as f32
+    }
+
+    fn labels(&self) -> Vec<String> {
+        self.ticks
+            .iter()
+            .map(|tick| format_tick(*tick, self.step()))
+            .collect()
+    }
+}
+
+fn legend(canvas: &mut Canvas, spec: &ChartSpec, area: Rect) {
+    let font = StrokeFont::simplex();
+    let mut x = area.x;
+    let y = area.y + area.height / 2.0;
+    for (i, series) in spec.series.iter().enumerate() {
+        let name = series.name.clone().unwrap_or(format!("Series {}", i + 1));
+        match spec.chart_type {
+            ChartType::Bar => canvas.bar(
+                i,
+                Rect {
+                    x,
+                    y: y - 7.0,
+                    width: 14.0,
+                    height: 14.0,
+                },
+            ),
+            ChartType::Line => {
+                canvas.series_line(i, vec![(x, y), (x + 20.0, y)]);
+                canvas.marker(i, (x + 10.0, y));
+            }
+            _ => canvas.marker(i, (x + 7.0, y)),
+        }
+        x += 26.0;
+        canvas.text(&name, (x, y), LABEL_SIZE, Anchor::Start);
+        x += font.text_width(&name, LABEL_SIZE) + 20.0;
+    }
+}
+
+/// How far round the circle each slice goes, in radians, with negative
+/// values counted as empty slices
+fn slice_sweeps(values: &[f64]) -> Result<Vec<f32>> {
+    let total: f64 = values.iter().map(|v| v.max(0.0)).sum();
+    if total <= 0.0 {
+        bail!("Pie chart needs positive values");
+    }
+    Ok(values
+        .iter()
+        .map(|value| (value.max(0.0) / total) as f32 * std::f32::consts::TAU)
+        .collect())
+}
+
+fn draw_pie(canvas: &mut Canvas, spec: &ChartSpec, area: Rect) -> Result<()> {
+    let values: Vec<f64> = spec
+        .series
+        .first()
+        .map(|s| s.values.iter().map(|v| v.max(0.0)).collect())
+        .unwrap_or_default();
+    let sweeps = slice_sweeps(&values)?;
+    let total: f64 = values.iter().sum();
+    let labels: Vec<String> = values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let label = spec.labels.get(i).cloned().unwrap_or_default();
+            format!("{} {:.0}%", label, value / total * 100.0)
+                .trim()
+                .to_string()
+        })
+        .collect();
+
+    // Leave room around the pie for slice labels
+    let label_width = labels
+        .iter()
+        .map(|l| StrokeFont::simplex().text_width(l, LABEL_SIZE))
+        .fold(0.0, f32::max);
+    let radius = (area.width / 2.0 - label_width - 16.0)
+        .min(area.height / 2.0 - 24.0)
+        .max(10.0);
+    let center = (area.x + area.width / 2.0, area.y + area.height / 2.0);
+    let point_at = |angle: f32, r: f32| (center.0 + r * angle.sin(), center.1 - r * angle.cos());
+
+    let circle: Polyline = (0..=72)
+        .map(|i| point_at(i as f32 / 72.0 * std::f32::consts::TAU, radius))
+        .collect();
+    canvas.solid.push(circle);
+
+    let mut angle = 0.0f32;
+    for (i, sweep) in sweeps.into_iter().enumerate() {
+        if values.len() > 1 {
+            canvas.line(center, point_at(angle, radius));
+        }
+        let middle = angle + sweep / 2.0;
+        let (lx, ly) = point_at(middle, radius + 12.0);
+        let anchor = if middle.sin() > 0.2 {
+            Anchor::Start
+        } else if middle.sin() < -0.2 {
+            Anchor::End
+        } else {
+            Anchor::Middle
+        };
+        canvas.text(&labels[i], (lx, ly), LABEL_SIZE, anchor);
+        angle += sweep;
+    }
+    Ok(())
+}
+
+fn draw_axes_chart(canvas: &mut Canvas, spec: &ChartSpec, area: Rect) -> Result<()> {
+    let font = StrokeFont::simplex();
+    let numeric_x = spec.chart_type == ChartType::Scatter
+        || (spec.chart_type == ChartType::Line && spec.series.iter().any(|s| !s.points.is_empty()));
+
+    if numeric_x {
+        if let Some(i) = spec.series.iter().position(|s| s.points.is_empty()) {
+            bail!(
+                "Series {} has no points; give every series of this chart as [x, y] points",
+                i + 1
+            );
+        }
+    }
+
+    let y_values = spec.series.iter().flat_map(|s| {
+        s.values
+            .iter()
+            .copied()
+            .chain(s.points.iter().map(|p| p[1]))
+    });
+    let y_axis = Axis::new(y_values, spec.chart_type == ChartType::Bar);
+    let y_labels = y_axis.labels();
+
+    let category_count = if numeric_x {
+        0
+    } else {
+        spec.series
+            .iter()
+            .map(|s| s.values.len())
+            .max()
+            .unwrap_or(0)
+            .max(spec.labels.len())
+    };
+    if !numeric_x && category_count == 0 {
+        bail!("Chart has no values to plot");
+    }
+
+    // Make room for tick labels and axis titles around the plot
+    let y_label_width = y_labels
+        .iter()
+        .map(|l| font.text_width(l, LABEL_SIZE))
+        .fold(0.0, f32::max);
+    let left = y_label_width
+        + 10.0
+        + if spec.y_axis_title.is_some() {
+            24.0
+        } else {
+            0.0
+        };
+    let bottom = 24.0
+        + if spec.x_axis_title.is_some() {
+            24.0
+        } else {
+            0.0
+        };
+    let plot = Rect {
+        x: area.x + left,
+        y: area.y + 8.0,
+        width: (area.width - left - 8.0).max(10.0),
+        height: (area.height - bottom - 8.0).max(10.0),
+    };
+
+    // Axes and y ticks
+    canvas.line((plot.x, plot.y), (plot.x, plot.bottom()));
+    canvas.line((plot.x, plot.bottom()), (plot.right(), plot.bottom()));
+    for (tick, label) in y_axis.ticks.iter().zip(&y_labels) {
+        let y = y_axis.map(*tick, plot.bottom(), plot.y);
+        canvas.line((plot.x - 4.0, y), (plot.x, y));
+        canvas.text(label, (plot.x - 8.0, y), LABEL_SIZE, Anchor::End);
+    }
+    if let Some(title) = &spec.y_axis_title {
+        canvas.vertical_text(
+            title,
+            (area.x + 10.0, plot.y + plot.height / 2.0),
+            LABEL_SIZE,
+        );
+    }
+    if let Some(title) = &spec.x_axis_title {
+        canvas.text(
+            title,
+            (plot.x + plot.width / 2.0, area.bottom() - 12.0),
+            LABEL_SIZE,
+            Anchor::Middle,
+        );
+    }
+
+    if numeric_x {
+        let x_axis = Axis::new(
+            spec.series
+                .iter()
+                .flat_map(|s| s.points.iter().map(|p| p[0])),
+            false,
+        );
+        for (tick, label) in x_axis.ticks.iter().zip(x_axis.labels()) {
+            let x = x_axis.map(*tick, plot.x, plot.right());
+            canvas.line((x, plot.bottom()), (x, plot.bottom() + 4.0));
+            canvas.text(
+                &label,
+                (x, plot.bottom() + 14.0),
+                LABEL_SIZE,
+                Anchor::Middle,
+            );
+        }
+        for (i, series) in spec.series.iter().enumerate() {
+            let points: Polyline = series
+                .points
+                .iter()
+                .map(|p| {
+                    (
+                        x_axis.map(p[0], plot.x, plot.right()),
+                        y_axis.map(p[1], plot.bottom(), plot.y),
+                    )
+                })
+                .collect();
+            for point in &points {
+                canvas.marker(i, *point);
+            }
+            if spec.chart_type == ChartType::Line {
+                canvas.series_line(i, points);
+            }
+        }
+        return Ok(());
+    }
+
+    let group_width = plot.width / category_count as f32;
+    for (i, label) in spec.labels.iter().enumerate().take(category_count) {
+        let x = plot.x + group_width * (i as f32 + 0.5);
+        canvas.text(label, (x, plot.bottom() + 14.0), LABEL_SIZE, Anchor::Middle);
+    }
+    let zero_y = y_axis.map(0.0, plot.bottom(), plot.y);
+    let series_count = spec.series.len().max(1) as f32;
+    for (i, series) in spec.series.iter().enumerate() {
+        match spec.chart_type {
+            ChartType::Bar => {
+                let bar_width = group_width * 0.8 / series_count;
+                for (j, value) in series.values.iter().enumerate() {
+                    let x = plot.x + group_width * (j as f32 + 0.1) + bar_width * i as f32;
+                    let y = y_axis.map(*value, plot.bottom(), plot.y);
+                    canvas.bar(
+                        i,
+                        Rect {
+                            x,
+                            y: y.min(zero_y),
+                            width: bar_width,
+                            height: (y - zero_y).abs(),
+                        },
+                    );
+                }
+            }
+            _ => {
+                let points: Polyline = series
+                    .values
+                    .iter()
+                    .enumerate()
+                    .map(|(j, value)| {
+                        (
+                            plot.x + group_width * (j as f32 + 0.5),
+                            y_axis.map(*value, plot.bottom(), plot.y),
+                        )
+                    })
+                    .collect();
+                for point in &points {
+                    canvas.marker(i, *point);
+                }
+                if spec.chart_type == ChartType::Line {
+                    canvas.series_line(i, points);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Render a chart into a full-screen SVG drawn with plain black lines
+pub fn chart_to_svg(spec: &ChartSpec, (width, height): (u32, u32)) -> Result<String> {
+    if spec.series.is_empty() {
+        bail!("Chart has no series");
+    }
+    let mut canvas = Canvas::default();
+    let mut area = Rect {
+        x: spec.x,
+        y: spec.y,
+        width: spec.width,
+        height: spec.height,
+    };
+
+    if let Some(title) = &spec.title {
+        canvas.text(
+            title,
+            (area.x + area.width / 2.0, area.y + 14.0),
+            TITLE_SIZE,
+            Anchor::Middle,
+        );
+        area.y += 36.0;
+        area.height -= 36.0;
+    }
+    if spec.series.len() > 1 && spec.chart_type != ChartType::Pie {
+        area.height -= 30.0;
+        legend(
+            &mut canvas,
+            spec,
+            Rect {
+                x: area.x,
+                y: area.bottom() + 6.0,
+                width: area.width,
+                height: 24.0,
+            },
+        );
+    }
+
+    match spec.chart_type {
+        ChartType::Pie => draw_pie(&mut canvas, spec, area)?,
+        _ => draw_axes_chart(&mut canvas, spec, area)?,
+    }
+    Ok(canvas.to_svg(width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(chart_type: ChartType, series: Vec<Series>) -> ChartSpec {
+        ChartSpec {
+            chart_type,
+            title: None,
+            labels: vec![],
+            series,
+            x_axis_title: None,
+            y_axis_title: None,
+            x: 0.0,
+            y: 0.0,
+            width: 400.0,
+            height: 300.0,
+        }
+    }
+
+    fn series(values: Vec<f64>, points: Vec<[f64; 2]>) -> Series {
+        Series {
+            name: None,
+            values,
+            points,
+        }
+    }
+
+    fn assert_ticks(ticks: &[f64], expected: &[f64]) {
+        assert_eq!(
+            ticks.len(),
+            expected.len(),
+            "{:?} is not {:?}",
+            ticks,
+            expected
+        );
+        for (tick, want) in ticks.iter().zip(expected) {
+            assert!(
+                (tick - want).abs() < 1e-9 * want.abs().max(1e-9),
+                "{:?} is not {:?}",
+                ticks,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn ticks_are_round_and_cover_the_range() {
+        assert_ticks(
+            &nice_ticks(0.0, 47.0, 5),
+            &[0.0, 10.0, 20.0, 30.0, 40.0, 50.0],
+        );
+        assert_ticks(&nice_ticks(3.0, 17.0, 5), &[0.0, 5.0, 10.0, 15.0, 20.0]);
+        assert_ticks(&nice_ticks(0.0, 1.0, 5), &[0.0, 0.2, 0.4, 0.6, 0.8, 1.0]);
+    }
+
+    #[test]
+    fn ticks_for_negative_ranges() {
+        assert_ticks(
+            &nice_ticks(-37.0, -3.0, 5),
+            &[-40.0, -30.0, -20.0, -10.0, 0.0],
+        );
+        assert_ticks(
+            &nice_ticks(-12.0, 8.0, 5),
+            &[-15.0, -10.0, -5.0, 0.0, 5.0, 10.0],
+        );
+        assert_eq!(
+            Axis::new([-0.3, 0.2].into_iter(), false).labels(),
+            ["-0.3", "-0.2", "-0.1", "0.0", "0.1", "0.2"]
+        );
+    }
+
+    #[test]
+    fn ticks_when_every_value_is_the_same() {
+        assert_ticks(&nice_ticks(5.0, 5.0, 5), &[4.0, 4.5, 5.0, 5.5, 6.0]);
+        assert_ticks(&nice_ticks(0.0, 0.0, 5), &[-1.0, -0.5, 0.0, 0.5, 1.0]);
+        let axis = Axis::new([1e6, 1e6].into_iter(), false);
+        assert!(axis.min() < 1e6 && axis.max() > 1e6);
+    }
+
+    #[test]
+    fn ticks_for_tiny_spans() {
+        let ticks = nice_ticks(1.0001, 1.0002, 5);
+        assert_ticks(
+            &ticks,
+            &[1.0001, 1.00012, 1.00014, 1.00016, 1.00018, 1.0002],
+        );
+        let axis = Axis {
+            ticks: ticks.clone(),
+        };
+        assert_eq!(axis.labels()[1], "1.00012");
+
+        let ticks = nice_ticks(2e-9, 7e-9, 5);
+        assert!(ticks[0] <= 2e-9 && ticks[ticks.len() - 1] >= 7e-9);
+        let step = ticks[1] - ticks[0];
+        assert!((step - 1e-9).abs() < 1e-18);
+        for pair in ticks.windows(2) {
+            assert!((pair[1] - pair[0] - step).abs() < 1e-18);
+        }
+    }
+
+    #[test]
+    fn values_map_to_pixels_along_the_axis() {
+        let axis = Axis::new([3.0, 47.0].into_iter(), true);
+        assert_eq!((axis.min(), axis.max()), (0.0, 50.0));
+        // y runs up the screen, from the bottom of the plot to the top
+        assert_eq!(axis.map(0.0, 500.0, 100.0), 500.0);
+        assert_eq!(axis.map(25.0, 500.0, 100.0), 300.0);
+        assert_eq!(axis.map(50.0, 500.0, 100.0), 100.0);
+        assert_eq!(axis.map(10.0, 40.0, 440.0), 120.0);
+
+        let axis = Axis::new([-20.0, 20.0].into_iter(), false);
+        assert_eq!(axis.map(0.0, 0.0, 400.0), 200.0);
+        assert_eq!(axis.map(-20.0, 0.0, 400.0), 0.0);
+    }
+
+    #[test]
+    fn pie_slices_go_all_the_way_round() {
+        for values in [
+            vec![1.0, 2.0, 3.0],
+            vec![0.1; 7],
+            vec![1e-6, 1e6, 33.3],
+            vec![5.0],
+            vec![3.0, -2.0, 0.0, 4.0],
+        ] {
+            let sweeps = slice_sweeps(&values).unwrap();
+            let degrees: f32 = sweeps.iter().map(|sweep| sweep.to_degrees()).sum();
+            assert!(
+                (degrees - 360.0).abs() < 1e-3,
+                "{:?} sums to {}",
+                values,
+                degrees
+            );
+            assert!(sweeps.iter().all(|sweep| *sweep >= 0.0));
+        }
+        let sweeps = slice_sweeps(&[1.0, 3.0]).unwrap();
+        assert!((sweeps[0].to_degrees() - 90.0).abs() < 1e-4);
+        assert!(slice_sweeps(&[]).is_err());
+        assert!(slice_sweeps(&[0.0, -1.0]).is_err());
+    }
+
+    #[test]
+    fn numeric_line_charts_reject_value_series() {
+        let chart = spec(
+            ChartType::Line,
+            vec![
+                series(vec![], vec![[0.0, 1.0], [2.0, 3.0]]),
+                series(vec![1.0, 2.0], vec![]),
+            ],
+        );
+        let err = chart_to_svg(&chart, (768, 1024)).unwrap_err();
+        assert!(err.to_string().starts_with("Series 2 has no points"));
+    }
+
+    #[test]
+    fn point_series_are_drawn() {
+        let chart = spec(
+            ChartType::Scatter,
+            vec![series(vec![], vec![[0.0, 1.0], [2.0, 3.0]])],
+        );
+        assert!(chart_to_svg(&chart, (768, 1024)).is_ok());
+    }
+}
@@ -0,0 +1,71 @@
+//! Pieces of the casual dates people write, shared by calendar days and todo
+//! due dates. Each caller decides what a bare weekday means: the calendar
+//! takes today's, a due date the next one after today.
+
+use chrono::{Datelike, NaiveDate, Weekday};
+
+const DAYS: [(&str, Weekday); 7] = [
+    ("monday", Weekday::Mon),
+    ("tuesday", Weekday::Tue),
+    ("wednesday", Weekday::Wed),
+    ("thursday", Weekday::Thu),
+    ("friday", Weekday::Fri),
+    ("saturday", Weekday::Sat),
+    ("sunday", Weekday::Sun),
+];
+
+/// A lowercase weekday name or an abbreviation of at least three letters,
+/// like "wed" or "thurs."
+pub fn weekday(name: &str) -> Option<Weekday> {
+    let name = name.trim_end_matches('.');
+    DAYS.iter()
+        .find(|(full, _)| name.len() >= 3 && full.starts_with(name))
+        .map(|(_, day)| *day)
+}
+
+/// Days from today to the given weekday, 0 to 6
+pub fn days_until(today: NaiveDate, day: Weekday) -> i64 {
+    (day.num_days_from_monday() as i64 + 7 - today.weekday().num_days_from_monday() as i64) % 7
+}
+
+/// The days in a count like "3 days" or "2 weeks", or None if it isn't one
+pub fn day_count(count: &str, unit: &str) -> Option<i64> {
+    let count: i64 = count.parse().ok()?;
+    match unit.trim_end_matches('s') {
+        "day" => Some(count),
+        "week" => count.checked_mul(7),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weekday_names_and_abbreviations() {
+        assert_eq!(weekday("monday"), Some(Weekday::Mon));
+        assert_eq!(weekday("thurs."), Some(Weekday::Thu));
+        assert_eq!(weekday("sat"), Some(Weekday::Sat));
+        assert_eq!(weekday("mo"), None);
+        assert_eq!(weekday("someday"), None);
+    }
+
+    #[test]
+    fn days_until_today_is_zero() {
+        // 2024-10-23 is a Wednesday
+        let today = NaiveDate::from_ymd_opt(2024, 10, 23).unwrap();
+        assert_eq!(days_until(today, Weekday::Wed), 0);
+        assert_eq!(days_until(today, Weekday::Fri), 2);
+        assert_eq!(days_until(today, Weekday::Mon), 5);
+    }
+
+    #[test]
+    fn day_counts() {
+        assert_eq!(day_count("3", "days"), Some(3));
+        assert_eq!(day_count("1", "week"), Some(7));
+        assert_eq!(day_count("2", "months"), None);
+        assert_eq!(day_count("a", "days"), None);
+        assert_eq!(day_count(&i64::MAX.to_string(), "weeks"), None);
+    }
+}
@@ -0,0 +1,319 @@
+//! Ways to draw filled areas with the pen. Scanning a big area solid sends
+//! a flood of pen events that the tablet struggles with, so fills can be
+//! drawn as an outline with hatching inside instead.
+
+use std::f32::consts::FRAC_PI_4;
+
+use crate::trace::trace_outlines;
+use crate::util::Polyline;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FillStyle {
+    /// Every pixel, row by row; the default
+    Solid,
+    Outline,
+    /// Outline with diagonal lines inside
+    Hatch,
+    /// Outline with diagonal lines both ways inside
+    CrossHatch,
+    /// Dots scattered over the area, without an outline
+    Stipple,
+}
+
+/// The fill style and how far apart its lines or dots are, in pixels
+#[derive(Debug, Clone, Copy)]
+pub struct FillSettings {
+    pub style: FillStyle,
+    pub spacing: f32,
+}
+
+impl FillStyle {
+    pub fn parse(style: &str) -> Option<FillStyle> {
+        match style.to_lowercase().replace('_', "-").as_str() {
+            "solid" => Some(FillStyle::Solid),
+            "outline" => Some(FillStyle::Outline),
+            "hatch" => Some(FillStyle::Hatch),
+            "cross-hatch" | "crosshatch" => Some(FillStyle::CrossHatch),
+            "stipple" => Some(FillStyle::Stipple),
+            _ => None,
+        }
+    }
+}
+
+/// Strokes that draw the filled pixels of `bitmap` in the given style, with
+/// hatch lines or dots `spacing` pixels apart. Solid fills have no strokes;
+/// they are drawn from the bitmap.
+pub fn fill_strokes(bitmap: &[Vec<bool>], style: FillStyle, spacing: f32) -> Vec<Polyline> {
+    let spacing = spacing.max(2.0);
+    match style {
+        FillStyle::Solid => Vec::new(),
+        FillStyle::Outline => trace_outlines(bitmap),
+        FillStyle::Hatch => {
+            let mut strokes = trace_outlines(bitmap);
+            strokes.extend(hatch(bitmap, FRAC_PI_4, spacing));
+            strokes
+        }
+        FillStyle::CrossHatch => {
+            let mut strokes = trace_outlines(bitmap);
+            strokes.extend(hatch(bitmap, FRAC_PI_4, spacing));
+            strokes.extend(hatch(bitmap, -FRAC_PI_4, spacing));
+            strokes
+        }
+        FillStyle::Stipple => stipple(bitmap, spacing),
+    }
+}
+
+fn inside(bitmap: &[Vec<bool>], (x, y): (f32, f32)) -> bool {
+    x >= 0.0
+        && y >= 0.0
+        && bitmap
+            .get(y as usize)
+            .and_then(|row| row.get(x as usize))
+            .copied()
+            .unwrap_or(false)
+}
+
+/// Parallel lines at `angle` across the filled pixels, going back and forth
+/// so the pen doesn't travel all the way back for each line
+fn hatch(bitmap: &[Vec<bool>], angle: f32, spacing: f32) -> Vec<Polyline> {
+    let height = bitmap.len() as f32;
+    let width = bitmap.first().map_or(0, |row| row.len()) as f32;
+    let direction = (angle.cos(), angle.sin());
+    let normal = (-direction.1, direction.0);
+
+    // How far the corners of the screen reach along each axis of the lines
+    let corners = [(0.0, 0.0), (width, 0.0), (0.0, height), (width, height)];
+    let project = |axis: (f32, f32)| {
+        let values = corners.map(|(x, y): (f32, f32)| x * axis.0 + y * axis.1);
+        (
+            values.iter().copied().fold(f32::MAX, f32::min),
+            values.iter().copied().fold(f32::MIN, f32::max),
+        )
+    };
+    let (along_min, along_max) = project(direction);
+    let (across_min, across_max) = project(normal);
+
+    let mut strokes = Vec::new();
+    let mut across = across_min + spacing / 2.0;
+    let mut forward = true;
+    while across < across_max {
+        let mut runs: Vec<Polyline> = Vec::new();
+        let mut run: Option<((f32, f32), (f32, f32))> = None;
+        let mut along = along_min;
+        while along <= along_max {
+            let point = (
+                direction.0 * along + normal.0 * across,
+                direction.1 * along + normal.1 * across,
+            );
+            if inside(bitmap, point) {
+                run = Some(match run {
+                    Some((start, _)) => (start, point),
+                    None => (point, point),
+                });
+            } else if let Some((start, end)) = run.take() {
+                runs.push(vec![start, end]);
+            }
+            along += 1.0;
+        }
+        if let Some((start, end)) = run {
+            runs.push(vec![start, end]);
+        }
+        if !forward {
+            runs.reverse();
+            for run in &mut runs {
+                run.reverse();
+            }
+        }
+        strokes.extend(runs);
+        forward = !forward;
+        across += spacing;
+    }
+    strokes
+}
+
+/// Dots on a grid, each nudged by a fixed pseudo-random amount so the
+/// pattern doesn't look mechanical
+fn stipple(bitmap: &[Vec<bool>], spacing: f32) -> Vec<Polyline> {
+    let height = bitmap.len();
+    let width = bitmap.first().map_or(0, |row| row.len());
+    let jitter = |x: usize, y: usize, salt: usize| {
+        let mut hash = (x as u32).wrapping_mul(0x9e37_79b9) ^ (y as u32).wrapping_mul(0x85eb_ca6b);
+        hash ^= (salt as u32).wrapping_mul(0xc2b2_ae35);
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(0x2c1b_3c6d);
+        hash ^= hash >> 12;
+        (hash % 1000) as f32 / 1000.0 - 0.5
+    };
+
+    let mut dots = Vec::new();
+    let columns = (width as f32 / spacing).ceil() as usize;
+    let rows = (height as f32 / spacing).ceil() as usize;
+    for row in 0..rows {
+        // Offset every other row for a less grid-like look
+        let shift = if row % 2 == 0 { 0.0 } else { spacing / 2.0 };
+        for column in 0..columns {
+            let point = (
+                column as f32 * spacing + shift + jitter(column, row, 0) * spacing * 0.5,
+                row as f32 * spacing + jitter(column, row, 1) * spacing * 0.5,
+            );
+            if inside(bitmap, point) {
+                dots.push(vec![point]);
+            }
+        }
+    }
+    dots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACING: f32 = 8.0;
+
+    fn bitmap(inked: impl Fn(f32, f32) -> bool) -> Vec<Vec<bool>> {
+        (0..200)
+            .map(|y| {
+                (0..200)
+                    .map(|x| inked(x as f32 + 0.5, y as f32 + 0.5))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn square() -> Vec<Vec<bool>> {
+        bitmap(|x, y| (20.0..180.0).contains(&x) && (20.0..180.0).contains(&y))
+    }
+
+    // Concave, with a notch cut out of the top right
+    fn l_shape() -> Vec<Vec<bool>> {
+        bitmap(|x, y| {
+            (20.0..180.0).contains(&x) && (20.0..180.0).contains(&y) && !(x >= 80.0 && y < 120.0)
+        })
+    }
+
+    // A ring, with a hole in the middle
+    fn ring() -> Vec<Vec<bool>> {
+        bitmap(|x, y| {
+            let r = ((x - 100.0).powi(2) + (y - 100.0).powi(2)).sqrt();
+            (40.0..90.0).contains(&r)
+        })
+    }
+
+    // Inked, or at most a pixel from ink, to allow for lines clipping the
+    // corner of a pixel
+    fn near_ink(bitmap: &[Vec<bool>], (x, y): (f32, f32)) -> bool {
+        [(0.0, 0.0), (-1.0, 0.0), (1.0, 0.0), (0.0, -1.0), (0.0, 1.0)]
+            .iter()
+            .any(|(dx, dy)| inside(bitmap, (x + dx, y + dy)))
+    }
+
+    fn assert_inside(bitmap: &[Vec<bool>], strokes: &[Polyline]) {
+        for stroke in strokes {
+            for pair in stroke.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                let length = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+                let steps = (length * 2.0).ceil().max(1.0) as usize;
+                for step in 0..=steps {
+                    let t = step as f32 / steps as f32;
+                    let point = (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1));
+                    assert!(near_ink(bitmap, point), "{:?} is outside", point);
+                }
+            }
+        }
+    }
+
+    // Distances between neighbouring lines, measured across them
+    fn line_gaps(strokes: &[Polyline], angle: f32) -> Vec<f32> {
+        let normal = (-angle.sin(), angle.cos());
+        let mut offsets: Vec<f32> = strokes
+            .iter()
+            .map(|stroke| stroke[0].0 * normal.0 + stroke[0].1 * normal.1)
+            .collect();
+        offsets.sort_by(f32::total_cmp);
+        offsets.dedup_by(|a, b| (*a - *b).abs() < 0.5);
+        offsets.windows(2).map(|pair| pair[1] - pair[0]).collect()
+    }
+
+    #[test]
+    fn hatch_lines_stay_inside_and_are_evenly_spaced() {
+        for shape in [square(), l_shape(), ring()] {
+            let lines = hatch(&shape, FRAC_PI_4, SPACING);
+            assert!(!lines.is_empty());
+            assert_inside(&shape, &lines);
+            for line in &lines {
+                let (dx, dy) = (line[1].0 - line[0].0, line[1].1 - line[0].1);
+                assert!((dx - dy).abs() < 0.01, "{:?} is not at 45 degrees", line);
+            }
+            for gap in line_gaps(&lines, FRAC_PI_4) {
+                assert!((gap - SPACING).abs() < 0.01, "lines are {} apart", gap);
+            }
+        }
+    }
+
+    #[test]
+    fn hatch_lines_stop_at_notches_and_holes() {
+        // Lines through the hole are split in two, so there are more runs
+        // than lines across
+        let lines = hatch(&ring(), FRAC_PI_4, SPACING);
+        assert!(lines.len() > line_gaps(&lines, FRAC_PI_4).len() + 1);
+        for line in &lines {
+            let middle = ((line[0].0 + line[1].0) / 2.0, (line[0].1 + line[1].1) / 2.0);
+            assert!(near_ink(&ring(), middle));
+        }
+        assert_inside(&l_shape(), &hatch(&l_shape(), -FRAC_PI_4, SPACING));
+    }
+
+    #[test]
+    fn cross_hatch_goes_both_ways() {
+        let shape = l_shape();
+        let strokes = fill_strokes(&shape, FillStyle::CrossHatch, SPACING);
+        let outline = trace_outlines(&shape).len();
+        let lines = &strokes[outline..];
+        assert_inside(&shape, lines);
+        let rising = lines
+            .iter()
+            .filter(|line| (line[1].0 - line[0].0) * (line[1].1 - line[0].1) < 0.0)
+            .count();
+        let falling = lines
+            .iter()
+            .filter(|line| (line[1].0 - line[0].0) * (line[1].1 - line[0].1) > 0.0)
+            .count();
+        assert!(rising > 0 && falling > 0);
+        assert!(
+            fill_strokes(&shape, FillStyle::Hatch, SPACING).len() < strokes.len(),
+            "cross-hatch should add a second set of lines"
+        );
+    }
+
+    #[test]
+    fn stipple_dots_stay_inside_and_apart() {
+        for shape in [square(), l_shape(), ring()] {
+            let dots = stipple(&shape, SPACING);
+            assert!(dots.iter().all(|dot| dot.len() == 1));
+            assert!(dots.iter().all(|dot| inside(&shape, dot[0])));
+            for (i, a) in dots.iter().enumerate() {
+                for b in &dots[i + 1..] {
+                    let d = ((a[0].0 - b[0].0).powi(2) + (a[0].1 - b[0].1).powi(2)).sqrt();
+                    assert!(d >= SPACING * 0.45, "dots are only {} apart", d);
+                }
+            }
+            // Roughly one dot per spacing squared of area
+            let area = shape.iter().flatten().filter(|inked| **inked).count() as f32;
+            let expected = area / (SPACING * SPACING);
+            let count = dots.len() as f32;
+            assert!((count - expected).abs() < expected * 0.2, "{} dots", count);
+        }
+        // No dots in the hole
+        assert!(stipple(&ring(), SPACING).iter().all(|dot| {
+            let (x, y) = dot[0];
+            ((x - 100.0).powi(2) + (y - 100.0).powi(2)).sqrt() >= 39.0
+        }));
+    }
+
+    #[test]
+    fn solid_fills_are_drawn_from_the_bitmap() {
+        assert!(fill_strokes(&square(), FillStyle::Solid, SPACING).is_empty());
+        assert_eq!(FillStyle::parse("Cross_Hatch"), Some(FillStyle::CrossHatch));
+        assert_eq!(FillStyle::parse("zigzag"), None);
+    }
+}
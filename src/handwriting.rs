@@ -0,0 +1,187 @@
+use crate::stroke_font::{StrokeFont, CAP_HEIGHT, DESCENT, UNITS_PER_EM};
+use crate::util::Polyline;
+
+// Distance between baselines, relative to the font size
+const LINE_SPACING: f32 = 1.3;
+// Narrowest line, relative to the font size, so words aren't broken into
+// single letters stacked down the page
+const MIN_LINE_WIDTH: f32 = 4.0;
+
+/// Break text into lines no wider than `max_width` at the given font size.
+/// Explicit newlines are kept, and words too long for a line are split.
+pub fn wrap_text(text: &str, size: f32, max_width: f32) -> Vec<String> {
+    let font = StrokeFont::simplex();
+    let space = font.text_width(" ", size);
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        let mut line_width = 0.0;
+        for word in paragraph.split_whitespace() {
+            let mut word = word.to_string();
+            let mut word_width = font.text_width(&word, size);
+
+            if !line.is_empty() && line_width + space + word_width > max_width {
+                lines.push(std::mem::take(&mut line));
+                line_width = 0.0;
+            }
+
+            // Hard-break words that can not fit on a line of their own
+            while word_width > max_width && word.chars().count() > 1 {
+                let mut head = String::new();
+                let mut head_width = 0.0;
+                for c in word.chars() {
+                    let c_width = font.text_width(&c.to_string(), size);
+                    if !head.is_empty() && head_width + c_width > max_width {
+                        break;
+                    }
+                    head.push(c);
+                    head_width += c_width;
+                }
+                word = word[head.len()..].to_string();
+                word_width = font.text_width(&word, size);
+                lines.push(head);
+            }
+
+            if !line.is_empty() {
+                line.push(' ');
+                line_width += space;
+            }
+            line.push_str(&word);
+            line_width += word_width;
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Where lines starting at `x` can begin and how wide they can be on a
+/// screen `screen_width` across. Text asked for at or past the right edge
+/// is moved back onto the screen, and lines are never narrower than a few
+/// letters or wider than the room left.
+pub fn line_span(x: f32, max_width: Option<f32>, size: f32, screen_width: f32) -> (f32, f32) {
+    let min_width = (size * MIN_LINE_WIDTH).min(screen_width);
+    let x = x.clamp(0.0, screen_width - min_width);
+    let room = screen_width - x;
+    (x, max_width.unwrap_or(room).clamp(min_width, room))
+}
+
+/// Lay out text as pen strokes with the top-left of the first line at (x, y)
+pub fn text_to_strokes(text: &str, (x, y): (f32, f32), size: f32, max_width: f32) -> Vec<Polyline> {
+    let font = StrokeFont::simplex();
+    let first_baseline = y + CAP_HEIGHT * size / UNITS_PER_EM;
+    wrap_text(text, size, max_width)
+        .iter()
+        .enumerate()
+        .flat_map(|(i, line)| {
+            let baseline = first_baseline + i as f32 * size * LINE_SPACING;
+            font.text_strokes(line, (x, baseline), size)
+        })
+        .collect()
+}
+
+/// Width and height of the box `text_to_strokes` fills, from the top of the
+/// capitals on the first line to the descenders on the last
+pub fn text_bounds(text: &str, size: f32, max_width: f32) -> (f32, f32) {
+    let font = StrokeFont::simplex();
+    let lines = wrap_text(text, size, max_width);
+    let width = lines
+        .iter()
+        .map(|line| font.text_width(line.trim_end(), size))
+        .fold(0.0, f32::max);
+    let height = (CAP_HEIGHT + DESCENT) * size / UNITS_PER_EM
+        + lines.len().saturating_sub(1) as f32 * size * LINE_SPACING;
+    (width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // At this size a pixel is a font unit
+    const SIZE: f32 = UNITS_PER_EM;
+
+    fn width(text: &str) -> f32 {
+        StrokeFont::simplex().text_width(text, SIZE)
+    }
+
+    #[test]
+    fn words_wrap_at_the_width_limit() {
+        let fits = width("one") + width(" ") + width("two");
+        assert_eq!(wrap_text("one two three", SIZE, fits), ["one two", "three"]);
+        assert_eq!(
+            wrap_text("one two three", SIZE, fits - 0.5),
+            ["one", "two", "three"]
+        );
+        assert_eq!(wrap_text("one   two", SIZE, f32::INFINITY), ["one two"]);
+        for line in wrap_text("the quick brown fox jumps over the lazy dog", SIZE, 150.0) {
+            assert!(width(&line) <= 150.0, "{:?} is too wide", line);
+        }
+    }
+
+    #[test]
+    fn words_longer_than_a_line_are_broken() {
+        let max_width = width("abcd");
+        let lines = wrap_text("xy abcdefghij", SIZE, max_width);
+        assert_eq!(lines, ["xy", "abcd", "efgh", "ij"]);
+
+        // Characters the font lacks are broken by their fallback width
+        let lines = wrap_text("éééééé", SIZE, width("??"));
+        assert_eq!(lines, ["éé", "éé", "éé"]);
+
+        // A single character wider than the line still gets written
+        assert_eq!(wrap_text("WW", SIZE, 1.0), ["W", "W"]);
+    }
+
+    #[test]
+    fn lines_stay_on_the_screen_and_a_few_letters_wide() {
+        assert_eq!(line_span(100.0, None, SIZE, 768.0), (100.0, 668.0));
+        assert_eq!(line_span(100.0, Some(200.0), SIZE, 768.0), (100.0, 200.0));
+        assert_eq!(line_span(100.0, Some(2000.0), SIZE, 768.0), (100.0, 668.0));
+        assert_eq!(line_span(-50.0, None, SIZE, 768.0), (0.0, 768.0));
+        // At or past the right edge, the text is moved back on
+        let min_width = SIZE * MIN_LINE_WIDTH;
+        for x in [740.0, 768.0, 900.0] {
+            assert_eq!(
+                line_span(x, None, SIZE, 768.0),
+                (768.0 - min_width, min_width)
+            );
+        }
+        assert_eq!(
+            line_span(100.0, Some(-20.0), SIZE, 768.0),
+            (100.0, min_width)
+        );
+        // Text too big for any line gets the whole screen
+        assert_eq!(line_span(300.0, None, 500.0, 768.0), (0.0, 768.0));
+
+        // Words are written whole rather than a letter per line
+        let (_, width) = line_span(900.0, None, SIZE, 768.0);
+        assert_eq!(wrap_text("two words", SIZE, width), ["two", "words"]);
+    }
+
+    #[test]
+    fn explicit_newlines_start_new_lines() {
+        assert_eq!(
+            wrap_text("one\ntwo\n\nthree\n", SIZE, f32::INFINITY),
+            ["one", "two", "", "three"]
+        );
+        assert_eq!(wrap_text("a b\r\nc", SIZE, width("a b")), ["a b", "c"]);
+    }
+
+    #[test]
+    fn lines_are_written_one_below_another() {
+        let font = StrokeFont::simplex();
+        let strokes = text_to_strokes("A\nB", (10.0, 20.0), SIZE, f32::INFINITY);
+        let mut expected = font.text_strokes("A", (10.0, 20.0 + CAP_HEIGHT), SIZE);
+        expected.extend(font.text_strokes(
+            "B",
+            (10.0, 20.0 + CAP_HEIGHT + SIZE * LINE_SPACING),
+            SIZE,
+        ));
+        assert_eq!(strokes, expected);
+
+        let (w, h) = text_bounds("A\nBB", SIZE, f32::INFINITY);
+        assert_eq!(w, width("BB"));
+        assert_eq!(h, CAP_HEIGHT + DESCENT + SIZE * LINE_SPACING);
+    }
+}
@@ -0,0 +1,146 @@
+//! What ghostwriter put on the page for each answer, so an answer the user
+//! doesn't like can be taken back off again before trying another model.
+
+use anyhow::Result;
+use std::collections::VecDeque;
+
+use crate::keyboard::Keyboard;
+use crate::pen::Pen;
+use crate::util::Polyline;
+
+/// Answers kept for undoing, oldest dropped first
+const MAX_OUTPUTS: usize = 10;
+
+/// Everything one answer drew and typed
+#[derive(Debug, Clone, Default)]
+pub struct Output {
+    /// Every pen stroke, in screen coordinates, as it was sent
+    pub strokes: Vec<Polyline>,
+    /// Characters typed into the text layer, not counting progress dots
+    pub chars_typed: usize,
+}
+
+impl Output {
+    pub fn is_empty(&self) -> bool {
+        self.strokes.is_empty() && self.chars_typed == 0
+    }
+}
+
+#[derive(Default)]
+pub struct Journal {
+    outputs: VecDeque<Output>,
+}
+
+impl Journal {
+    pub fn new() -> Journal {
+        Journal::default()
+    }
+
+    /// Remember an answer; ones that left nothing on the page are skipped so
+    /// undo always takes back something visible
+    pub fn record(&mut self, output: Output) {
+        if output.is_empty() {
+            return;
+        }
+        if self.outputs.len() == MAX_OUTPUTS {
+            self.outputs.pop_front();
+        }
+        self.outputs.push_back(output);
+    }
+
+    /// The most recent answer still on the page, if any
+    pub fn take_last(&mut self) -> Option<Output> {
+        self.outputs.pop_back()
+    }
+
+    /// Erase the strokes and delete the text of the last answer still on the
+    /// page. Strokes are erased exactly as they were drawn; text is deleted
+    /// from the cursor, which is left after the answer.
+    pub fn undo_last(&mut self, keyboard: &mut Keyboard, pen: &mut Pen) -> Result<()> {
+        let Some(output) = self.take_last() else {
+            println!("Nothing to undo");
+            return Ok(());
+        };
+        println!(
+            "Undoing {} strokes and {} characters",
+            output.strokes.len(),
+            output.chars_typed
+        );
+        keyboard.progress()?;
+        for stroke in &output.strokes {
+            pen.erase_stroke(stroke)?;
+        }
+        keyboard.progress_end()?;
+        keyboard.backspace(output.chars_typed)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(pen: &mut Pen, keyboard: &mut Keyboard, text: &str, strokes: &[Polyline]) -> Output {
+        pen.draw_strokes(strokes).unwrap();
+        keyboard.string_to_keypresses(text).unwrap();
+        Output {
+            strokes: pen.take_drawn(),
+            chars_typed: keyboard.take_typed().chars().count(),
+        }
+    }
+
+    #[test]
+    fn dry_runs_are_journaled_and_undone() {
+        let mut pen = Pen::new(true);
+        let mut keyboard = Keyboard::new(true, false);
+        let mut journal = Journal::new();
+
+        let first = answer(
+            &mut pen,
+            &mut keyboard,
+            "x = 4\n",
+            &[vec![(100.0, 100.0), (200.0, 150.0), (300.0, 100.0)]],
+        );
+        assert_eq!(first.strokes.len(), 1);
+        assert_eq!(first.chars_typed, 6);
+        journal.record(first.clone());
+        journal.record(answer(&mut pen, &mut keyboard, "", &[]));
+        let second = answer(
+            &mut pen,
+            &mut keyboard,
+            "café",
+            &[vec![(10.0, 10.0)], vec![(50.0, 500.0), (60.0, 520.0)]],
+        );
+        assert_eq!(second.strokes.len(), 2);
+        // There's no key for é, so it isn't typed or counted
+        assert_eq!(second.chars_typed, 3);
+        journal.record(second.clone());
+
+        // The latest answer goes first, and the empty one was never kept
+        journal.undo_last(&mut keyboard, &mut pen).unwrap();
+        assert_eq!(pen.take_erased(), second.strokes);
+        assert_eq!(keyboard.take_deleted(), 3);
+        journal.undo_last(&mut keyboard, &mut pen).unwrap();
+        assert_eq!(pen.take_erased(), first.strokes);
+        assert_eq!(keyboard.take_deleted(), 6);
+        journal.undo_last(&mut keyboard, &mut pen).unwrap();
+        assert!(pen.take_erased().is_empty());
+        assert_eq!(keyboard.take_deleted(), 0);
+    }
+
+    #[test]
+    fn only_the_latest_outputs_are_kept() {
+        let mut journal = Journal::new();
+        for chars_typed in 1..=MAX_OUTPUTS + 5 {
+            journal.record(Output {
+                strokes: Vec::new(),
+                chars_typed,
+            });
+        }
+        let mut undone = Vec::new();
+        while let Some(output) = journal.take_last() {
+            undone.push(output.chars_typed);
+        }
+        assert_eq!(undone, (6..=MAX_OUTPUTS + 5).rev().collect::<Vec<_>>());
+    }
+}
@@ -7,11 +7,16 @@ use evdev::{
     uinput::VirtualDevice, uinput::VirtualDeviceBuilder, AttributeSet, EventType, InputEvent, Key,
 };
 
+use crate::cancel::{CancelFlag, Cancelled};
+
 pub struct Keyboard {
     device: Option<VirtualDevice>,
     key_map: HashMap<char, (Key, bool)>,
     progress_count: u32,
     no_draw_progress: bool,
+    cancel: CancelFlag,
+    typed: String,
+    deleted: usize,
 }
 
 impl Keyboard {
@@ -27,6 +32,9 @@ impl Keyboard {
             key_map: Self::create_key_map(),
             progress_count: 0,
             no_draw_progress,
+            cancel: CancelFlag::new(),
+            typed: String::new(),
+            deleted: 0,
         }
     }
 
@@ -236,38 +244,91 @@ impl Keyboard {
         Ok(())
     }
 
-    pub fn string_to_keypresses(&mut self, input: &str) -> Result<(), evdev::Error> {
+    /// Stop typing, with the modifiers let go, once this flag is cancelled
+    pub fn set_cancel(&mut self, cancel: CancelFlag) {
+        self.cancel = cancel;
+    }
+
+    /// Text typed since the last call, not counting progress dots
+    pub fn take_typed(&mut self) -> String {
+        std::mem::take(&mut self.typed)
+    }
+
+    /// How many characters `backspace` deleted since the last call, not
+    /// counting progress dots
+    pub fn take_deleted(&mut self) -> usize {
+        std::mem::take(&mut self.deleted)
+    }
+
+    /// Delete the last `count` characters before the cursor
+    pub fn backspace(&mut self, count: usize) -> Result<()> {
+        self.press_backspace(count)?;
+        self.deleted += count;
+        Ok(())
+    }
+
+    fn press_backspace(&mut self, count: usize) -> Result<()> {
+        for _ in 0..count {
+            self.press_keys("\x08", false)?;
+        }
+        Ok(())
+    }
+
+    /// Let go of Shift and Ctrl, in case typing stopped while they were held
+    pub fn release_modifiers(&mut self) -> Result<()> {
+        self.key_up(Key::KEY_LEFTSHIFT)?;
+        self.key_up(Key::KEY_LEFTCTRL)?;
         if let Some(device) = &mut self.device {
-            for c in input.chars() {
-                if let Some(&(key, shift)) = self.key_map.get(&c) {
-                    if shift {
-                        // Press Shift
-                        device.emit(&[InputEvent::new(
-                            EventType::KEY,
-                            Key::KEY_LEFTSHIFT.code(),
-                            1,
-                        )])?;
-                    }
-
-                    // Press key
-                    device.emit(&[InputEvent::new(EventType::KEY, key.code(), 1)])?;
-
-                    // Release key
-                    device.emit(&[InputEvent::new(EventType::KEY, key.code(), 0)])?;
-
-                    if shift {
-                        // Release Shift
-                        device.emit(&[InputEvent::new(
-                            EventType::KEY,
-                            Key::KEY_LEFTSHIFT.code(),
-                            0,
-                        )])?;
-                    }
-
-                    // Sync event
-                    device.emit(&[InputEvent::new(EventType::SYNCHRONIZATION, 0, 0)])?;
-                    thread::sleep(time::Duration::from_millis(10));
+            device.emit(&[InputEvent::new(EventType::SYNCHRONIZATION, 0, 0)])?;
+        }
+        Ok(())
+    }
+
+    pub fn string_to_keypresses(&mut self, input: &str) -> Result<()> {
+        self.press_keys(input, true)
+    }
+
+    fn press_keys(&mut self, input: &str, counted: bool) -> Result<()> {
+        for c in input.chars() {
+            if self.cancel.is_cancelled() {
+                self.release_modifiers()?;
+                return Err(Cancelled.into());
+            }
+            let Some(&(key, shift)) = self.key_map.get(&c) else {
+                continue;
+            };
+            if let Some(device) = &mut self.device {
+                if shift {
+                    // Press Shift
+                    device.emit(&[InputEvent::new(
+                        EventType::KEY,
+                        Key::KEY_LEFTSHIFT.code(),
+                        1,
+                    )])?;
                 }
+
+                // Press key
+                device.emit(&[InputEvent::new(EventType::KEY, key.code(), 1)])?;
+
+                // Release key
+                device.emit(&[InputEvent::new(EventType::KEY, key.code(), 0)])?;
+
+                if shift {
+                    // Release Shift
+                    device.emit(&[InputEvent::new(
+                        EventType::KEY,
+                        Key::KEY_LEFTSHIFT.code(),
+                        0,
+                    )])?;
+                }
+
+                // Sync event
+                device.emit(&[InputEvent::new(EventType::SYNCHRONIZATION, 0, 0)])?;
+                thread::sleep(time::Duration::from_millis(10));
+            }
+            // Tracked without a device too, so dry runs journal the same text
+            if counted {
+                self.typed.push(c);
             }
         }
         Ok(())
@@ -278,7 +339,7 @@ impl Keyboard {
         if shift {
             self.key_down(Key::KEY_LEFTSHIFT)?;
         }
-        self.string_to_keypresses(button)?;
+        self.press_keys(button, false)?;
         if shift {
             self.key_up(Key::KEY_LEFTSHIFT)?;
         }
@@ -310,7 +371,7 @@ impl Keyboard {
         if self.no_draw_progress {
             return Ok(());
         }
-        self.string_to_keypresses(".")?;
+        self.press_keys(".", false)?;
         self.progress_count += 1;
         Ok(())
     }
@@ -320,9 +381,7 @@ impl Keyboard {
             return Ok(());
         }
         // Send a backspace for each progress
-        for _ in 0..self.progress_count {
-            self.string_to_keypresses("\x08")?;
-        }
+        self.press_backspace(self.progress_count as usize)?;
         self.progress_count = 0;
         Ok(())
     }
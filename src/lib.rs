@@ -1,7 +1,35 @@
+pub mod calculator;
+pub mod calendar;
+pub mod cancel;
+pub mod chart;
+pub mod dates;
+pub mod fill;
+pub mod handwriting;
+pub mod journal;
 pub mod keyboard;
 pub mod llm_engine;
+pub mod markdown;
+pub mod marks;
+pub mod math;
+pub mod memory;
+pub mod messages;
+pub mod notes;
+pub mod optimize;
+pub mod pacing;
+pub mod page_reader;
 pub mod pen;
+pub mod placement;
+pub mod plugins;
+pub mod preview;
+pub mod router;
+#[cfg(test)]
+mod scratch;
 pub mod screenshot;
 pub mod segmenter;
+pub mod stroke_font;
+pub mod svg_fit;
+pub mod todo;
 pub mod touch;
+pub mod trace;
 pub mod util;
+pub mod vector;
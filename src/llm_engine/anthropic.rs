@@ -1,4 +1,7 @@
-use super::LLMEngine;
+use super::{
+    check_tool_names, is_cancelled, tool_enabled, tool_loops, LLMEngine, ToolCallback,
+    MAX_TOOL_STEPS,
+};
 use crate::util::{option_or_env, option_or_env_fallback, OptionMap};
 use anyhow::Result;
 use serde_json::json;
@@ -9,7 +12,7 @@ use ureq::Error;
 pub struct Tool {
     name: String,
     definition: json,
-    callback: Option<Box<dyn FnMut(json)>>,
+    callback: Option<ToolCallback>,
 }
 
 pub struct Anthropic {
@@ -17,6 +20,7 @@ pub struct Anthropic {
     api_key: String,
     base_url: String,
     tools: Vec<Tool>,
+    enabled: Option<Vec<String>>,
     content: Vec<json>,
 }
 
@@ -36,9 +40,9 @@ impl Anthropic {
 
 impl LLMEngine for Anthropic {
     fn new(options: &OptionMap) -> Self {
-        let api_key = option_or_env(&options, "api_key", "ANTHROPIC_API_KEY");
+        let api_key = option_or_env(options, "api_key", "ANTHROPIC_API_KEY");
         let base_url = option_or_env_fallback(
-            &options,
+            options,
             "base_url",
             "ANTHROPIC_BASE_URL",
             "https://api.anthropic.com",
@@ -49,11 +53,12 @@ impl LLMEngine for Anthropic {
             base_url,
             api_key,
             tools: Vec::new(),
+            enabled: None,
             content: Vec::new(),
         }
     }
 
-    fn register_tool(&mut self, name: &str, definition: json, callback: Box<dyn FnMut(json)>) {
+    fn register_tool(&mut self, name: &str, definition: json, callback: ToolCallback) {
         self.tools.push(Tool {
             name: name.to_string(),
             definition,
@@ -61,6 +66,14 @@ impl LLMEngine for Anthropic {
         });
     }
 
+    fn select_tools(&mut self, names: Option<Vec<String>>) -> Result<()> {
+        if let Some(names) = &names {
+            check_tool_names(names, self.tools.iter().map(|tool| tool.name.as_str()))?;
+        }
+        self.enabled = names;
+        Ok(())
+    }
+
     fn add_text_content(&mut self, text: &str) {
         self.add_content(json!({
             "type": "text",
@@ -84,68 +97,89 @@ impl LLMEngine for Anthropic {
     }
 
     fn execute(&mut self) -> Result<()> {
-        let body = json!({
-            "model": self.model,
-            "max_tokens": 5000,
-            "messages": [{
-                "role": "user",
-                "content": self.content
-            }],
-            "tools": self.tools.iter().map(|tool| Self::anthropic_tool_definition(tool)).collect::<Vec<_>>(),
-            "tool_choice": {
-                "type": "any",
-                "disable_parallel_tool_use": true
-            }
-        });
+        let mut messages = vec![json!({
+            "role": "user",
+            "content": self.content
+        })];
 
-        // print body for debugging
-        // println!("Request: {}", body);
-
-        let raw_response = ureq::post(&format!("{}/v1/messages", self.base_url))
-            .set("x-api-key", self.api_key.as_str())
-            .set("anthropic-version", "2023-06-01")
-            .set("Content-Type", "application/json")
-            .send_json(&body);
-
-        let response = match raw_response {
-            Ok(response) => response,
-            Err(Error::Status(code, response)) => {
-                println!("Error: {}", code);
-                let json: json = response.into_json()?;
-                println!("Response: {}", json);
-                return Err(anyhow::anyhow!("API ERROR"));
-            }
-            Err(_) => return Err(anyhow::anyhow!("OTHER API ERROR")),
-        };
-
-        let json: json = response.into_json().unwrap();
-        // println!("Response: {}", json);
-        let tool_calls = &json["content"];
-        if let Some(tool_call) = tool_calls.get(0) {
-            let function_name = tool_call["name"].as_str().unwrap();
+        for _ in 0..MAX_TOOL_STEPS {
+            let body = json!({
+                "model": self.model,
+                "max_tokens": 5000,
+                "messages": messages,
+                "tools": self.tools.iter().filter(|tool| tool_enabled(&self.enabled, &tool.name)).map(Self::anthropic_tool_definition).collect::<Vec<_>>(),
+                "tool_choice": {
+                    "type": "any",
+                    "disable_parallel_tool_use": true
+                }
+            });
+
+            // print body for debugging
+            // println!("Request: {}", body);
+
+            let raw_response = ureq::post(&format!("{}/v1/messages", self.base_url))
+                .set("x-api-key", self.api_key.as_str())
+                .set("anthropic-version", "2023-06-01")
+                .set("Content-Type", "application/json")
+                .send_json(&body);
+
+            let response = match raw_response {
+                Ok(response) => response,
+                Err(Error::Status(code, response)) => {
+                    println!("Error: {}", code);
+                    let json: json = response.into_json()?;
+                    println!("Response: {}", json);
+                    return Err(anyhow::anyhow!("API ERROR"));
+                }
+                Err(_) => return Err(anyhow::anyhow!("OTHER API ERROR")),
+            };
+
+            let json: json = response.into_json()?;
+            // println!("Response: {}", json);
+            let tool_call = json["content"]
+                .as_array()
+                .and_then(|content| content.iter().find(|block| block["type"] == "tool_use"))
+                .ok_or_else(|| anyhow::anyhow!("No tool calls found in response"))?;
+            let function_name = tool_call["name"].as_str().unwrap_or_default();
             let function_input = &tool_call["input"];
+            let enabled = &self.enabled;
             let tool = self
                 .tools
                 .iter_mut()
-                .find(|tool| tool.name == function_name);
-            if let Some(tool) = tool {
-                if let Some(callback) = &mut tool.callback {
-                    callback(function_input.clone());
-                    Ok(())
-                } else {
-                    Err(anyhow::anyhow!(
-                        "No callback registered for tool {}",
-                        function_name
-                    ))
-                }
-            } else {
-                Err(anyhow::anyhow!(
-                    "No tool registered with name {}",
-                    function_name
-                ))
+                .find(|tool| tool.name == function_name && tool_enabled(enabled, &tool.name))
+                .ok_or_else(|| anyhow::anyhow!("No tool registered with name {}", function_name))?;
+            let loops = tool_loops(&tool.definition, function_input);
+            let callback = tool.callback.as_mut().ok_or_else(|| {
+                anyhow::anyhow!("No callback registered for tool {}", function_name)
+            })?;
+            let result = callback(function_input.clone());
+            if !loops || is_cancelled(&result) {
+                return result.map(|_| ());
             }
-        } else {
-            Err(anyhow::anyhow!("No tool calls found in response"))
+
+            let (result, is_error) = match result {
+                Ok(result) => (result, false),
+                Err(e) => (e.to_string(), true),
+            };
+            println!("Tool {} returned: {}", function_name, result);
+            messages.push(json!({
+                "role": "assistant",
+                "content": json["content"]
+            }));
+            messages.push(json!({
+                "role": "user",
+                "content": [{
+                    "type": "tool_result",
+                    "tool_use_id": tool_call["id"],
+                    "content": result,
+                    "is_error": is_error
+                }]
+            }));
         }
+
+        Err(anyhow::anyhow!(
+            "Stopped after {} tool steps without any output",
+            MAX_TOOL_STEPS
+        ))
     }
 }
@@ -1,4 +1,7 @@
-use super::LLMEngine;
+use super::{
+    check_tool_names, is_cancelled, tool_enabled, tool_loops, LLMEngine, ToolCallback,
+    MAX_TOOL_STEPS,
+};
 use crate::util::{option_or_env, option_or_env_fallback, OptionMap};
 use anyhow::Result;
 use serde_json::json;
@@ -9,7 +12,7 @@ use ureq::Error;
 pub struct Tool {
     name: String,
     definition: json,
-    callback: Option<Box<dyn FnMut(json)>>,
+    callback: Option<ToolCallback>,
 }
 
 pub struct Google {
@@ -17,6 +20,7 @@ pub struct Google {
     base_url: String,
     api_key: String,
     tools: Vec<Tool>,
+    enabled: Option<Vec<String>>,
     content: Vec<json>,
 }
 
@@ -36,9 +40,9 @@ impl Google {
 
 impl LLMEngine for Google {
     fn new(options: &OptionMap) -> Self {
-        let api_key = option_or_env(&options, "api_key", "GOOGLE_API_KEY");
+        let api_key = option_or_env(options, "api_key", "GOOGLE_API_KEY");
         let base_url = option_or_env_fallback(
-            &options,
+            options,
             "base_url",
             "GOOGLE_BASE_URL",
             "https://generativelanguage.googleapis.com",
@@ -50,11 +54,12 @@ impl LLMEngine for Google {
             base_url,
             api_key,
             tools: Vec::new(),
+            enabled: None,
             content: Vec::new(),
         }
     }
 
-    fn register_tool(&mut self, name: &str, definition: json, callback: Box<dyn FnMut(json)>) {
+    fn register_tool(&mut self, name: &str, definition: json, callback: ToolCallback) {
         self.tools.push(Tool {
             name: name.to_string(),
             definition,
@@ -62,6 +67,14 @@ impl LLMEngine for Google {
         });
     }
 
+    fn select_tools(&mut self, names: Option<Vec<String>>) -> Result<()> {
+        if let Some(names) = &names {
+            check_tool_names(names, self.tools.iter().map(|tool| tool.name.as_str()))?;
+        }
+        self.enabled = names;
+        Ok(())
+    }
+
     fn add_text_content(&mut self, text: &str) {
         self.add_content(json!({
             "text": text,
@@ -82,67 +95,94 @@ impl LLMEngine for Google {
     }
 
     fn execute(&mut self) -> Result<()> {
-        let body = json!({
-            "contents": [{
-                "role": "user",
-                "parts": self.content
-            }],
-            "tools": [{ "function_declarations": self.tools.iter().map(|tool| Self::google_tool_definition(tool)).collect::<Vec<_>>() }],
-            "tool_config": {
-                "function_calling_config": {
-                    "mode": "ANY"
+        let mut contents = vec![json!({
+            "role": "user",
+            "parts": self.content
+        })];
+
+        for _ in 0..MAX_TOOL_STEPS {
+            let body = json!({
+                "contents": contents,
+                "tools": [{ "function_declarations": self.tools.iter().filter(|tool| tool_enabled(&self.enabled, &tool.name)).map(Self::google_tool_definition).collect::<Vec<_>>() }],
+                "tool_config": {
+                    "function_calling_config": {
+                        "mode": "ANY"
+                    }
                 }
-            }
-        });
-
-        // print body for debugging
-        // println!("Request: {}", body);
-        let raw_response = ureq::post(format!("{}/v1beta/models/{}:generateContent?key={}", self.base_url, self.model, self.api_key).as_str())
+            });
+
+            // print body for debugging
+            // println!("Request: {}", body);
+            let raw_response = ureq::post(
+                format!(
+                    "{}/v1beta/models/{}:generateContent?key={}",
+                    self.base_url, self.model, self.api_key
+                )
+                .as_str(),
+            )
             .set("Content-Type", "application/json")
             .send_json(&body);
 
-        let response = match raw_response {
-            Ok(response) => response,
-            Err(Error::Status(code, response)) => {
-                println!("Error: {}", code);
-                let json: json = response.into_json()?;
-                println!("Response: {}", json);
-                return Err(anyhow::anyhow!("API ERROR"));
-            }
-            Err(_) => return Err(anyhow::anyhow!("OTHER API ERROR")),
-        };
-
-        let json: json = response.into_json().unwrap();
-        // println!("Response: {}", json);
-
-        let tool_calls = &json["candidates"][0]["content"]["parts"];
-
-        if let Some(tool_call) = tool_calls.get(0) {
-            let function_name = tool_call["functionCall"]["name"].as_str().unwrap();
-            let function_input = &tool_call["functionCall"]["args"];
+            let response = match raw_response {
+                Ok(response) => response,
+                Err(Error::Status(code, response)) => {
+                    println!("Error: {}", code);
+                    let json: json = response.into_json()?;
+                    println!("Response: {}", json);
+                    return Err(anyhow::anyhow!("API ERROR"));
+                }
+                Err(_) => return Err(anyhow::anyhow!("OTHER API ERROR")),
+            };
+
+            let json: json = response.into_json()?;
+            // println!("Response: {}", json);
+
+            let parts = &json["candidates"][0]["content"]["parts"];
+            let function_call = parts
+                .as_array()
+                .and_then(|parts| parts.iter().find(|part| part.get("functionCall").is_some()))
+                .map(|part| &part["functionCall"])
+                .ok_or_else(|| anyhow::anyhow!("No tool calls found in response"))?;
+            let function_name = function_call["name"].as_str().unwrap_or_default();
+            let function_input = &function_call["args"];
+            let enabled = &self.enabled;
             let tool = self
                 .tools
                 .iter_mut()
-                .find(|tool| tool.name == function_name);
-
-            if let Some(tool) = tool {
-                if let Some(callback) = &mut tool.callback {
-                    callback(function_input.clone());
-                    Ok(())
-                } else {
-                    Err(anyhow::anyhow!(
-                        "No callback registered for tool {}",
-                        function_name
-                    ))
-                }
-            } else {
-                Err(anyhow::anyhow!(
-                    "No tool registered with name {}",
-                    function_name
-                ))
+                .find(|tool| tool.name == function_name && tool_enabled(enabled, &tool.name))
+                .ok_or_else(|| anyhow::anyhow!("No tool registered with name {}", function_name))?;
+            let loops = tool_loops(&tool.definition, function_input);
+            let callback = tool.callback.as_mut().ok_or_else(|| {
+                anyhow::anyhow!("No callback registered for tool {}", function_name)
+            })?;
+            let result = callback(function_input.clone());
+            if !loops || is_cancelled(&result) {
+                return result.map(|_| ());
             }
-        } else {
-            Err(anyhow::anyhow!("No tool calls found in response"))
+
+            let response = match result {
+                Ok(result) => json!({ "result": result }),
+                Err(e) => json!({ "error": e.to_string() }),
+            };
+            println!("Tool {} returned: {}", function_name, response);
+            contents.push(json!({
+                "role": "model",
+                "parts": parts
+            }));
+            contents.push(json!({
+                "role": "user",
+                "parts": [{
+                    "functionResponse": {
+                        "name": function_name,
+                        "response": response
+                    }
+                }]
+            }));
         }
+
+        Err(anyhow::anyhow!(
+            "Stopped after {} tool steps without any output",
+            MAX_TOOL_STEPS
+        ))
     }
 }
@@ -1,18 +1,90 @@
 pub mod anthropic;
-pub mod openai;
 pub mod google;
+pub mod openai;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use serde_json::Value as json;
 use std::collections::HashMap;
 
+use crate::cancel::Cancelled;
+
+/// Tools return a result that is sent back to the model when they loop
+pub type ToolCallback = Box<dyn FnMut(json) -> Result<String>>;
+
+/// Upper bound on model round-trips for one execute, in case a model keeps
+/// calling looping tools without ever producing output
+pub const MAX_TOOL_STEPS: usize = 10;
+
+/// Tools with `"next_action": "loop"` in their definition hand their result
+/// back to the model instead of ending the turn. With `"optional"` the model
+/// picks per call by passing `"continue": true`.
+pub fn tool_loops(definition: &json, arguments: &json) -> bool {
+    match definition["next_action"].as_str() {
+        Some("loop") => true,
+        Some("optional") => arguments["continue"] == true,
+        _ => false,
+    }
+}
+
+/// The user stopping a drawing ends the turn, even for looping tools
+pub fn is_cancelled(result: &Result<String>) -> bool {
+    matches!(result, Err(e) if e.is::<Cancelled>())
+}
+
+/// Whether a tool is offered to the model, given the names a prompt file
+/// limits it to, if any
+pub fn tool_enabled(enabled: &Option<Vec<String>>, name: &str) -> bool {
+    enabled
+        .as_ref()
+        .is_none_or(|enabled| enabled.iter().any(|enabled| enabled == name))
+}
+
+/// A prompt file naming a tool that doesn't exist is a mistake worth
+/// hearing about, rather than the tool quietly missing
+pub fn check_tool_names<'a>(
+    names: &[String],
+    registered: impl Iterator<Item = &'a str> + Clone,
+) -> Result<()> {
+    for name in names {
+        if !registered.clone().any(|registered| registered == name) {
+            bail!("There is no tool named {}", name);
+        }
+    }
+    Ok(())
+}
+
 pub trait LLMEngine {
     fn new(options: &HashMap<String, String>) -> Self
     where
         Self: Sized;
-    fn register_tool(&mut self, name: &str, definition: json, callback: Box<dyn FnMut(json)>);
+    fn register_tool(&mut self, name: &str, definition: json, callback: ToolCallback);
+    /// Offer only these registered tools to the model, or all of them
+    fn select_tools(&mut self, names: Option<Vec<String>>) -> Result<()>;
     fn add_text_content(&mut self, text: &str);
     fn add_image_content(&mut self, base64_image: &str);
     fn clear_content(&mut self);
     fn execute(&mut self) -> Result<()>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompts_can_limit_the_tools() {
+        let registered = ["draw_text", "draw_svg", "calculate"];
+        let math = vec!["calculate".to_string()];
+        assert!(check_tool_names(&math, registered.into_iter()).is_ok());
+        assert!(tool_enabled(&Some(math.clone()), "calculate"));
+        assert!(!tool_enabled(&Some(math), "draw_svg"));
+        assert!(tool_enabled(&None, "draw_svg"));
+
+        let typo = vec!["calculator".to_string()];
+        assert_eq!(
+            check_tool_names(&typo, registered.into_iter())
+                .unwrap_err()
+                .to_string(),
+            "There is no tool named calculator"
+        );
+    }
+}
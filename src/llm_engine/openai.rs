@@ -1,4 +1,7 @@
-use super::LLMEngine;
+use super::{
+    check_tool_names, is_cancelled, tool_enabled, tool_loops, LLMEngine, ToolCallback,
+    MAX_TOOL_STEPS,
+};
 use crate::util::{option_or_env, option_or_env_fallback, OptionMap};
 use anyhow::Result;
 use serde_json::json;
@@ -9,7 +12,7 @@ use ureq::Error;
 pub struct Tool {
     name: String,
     definition: json,
-    callback: Option<Box<dyn FnMut(json)>>,
+    callback: Option<ToolCallback>,
 }
 
 pub struct OpenAI {
@@ -17,6 +20,7 @@ pub struct OpenAI {
     base_url: String,
     api_key: String,
     tools: Vec<Tool>,
+    enabled: Option<Vec<String>>,
     content: Vec<json>,
 }
 
@@ -39,9 +43,9 @@ impl OpenAI {
 
 impl LLMEngine for OpenAI {
     fn new(options: &OptionMap) -> Self {
-        let api_key = option_or_env(&options, "api_key", "OPENAI_API_KEY");
+        let api_key = option_or_env(options, "api_key", "OPENAI_API_KEY");
         let base_url = option_or_env_fallback(
-            &options,
+            options,
             "base_url",
             "OPENAI_BASE_URL",
             "https://api.openai.com",
@@ -53,11 +57,12 @@ impl LLMEngine for OpenAI {
             base_url,
             api_key,
             tools: Vec::new(),
+            enabled: None,
             content: Vec::new(),
         }
     }
 
-    fn register_tool(&mut self, name: &str, definition: json, callback: Box<dyn FnMut(json)>) {
+    fn register_tool(&mut self, name: &str, definition: json, callback: ToolCallback) {
         self.tools.push(Tool {
             name: name.to_string(),
             definition,
@@ -65,6 +70,14 @@ impl LLMEngine for OpenAI {
         });
     }
 
+    fn select_tools(&mut self, names: Option<Vec<String>>) -> Result<()> {
+        if let Some(names) = &names {
+            check_tool_names(names, self.tools.iter().map(|tool| tool.name.as_str()))?;
+        }
+        self.enabled = names;
+        Ok(())
+    }
+
     fn add_text_content(&mut self, text: &str) {
         self.add_content(json!({
             "type": "text",
@@ -86,67 +99,77 @@ impl LLMEngine for OpenAI {
     }
 
     fn execute(&mut self) -> Result<()> {
-        let body = json!({
-            "model": self.model,
-            "messages": [{
-                "role": "user",
-                "content": self.content
-            }],
-            "tools": self.tools.iter().map(|tool| Self::openai_tool_definition(tool)).collect::<Vec<_>>(),
-            "tool_choice": "required",
-            "parallel_tool_calls": false
-        });
-
-        // print body for debugging
-        // println!("Request: {}", body);
-        let raw_response = ureq::post(format!("{}/v1/chat/completions", self.base_url).as_str())
-            .set("Authorization", &format!("Bearer {}", self.api_key))
-            .set("Content-Type", "application/json")
-            .send_json(&body);
-
-        let response = match raw_response {
-            Ok(response) => response,
-            Err(Error::Status(code, response)) => {
-                println!("Error: {}", code);
-                let json: json = response.into_json()?;
-                println!("Response: {}", json);
-                return Err(anyhow::anyhow!("API ERROR"));
-            }
-            Err(_) => return Err(anyhow::anyhow!("OTHER API ERROR")),
-        };
-
-        let json: json = response.into_json().unwrap();
-        // println!("Response: {}", json);
-
-        let tool_calls = &json["choices"][0]["message"]["tool_calls"];
-
-        if let Some(tool_call) = tool_calls.get(0) {
-            let function_name = tool_call["function"]["name"].as_str().unwrap();
-            let function_input_raw = tool_call["function"]["arguments"].as_str().unwrap();
-            let function_input = serde_json::from_str::<json>(function_input_raw).unwrap();
+        let mut messages = vec![json!({
+            "role": "user",
+            "content": self.content
+        })];
+
+        for _ in 0..MAX_TOOL_STEPS {
+            let body = json!({
+                "model": self.model,
+                "messages": messages,
+                "tools": self.tools.iter().filter(|tool| tool_enabled(&self.enabled, &tool.name)).map(Self::openai_tool_definition).collect::<Vec<_>>(),
+                "tool_choice": "required",
+                "parallel_tool_calls": false
+            });
+
+            // print body for debugging
+            // println!("Request: {}", body);
+            let raw_response =
+                ureq::post(format!("{}/v1/chat/completions", self.base_url).as_str())
+                    .set("Authorization", &format!("Bearer {}", self.api_key))
+                    .set("Content-Type", "application/json")
+                    .send_json(&body);
+
+            let response = match raw_response {
+                Ok(response) => response,
+                Err(Error::Status(code, response)) => {
+                    println!("Error: {}", code);
+                    let json: json = response.into_json()?;
+                    println!("Response: {}", json);
+                    return Err(anyhow::anyhow!("API ERROR"));
+                }
+                Err(_) => return Err(anyhow::anyhow!("OTHER API ERROR")),
+            };
+
+            let json: json = response.into_json()?;
+            // println!("Response: {}", json);
+
+            let message = &json["choices"][0]["message"];
+            let tool_call = message["tool_calls"]
+                .get(0)
+                .ok_or_else(|| anyhow::anyhow!("No tool calls found in response"))?;
+            let function_name = tool_call["function"]["name"].as_str().unwrap_or_default();
+            let function_input_raw = tool_call["function"]["arguments"].as_str().unwrap_or("{}");
+            let function_input = serde_json::from_str::<json>(function_input_raw)?;
+            let enabled = &self.enabled;
             let tool = self
                 .tools
                 .iter_mut()
-                .find(|tool| tool.name == function_name);
-
-            if let Some(tool) = tool {
-                if let Some(callback) = &mut tool.callback {
-                    callback(function_input.clone());
-                    Ok(())
-                } else {
-                    Err(anyhow::anyhow!(
-                        "No callback registered for tool {}",
-                        function_name
-                    ))
-                }
-            } else {
-                Err(anyhow::anyhow!(
-                    "No tool registered with name {}",
-                    function_name
-                ))
+                .find(|tool| tool.name == function_name && tool_enabled(enabled, &tool.name))
+                .ok_or_else(|| anyhow::anyhow!("No tool registered with name {}", function_name))?;
+            let loops = tool_loops(&tool.definition, &function_input);
+            let callback = tool.callback.as_mut().ok_or_else(|| {
+                anyhow::anyhow!("No callback registered for tool {}", function_name)
+            })?;
+            let result = callback(function_input);
+            if !loops || is_cancelled(&result) {
+                return result.map(|_| ());
             }
-        } else {
-            Err(anyhow::anyhow!("No tool calls found in response"))
+
+            let result = result.unwrap_or_else(|e| format!("Error: {}", e));
+            println!("Tool {} returned: {}", function_name, result);
+            messages.push(message.clone());
+            messages.push(json!({
+                "role": "tool",
+                "tool_call_id": tool_call["id"],
+                "content": result
+            }));
         }
+
+        Err(anyhow::anyhow!(
+            "Stopped after {} tool steps without any output",
+            MAX_TOOL_STEPS
+        ))
     }
 }
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use std::sync::{Arc, Mutex};
 
 use serde_json::Value as json;
@@ -11,19 +11,45 @@ use dotenv::dotenv;
 
 use rust_embed::Embed;
 
+use image::GrayImage;
+
 use ghostwriter::{
+    calculator::calculate,
+    cancel::{CancelFlag, Cancelled},
+    calendar::{local_zone, parse_day, parse_time, Calendar},
+    chart::{chart_to_svg, ChartSpec},
+    fill::{FillSettings, FillStyle},
+    handwriting::{line_span, text_to_strokes},
+    journal::{Journal, Output},
     keyboard::Keyboard,
     llm_engine::{anthropic::Anthropic, openai::OpenAI, google::Google, LLMEngine},
-    pen::Pen,
+    markdown::{parse_blocks, TextStyle},
+    marks::{mark_strokes, Mark},
+    math::{math_to_svg, plain_text, typeset},
+    memory::{Memory, MEMORY_FACTS},
+    messages::{retry_outbox, Messenger, OutgoingMessage},
+    notes::NoteStore,
+    optimize::{optimize_strokes, pen_up_travel},
+    pacing::{Pacing, MIN_EVENTS_PER_SECOND},
+    page_reader::PageReader,
+    pen::{Pen, PenStyle},
+    placement::{place_text, InkMap, Side},
+    preview::{dotted_box, preview_style, Bounds, Rejected, PADDING, TAP_TIMEOUT},
+    plugins::load_plugins,
+    router::{Router, RouterConfig},
     screenshot::Screenshot,
-    segmenter::analyze_image,
-    touch::Touch,
-    util::{svg_to_bitmap, write_bitmap_to_file, OptionMap},
+    segmenter::{analyze_image, describe_grids, detect_grids, load_screen, Grid},
+    svg_fit::fit_svg,
+    todo::TodoList,
+    touch::{watch_for_cancel, Touch, Trigger},
+    util::{strokes_to_bitmap_with_width, write_bitmap_to_file, OptionMap, Polyline},
+    vector::svg_to_drawing,
 };
 
 const REMARKABLE_WIDTH: u32 = 768;
 const REMARKABLE_HEIGHT: u32 = 1024;
 
+
 #[derive(Embed)]
 #[folder = "prompts/"]
 struct Asset;
@@ -71,6 +97,46 @@ struct Args {
     #[arg(long)]
     no_draw_progress: bool,
 
+    /// How fast to draw: fast, safe or debug
+    #[arg(long, default_value = "safe")]
+    pacing: String,
+
+    /// Most pen events per second, overriding the pacing preset; at least 200
+    #[arg(long)]
+    events_per_second: Option<f64>,
+
+    /// Split strokes with more points than this, overriding the pacing preset
+    #[arg(long)]
+    max_stroke_points: Option<usize>,
+
+    /// Slow down when a pen write takes longer than this many milliseconds
+    #[arg(long)]
+    slow_write_ms: Option<f64>,
+
+    /// Slow down when xochitl uses more than this share of a core, overriding
+    /// the pacing preset; 0 turns it off
+    #[arg(long)]
+    busy_cpu: Option<f64>,
+
+    /// How to draw filled areas: solid, outline, hatch, cross-hatch or stipple
+    #[arg(long, default_value = "solid")]
+    fill_style: String,
+
+    /// Space between hatch lines or stipple dots, in pixels
+    #[arg(long, default_value_t = 6.0)]
+    fill_spacing: f32,
+
+    /// Draw a dotted box where draw_svg and draw_text output will go first,
+    /// and only draw it after a tap inside the box; a tap outside rejects it.
+    /// Dashes that would touch ink from before the answer are left out, so
+    /// erasing the box doesn't take that ink with it
+    #[arg(long)]
+    preview: bool,
+
+    /// Times to ask the model again after a rejected preview
+    #[arg(long, default_value_t = 1)]
+    preview_retries: u32,
+
     /// Input PNG file for testing
     #[arg(long)]
     input_png: Option<String>,
@@ -102,15 +168,77 @@ struct Args {
     /// Apply segmentation
     #[arg(long)]
     apply_segmentation: bool,
+
+    /// Directory for data kept between runs, like notes, todo.txt and memory
+    #[arg(long, default_value = "ghostwriter-data")]
+    data_dir: String,
+
+    /// Model that reads the page to pick remembered facts, once there are
+    /// too many to send them all [default: the main model]
+    #[arg(long)]
+    memory_model: Option<String>,
+
+    /// iCalendar file for the calendar tools [default: DATA_DIR/calendar.ics]
+    #[arg(long)]
+    calendar_file: Option<String>,
+
+    /// Time zone for calendar times, like Europe/Berlin [default: system zone]
+    #[arg(long)]
+    timezone: Option<String>,
+
+    /// Recipients and transports for send_message [default: DATA_DIR/messages.json]
+    #[arg(long)]
+    messages_config: Option<String>,
+
+    /// Directory of WebAssembly tool plugins [default: DATA_DIR/plugins]
+    #[arg(long)]
+    plugins_dir: Option<String>,
+
+    /// Import a Taskwarrior JSON export into todo.txt and exit ("-" for stdin)
+    #[arg(long)]
+    todo_import: Option<String>,
+
+    /// Export todo.txt as Taskwarrior JSON and exit ("-" for stdout)
+    #[arg(long)]
+    todo_export: Option<String>,
 }
 
 fn main() -> Result<()> {
     dotenv().ok();
     let args = Args::parse();
 
+    if args.todo_import.is_some() || args.todo_export.is_some() {
+        return todo_transfer(&args);
+    }
+
     ghostwriter(&args)
 }
 
+fn todo_transfer(args: &Args) -> Result<()> {
+    let todo = TodoList::new(std::path::Path::new(&args.data_dir).join("todo.txt"));
+    if let Some(import) = &args.todo_import {
+        let data = if import == "-" {
+            std::io::read_to_string(std::io::stdin())?
+        } else {
+            std::fs::read_to_string(import)?
+        };
+        let added = todo.import_taskwarrior(&data)?;
+        // Stay quiet when the export goes to stdout, so it is only JSON
+        if args.todo_export.as_deref() != Some("-") {
+            println!("Imported {} tasks", added);
+        }
+    }
+    if let Some(export) = &args.todo_export {
+        let data = todo.export_taskwarrior()?;
+        if export == "-" {
+            println!("{}", data);
+        } else {
+            std::fs::write(export, data)?;
+        }
+    }
+    Ok(())
+}
+
 macro_rules! shared {
    ($x:expr) => { Arc::new(Mutex::new($x)) }
 }
@@ -122,26 +250,161 @@ macro_rules! lock {
 fn draw_text(text: &str, keyboard: &mut Keyboard) -> Result<()> {
     keyboard.progress()?;
     keyboard.progress_end()?;
+    for block in parse_blocks(text) {
+        match block.style {
+            TextStyle::Title => keyboard.key_cmd_title()?,
+            TextStyle::Subheading => keyboard.key_cmd_subheading()?,
+            TextStyle::Body => keyboard.key_cmd_body()?,
+            TextStyle::Bullet => keyboard.key_cmd_bullet()?,
+        }
+        keyboard.string_to_keypresses(&block.text)?;
+        keyboard.string_to_keypresses("\n")?;
+    }
+    // Leave the cursor on a plain line below the response
     keyboard.key_cmd_body()?;
-    keyboard.string_to_keypresses(text)?;
-    keyboard.string_to_keypresses("\n\n")?;
+    keyboard.string_to_keypresses("\n")?;
+    Ok(())
+}
+
+/// Add how much of a stopped answer made it onto the page to the model
+/// output file, next to the tool call that was stopped
+fn record_stopped_output(
+    model_output_file: &str,
+    strokes_drawn: usize,
+    chars_typed: usize,
+) -> Result<()> {
+    let mut model_output = std::fs::read_to_string(model_output_file)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<json>(&contents).ok())
+        .filter(json::is_object)
+        .unwrap_or_else(|| serde_json::json!({}));
+    model_output["stopped_by_user"] = serde_json::json!({
+        "strokes_drawn": strokes_drawn,
+        "chars_typed": chars_typed,
+    });
+    std::fs::write(model_output_file, model_output.to_string())?;
+    Ok(())
+}
+
+/// Draw a dotted box round where output will go and wait for a tap: inside
+/// the box goes ahead, outside or no tap in time rejects the output. The box
+/// is erased and left out of the journal either way, even when the user
+/// stops the answer while it is showing.
+fn confirm_placement(
+    tool: &str,
+    bounds: Bounds,
+    pen: &mut Pen,
+    (touch, screen): (&mut Touch, &GrayImage),
+) -> Result<()> {
+    let frame = bounds.padded(PADDING, (REMARKABLE_WIDTH, REMARKABLE_HEIGHT));
+    let outline = dotted_box(&frame, screen);
+    let recorded = pen.strokes_drawn();
+    let drawn = pen.draw_strokes_styled(&outline, &preview_style());
+    let tap = if drawn.is_ok() {
+        println!(
+            "Previewing {} output at {}, tap inside the box to draw it or outside to reject it",
+            tool, bounds
+        );
+        touch.wait_for_tap(TAP_TIMEOUT)?
+    } else {
+        None
+    };
+    pen.ignoring_cancel(|pen| {
+        for dash in &outline {
+            pen.erase_stroke(dash)?;
+        }
+        Ok(())
+    })?;
+    pen.forget_strokes_after(recorded);
+    drawn?;
+    if pen.is_cancelled() {
+        return Err(Cancelled.into());
+    }
+    if !tap.is_some_and(|tap| frame.contains(tap)) {
+        return Err(Rejected {
+            tool: tool.to_string(),
+            bounds,
+        }
+        .into());
+    }
     Ok(())
 }
 
 fn draw_svg(
     svg_data: &str,
+    fill: FillSettings,
     keyboard: &mut Keyboard,
     pen: &mut Pen,
+    preview: Option<(&mut Touch, &GrayImage)>,
     save_bitmap: Option<&String>,
     no_draw: bool,
 ) -> Result<()> {
     keyboard.progress()?;
-    let bitmap = svg_to_bitmap(svg_data, REMARKABLE_WIDTH, REMARKABLE_HEIGHT)?;
+    let mut drawing = svg_to_drawing(svg_data, REMARKABLE_WIDTH, REMARKABLE_HEIGHT)?;
+    drawing.set_fill(fill);
+    let (mut events, mut travel) = ((0, 0), (0.0, 0.0));
+    for layer in &mut drawing.layers {
+        events.0 += pen.stroke_events(&layer.strokes, &layer.style);
+        travel.0 += pen_up_travel(&layer.strokes);
+        layer.strokes = optimize_strokes(&layer.strokes);
+        events.1 += pen.stroke_events(&layer.strokes, &layer.style);
+        travel.1 += pen_up_travel(&layer.strokes);
+    }
+    println!(
+        "Pen events: {} -> {}, pen-up travel: {:.0}px -> {:.0}px",
+        events.0, events.1, travel.0, travel.1
+    );
     if let Some(save_bitmap) = save_bitmap {
+        write_bitmap_to_file(&drawing.to_bitmap()?, save_bitmap)?;
+    }
+    if !no_draw {
+        let bounds = drawing
+            .layers
+            .iter()
+            .filter_map(|layer| Bounds::of_strokes(&layer.strokes))
+            .chain(Bounds::of_bitmap(&drawing.fills))
+            .reduce(Bounds::union);
+        if let (Some(preview), Some(bounds)) = (preview, bounds) {
+            confirm_placement("draw_svg", bounds, pen, preview)?;
+        }
+        let events_before = pen.events_sent();
+        for layer in &drawing.layers {
+            pen.draw_strokes_styled(&layer.strokes, &layer.style)?;
+        }
+        println!("Pen events sent: {}", pen.events_sent() - events_before);
+        if drawing.has_fills() {
+            pen.draw_bitmap(&drawing.fills)?;
+        }
+    }
+    keyboard.progress_end()?;
+    Ok(())
+}
+
+/// The grids on the screen, detected the first time they are asked for
+fn screen_grids<'a>(grids: &'a mut Option<Vec<Grid>>, screen: &GrayImage) -> &'a [Grid] {
+    grids.get_or_insert_with(|| detect_grids(screen))
+}
+
+fn draw_strokes(
+    strokes: &[Polyline],
+    style: PenStyle,
+    keyboard: &mut Keyboard,
+    pen: &mut Pen,
+    save_bitmap: Option<&String>,
+    no_draw: bool,
+) -> Result<()> {
+    keyboard.progress()?;
+    if let Some(save_bitmap) = save_bitmap {
+        let bitmap = strokes_to_bitmap_with_width(
+            strokes,
+            style.line_width(),
+            REMARKABLE_WIDTH,
+            REMARKABLE_HEIGHT,
+        )?;
         write_bitmap_to_file(&bitmap, save_bitmap)?;
     }
     if !no_draw {
-        pen.draw_bitmap(&bitmap)?;
+        pen.draw_strokes_styled(strokes, &style)?;
     }
     keyboard.progress_end()?;
     Ok(())
@@ -159,17 +422,10 @@ fn load_config(filename: &str) -> String {
     }
 }
 
-fn ghostwriter(args: &Args) -> Result<()> {
-    let keyboard = shared!(Keyboard::new(
-        args.no_draw,
-        args.no_draw_progress,
-    ));
-    let pen = shared!(Pen::new(args.no_draw));
-    let touch = shared!(Touch::new(args.no_draw));
-
+fn create_engine(args: &Args, model: &str) -> Box<dyn LLMEngine> {
     let mut engine_options = OptionMap::new();
 
-    let model = args.model.clone();
+    let model = model.to_string();
     engine_options.insert("model".to_string(), model.clone());
 
     let engine_name = if let Some(engine) = args.engine.clone() {
@@ -196,17 +452,76 @@ fn ghostwriter(args: &Args) -> Result<()> {
         engine_options.insert("api_key".to_string(), args.engine_api_key.clone().unwrap());
     }
 
-    let mut engine: Box<dyn LLMEngine> = match engine_name.as_str() {
+    match engine_name.as_str() {
         "openai" => Box::new(OpenAI::new(&engine_options)),
         "anthropic" => Box::new(Anthropic::new(&engine_options)),
         "google" => Box::new(Google::new(&engine_options)),
         _ => panic!("Unknown engine {}", engine_name),
+    }
+}
+
+fn ghostwriter(args: &Args) -> Result<()> {
+    let keyboard = shared!(Keyboard::new(
+        args.no_draw,
+        args.no_draw_progress,
+    ));
+    let mut pacing =
+        Pacing::preset(&args.pacing).ok_or_else(|| anyhow!("Unknown pacing {}", args.pacing))?;
+    if let Some(events_per_second) = args.events_per_second {
+        if events_per_second.is_nan() || events_per_second < MIN_EVENTS_PER_SECOND {
+            bail!(
+                "--events-per-second must be at least {}",
+                MIN_EVENTS_PER_SECOND
+            );
+        }
+        pacing.events_per_second = events_per_second;
+    }
+    if let Some(max_stroke_points) = args.max_stroke_points {
+        pacing.max_stroke_points = max_stroke_points;
+    }
+    if let Some(slow_write_ms) = args.slow_write_ms {
+        pacing.slow_write = std::time::Duration::try_from_secs_f64(slow_write_ms / 1000.0)
+            .map_err(|_| anyhow!("Bad --slow-write-ms {}", slow_write_ms))?;
+    }
+    if let Some(busy_cpu) = args.busy_cpu {
+        pacing.busy_cpu = (busy_cpu > 0.0).then_some(busy_cpu);
+    }
+    let pen = shared!(Pen::new(args.no_draw));
+    lock!(pen).set_pacing(pacing);
+    let touch = shared!(Touch::new(args.no_draw));
+    let cancel = CancelFlag::new();
+    if !args.no_draw {
+        watch_for_cancel(cancel.clone());
+    }
+    lock!(pen).set_cancel(cancel.clone());
+    lock!(keyboard).set_cancel(cancel.clone());
+    // Grids are only looked for once a tool needs them, since it is slow
+    let grids = shared!(None::<Vec<Grid>>);
+    let fill = FillSettings {
+        style: FillStyle::parse(&args.fill_style)
+            .ok_or_else(|| anyhow!("Unknown fill style {}", args.fill_style))?,
+        spacing: args.fill_spacing,
     };
+    let screen = shared!(GrayImage::from_pixel(
+        REMARKABLE_WIDTH,
+        REMARKABLE_HEIGHT,
+        image::Luma([255])
+    ));
+    // The size of the image sent to the model, which can differ from the
+    // screen when testing with --input-png
+    let shown_size = shared!((REMARKABLE_WIDTH, REMARKABLE_HEIGHT));
+
+    let mut engine = create_engine(args, &args.model);
+    let mut journal = Journal::new();
 
     let output_file = args.output_file.clone();
+    let model_output_file = args.model_output_file.clone();
     let no_draw = args.no_draw;
+    let preview = args.preview;
     let keyboard_clone = Arc::clone(&keyboard);
+    let pen_clone = Arc::clone(&pen);
     let touch_clone = Arc::clone(&touch);
+    let screen_clone = Arc::clone(&screen);
 
     let tool_config_draw_text = load_config("tool_draw_text.json");
 
@@ -216,25 +531,47 @@ fn ghostwriter(args: &Args) -> Result<()> {
         Box::new(move |arguments: json| {
             let text = arguments["text"].as_str().unwrap();
             if let Some(output_file) = &output_file {
-                std::fs::write(output_file, text).unwrap();
+                std::fs::write(output_file, text)?;
+            }
+            if let Some(model_output_file) = &model_output_file {
+                let model_output = serde_json::json!({
+                    "function": "draw_text",
+                    "arguments": arguments,
+                });
+                std::fs::write(model_output_file, model_output.to_string())?;
             }
             if !no_draw {
+                if preview {
+                    let screen = lock!(screen_clone);
+                    confirm_placement(
+                        "draw_text",
+                        Bounds::of_text(text, &screen),
+                        &mut lock!(pen_clone),
+                        (&mut lock!(touch_clone), &screen),
+                    )?;
+                }
                 // Touch in the middle bottom to make sure we go below any new drawing
-                lock!(touch_clone).touch_start((384, 1000)).unwrap(); // middle bottom
-                lock!(touch_clone).touch_stop().unwrap();
+                lock!(touch_clone).touch_start((384, 1000))?; // middle bottom
+                lock!(touch_clone).touch_stop()?;
 
                 let mut keyboard = lock!(keyboard_clone);
 
-                draw_text(text, &mut keyboard).unwrap();
+                draw_text(text, &mut keyboard)?;
             }
+            Ok(String::new())
         }),
     );
 
     let output_file = args.output_file.clone();
+    let model_output_file = args.model_output_file.clone();
     let save_bitmap = args.save_bitmap.clone();
     let no_draw = args.no_draw;
+    let preview = args.preview;
     let keyboard_clone = Arc::clone(&keyboard);
     let pen_clone = Arc::clone(&pen);
+    let touch_clone = Arc::clone(&touch);
+    let screen_clone = Arc::clone(&screen);
+    let shown_size_clone = Arc::clone(&shown_size);
 
     let tool_config_draw_svg = load_config("tool_draw_svg.json");
     engine.register_tool(
@@ -243,40 +580,734 @@ fn ghostwriter(args: &Args) -> Result<()> {
         Box::new(move |arguments: json| {
             let svg_data = arguments["svg"].as_str().unwrap();
             if let Some(output_file) = &output_file {
-                std::fs::write(output_file, svg_data).unwrap();
+                std::fs::write(output_file, svg_data)?;
             }
+            let (svg_data, fit) = fit_svg(
+                svg_data,
+                *lock!(shown_size_clone),
+                (REMARKABLE_WIDTH, REMARKABLE_HEIGHT),
+            );
+            println!("Fitting SVG to the screen: {}", fit);
+            for warning in &fit.warnings {
+                println!("Suspicious SVG size: {}", warning);
+            }
+            if let Some(model_output_file) = &model_output_file {
+                let model_output = serde_json::json!({
+                    "function": "draw_svg",
+                    "arguments": arguments,
+                    "svg_fit": fit,
+                });
+                std::fs::write(model_output_file, model_output.to_string())?;
+            }
+            let fill = FillSettings {
+                style: arguments["fill"]
+                    .as_str()
+                    .and_then(FillStyle::parse)
+                    .unwrap_or(fill.style),
+                ..fill
+            };
             let mut keyboard = lock!(keyboard_clone);
             let mut pen = lock!(pen_clone);
+            let mut touch = lock!(touch_clone);
+            let screen = lock!(screen_clone);
             draw_svg(
-                svg_data,
+                &svg_data,
+                fill,
+                &mut keyboard,
+                &mut pen,
+                preview.then_some((&mut *touch, &*screen)),
+                save_bitmap.as_ref(),
+                no_draw,
+            )?;
+            Ok(String::new())
+        }),
+    );
+
+    let output_file = args.output_file.clone();
+    let save_bitmap = args.save_bitmap.clone();
+    let no_draw = args.no_draw;
+    let preview = args.preview;
+    let keyboard_clone = Arc::clone(&keyboard);
+    let pen_clone = Arc::clone(&pen);
+    let touch_clone = Arc::clone(&touch);
+    let screen_clone = Arc::clone(&screen);
+
+    let tool_config_draw_math = load_config("tool_draw_math.json");
+    engine.register_tool(
+        "draw_math",
+        serde_json::from_str::<serde_json::Value>(tool_config_draw_math.as_str())?,
+        Box::new(move |arguments: json| {
+            let latex = arguments["latex"]
+                .as_str()
+                .ok_or_else(|| anyhow!("latex is required"))?;
+            let x = arguments["x"].as_f64().unwrap_or(0.0) as f32;
+            let y = arguments["y"].as_f64().unwrap_or(0.0) as f32;
+            let font_size = arguments["font_size"].as_f64().unwrap_or(32.0) as f32;
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, latex)?;
+            }
+            let math = typeset(latex, font_size).unwrap_or_else(|e| {
+                println!("Error typesetting math: {}. Drawing it as plain text.", e);
+                plain_text(latex, font_size)
+            });
+            println!(
+                "Typeset math into {:.0}x{:.0} px at ({}, {})",
+                math.width,
+                math.height(),
+                x,
+                y
+            );
+            let svg_data = math_to_svg(
+                &math,
+                (x, y),
+                font_size,
+                (REMARKABLE_WIDTH, REMARKABLE_HEIGHT),
+            );
+            let mut keyboard = lock!(keyboard_clone);
+            let mut pen = lock!(pen_clone);
+            let mut touch = lock!(touch_clone);
+            let screen = lock!(screen_clone);
+            draw_svg(
+                &svg_data,
+                fill,
+                &mut keyboard,
+                &mut pen,
+                preview.then_some((&mut *touch, &*screen)),
+                save_bitmap.as_ref(),
+                no_draw,
+            )?;
+            Ok(String::new())
+        }),
+    );
+
+    let output_file = args.output_file.clone();
+    let save_bitmap = args.save_bitmap.clone();
+    let no_draw = args.no_draw;
+    let keyboard_clone = Arc::clone(&keyboard);
+    let pen_clone = Arc::clone(&pen);
+
+    let tool_config_draw_handwriting = load_config("tool_draw_handwriting.json");
+    engine.register_tool(
+        "draw_handwriting",
+        serde_json::from_str::<serde_json::Value>(tool_config_draw_handwriting.as_str())?,
+        Box::new(move |arguments: json| {
+            let text = arguments["text"]
+                .as_str()
+                .ok_or_else(|| anyhow!("text is required"))?;
+            let x = arguments["x"].as_f64().unwrap_or(0.0) as f32;
+            let y = arguments["y"].as_f64().unwrap_or(0.0) as f32;
+            let size = arguments["size"].as_f64().unwrap_or(32.0) as f32;
+            let (x, max_width) = line_span(
+                x,
+                arguments["max_width"].as_f64().map(|w| w as f32),
+                size,
+                REMARKABLE_WIDTH as f32,
+            );
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, text)?;
+            }
+            let strokes = text_to_strokes(text, (x, y), size, max_width);
+            let mut keyboard = lock!(keyboard_clone);
+            let mut pen = lock!(pen_clone);
+            draw_strokes(
+                &strokes,
+                PenStyle::handwriting(),
+                &mut keyboard,
+                &mut pen,
+                save_bitmap.as_ref(),
+                no_draw,
+            )?;
+            Ok(String::new())
+        }),
+    );
+
+    let output_file = args.output_file.clone();
+    let save_bitmap = args.save_bitmap.clone();
+    let no_draw = args.no_draw;
+    let preview = args.preview;
+    let keyboard_clone = Arc::clone(&keyboard);
+    let pen_clone = Arc::clone(&pen);
+    let touch_clone = Arc::clone(&touch);
+    let screen_clone = Arc::clone(&screen);
+
+    let tool_config_draw_chart = load_config("tool_draw_chart.json");
+    engine.register_tool(
+        "draw_chart",
+        serde_json::from_str::<serde_json::Value>(tool_config_draw_chart.as_str())?,
+        Box::new(move |arguments: json| {
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, arguments.to_string())?;
+            }
+            let spec: ChartSpec =
+                serde_json::from_value(arguments).map_err(|e| anyhow!("Invalid chart: {}", e))?;
+            let svg_data = chart_to_svg(&spec, (REMARKABLE_WIDTH, REMARKABLE_HEIGHT))?;
+            let mut keyboard = lock!(keyboard_clone);
+            let mut pen = lock!(pen_clone);
+            let mut touch = lock!(touch_clone);
+            let screen = lock!(screen_clone);
+            draw_svg(
+                &svg_data,
+                fill,
+                &mut keyboard,
+                &mut pen,
+                preview.then_some((&mut *touch, &*screen)),
+                save_bitmap.as_ref(),
+                no_draw,
+            )?;
+            Ok(String::new())
+        }),
+    );
+
+    let output_file = args.output_file.clone();
+    let no_draw = args.no_draw;
+    let keyboard_clone = Arc::clone(&keyboard);
+    let pen_clone = Arc::clone(&pen);
+
+    let tool_config_erase_region = load_config("tool_erase_region.json");
+    engine.register_tool(
+        "erase_region",
+        serde_json::from_str::<serde_json::Value>(tool_config_erase_region.as_str())?,
+        Box::new(move |arguments: json| {
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, arguments.to_string())?;
+            }
+            let polygon: Option<Vec<(f32, f32)>> = arguments["points"].as_array().map(|points| {
+                points
+                    .iter()
+                    .filter_map(|point| {
+                        Some((point[0].as_f64()? as f32, point[1].as_f64()? as f32))
+                    })
+                    .collect()
+            });
+            let x = arguments["x"].as_f64().unwrap_or(0.0) as f32;
+            let y = arguments["y"].as_f64().unwrap_or(0.0) as f32;
+            let width = arguments["width"].as_f64().unwrap_or(0.0) as f32;
+            let height = arguments["height"].as_f64().unwrap_or(0.0) as f32;
+            let mut keyboard = lock!(keyboard_clone);
+            let mut pen = lock!(pen_clone);
+            keyboard.progress()?;
+            if !no_draw {
+                match &polygon {
+                    Some(polygon) if polygon.len() >= 3 => pen.erase_polygon(polygon)?,
+                    _ => pen.erase_rect((x, y), (width, height))?,
+                }
+            }
+            keyboard.progress_end()?;
+            Ok(match polygon {
+                Some(polygon) if polygon.len() >= 3 => {
+                    format!("Erased the polygon {:?}", polygon)
+                }
+                _ => format!(
+                    "Erased the rectangle at x={}, y={}, width={}, height={}",
+                    x, y, width, height
+                ),
+            })
+        }),
+    );
+
+    let grids_clone = Arc::clone(&grids);
+    let screen_clone = Arc::clone(&screen);
+    let tool_config_find_grids = load_config("tool_find_grids.json");
+    engine.register_tool(
+        "find_grids",
+        serde_json::from_str::<serde_json::Value>(tool_config_find_grids.as_str())?,
+        Box::new(move |_arguments: json| {
+            let mut grids = lock!(grids_clone);
+            let description = describe_grids(screen_grids(&mut grids, &lock!(screen_clone)));
+            if description.is_empty() {
+                return Ok("No grids or boxes were found on the screen".to_string());
+            }
+            Ok(description)
+        }),
+    );
+
+    let output_file = args.output_file.clone();
+    let save_bitmap = args.save_bitmap.clone();
+    let no_draw = args.no_draw;
+    let keyboard_clone = Arc::clone(&keyboard);
+    let pen_clone = Arc::clone(&pen);
+    let grids_clone = Arc::clone(&grids);
+    let screen_clone = Arc::clone(&screen);
+
+    let tool_config_mark_cell = load_config("tool_mark_cell.json");
+    engine.register_tool(
+        "mark_cell",
+        serde_json::from_str::<serde_json::Value>(tool_config_mark_cell.as_str())?,
+        Box::new(move |arguments: json| {
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, arguments.to_string())?;
+            }
+            let grid_id = arguments["grid"].as_u64().unwrap_or(0) as usize;
+            let row = arguments["row"].as_u64().unwrap_or(0) as usize;
+            let column = arguments["column"].as_u64().unwrap_or(0) as usize;
+            let kind = arguments["mark"].as_str().unwrap_or_default();
+            let Some(mark) = Mark::parse(kind, arguments["text"].as_str()) else {
+                anyhow::bail!("Unknown mark '{}', or text missing for a text mark", kind);
+            };
+            let cell = {
+                let mut grids = lock!(grids_clone);
+                let grids = screen_grids(&mut grids, &lock!(screen_clone));
+                let grid = grid_id
+                    .checked_sub(1)
+                    .and_then(|i| grids.get(i))
+                    .ok_or_else(|| anyhow::anyhow!("There is no grid {}", grid_id))?;
+                grid.cell(row, column).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Grid {} has {} rows and {} columns, there is no row {} column {}",
+                        grid_id,
+                        grid.rows,
+                        grid.columns,
+                        row,
+                        column
+                    )
+                })?
+            };
+            let strokes = mark_strokes(&mark, cell.center, cell.width, cell.height);
+            let mut keyboard = lock!(keyboard_clone);
+            let mut pen = lock!(pen_clone);
+            draw_strokes(
+                &strokes,
+                PenStyle::handwriting(),
+                &mut keyboard,
+                &mut pen,
+                save_bitmap.as_ref(),
+                no_draw,
+            )?;
+            Ok(format!(
+                "Drew {} in grid {} row {} column {}",
+                kind, grid_id, row, column
+            ))
+        }),
+    );
+
+    let output_file = args.output_file.clone();
+    let save_bitmap = args.save_bitmap.clone();
+    let no_draw = args.no_draw;
+    let keyboard_clone = Arc::clone(&keyboard);
+    let pen_clone = Arc::clone(&pen);
+    let screen_clone = Arc::clone(&screen);
+
+    let tool_config_place_text = load_config("tool_place_text.json");
+    engine.register_tool(
+        "place_text",
+        serde_json::from_str::<serde_json::Value>(tool_config_place_text.as_str())?,
+        Box::new(move |arguments: json| {
+            let text = arguments["text"].as_str().unwrap_or_default();
+            let x = arguments["x"].as_f64().unwrap_or(0.0) as f32;
+            let y = arguments["y"].as_f64().unwrap_or(0.0) as f32;
+            let side = arguments["side"]
+                .as_str()
+                .and_then(Side::parse)
+                .unwrap_or(Side::Right);
+            let size = arguments["size"].as_f64().unwrap_or(32.0) as f32;
+            let max_width = arguments["max_width"].as_f64().map(|w| w as f32);
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, text)?;
+            }
+            let placement = {
+                let mut screen = lock!(screen_clone);
+                let placement =
+                    place_text(&InkMap::new(&screen), text, (x, y), side, size, max_width);
+                // Count the new text as ink in case more is placed this turn
+                let (left, top) = placement.origin;
+                let (width, height) = placement.bounds;
+                for py in top.max(0.0) as u32..((top + height) as u32).min(REMARKABLE_HEIGHT) {
+                    for px in left.max(0.0) as u32..((left + width) as u32).min(REMARKABLE_WIDTH) {
+                        screen.put_pixel(px, py, image::Luma([0]));
+                    }
+                }
+                placement
+            };
+            let strokes = text_to_strokes(
+                text,
+                placement.origin,
+                placement.size,
+                placement.max_width,
+            );
+            let mut keyboard = lock!(keyboard_clone);
+            let mut pen = lock!(pen_clone);
+            draw_strokes(
+                &strokes,
+                PenStyle::handwriting(),
                 &mut keyboard,
                 &mut pen,
                 save_bitmap.as_ref(),
                 no_draw,
-            )
-            .unwrap();
+            )?;
+            let (left, top) = placement.origin;
+            let (width, height) = placement.bounds;
+            Ok(format!(
+                "Wrote the text at x={:.0}, y={:.0}, width={:.0}, height={:.0} with size {:.0}{}",
+                left,
+                top,
+                width,
+                height,
+                placement.size,
+                if placement.overlaps {
+                    "; there was no free space nearby, so it overlaps existing ink"
+                } else {
+                    ""
+                }
+            ))
+        }),
+    );
+
+    let notes_dir = std::path::Path::new(&args.data_dir).join("notes");
+
+    let tool_config_list_notes = load_config("tool_list_notes.json");
+    let notes = NoteStore::new(&notes_dir);
+    engine.register_tool(
+        "list_notes",
+        serde_json::from_str::<serde_json::Value>(tool_config_list_notes.as_str())?,
+        Box::new(move |arguments: json| {
+            notes.describe(arguments["search"].as_str().unwrap_or_default())
+        }),
+    );
+
+    let tool_config_read_note = load_config("tool_read_note.json");
+    let notes = NoteStore::new(&notes_dir);
+    engine.register_tool(
+        "read_note",
+        serde_json::from_str::<serde_json::Value>(tool_config_read_note.as_str())?,
+        Box::new(move |arguments: json| {
+            let name = arguments["name"].as_str().unwrap_or_default();
+            notes.read(name)
         }),
     );
 
+    let output_file = args.output_file.clone();
+    let tool_config_write_note = load_config("tool_write_note.json");
+    let notes = NoteStore::new(&notes_dir);
+    engine.register_tool(
+        "write_note",
+        serde_json::from_str::<serde_json::Value>(tool_config_write_note.as_str())?,
+        Box::new(move |arguments: json| {
+            let name = arguments["name"].as_str().unwrap_or_default();
+            let content = arguments["content"].as_str().unwrap_or_default();
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, arguments.to_string())?;
+            }
+            notes.write(name, content)?;
+            Ok(format!("Saved note '{}'", name))
+        }),
+    );
+
+    let output_file = args.output_file.clone();
+    let tool_config_append_note = load_config("tool_append_note.json");
+    let notes = NoteStore::new(&notes_dir);
+    engine.register_tool(
+        "append_note",
+        serde_json::from_str::<serde_json::Value>(tool_config_append_note.as_str())?,
+        Box::new(move |arguments: json| {
+            let name = arguments["name"].as_str().unwrap_or_default();
+            let content = arguments["content"].as_str().unwrap_or_default();
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, arguments.to_string())?;
+            }
+            notes.append(name, content)?;
+            Ok(format!("Added to note '{}'", name))
+        }),
+    );
+
+    let todo_path = std::path::Path::new(&args.data_dir).join("todo.txt");
+    let today = || chrono::Local::now().date_naive();
+
+    let output_file = args.output_file.clone();
+    let tool_config_todo_add = load_config("tool_todo_add.json");
+    let todo = TodoList::new(&todo_path);
+    engine.register_tool(
+        "todo_add",
+        serde_json::from_str::<serde_json::Value>(tool_config_todo_add.as_str())?,
+        Box::new(move |arguments: json| {
+            let text = arguments["text"].as_str().unwrap_or_default();
+            let priority = arguments["priority"]
+                .as_str()
+                .and_then(|p| p.trim().chars().next());
+            let due = arguments["due"].as_str();
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, arguments.to_string())?;
+            }
+            let (id, item) = todo.add(text, priority, due, today())?;
+            Ok(format!("Added todo item {}: {}", id, item))
+        }),
+    );
+
+    let tool_config_todo_list = load_config("tool_todo_list.json");
+    let todo = TodoList::new(&todo_path);
+    engine.register_tool(
+        "todo_list",
+        serde_json::from_str::<serde_json::Value>(tool_config_todo_list.as_str())?,
+        Box::new(move |arguments: json| {
+            let filter = arguments["filter"].as_str().unwrap_or_default();
+            let include_done = arguments["include_done"].as_bool().unwrap_or(false);
+            todo.list(filter, include_done, today())
+        }),
+    );
+
+    let output_file = args.output_file.clone();
+    let tool_config_todo_complete = load_config("tool_todo_complete.json");
+    let todo = TodoList::new(&todo_path);
+    engine.register_tool(
+        "todo_complete",
+        serde_json::from_str::<serde_json::Value>(tool_config_todo_complete.as_str())?,
+        Box::new(move |arguments: json| {
+            let ids: Vec<usize> = arguments["ids"]
+                .as_array()
+                .map(|ids| {
+                    ids.iter()
+                        .filter_map(|id| id.as_u64())
+                        .map(|id| id as usize)
+                        .collect()
+                })
+                .unwrap_or_default();
+            let text = arguments["text"].as_str();
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, arguments.to_string())?;
+            }
+            todo.complete(&ids, text, today())
+        }),
+    );
+
+    let tool_config_todo_due = load_config("tool_todo_due.json");
+    let todo = TodoList::new(&todo_path);
+    engine.register_tool(
+        "todo_due",
+        serde_json::from_str::<serde_json::Value>(tool_config_todo_due.as_str())?,
+        Box::new(move |arguments: json| {
+            let days = arguments["days"].as_i64().unwrap_or(7);
+            todo.due(days, today())
+        }),
+    );
+
+    let memory_path = std::path::Path::new(&args.data_dir).join("memory.jsonl");
+
+    let output_file = args.output_file.clone();
+    let tool_config_remember = load_config("tool_remember.json");
+    let memory = Memory::new(&memory_path);
+    engine.register_tool(
+        "remember",
+        serde_json::from_str::<serde_json::Value>(tool_config_remember.as_str())?,
+        Box::new(move |arguments: json| {
+            let fact = arguments["fact"].as_str().unwrap_or_default();
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, fact)?;
+            }
+            let id = memory.remember(fact, &chrono::Local::now().date_naive().to_string())?;
+            Ok(format!("Remembered as fact {}", id))
+        }),
+    );
+
+    let output_file = args.output_file.clone();
+    let tool_config_forget = load_config("tool_forget.json");
+    let memory = Memory::new(&memory_path);
+    engine.register_tool(
+        "forget",
+        serde_json::from_str::<serde_json::Value>(tool_config_forget.as_str())?,
+        Box::new(move |arguments: json| {
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, arguments.to_string())?;
+            }
+            let id = arguments["id"]
+                .as_u64()
+                .ok_or_else(|| anyhow::anyhow!("Give the number of the fact to forget"))?;
+            let fact = memory.forget(id)?;
+            Ok(format!("Forgot fact {}: {}", id, fact.fact))
+        }),
+    );
+
+    let output_file = args.output_file.clone();
+    let tool_config_recall = load_config("tool_recall.json");
+    let memory = Memory::new(&memory_path);
+    engine.register_tool(
+        "recall",
+        serde_json::from_str::<serde_json::Value>(tool_config_recall.as_str())?,
+        Box::new(move |arguments: json| {
+            let query = arguments["query"].as_str().unwrap_or_default();
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, query)?;
+            }
+            let facts = memory.relevant(query, MEMORY_FACTS)?;
+            if facts.is_empty() {
+                return Ok("No remembered facts match".to_string());
+            }
+            Ok(facts
+                .iter()
+                .map(|fact| format!("{}. {}", fact.id, fact.fact))
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }),
+    );
+    let memory = Memory::new(&memory_path);
+
+    let calendar_path = args
+        .calendar_file
+        .clone()
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| std::path::Path::new(&args.data_dir).join("calendar.ics"));
+    let zone = local_zone(args.timezone.as_deref())?;
+
+    let tool_config_calendar_query = load_config("tool_calendar_query.json");
+    let calendar = Calendar::new(&calendar_path, zone);
+    engine.register_tool(
+        "calendar_query",
+        serde_json::from_str::<serde_json::Value>(tool_config_calendar_query.as_str())?,
+        Box::new(move |arguments: json| {
+            let today = calendar.today();
+            let first = match arguments["date"].as_str() {
+                Some("today") | Some("") | None => today,
+                Some(date) => parse_day(date, today)?,
+            };
+            let days = arguments["days"].as_u64().unwrap_or(1) as u32;
+            let search = arguments["search"].as_str().unwrap_or_default();
+            calendar.agenda(first, days, search)
+        }),
+    );
+
+    let output_file = args.output_file.clone();
+    let tool_config_calendar_add_event = load_config("tool_calendar_add_event.json");
+    let calendar = Calendar::new(&calendar_path, zone);
+    engine.register_tool(
+        "calendar_add_event",
+        serde_json::from_str::<serde_json::Value>(tool_config_calendar_add_event.as_str())?,
+        Box::new(move |arguments: json| {
+            let summary = arguments["summary"].as_str().unwrap_or_default();
+            let date = parse_day(
+                arguments["date"].as_str().unwrap_or("today"),
+                calendar.today(),
+            )?;
+            let time = arguments["time"]
+                .as_str()
+                .filter(|time| !time.trim().is_empty())
+                .map(parse_time)
+                .transpose()?;
+            let length = match time {
+                Some(_) => chrono::Duration::try_minutes(
+                    arguments["duration_minutes"].as_i64().unwrap_or(60),
+                ),
+                None => chrono::Duration::try_days(arguments["days"].as_i64().unwrap_or(1)),
+            }
+            .ok_or_else(|| anyhow!("The event is too long"))?;
+            let location = arguments["location"].as_str().unwrap_or_default();
+            if let Some(output_file) = &output_file {
+                std::fs::write(output_file, arguments.to_string())?;
+            }
+            let event = calendar.add_event(summary, date, time, length, location)?;
+            Ok(format!("Added {}", event))
+        }),
+    );
+
+    let messages_config = args
+        .messages_config
+        .clone()
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| std::path::Path::new(&args.data_dir).join("messages.json"));
+    let messenger = Arc::new(Messenger::load(
+        &messages_config,
+        std::path::Path::new(&args.data_dir).join("outbox"),
+    )?);
+    // Without configured recipients there is nobody to send to
+    if !messenger.recipients().is_empty() {
+        retry_outbox(Arc::clone(&messenger));
+        let output_file = args.output_file.clone();
+        let tool_config_send_message = load_config("tool_send_message.json");
+        let mut definition =
+            serde_json::from_str::<serde_json::Value>(tool_config_send_message.as_str())?;
+        definition["parameters"]["properties"]["to"]["enum"] = serde_json::json!(messenger.recipients());
+        engine.register_tool(
+            "send_message",
+            definition,
+            Box::new(move |arguments: json| {
+                let message = OutgoingMessage::new(
+                    arguments["to"].as_str().unwrap_or_default(),
+                    arguments["subject"].as_str().unwrap_or_default(),
+                    arguments["body"].as_str().unwrap_or_default(),
+                )?;
+                if let Some(output_file) = &output_file {
+                    std::fs::write(output_file, arguments.to_string())?;
+                }
+                let sent = messenger.send(&message)?;
+                Ok(format!("Message to {} {}", message.to, sent))
+            }),
+        );
+    }
+
+    let plugins_dir = args
+        .plugins_dir
+        .clone()
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| std::path::Path::new(&args.data_dir).join("plugins"));
+    // Plugins can't take the name of a built-in tool
+    let builtin_tools: Vec<String> = Asset::iter()
+        .filter(|file| file.starts_with("tool_") && file.ends_with(".json"))
+        .filter_map(|file| {
+            let definition = serde_json::from_str::<json>(&load_config(&file)).ok()?;
+            Some(definition["name"].as_str()?.to_string())
+        })
+        .collect();
+    for plugin in load_plugins(&plugins_dir, &builtin_tools) {
+        let name = plugin.name.clone();
+        let definition = plugin.definition.clone();
+        engine.register_tool(
+            &name,
+            definition,
+            Box::new(move |arguments: json| plugin.call(&arguments)),
+        );
+    }
+
+    let tool_config_calculate = load_config("tool_calculate.json");
+    engine.register_tool(
+        "calculate",
+        serde_json::from_str::<serde_json::Value>(tool_config_calculate.as_str())?,
+        Box::new(move |arguments: json| {
+            let expression = arguments["expression"].as_str().unwrap_or_default();
+            let decimals = arguments["decimals"].as_u64().map(|d| d as usize);
+            calculate(expression, decimals)
+        }),
+    );
+
+    // A prompt file with routes picks the prompt from a tag on each page
+    let prompt_file_json = serde_json::from_str::<serde_json::Value>(&load_config(&args.prompt))?;
+    let mut router = RouterConfig::from_prompt(&prompt_file_json)
+        .map_err(|e| anyhow!("Bad routes in {}: {}", args.prompt, e))?
+        .map(|config| {
+            let model = config.model.clone().unwrap_or(args.model.clone());
+            Router::new(config, create_engine(args, &model))
+        });
+
+    // What the last answer typed, to look up remembered facts with
+    let mut previous_answer = String::new();
+    let memory_model = args.memory_model.clone().unwrap_or(args.model.clone());
+    let mut page_reader = PageReader::new(create_engine(args, &memory_model));
+
     loop {
         if args.no_trigger {
             println!("Skipping waiting for trigger");
         } else {
-            println!("Waiting for trigger (hand-touch in the upper-right corner)...");
-            lock!(touch).wait_for_trigger()?;
+            println!("Waiting for trigger (hand-touch in the upper-right corner, hold to undo)...");
+            if lock!(touch).wait_for_trigger()? == Trigger::Undo {
+                journal.undo_last(&mut lock!(keyboard), &mut lock!(pen))?;
+                continue;
+            }
         }
 
         lock!(keyboard).progress()?;
 
-        let base64_image = if let Some(input_png) = &args.input_png {
-            BASE64_STANDARD.encode(std::fs::read(input_png)?)
+        let png_data = if let Some(input_png) = &args.input_png {
+            std::fs::read(input_png)?
         } else {
             let screenshot = Screenshot::new()?;
             if let Some(save_screenshot) = &args.save_screenshot {
                 screenshot.save_image(save_screenshot)?;
             }
-            screenshot.base64()?
+            screenshot.png_data().to_vec()
         };
+        let base64_image = BASE64_STANDARD.encode(&png_data);
+        *lock!(shown_size) = image::ImageReader::new(std::io::Cursor::new(&png_data))
+            .with_guessed_format()?
+            .into_dimensions()?;
+        let screen_image = load_screen(&png_data)?;
+        *lock!(grids) = None;
+        *lock!(screen) = screen_image;
         lock!(keyboard).progress()?;
 
         if args.no_submit {
@@ -299,14 +1330,73 @@ fn ghostwriter(args: &Args) -> Result<()> {
         };
         // println!("Segmentation description: {}", segmentation_description);
 
-        let prompt_general_raw = load_config(&args.prompt);
+        let (prompt_file, tag) = match router.as_mut() {
+            Some(router) => {
+                let route = router.route(&lock!(screen));
+                println!(
+                    "Routed to {} (tag: {})",
+                    route.prompt,
+                    route.tag.as_deref().unwrap_or("none")
+                );
+                (route.prompt, route.tag)
+            }
+            None => (args.prompt.clone(), None),
+        };
+
+        let prompt_general_raw = load_config(&prompt_file);
         let prompt_general_json =
             serde_json::from_str::<serde_json::Value>(prompt_general_raw.as_str())?;
-        let prompt = prompt_general_json["prompt"].as_str().unwrap();
+        let prompt = prompt_general_json["prompt"]
+            .as_str()
+            .ok_or_else(|| anyhow!("{} has no prompt", prompt_file))?;
+        // A prompt file can limit the tools the model is offered
+        let tools = match &prompt_general_json["tools"] {
+            serde_json::Value::Null => None,
+            tools => Some(
+                serde_json::from_value::<Vec<String>>(tools.clone())
+                    .map_err(|_| anyhow!("tools in {} should be a list of names", prompt_file))?,
+            ),
+        };
+        engine
+            .select_tools(tools)
+            .map_err(|e| anyhow!("{} in {}", e, prompt_file))?;
+
+        // The facts given with the page are the ones closest to the words on
+        // it, its tag and the last answer. Reading the words takes a model
+        // call, so it is only done when there are too many facts to send.
+        let page_words = if memory.load()?.len() > MEMORY_FACTS {
+            page_reader.read(&lock!(screen))
+        } else {
+            String::new()
+        };
+        let memory_query = [
+            page_words.as_str(),
+            tag.as_deref().unwrap_or_default(),
+            previous_answer.as_str(),
+        ]
+        .join("\n");
+        let facts = memory.for_request(&memory_query, MEMORY_FACTS)?;
 
         engine.clear_content();
         engine.add_text_content(prompt);
 
+        if let Some(tag) = tag {
+            engine.add_text_content(
+                format!("The user tagged this page #{} to pick these instructions. The tag is not part of the content, so don't respond to it.", tag).as_str()
+            );
+        }
+
+        if !facts.is_empty() {
+            let facts = facts
+                .iter()
+                .map(|fact| format!("{}. {}", fact.id, fact.fact))
+                .collect::<Vec<_>>()
+                .join("\n");
+            engine.add_text_content(
+                format!("Here are facts you were asked to remember in earlier sessions. Use them when they are relevant, and call recall to look up others about what's on the page. If one is wrong or outdated, call forget with its number.\n\n{}", facts).as_str()
+            );
+        }
+
         if args.apply_segmentation {
             engine.add_text_content(
                format!("Here are interesting regions based on an automatic segmentation algorithm. Use them to help identify the exact location of interesting features.\n\n{}", segmentation_description).as_str()
@@ -315,7 +1405,56 @@ fn ghostwriter(args: &Args) -> Result<()> {
 
         engine.add_image_content(&base64_image);
 
-        engine.execute()?;
+        lock!(pen).take_drawn();
+        lock!(pen).take_erased();
+        lock!(keyboard).take_typed();
+        lock!(keyboard).take_deleted();
+        cancel.arm();
+        let mut result = engine.execute();
+        let mut retries = args.preview_retries;
+        while retries > 0 {
+            let Err(rejected) = &result else { break };
+            let Some(rejected) = rejected.downcast_ref::<Rejected>() else {
+                break;
+            };
+            retries -= 1;
+            println!("{}, asking again", rejected);
+            lock!(keyboard).progress_end()?;
+            engine.add_text_content(
+                format!("You answered with {} at {}, but the user turned down a preview of it there. Answer again, placing your output somewhere else on the page.", rejected.tool, rejected.bounds).as_str()
+            );
+            result = engine.execute();
+        }
+        cancel.disarm();
+        // Journal whatever made it onto the page, even if the answer failed
+        // part way, so it can still be undone
+        let typed = lock!(keyboard).take_typed();
+        let output = Output {
+            strokes: lock!(pen).take_drawn(),
+            chars_typed: typed.chars().count(),
+        };
+        previous_answer = typed;
+        let (strokes_drawn, chars_typed) = (output.strokes.len(), output.chars_typed);
+        journal.record(output);
+        match result {
+            Err(e) if e.is::<Cancelled>() => {
+                let mut keyboard = lock!(keyboard);
+                keyboard.release_modifiers()?;
+                keyboard.progress_end()?;
+                println!(
+                    "Stopped by touch after drawing {} strokes and typing {} characters",
+                    strokes_drawn, chars_typed
+                );
+                if let Some(model_output_file) = &args.model_output_file {
+                    record_stopped_output(model_output_file, strokes_drawn, chars_typed)?;
+                }
+            }
+            Err(e) if e.is::<Rejected>() => {
+                lock!(keyboard).progress_end()?;
+                println!("{}", e);
+            }
+            result => result?,
+        }
 
         if args.no_loop {
             break Ok(());
@@ -0,0 +1,230 @@
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
+
+/// The paragraph styles the reMarkable text layer supports
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextStyle {
+    Title,
+    Subheading,
+    Body,
+    Bullet,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextBlock {
+    pub style: TextStyle,
+    pub text: String,
+}
+
+fn finish(current: &mut Option<TextBlock>, blocks: &mut Vec<TextBlock>) {
+    if let Some(mut block) = current.take() {
+        // Keep leading spaces, they are the indentation of nested list items
+        block.text = block.text.trim_end().to_string();
+        if !block.text.trim().is_empty() {
+            blocks.push(block);
+        }
+    }
+}
+
+/// Split Markdown into paragraphs tagged with the closest reMarkable style.
+/// Inline formatting is dropped since the keyboard can't express it.
+pub fn parse_blocks(markdown: &str) -> Vec<TextBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<TextBlock> = None;
+    // One entry per open list: the next number for ordered lists, None for bullets
+    let mut lists: Vec<Option<u64>> = Vec::new();
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                finish(&mut current, &mut blocks);
+                let style = if level == HeadingLevel::H1 {
+                    TextStyle::Title
+                } else {
+                    TextStyle::Subheading
+                };
+                current = Some(TextBlock {
+                    style,
+                    text: String::new(),
+                });
+            }
+            Event::Start(Tag::List(start)) => {
+                finish(&mut current, &mut blocks);
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                finish(&mut current, &mut blocks);
+                lists.pop();
+            }
+            Event::Start(Tag::Item) => {
+                finish(&mut current, &mut blocks);
+                let depth = lists.len().saturating_sub(1);
+                let indent = "  ".repeat(depth);
+                current = Some(match lists.last_mut() {
+                    // There is no numbered style, so numbered items are typed out
+                    Some(Some(number)) => {
+                        let text = format!("{}{}. ", indent, number);
+                        *number += 1;
+                        TextBlock {
+                            style: TextStyle::Body,
+                            text,
+                        }
+                    }
+                    _ => TextBlock {
+                        style: TextStyle::Bullet,
+                        text: indent,
+                    },
+                });
+            }
+            // Paragraphs inside a list item belong to the item
+            Event::Start(Tag::Paragraph) | Event::Start(Tag::CodeBlock(_)) if current.is_none() => {
+                current = Some(TextBlock {
+                    style: TextStyle::Body,
+                    text: String::new(),
+                });
+            }
+            Event::End(TagEnd::Item) => finish(&mut current, &mut blocks),
+            Event::End(TagEnd::Paragraph)
+            | Event::End(TagEnd::Heading(_))
+            | Event::End(TagEnd::CodeBlock)
+                if lists.is_empty() =>
+            {
+                finish(&mut current, &mut blocks)
+            }
+            // Later paragraphs of a list item carry on after a space
+            Event::End(TagEnd::Paragraph) | Event::End(TagEnd::CodeBlock) => {
+                if let Some(block) = current.as_mut() {
+                    block.text.push(' ');
+                }
+            }
+            Event::Text(text) | Event::Code(text) => {
+                current
+                    .get_or_insert_with(|| TextBlock {
+                        style: TextStyle::Body,
+                        text: String::new(),
+                    })
+                    .text
+                    .push_str(&text);
+            }
+            Event::SoftBreak => {
+                if let Some(block) = current.as_mut() {
+                    block.text.push(' ');
+                }
+            }
+            Event::HardBreak => {
+                if let Some(block) = current.as_mut() {
+                    block.text.push('\n');
+                }
+            }
+            _ => {}
+        }
+    }
+    finish(&mut current, &mut blocks);
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TextStyle::*;
+
+    fn styled(markdown: &str) -> Vec<(TextStyle, String)> {
+        parse_blocks(markdown)
+            .into_iter()
+            .map(|block| (block.style, block.text))
+            .collect()
+    }
+
+    fn expect(blocks: &[(TextStyle, &str)]) -> Vec<(TextStyle, String)> {
+        blocks
+            .iter()
+            .map(|(style, text)| (*style, text.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn headings_are_titles_and_subheadings() {
+        assert_eq!(
+            styled("# Trip plan\n## Day one\n### Morning\nSetext\n======\n"),
+            expect(&[
+                (Title, "Trip plan"),
+                (Subheading, "Day one"),
+                (Subheading, "Morning"),
+                (Title, "Setext"),
+            ])
+        );
+    }
+
+    #[test]
+    fn paragraphs_are_body_text() {
+        assert_eq!(
+            styled("First line\nwraps on.\n\nSecond  \nbroken here.\n\n> Quoted\n"),
+            expect(&[
+                (Body, "First line wraps on."),
+                (Body, "Second\nbroken here."),
+                (Body, "Quoted"),
+            ])
+        );
+    }
+
+    #[test]
+    fn bullets_and_nested_lists() {
+        assert_eq!(
+            styled("- Fruit\n  - Apples\n    - Green\n  - Pears\n- Bread\n* Other\n"),
+            expect(&[
+                (Bullet, "Fruit"),
+                (Bullet, "  Apples"),
+                (Bullet, "    Green"),
+                (Bullet, "  Pears"),
+                (Bullet, "Bread"),
+                (Bullet, "Other"),
+            ])
+        );
+    }
+
+    #[test]
+    fn numbered_lists_are_typed_out() {
+        assert_eq!(
+            styled("3. Third\n4. Fourth\n   - Detail\n   1. Sub-step\n   2. Next\n5. Fifth\n"),
+            expect(&[
+                (Body, "3. Third"),
+                (Body, "4. Fourth"),
+                (Bullet, "  Detail"),
+                (Body, "  1. Sub-step"),
+                (Body, "  2. Next"),
+                (Body, "5. Fifth"),
+            ])
+        );
+    }
+
+    #[test]
+    fn emphasis_is_dropped_but_its_text_kept() {
+        assert_eq!(
+            styled("Some *soft*, **bold** and ***both*** words, a [link](https://example.com).\n\n# A _stressed_ title\n- **Item**\n"),
+            expect(&[
+                (Body, "Some soft, bold and both words, a link."),
+                (Title, "A stressed title"),
+                (Bullet, "Item"),
+            ])
+        );
+    }
+
+    #[test]
+    fn code_is_kept_as_typed() {
+        assert_eq!(
+            styled("Run `cargo test` now.\n\n```rust\nlet x = 1;\nlet y = 2;\n```\n\n    indented code\n"),
+            expect(&[
+                (Body, "Run cargo test now."),
+                (Body, "let x = 1;\nlet y = 2;"),
+                (Body, "indented code"),
+            ])
+        );
+    }
+
+    #[test]
+    fn list_item_paragraphs_are_kept_apart() {
+        let blocks = parse_blocks("- First paragraph.\n\n  Second paragraph.\n- Next item\n");
+        let texts: Vec<&str> = blocks.iter().map(|block| block.text.as_str()).collect();
+        assert_eq!(texts, ["First paragraph. Second paragraph.", "Next item"]);
+        assert!(blocks.iter().all(|block| block.style == TextStyle::Bullet));
+    }
+}
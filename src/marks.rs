@@ -0,0 +1,147 @@
+use std::f32::consts::TAU;
+
+use crate::stroke_font::{StrokeFont, CAP_HEIGHT, UNITS_PER_EM};
+use crate::util::Polyline;
+
+// Marks fill this much of the smaller side of their cell
+const MARK_SCALE: f32 = 0.6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mark {
+    X,
+    O,
+    Check,
+    Dot,
+    Text(String),
+}
+
+impl Mark {
+    pub fn parse(kind: &str, text: Option<&str>) -> Option<Mark> {
+        match kind.to_lowercase().as_str() {
+            "x" => Some(Mark::X),
+            "o" => Some(Mark::O),
+            "check" => Some(Mark::Check),
+            "dot" => Some(Mark::Dot),
+            "text" => Some(Mark::Text(text?.to_string())),
+            _ => None,
+        }
+    }
+}
+
+fn circle((cx, cy): (f32, f32), radius: f32) -> Polyline {
+    // Overlap the start a little so the pen closes the loop
+    (0..=50)
+        .map(|i| {
+            let angle = i as f32 / 48.0 * TAU;
+            (cx + radius * angle.cos(), cy + radius * angle.sin())
+        })
+        .collect()
+}
+
+/// Strokes for a mark centered in a box of the given size
+pub fn mark_strokes(mark: &Mark, (cx, cy): (f32, f32), width: f32, height: f32) -> Vec<Polyline> {
+    let half = width.min(height) * MARK_SCALE / 2.0;
+    match mark {
+        Mark::X => vec![
+            vec![(cx - half, cy - half), (cx + half, cy + half)],
+            vec![(cx + half, cy - half), (cx - half, cy + half)],
+        ],
+        Mark::O => vec![circle((cx, cy), half)],
+        Mark::Check => vec![vec![
+            (cx - half * 0.8, cy),
+            (cx - half * 0.25, cy + half * 0.6),
+            (cx + half * 0.8, cy - half * 0.7),
+        ]],
+        Mark::Dot => {
+            // Spiral inwards to fill the dot
+            let radius = (half * 0.15).max(3.0);
+            let turns = (radius / 1.5).ceil();
+            let steps = (turns * 24.0) as usize;
+            vec![(0..=steps)
+                .map(|i| {
+                    let t = i as f32 / steps as f32;
+                    let angle = t * turns * TAU;
+                    let r = radius * (1.0 - t);
+                    (cx + r * angle.cos(), cy + r * angle.sin())
+                })
+                .collect()]
+        }
+        Mark::Text(text) => {
+            let font = StrokeFont::simplex();
+            let unit_width = font.text_width(text, 1.0);
+            let by_height = height * MARK_SCALE * UNITS_PER_EM / CAP_HEIGHT;
+            let by_width = if unit_width > 0.0 {
+                width * 0.8 / unit_width
+            } else {
+                by_height
+            };
+            let size = by_height.min(by_width);
+            let cap = CAP_HEIGHT * size / UNITS_PER_EM;
+            let origin = (cx - font.text_width(text, size) / 2.0, cy + cap / 2.0);
+            font.text_strokes(text, origin, size)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(strokes: &[Polyline]) -> (f32, f32, f32, f32) {
+        strokes.iter().flatten().fold(
+            (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+            |(x0, y0, x1, y1), &(x, y)| (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+        )
+    }
+
+    #[test]
+    fn marks_fit_the_smaller_side_of_the_cell() {
+        for mark in [Mark::X, Mark::O, Mark::Check] {
+            let (x0, y0, x1, y1) = extent(&mark_strokes(&mark, (200.0, 100.0), 200.0, 100.0));
+            assert!(x1 - x0 <= 60.5 && y1 - y0 <= 60.5, "{:?}", mark);
+            assert!(
+                ((x0 + x1) / 2.0 - 200.0).abs() < 5.0 && ((y0 + y1) / 2.0 - 100.0).abs() < 10.0
+            );
+        }
+        let x = mark_strokes(&Mark::X, (200.0, 100.0), 200.0, 100.0);
+        assert_eq!(
+            x,
+            vec![
+                vec![(170.0, 70.0), (230.0, 130.0)],
+                vec![(230.0, 70.0), (170.0, 130.0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn circles_close_their_loop() {
+        let o = &mark_strokes(&Mark::O, (50.0, 50.0), 100.0, 100.0)[0];
+        let (first, last) = (o[0], o[o.len() - 1]);
+        assert!(last.1 > first.1, "the end overlaps past the start");
+        assert!((first.0 - 80.0).abs() < 0.01 && (first.1 - 50.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn dots_spiral_to_the_center() {
+        let dot = &mark_strokes(&Mark::Dot, (50.0, 50.0), 100.0, 100.0)[0];
+        let last = dot[dot.len() - 1];
+        assert!((last.0 - 50.0).abs() < 0.01 && (last.1 - 50.0).abs() < 0.01);
+        assert!((dot[0].0 - 54.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn long_text_shrinks_to_the_cell_width() {
+        let strokes = mark_strokes(&Mark::Text("Wednesday".into()), (100.0, 50.0), 100.0, 100.0);
+        let (x0, _, x1, _) = extent(&strokes);
+        assert!(x1 - x0 <= 80.5);
+        assert!(((x0 + x1) / 2.0 - 100.0).abs() < 5.0);
+    }
+
+    #[test]
+    fn parses_marks() {
+        assert_eq!(Mark::parse("X", None), Some(Mark::X));
+        assert_eq!(Mark::parse("text", Some("7")), Some(Mark::Text("7".into())));
+        assert_eq!(Mark::parse("text", None), None);
+        assert_eq!(Mark::parse("star", None), None);
+    }
+}
@@ -0,0 +1,1207 @@
+use anyhow::{anyhow, bail, Result};
+
+use crate::stroke_font::{StrokeFont, UNITS_PER_EM};
+use crate::util::{strokes_to_path_data, Polyline};
+
+// Height of the math axis (the middle of '+', '=' and fraction bars) in font units
+const AXIS_HEIGHT: f32 = 9.0;
+
+// How far italic letters lean, as a fraction of their height
+const ITALIC_SLANT: f32 = 0.2;
+
+// Scripts, fraction parts and matrix cells shrink by this much each level
+const SCRIPT_SCALE: f32 = 0.7;
+const FRACTION_SCALE: f32 = 0.85;
+const MIN_SIZE: f32 = 10.0;
+
+// Groups, scripts and arguments nested deeper than this are an error rather
+// than a stack overflow
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Command(String),
+    Char(char),
+    Space,
+    Open,
+    Close,
+    Sup,
+    Sub,
+    Align,
+    NewRow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MatrixKind {
+    Matrix,
+    Cases,
+    Aligned,
+    Lines,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Row(Vec<Node>),
+    Symbol {
+        c: char,
+        italic: bool,
+    },
+    Text(String),
+    BinaryOp(char),
+    Relation(char),
+    Space(f32),
+    Frac(Box<Node>, Box<Node>),
+    Sqrt {
+        radicand: Box<Node>,
+        index: Option<Box<Node>>,
+    },
+    Scripts {
+        base: Box<Node>,
+        sup: Option<Box<Node>>,
+        sub: Option<Box<Node>>,
+    },
+    BigOp {
+        symbol: char,
+        limits: bool,
+    },
+    Function {
+        name: String,
+        limits: bool,
+    },
+    Delimited {
+        open: char,
+        body: Box<Node>,
+        close: char,
+    },
+    Matrix {
+        kind: MatrixKind,
+        rows: Vec<Vec<Node>>,
+        open: char,
+        close: char,
+    },
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.peek().copied() {
+                Some(next) if next.is_ascii_alphabetic() => {
+                    let mut name = String::new();
+                    while let Some(&next) = chars.peek() {
+                        if !next.is_ascii_alphabetic() {
+                            break;
+                        }
+                        name.push(next);
+                        chars.next();
+                    }
+                    tokens.push(Token::Command(name));
+                }
+                Some('\\') => {
+                    chars.next();
+                    tokens.push(Token::NewRow);
+                }
+                Some(next) => {
+                    chars.next();
+                    tokens.push(Token::Command(next.to_string()));
+                }
+                None => {}
+            },
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '^' => tokens.push(Token::Sup),
+            '_' => tokens.push(Token::Sub),
+            '&' => tokens.push(Token::Align),
+            c if c.is_whitespace() => {
+                if tokens.last() != Some(&Token::Space) {
+                    tokens.push(Token::Space);
+                }
+            }
+            c => tokens.push(Token::Char(c)),
+        }
+    }
+    tokens
+}
+
+fn greek(name: &str) -> Option<char> {
+    let c = match name {
+        "alpha" => 'α',
+        "beta" => 'β',
+        "gamma" => 'γ',
+        "delta" => 'δ',
+        "epsilon" | "varepsilon" => 'ε',
+        "theta" | "vartheta" => 'θ',
+        "lambda" => 'λ',
+        "mu" => 'μ',
+        "pi" => 'π',
+        "rho" => 'ρ',
+        "sigma" => 'σ',
+        "tau" => 'τ',
+        "phi" | "varphi" => 'φ',
+        "omega" => 'ω',
+        "Gamma" => 'Γ',
+        "Delta" => 'Δ',
+        "Theta" => 'Θ',
+        "Lambda" => 'Λ',
+        "Sigma" => 'Σ',
+        "Pi" => 'Π',
+        "Phi" => 'Φ',
+        "Omega" => 'Ω',
+        _ => return None,
+    };
+    Some(c)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(&Token::Space) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        self.skip_spaces();
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            other => bail!("Expected {:?} but found {:?}", expected, other),
+        }
+    }
+
+    fn at_row_end(&self, close_char: Option<char>) -> bool {
+        match self.peek() {
+            None | Some(Token::Close) | Some(Token::Align) | Some(Token::NewRow) => true,
+            Some(Token::Command(name)) => name == "right" || name == "end",
+            Some(Token::Char(c)) => Some(*c) == close_char,
+            _ => false,
+        }
+    }
+
+    fn parse_row(&mut self, close_char: Option<char>) -> Result<Node> {
+        let mut nodes = Vec::new();
+        loop {
+            self.skip_spaces();
+            if self.at_row_end(close_char) {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_scripts(atom)?);
+        }
+        Ok(Node::Row(nodes))
+    }
+
+    fn parse_scripts(&mut self, base: Node) -> Result<Node> {
+        let mut sup = None;
+        let mut sub = None;
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some(Token::Sup) => {
+                    self.next();
+                    sup = Some(Box::new(self.parse_atom()?));
+                }
+                Some(Token::Sub) => {
+                    self.next();
+                    sub = Some(Box::new(self.parse_atom()?));
+                }
+                Some(Token::Char('\'')) => {
+                    self.next();
+                    sup = Some(Box::new(Node::Symbol {
+                        c: '\'',
+                        italic: false,
+                    }));
+                }
+                _ => break,
+            }
+        }
+        if sup.is_none() && sub.is_none() {
+            Ok(base)
+        } else {
+            Ok(Node::Scripts {
+                base: Box::new(base),
+                sup,
+                sub,
+            })
+        }
+    }
+
+    // Raw text of a braced argument, keeping spaces (for \text and \begin)
+    fn parse_raw_group(&mut self) -> Result<String> {
+        self.expect(Token::Open)?;
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            match self.next() {
+                None => bail!("Unclosed text group"),
+                Some(Token::Open) => depth += 1,
+                Some(Token::Close) if depth == 0 => break,
+                Some(Token::Close) => depth -= 1,
+                Some(Token::Char(c)) => text.push(c),
+                Some(Token::Space) => text.push(' '),
+                Some(Token::Command(name)) => text.push_str(&name),
+                Some(Token::Sup) => text.push('^'),
+                Some(Token::Sub) => text.push('_'),
+                Some(Token::Align) => text.push('&'),
+                Some(Token::NewRow) => text.push(' '),
+            }
+        }
+        Ok(text)
+    }
+
+    fn parse_delimiter(&mut self) -> Result<char> {
+        self.skip_spaces();
+        match self.next() {
+            Some(Token::Char(c)) => Ok(c),
+            Some(Token::Command(name)) => match name.as_str() {
+                "{" | "lbrace" => Ok('{'),
+                "}" | "rbrace" => Ok('}'),
+                "|" | "Vert" => Ok('‖'),
+                "vert" => Ok('|'),
+                "langle" => Ok('<'),
+                "rangle" => Ok('>'),
+                _ => bail!("Unknown delimiter \\{}", name),
+            },
+            other => bail!("Expected a delimiter but found {:?}", other),
+        }
+    }
+
+    // Everything that nests goes through here, so this is where the depth
+    // is counted
+    fn parse_atom(&mut self) -> Result<Node> {
+        if self.depth == MAX_DEPTH {
+            bail!("Expression is nested more than {} levels deep", MAX_DEPTH);
+        }
+        self.depth += 1;
+        let atom = self.parse_nested_atom();
+        self.depth -= 1;
+        atom
+    }
+
+    fn parse_nested_atom(&mut self) -> Result<Node> {
+        self.skip_spaces();
+        let token = self
+            .next()
+            .ok_or_else(|| anyhow!("Unexpected end of expression"))?;
+        match token {
+            Token::Open => {
+                let row = self.parse_row(None)?;
+                self.expect(Token::Close)?;
+                Ok(row)
+            }
+            Token::Char(c) => Ok(match c {
+                c if c.is_ascii_alphabetic() => Node::Symbol { c, italic: true },
+                '+' => Node::BinaryOp('+'),
+                '-' => Node::BinaryOp('−'),
+                '*' => Node::BinaryOp('·'),
+                '/' => Node::Symbol {
+                    c: '/',
+                    italic: false,
+                },
+                '=' | '<' | '>' => Node::Relation(c),
+                '×' | '÷' | '±' | '·' | '−' => Node::BinaryOp(c),
+                '≤' | '≥' | '≠' | '≈' | '→' | '⇒' | '∈' => Node::Relation(c),
+                c => Node::Symbol { c, italic: false },
+            }),
+            Token::Command(name) => self.parse_command(&name),
+            other => bail!("Unexpected {:?}", other),
+        }
+    }
+
+    fn parse_command(&mut self, name: &str) -> Result<Node> {
+        if let Some(c) = greek(name) {
+            return Ok(Node::Symbol {
+                c,
+                italic: c.is_lowercase(),
+            });
+        }
+        let node = match name {
+            // Arguments may be a group or a single token, like \frac12
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.parse_atom()?;
+                let denominator = self.parse_atom()?;
+                Node::Frac(Box::new(numerator), Box::new(denominator))
+            }
+            "sqrt" => {
+                self.skip_spaces();
+                let index = if self.peek() == Some(&Token::Char('[')) {
+                    self.next();
+                    let index = self.parse_row(Some(']'))?;
+                    self.expect(Token::Char(']'))?;
+                    Some(Box::new(index))
+                } else {
+                    None
+                };
+                let radicand = self.parse_atom()?;
+                Node::Sqrt {
+                    radicand: Box::new(radicand),
+                    index,
+                }
+            }
+            "text" | "mathrm" | "textrm" | "operatorname" | "mbox" => {
+                Node::Text(self.parse_raw_group()?)
+            }
+            "mathbf" | "mathit" | "boldsymbol" | "mathbb" | "mathcal" => self.parse_atom()?,
+            "left" => {
+                let open = self.parse_delimiter()?;
+                let body = self.parse_row(None)?;
+                self.skip_spaces();
+                match self.next() {
+                    Some(Token::Command(name)) if name == "right" => {}
+                    other => bail!("Expected \\right but found {:?}", other),
+                }
+                let close = self.parse_delimiter()?;
+                Node::Delimited {
+                    open,
+                    body: Box::new(body),
+                    close,
+                }
+            }
+            "begin" => self.parse_environment()?,
+            "sum" => Node::BigOp {
+                symbol: '∑',
+                limits: true,
+            },
+            "prod" => Node::BigOp {
+                symbol: '∏',
+                limits: true,
+            },
+            "int" => Node::BigOp {
+                symbol: '∫',
+                limits: false,
+            },
+            "lim" | "max" | "min" | "sup" | "inf" => Node::Function {
+                name: name.to_string(),
+                limits: true,
+            },
+            "sin" | "cos" | "tan" | "cot" | "sec" | "csc" | "arcsin" | "arccos" | "arctan"
+            | "sinh" | "cosh" | "tanh" | "log" | "ln" | "exp" | "det" | "gcd" | "mod" => {
+                Node::Function {
+                    name: name.to_string(),
+                    limits: false,
+                }
+            }
+            "times" => Node::BinaryOp('×'),
+            "div" => Node::BinaryOp('÷'),
+            "pm" => Node::BinaryOp('±'),
+            "cdot" => Node::BinaryOp('·'),
+            "leq" | "le" => Node::Relation('≤'),
+            "geq" | "ge" => Node::Relation('≥'),
+            "neq" | "ne" => Node::Relation('≠'),
+            "approx" => Node::Relation('≈'),
+            "to" | "rightarrow" => Node::Relation('→'),
+            "leftarrow" => Node::Relation('←'),
+            "Rightarrow" | "implies" => Node::Relation('⇒'),
+            "in" => Node::Relation('∈'),
+            "infty" => Node::Symbol {
+                c: '∞',
+                italic: false,
+            },
+            "partial" => Node::Symbol {
+                c: '∂',
+                italic: false,
+            },
+            "ldots" | "dots" => Node::Symbol {
+                c: '…',
+                italic: false,
+            },
+            "cdots" => Node::Symbol {
+                c: '⋯',
+                italic: false,
+            },
+            "circ" | "degree" => Node::Symbol {
+                c: '°',
+                italic: false,
+            },
+            "," => Node::Space(3.0 / 18.0),
+            ":" | ">" => Node::Space(4.0 / 18.0),
+            ";" => Node::Space(5.0 / 18.0),
+            " " => Node::Space(0.25),
+            "!" => Node::Space(-3.0 / 18.0),
+            "quad" => Node::Space(1.0),
+            "qquad" => Node::Space(2.0),
+            "{" | "}" | "%" | "$" | "#" | "&" | "_" | "|" => Node::Symbol {
+                c: name.chars().next().unwrap_or('?'),
+                italic: false,
+            },
+            // Anything we do not know is shown by name rather than dropped
+            _ => Node::Text(name.to_string()),
+        };
+        Ok(node)
+    }
+
+    fn parse_environment(&mut self) -> Result<Node> {
+        let environment = self.parse_raw_group()?;
+        if environment == "array" {
+            // Skip the column specification, everything is centered
+            self.parse_raw_group()?;
+        }
+        let (kind, open, close) = match environment.as_str() {
+            "matrix" | "array" | "smallmatrix" => (MatrixKind::Matrix, '.', '.'),
+            "pmatrix" => (MatrixKind::Matrix, '(', ')'),
+            "bmatrix" => (MatrixKind::Matrix, '[', ']'),
+            "Bmatrix" => (MatrixKind::Matrix, '{', '}'),
+            "vmatrix" => (MatrixKind::Matrix, '|', '|'),
+            "Vmatrix" => (MatrixKind::Matrix, '‖', '‖'),
+            "cases" => (MatrixKind::Cases, '{', '.'),
+            "aligned" | "align" | "align*" | "split" | "gathered" => {
+                (MatrixKind::Aligned, '.', '.')
+            }
+            _ => bail!("Unsupported environment {}", environment),
+        };
+        let rows = self.parse_rows()?;
+        self.skip_spaces();
+        match self.next() {
+            Some(Token::Command(name)) if name == "end" => {}
+            other => bail!("Expected \\end{{{}}} but found {:?}", environment, other),
+        }
+        self.parse_raw_group()?;
+        Ok(Node::Matrix {
+            kind,
+            rows,
+            open,
+            close,
+        })
+    }
+
+    fn parse_rows(&mut self) -> Result<Vec<Vec<Node>>> {
+        let mut rows = Vec::new();
+        let mut row = Vec::new();
+        loop {
+            row.push(self.parse_row(None)?);
+            match self.peek() {
+                Some(Token::Align) => {
+                    self.next();
+                }
+                Some(Token::NewRow) => {
+                    self.next();
+                    rows.push(std::mem::take(&mut row));
+                }
+                _ => break,
+            }
+        }
+        // A trailing \\ leaves an empty last row behind
+        let trailing_empty =
+            row.len() == 1 && matches!(&row[0], Node::Row(nodes) if nodes.is_empty());
+        if !trailing_empty || rows.is_empty() {
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+}
+
+/// The expression without the $...$, $$...$$, \(...\) or \[...\] that
+/// models like to wrap it in
+fn strip_delimiters(source: &str) -> &str {
+    let mut source = source.trim();
+    loop {
+        let inner = [("$$", "$$"), ("$", "$"), ("\\[", "\\]"), ("\\(", "\\)")]
+            .iter()
+            .find_map(|(open, close)| {
+                source
+                    .strip_prefix(open)
+                    .and_then(|s| s.strip_suffix(close))
+            });
+        match inner {
+            Some(inner) => source = inner.trim(),
+            None => return source,
+        }
+    }
+}
+
+fn parse(source: &str) -> Result<Node> {
+    let mut parser = Parser {
+        tokens: tokenize(strip_delimiters(source)),
+        pos: 0,
+        depth: 0,
+    };
+    let rows = parser.parse_rows()?;
+    parser.skip_spaces();
+    if let Some(token) = parser.peek() {
+        bail!("Unexpected {:?} in expression", token);
+    }
+    // Several lines at the top level (a derivation) are stacked like an aligned block
+    if rows.len() == 1 && rows[0].len() == 1 {
+        Ok(rows.into_iter().next().unwrap().into_iter().next().unwrap())
+    } else {
+        let kind = if rows.iter().any(|row| row.len() > 1) {
+            MatrixKind::Aligned
+        } else {
+            MatrixKind::Lines
+        };
+        Ok(Node::Matrix {
+            kind,
+            rows,
+            open: '.',
+            close: '.',
+        })
+    }
+}
+
+/// A laid out piece of math. Strokes are relative to the left end of the
+/// baseline, with y growing downwards like screen coordinates.
+#[derive(Debug, Clone, Default)]
+pub struct MathBox {
+    pub width: f32,
+    pub ascent: f32,
+    pub descent: f32,
+    pub strokes: Vec<Polyline>,
+}
+
+impl MathBox {
+    fn with_width(width: f32) -> Self {
+        MathBox {
+            width,
+            ..Default::default()
+        }
+    }
+
+    fn place(&mut self, other: &MathBox, dx: f32, dy: f32) {
+        for stroke in &other.strokes {
+            self.strokes
+                .push(stroke.iter().map(|(x, y)| (x + dx, y + dy)).collect());
+        }
+        self.ascent = self.ascent.max(other.ascent - dy);
+        self.descent = self.descent.max(other.descent + dy);
+    }
+
+    pub fn height(&self) -> f32 {
+        self.ascent + self.descent
+    }
+}
+
+fn glyph_box(c: char, size: f32, italic: bool) -> MathBox {
+    let font = StrokeFont::simplex();
+    let glyph = font.glyph(c);
+    let scale = size / UNITS_PER_EM;
+    let slant = if italic { ITALIC_SLANT } else { 0.0 };
+    let mut result = MathBox::with_width(glyph.advance * scale);
+    for stroke in &glyph.strokes {
+        let stroke: Polyline = stroke
+            .iter()
+            .map(|(x, y)| ((x - y * slant) * scale, y * scale))
+            .collect();
+        for (_, y) in &stroke {
+            result.ascent = result.ascent.max(-y);
+            result.descent = result.descent.max(*y);
+        }
+        result.strokes.push(stroke);
+    }
+    // Leave room for the lean so the next symbol does not collide with it
+    result.width += result.ascent * slant * 0.5;
+    result
+}
+
+fn text_box(text: &str, size: f32) -> MathBox {
+    let mut result = MathBox::default();
+    for c in text.chars() {
+        let glyph = glyph_box(c, size, false);
+        let x = result.width;
+        result.place(&glyph, x, 0.0);
+        result.width += glyph.width;
+    }
+    result
+}
+
+fn hbox(boxes: Vec<MathBox>) -> MathBox {
+    let mut result = MathBox::default();
+    for b in boxes {
+        let x = result.width;
+        result.place(&b, x, 0.0);
+        result.width += b.width;
+    }
+    result
+}
+
+fn axis(size: f32) -> f32 {
+    AXIS_HEIGHT * size / UNITS_PER_EM
+}
+
+fn smaller(size: f32, factor: f32) -> f32 {
+    (size * factor).max(MIN_SIZE)
+}
+
+// Delimiters are drawn to whatever height the content needs rather than
+// taken from the font, so they always wrap the whole matrix or fraction.
+fn delimiter_box(delimiter: char, half_height: f32, size: f32) -> MathBox {
+    let w = match delimiter {
+        '.' => return MathBox::with_width(size * 0.05),
+        '|' => size * 0.2,
+        _ => size * 0.35,
+    };
+    let h = half_height;
+    let center = -axis(size);
+    let at = |x: f32, y: f32| (x * w, center + y * h);
+    let strokes: Vec<Polyline> = match delimiter {
+        '(' | ')' => {
+            let curve: Polyline = (0..=12)
+                .map(|i| {
+                    let t = i as f32 / 6.0 - 1.0;
+                    let x = 0.25 + 0.5 * t * t;
+                    let x = if delimiter == '(' { x } else { 1.0 - x };
+                    at(x, t)
+                })
+                .collect();
+            vec![curve]
+        }
+        '[' => vec![vec![
+            at(0.8, -1.0),
+            at(0.3, -1.0),
+            at(0.3, 1.0),
+            at(0.8, 1.0),
+        ]],
+        ']' => vec![vec![
+            at(0.2, -1.0),
+            at(0.7, -1.0),
+            at(0.7, 1.0),
+            at(0.2, 1.0),
+        ]],
+        '{' | '}' => {
+            let points = [
+                (0.85, -1.0),
+                (0.55, -0.92),
+                (0.5, -0.15),
+                (0.15, 0.0),
+                (0.5, 0.15),
+                (0.55, 0.92),
+                (0.85, 1.0),
+            ];
+            let flip = delimiter == '}';
+            vec![points
+                .iter()
+                .map(|&(x, y)| at(if flip { 1.0 - x } else { x }, y))
+                .collect()]
+        }
+        '<' => vec![vec![at(0.8, -1.0), at(0.2, 0.0), at(0.8, 1.0)]],
+        '>' => vec![vec![at(0.2, -1.0), at(0.8, 0.0), at(0.2, 1.0)]],
+        '‖' => vec![
+            vec![at(0.3, -1.0), at(0.3, 1.0)],
+            vec![at(0.7, -1.0), at(0.7, 1.0)],
+        ],
+        _ => vec![vec![at(0.5, -1.0), at(0.5, 1.0)]],
+    };
+    MathBox {
+        width: w,
+        ascent: h - center,
+        descent: h + center,
+        strokes,
+    }
+}
+
+fn delimited(open: char, body: MathBox, close: char, size: f32) -> MathBox {
+    let center = axis(size);
+    let half_height = (body.ascent - center).max(body.descent + center) + size * 0.1;
+    let half_height = half_height.max(size * 0.45);
+    hbox(vec![
+        delimiter_box(open, half_height, size),
+        body,
+        delimiter_box(close, half_height, size),
+    ])
+}
+
+fn is_limits_base(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::BigOp { limits: true, .. } | Node::Function { limits: true, .. }
+    )
+}
+
+fn layout(node: &Node, size: f32) -> MathBox {
+    match node {
+        Node::Row(nodes) => layout_row(nodes, size),
+        Node::Symbol { c, italic } => glyph_box(*c, size, *italic),
+        Node::Text(text) => text_box(text, size),
+        Node::BinaryOp(c) | Node::Relation(c) => glyph_box(*c, size, false),
+        Node::Space(em) => MathBox::with_width(em * size),
+        Node::Function { name, .. } => text_box(name, size),
+        Node::BigOp { symbol, limits } => {
+            // Integrals lean like handwritten ones, sums and products stand upright
+            let mut op = glyph_box(*symbol, size * 1.6, !*limits);
+            // Center large operators on the math axis
+            let shift = (op.ascent - op.descent) / 2.0 - axis(size);
+            let mut result = MathBox::with_width(op.width);
+            for stroke in op.strokes.iter_mut() {
+                for point in stroke.iter_mut() {
+                    point.1 += shift;
+                }
+            }
+            op.ascent -= shift;
+            op.descent += shift;
+            result.place(&op, 0.0, 0.0);
+            result
+        }
+        Node::Frac(numerator, denominator) => {
+            let small = smaller(size, FRACTION_SCALE);
+            let num = layout(numerator, small);
+            let den = layout(denominator, small);
+            let pad = size * 0.12;
+            let gap = size * 0.15;
+            let width = num.width.max(den.width) + pad * 2.0;
+            let bar_y = -axis(size);
+            let mut result = MathBox::with_width(width);
+            result
+                .strokes
+                .push(vec![(pad * 0.5, bar_y), (width - pad * 0.5, bar_y)]);
+            result.place(&num, (width - num.width) / 2.0, bar_y - gap - num.descent);
+            result.place(&den, (width - den.width) / 2.0, bar_y + gap + den.ascent);
+            result
+        }
+        Node::Sqrt { radicand, index } => {
+            let body = layout(radicand, size);
+            let gap = size * 0.12;
+            let top = body.ascent.max(size * 0.5) + gap;
+            let bottom = body.descent.max(size * 0.05);
+            let sign_width = size * 0.45;
+            let index_box = index
+                .as_ref()
+                .map(|index| layout(index, smaller(size, SCRIPT_SCALE * 0.8)));
+            let offset = index_box
+                .as_ref()
+                .map(|b| (b.width - sign_width * 0.4).max(0.0))
+                .unwrap_or(0.0);
+            let end = offset + sign_width + body.width + gap;
+            let mut result = MathBox::with_width(end + gap);
+            result.strokes.push(vec![
+                (offset, -top * 0.35),
+                (offset + sign_width * 0.25, -top * 0.45),
+                (offset + sign_width * 0.55, bottom),
+                (offset + sign_width, -top),
+                (end, -top),
+            ]);
+            result.ascent = top;
+            result.descent = bottom;
+            result.place(&body, offset + sign_width + gap * 0.5, 0.0);
+            if let Some(index_box) = index_box {
+                result.place(&index_box, 0.0, -top * 0.5 - index_box.descent);
+            }
+            result
+        }
+        Node::Scripts { base, sup, sub } => {
+            layout_scripts(base, sup.as_deref(), sub.as_deref(), size)
+        }
+        Node::Delimited { open, body, close } => delimited(*open, layout(body, size), *close, size),
+        Node::Matrix {
+            kind,
+            rows,
+            open,
+            close,
+        } => {
+            let body = layout_matrix(*kind, rows, size);
+            if *open == '.' && *close == '.' {
+                body
+            } else {
+                delimited(*open, body, *close, size)
+            }
+        }
+    }
+}
+
+fn layout_row(nodes: &[Node], size: f32) -> MathBox {
+    let mut boxes = Vec::new();
+    for (i, node) in nodes.iter().enumerate() {
+        let operator = match node {
+            Node::Scripts { base, .. } => base.as_ref(),
+            node => node,
+        };
+        let (before, after) = match operator {
+            // A leading minus or plus is a sign, not an operator
+            Node::BinaryOp(_) if i == 0 => (0.0, 0.0),
+            Node::BinaryOp(_) => (4.0, 4.0),
+            Node::Relation(_) => (5.0, 5.0),
+            Node::Function { .. } | Node::BigOp { .. } if i > 0 => (3.0, 3.0),
+            Node::Function { .. } | Node::BigOp { .. } => (0.0, 3.0),
+            _ => (0.0, 0.0),
+        };
+        boxes.push(MathBox::with_width(size * before / 18.0));
+        boxes.push(layout(node, size));
+        boxes.push(MathBox::with_width(size * after / 18.0));
+    }
+    hbox(boxes)
+}
+
+fn layout_scripts(base: &Node, sup: Option<&Node>, sub: Option<&Node>, size: f32) -> MathBox {
+    let base_box = layout(base, size);
+    let small = smaller(size, SCRIPT_SCALE);
+    let sup_box = sup.map(|sup| layout(sup, small));
+    let sub_box = sub.map(|sub| layout(sub, small));
+
+    if is_limits_base(base) {
+        let width = [
+            Some(base_box.width),
+            sup_box.as_ref().map(|b| b.width),
+            sub_box.as_ref().map(|b| b.width),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(0.0, f32::max);
+        let gap = size * 0.1;
+        let mut result = MathBox::with_width(width);
+        result.place(&base_box, (width - base_box.width) / 2.0, 0.0);
+        if let Some(sup_box) = &sup_box {
+            let y = -base_box.ascent - gap - sup_box.descent;
+            result.place(sup_box, (width - sup_box.width) / 2.0, y);
+        }
+        if let Some(sub_box) = &sub_box {
+            let y = base_box.descent + gap + sub_box.ascent;
+            result.place(sub_box, (width - sub_box.width) / 2.0, y);
+        }
+        return result;
+    }
+
+    let kern = size * 0.05;
+    let mut result = MathBox::with_width(base_box.width);
+    result.place(&base_box, 0.0, 0.0);
+    let mut script_width: f32 = 0.0;
+    if let Some(sup_box) = &sup_box {
+        let shift = (size * 0.38).max(base_box.ascent - size * 0.25);
+        result.place(sup_box, base_box.width + kern, -shift);
+        script_width = script_width.max(sup_box.width);
+    }
+    if let Some(sub_box) = &sub_box {
+        let mut shift = (size * 0.18).max(base_box.descent * 0.8);
+        if sup_box.is_some() {
+            shift = shift.max(size * 0.25);
+        }
+        result.place(sub_box, base_box.width + kern * 0.5, shift);
+        script_width = script_width.max(sub_box.width);
+    }
+    result.width += kern + script_width;
+    result
+}
+
+fn layout_matrix(kind: MatrixKind, rows: &[Vec<Node>], size: f32) -> MathBox {
+    let cell_size = if kind == MatrixKind::Matrix {
+        smaller(size, 0.9)
+    } else {
+        size
+    };
+    let cells: Vec<Vec<MathBox>> = rows
+        .iter()
+        .map(|row| row.iter().map(|cell| layout(cell, cell_size)).collect())
+        .collect();
+    let columns = cells.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut column_widths = vec![0.0f32; columns];
+    for row in &cells {
+        for (i, cell) in row.iter().enumerate() {
+            column_widths[i] = column_widths[i].max(cell.width);
+        }
+    }
+    let row_gap = match kind {
+        MatrixKind::Matrix => size * 0.3,
+        _ => size * 0.45,
+    };
+    let column_gap = |i: usize| match kind {
+        // aligned pairs columns as right-aligned | left-aligned with no gap
+        MatrixKind::Aligned if i % 2 == 1 => 0.0,
+        MatrixKind::Aligned => size * 1.0,
+        _ => size * 0.8,
+    };
+
+    let row_heights: Vec<(f32, f32)> = cells
+        .iter()
+        .map(|row| {
+            row.iter().fold((size * 0.5, size * 0.1), |(a, d), cell| {
+                (a.max(cell.ascent), d.max(cell.descent))
+            })
+        })
+        .collect();
+    let total_height: f32 = row_heights.iter().map(|(a, d)| a + d).sum::<f32>()
+        + row_gap * (cells.len().saturating_sub(1)) as f32;
+
+    // Matrices are centered on the math axis, plain stacks of lines hang
+    // down from the first baseline
+    let mut y = match kind {
+        MatrixKind::Lines | MatrixKind::Aligned => -row_heights.first().map(|r| r.0).unwrap_or(0.0),
+        _ => -axis(size) - total_height / 2.0,
+    };
+    let mut result = MathBox::default();
+    for (row, (ascent, descent)) in cells.iter().zip(row_heights.iter()) {
+        let baseline = y + ascent;
+        let mut x = 0.0;
+        for (i, cell) in row.iter().enumerate() {
+            if i > 0 {
+                x += column_gap(i);
+            }
+            let cell_x = match kind {
+                MatrixKind::Cases | MatrixKind::Lines => x,
+                MatrixKind::Aligned if i % 2 == 1 => x,
+                MatrixKind::Aligned => x + column_widths[i] - cell.width,
+                MatrixKind::Matrix => x + (column_widths[i] - cell.width) / 2.0,
+            };
+            result.place(cell, cell_x, baseline);
+            x += column_widths[i];
+        }
+        result.width = result.width.max(x);
+        y = baseline + descent + row_gap;
+    }
+    result
+}
+
+/// Typeset a LaTeX math expression into strokes at the given font size
+pub fn typeset(latex: &str, size: f32) -> Result<MathBox> {
+    let node = parse(latex)?;
+    Ok(layout(&node, size))
+}
+
+/// The LaTeX source written out as it is, on one line, for when it can't
+/// be typeset; "\\frac{1}{2}" is still more use to the reader than "frac12"
+pub fn plain_text(latex: &str, size: f32) -> MathBox {
+    let source = strip_delimiters(latex)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    text_box(&source, size)
+}
+
+/// A full-screen SVG of math already laid out, with its top-left at (x, y)
+pub fn math_to_svg(
+    math: &MathBox,
+    (x, y): (f32, f32),
+    size: f32,
+    (width, height): (u32, u32),
+) -> String {
+    let strokes: Vec<Polyline> = math
+        .strokes
+        .iter()
+        .map(|stroke| {
+            stroke
+                .iter()
+                .map(|(sx, sy)| (sx + x, sy + y + math.ascent))
+                .collect()
+        })
+        .collect();
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}"><path d="{}" fill="none" stroke="black" stroke-width="{:.1}" stroke-linecap="round" stroke-linejoin="round"/></svg>"#,
+        width,
+        height,
+        strokes_to_path_data(&strokes),
+        (size / 16.0).max(1.5)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: f32 = 32.0;
+
+    // The one node of a single-line expression
+    fn single(latex: &str) -> Node {
+        match parse(latex).unwrap() {
+            Node::Row(mut nodes) if nodes.len() == 1 => nodes.remove(0),
+            node => node,
+        }
+    }
+
+    fn lowest(math: &MathBox) -> f32 {
+        math.strokes
+            .iter()
+            .flatten()
+            .map(|p| p.1)
+            .fold(f32::MIN, f32::max)
+    }
+
+    fn highest(math: &MathBox) -> f32 {
+        math.strokes
+            .iter()
+            .flatten()
+            .map(|p| p.1)
+            .fold(f32::MAX, f32::min)
+    }
+
+    #[test]
+    fn parses_fractions() {
+        assert!(matches!(single("\\frac{a+b}{2}"), Node::Frac(..)));
+        // Single token arguments need no braces
+        let Node::Frac(numerator, denominator) = single("\\frac12") else {
+            panic!("not a fraction");
+        };
+        assert!(matches!(*numerator, Node::Symbol { c: '1', .. }));
+        assert!(matches!(*denominator, Node::Symbol { c: '2', .. }));
+    }
+
+    #[test]
+    fn fractions_stack_around_the_bar() {
+        let math = typeset("\\frac{1}{2}", SIZE).unwrap();
+        let bar = &math.strokes[0];
+        assert_eq!(bar.len(), 2);
+        assert_eq!(bar[0].1, -axis(SIZE));
+        let one = glyph_box('1', smaller(SIZE, FRACTION_SCALE), false);
+        assert!(math.width >= one.width);
+        assert!(highest(&math) < bar[0].1 && lowest(&math) > bar[0].1);
+        assert!(math.ascent > one.ascent && math.descent > 0.0);
+    }
+
+    #[test]
+    fn scripts_go_above_and_below() {
+        let Node::Scripts { base, sup, sub } = single("x_i^2") else {
+            panic!("no scripts");
+        };
+        assert!(matches!(
+            *base,
+            Node::Symbol {
+                c: 'x',
+                italic: true
+            }
+        ));
+        assert!(sup.is_some() && sub.is_some());
+
+        let x = typeset("x", SIZE).unwrap();
+        let squared = typeset("x^2", SIZE).unwrap();
+        let indexed = typeset("x_i", SIZE).unwrap();
+        assert!(squared.width > x.width && indexed.width > x.width);
+        assert!(squared.ascent > x.ascent);
+        assert!(indexed.descent > x.descent);
+        // Scripts are smaller than the base
+        assert!(squared.width - x.width < x.width * 1.5);
+    }
+
+    #[test]
+    fn sums_put_limits_above_and_below() {
+        let math = typeset("\\sum_{i=1}^{n}", SIZE).unwrap();
+        let sum = typeset("\\sum", SIZE).unwrap();
+        assert!(math.ascent > sum.ascent && math.descent > sum.descent);
+        assert!((math.width - sum.width).abs() < SIZE);
+    }
+
+    #[test]
+    fn roots_cover_their_radicand() {
+        let Node::Sqrt { index, .. } = single("\\sqrt[3]{x}") else {
+            panic!("not a root");
+        };
+        assert!(index.is_some());
+        let root = typeset("\\sqrt{x}", SIZE).unwrap();
+        let cube_root = typeset("\\sqrt[3]{x}", SIZE).unwrap();
+        let x = typeset("x", SIZE).unwrap();
+        assert!(root.width > x.width);
+        assert!(cube_root.width >= root.width);
+        // The bar runs over the top of the x
+        let sign = &root.strokes[0];
+        assert!(sign.last().unwrap().1 < -x.ascent);
+    }
+
+    #[test]
+    fn matrices_have_rows_and_columns() {
+        let Node::Matrix {
+            kind,
+            rows,
+            open,
+            close,
+        } = single("\\begin{pmatrix} a & b \\\\ c & d \\\\ \\end{pmatrix}")
+        else {
+            panic!("not a matrix");
+        };
+        assert_eq!(kind, MatrixKind::Matrix);
+        // The trailing \\ doesn't add an empty row
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|row| row.len() == 2));
+        assert_eq!((open, close), ('(', ')'));
+
+        let math = typeset("\\begin{bmatrix} 1 & 0 \\\\ 0 & 1 \\end{bmatrix}", SIZE).unwrap();
+        let row = typeset("\\begin{bmatrix} 1 & 0 \\end{bmatrix}", SIZE).unwrap();
+        assert!(math.height() > row.height() * 1.5);
+        // Centered on the math axis
+        let middle = (highest(&math) + lowest(&math)) / 2.0;
+        assert!((middle + axis(SIZE)).abs() < 1.0);
+    }
+
+    #[test]
+    fn derivations_stack_lines() {
+        let math = typeset("x &= 1 + 1 \\\\ &= 2", SIZE).unwrap();
+        assert!(matches!(
+            parse("x &= 1 + 1 \\\\ &= 2").unwrap(),
+            Node::Matrix {
+                kind: MatrixKind::Aligned,
+                ..
+            }
+        ));
+        assert!(math.height() > SIZE * 1.5);
+    }
+
+    #[test]
+    fn unknown_commands_are_shown_by_name() {
+        let Node::Row(nodes) = parse("\\foo x").unwrap() else {
+            panic!("not a row");
+        };
+        assert!(matches!(&nodes[0], Node::Text(name) if name == "foo"));
+        assert!(typeset("\\foo", SIZE).unwrap().width > 0.0);
+    }
+
+    #[test]
+    fn malformed_latex_is_an_error() {
+        assert!(parse("\\frac{1}{").is_err());
+        assert!(parse("x^").is_err());
+        assert!(parse("\\left( x").is_err());
+        assert!(parse("\\begin{tikzpicture} x \\end{tikzpicture}").is_err());
+        assert!(parse("x }").is_err());
+    }
+
+    #[test]
+    fn plain_text_fallback_keeps_the_source() {
+        for latex in [
+            "\\frac{1}{",
+            "x }",
+            "\\begin{tikzpicture} x \\end{tikzpicture}",
+            "\\left( a",
+            "x^",
+        ] {
+            assert!(typeset(latex, SIZE).is_err(), "{}", latex);
+            assert!(plain_text(latex, SIZE).width > 0.0, "{}", latex);
+        }
+        // Written out character by character, backslash and braces included
+        let source = "\\frac{1}{";
+        assert_eq!(
+            plain_text(&format!("$ {} $", source), SIZE).width,
+            text_box(source, SIZE).width
+        );
+        assert_eq!(
+            plain_text("a\n  b", SIZE).width,
+            text_box("a b", SIZE).width
+        );
+    }
+
+    #[test]
+    fn delimiters_are_stripped() {
+        let plain = typeset("x^2", SIZE).unwrap().width;
+        for latex in [
+            "$x^2$",
+            "$$x^2$$",
+            "\\[x^2\\]",
+            "\\( x^2 \\)",
+            " $ \\[ x^2 \\] $ ",
+        ] {
+            assert_eq!(typeset(latex, SIZE).unwrap().width, plain, "{}", latex);
+        }
+        // A dollar sign inside the expression is left alone
+        assert!(typeset("\\$5", SIZE).is_ok());
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        let nested = |depth: usize| format!("{}x{}", "{".repeat(depth), "}".repeat(depth));
+        assert!(parse(&nested(MAX_DEPTH - 1)).is_ok());
+        for latex in [
+            nested(100_000),
+            "\\frac".repeat(100_000),
+            "x^".repeat(100_000),
+            "\\sqrt{".repeat(100_000),
+        ] {
+            assert!(parse(&latex).is_err());
+        }
+    }
+}
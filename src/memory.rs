@@ -0,0 +1,357 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// BM25 parameters, the usual defaults
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// Most remembered facts to include with a request, or to give back from one recall
+pub const MEMORY_FACTS: usize = 20;
+
+// Words too common to say anything about which facts are relevant
+const STOP_WORDS: &[&str] = &[
+    "a", "about", "an", "and", "are", "as", "at", "be", "by", "for", "from", "has", "have", "i",
+    "in", "is", "it", "its", "me", "my", "of", "on", "or", "that", "the", "their", "this", "to",
+    "was", "were", "will", "with", "you", "your",
+];
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Fact {
+    pub id: u64,
+    pub fact: String,
+    pub created: String,
+}
+
+/// Facts the assistant has been asked to remember, one JSON object per line
+pub struct Memory {
+    path: PathBuf,
+}
+
+// A line of the memory file, kept as written when it isn't a fact so that
+// saving never loses what someone typed into the file by hand
+enum Line {
+    Fact(Fact),
+    Other(String),
+}
+
+/// Lowercase words without stop words, with a plural "s" dropped so that
+/// "peanut" finds "peanuts"
+fn tokenize(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !STOP_WORDS.contains(word))
+        .map(|word| match word.strip_suffix('s') {
+            Some(stem) if stem.len() > 2 && !stem.ends_with('s') => stem.to_string(),
+            _ => word.to_string(),
+        })
+        .collect()
+}
+
+impl Memory {
+    pub fn new(path: impl AsRef<Path>) -> Memory {
+        Memory {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Lines that aren't a fact, say from editing the file by hand, are
+    /// skipped so the rest can still be used
+    pub fn load(&self) -> Result<Vec<Fact>> {
+        Ok(self
+            .read_lines()?
+            .into_iter()
+            .filter_map(|line| match line {
+                Line::Fact(fact) => Some(fact),
+                Line::Other(_) => None,
+            })
+            .collect())
+    }
+
+    fn read_lines(&self) -> Result<Vec<Line>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        Ok(fs::read_to_string(&self.path)?
+            .lines()
+            .map(|line| {
+                if line.trim().is_empty() {
+                    return Line::Other(line.to_string());
+                }
+                match serde_json::from_str(line) {
+                    Ok(fact) => Line::Fact(fact),
+                    Err(e) => {
+                        println!("Skipping bad line in {}: {}", self.path.display(), e);
+                        Line::Other(line.to_string())
+                    }
+                }
+            })
+            .collect())
+    }
+
+    /// Write the facts back, with the lines that weren't facts unchanged
+    fn save(&self, lines: &[Line]) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut content = String::new();
+        for line in lines {
+            match line {
+                Line::Fact(fact) => content.push_str(&serde_json::to_string(fact)?),
+                Line::Other(text) => content.push_str(text),
+            }
+            content.push('\n');
+        }
+        fs::write(&self.path, content)?;
+        Ok(())
+    }
+
+    /// Store a fact and return its id; remembering the same fact twice
+    /// returns the id it already has
+    pub fn remember(&self, fact: &str, created: &str) -> Result<u64> {
+        let fact = fact.trim();
+        if fact.is_empty() {
+            bail!("There is no fact to remember");
+        }
+        let mut lines = self.read_lines()?;
+        let facts = lines.iter().filter_map(|line| match line {
+            Line::Fact(fact) => Some(fact),
+            Line::Other(_) => None,
+        });
+        if let Some(existing) = facts
+            .clone()
+            .find(|existing| existing.fact.eq_ignore_ascii_case(fact))
+        {
+            return Ok(existing.id);
+        }
+        let id = facts.map(|fact| fact.id).max().unwrap_or(0) + 1;
+        lines.push(Line::Fact(Fact {
+            id,
+            fact: fact.to_string(),
+            created: created.to_string(),
+        }));
+        self.save(&lines)?;
+        Ok(id)
+    }
+
+    pub fn forget(&self, id: u64) -> Result<Fact> {
+        let mut lines = self.read_lines()?;
+        let Some(index) = lines
+            .iter()
+            .position(|line| matches!(line, Line::Fact(fact) if fact.id == id))
+        else {
+            bail!("There is no remembered fact number {}", id);
+        };
+        let Line::Fact(fact) = lines.remove(index) else {
+            unreachable!("the line was found as a fact");
+        };
+        self.save(&lines)?;
+        Ok(fact)
+    }
+
+    /// Up to `limit` facts that share a word with `query`, best match
+    /// first by BM25, newer facts first on a tie
+    pub fn relevant(&self, query: &str, limit: usize) -> Result<Vec<Fact>> {
+        let facts = self.load()?;
+        if facts.is_empty() {
+            return Ok(facts);
+        }
+
+        let documents: Vec<Vec<String>> = facts.iter().map(|fact| tokenize(&fact.fact)).collect();
+        let average_length = documents.iter().map(|words| words.len()).sum::<usize>() as f32
+            / documents.len() as f32;
+        let mut document_frequency: HashMap<&str, usize> = HashMap::new();
+        for words in &documents {
+            for word in words.iter().collect::<HashSet<_>>() {
+                *document_frequency.entry(word).or_default() += 1;
+            }
+        }
+        let query: HashSet<String> = tokenize(query).into_iter().collect();
+
+        let count = documents.len() as f32;
+        let mut scored: Vec<(f32, usize)> = documents
+            .iter()
+            .enumerate()
+            .map(|(i, words)| {
+                let length = words.len() as f32;
+                let score = query
+                    .iter()
+                    .filter_map(|term| {
+                        let frequency = words.iter().filter(|word| *word == term).count() as f32;
+                        let containing = *document_frequency.get(term.as_str())? as f32;
+                        let idf = ((count - containing + 0.5) / (containing + 0.5) + 1.0).ln();
+                        Some(
+                            idf * frequency * (K1 + 1.0)
+                                / (frequency + K1 * (1.0 - B + B * length / average_length)),
+                        )
+                    })
+                    .sum();
+                (score, i)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.cmp(&a.1)));
+
+        Ok(scored
+            .iter()
+            .filter(|(score, _)| *score > 0.0)
+            .take(limit)
+            .map(|(_, i)| facts[*i].clone())
+            .collect())
+    }
+
+    /// Facts to give the model with a request: all of them when there are
+    /// no more than `limit`, otherwise the `limit` most relevant to the
+    /// words on the page, or the newest ones when none match
+    pub fn for_request(&self, query: &str, limit: usize) -> Result<Vec<Fact>> {
+        let mut facts = self.load()?;
+        if facts.len() > limit {
+            let relevant = self.relevant(query, limit)?;
+            if !relevant.is_empty() {
+                return Ok(relevant);
+            }
+        }
+        facts.drain(..facts.len().saturating_sub(limit));
+        facts.reverse();
+        Ok(facts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn bad_lines_are_skipped() {
+        let dir = ScratchDir::new("memory-test");
+        let path = dir.join("memory.jsonl");
+        fs::write(
+            &path,
+            "{\"id\":1,\"fact\":\"Allergic to peanuts\",\"created\":\"2024-10-21\"}\nnot json\n",
+        )
+        .unwrap();
+        let memory = Memory::new(&path);
+        let facts = memory.relevant("peanut butter", 5).unwrap();
+        let id = memory.remember("Likes tea", "2024-10-22").unwrap();
+        assert_eq!(facts.len(), 1);
+        assert_eq!(id, 2);
+    }
+
+    #[test]
+    fn lines_that_are_not_facts_survive_saving() {
+        let dir = ScratchDir::new("memory-keep");
+        let path = dir.join("memory.jsonl");
+        fs::write(
+            &path,
+            "# Written by hand\n{\"id\":1,\"fact\":\"Allergic to peanuts\",\"created\":\"2024-10-21\"}\n\nnot json\n",
+        )
+        .unwrap();
+        let memory = Memory::new(&path);
+        let id = memory.remember("Likes tea", "2024-10-22").unwrap();
+        let after_remember = fs::read_to_string(&path).unwrap();
+        memory.forget(1).unwrap();
+        let after_forget = fs::read_to_string(&path).unwrap();
+        assert_eq!(id, 2);
+        assert_eq!(
+            after_remember,
+            "# Written by hand\n{\"id\":1,\"fact\":\"Allergic to peanuts\",\"created\":\"2024-10-21\"}\n\nnot json\n{\"id\":2,\"fact\":\"Likes tea\",\"created\":\"2024-10-22\"}\n"
+        );
+        assert_eq!(
+            after_forget,
+            "# Written by hand\n\nnot json\n{\"id\":2,\"fact\":\"Likes tea\",\"created\":\"2024-10-22\"}\n"
+        );
+    }
+
+    #[test]
+    fn matching_older_facts_beat_newer_ones() {
+        let dir = ScratchDir::new("memory-rank");
+        let path = dir.join("memory.jsonl");
+        let memory = Memory::new(&path);
+        memory
+            .remember("The user is allergic to peanuts", "2024-01-02")
+            .unwrap();
+        memory
+            .remember("The user's sister Anna lives in Berlin", "2024-01-03")
+            .unwrap();
+        for i in 0..30 {
+            memory
+                .remember(
+                    &format!("The user's meeting number {} went well", i),
+                    "2024-02-01",
+                )
+                .unwrap();
+        }
+        let facts = memory
+            .relevant("shopping list peanut butter bread milk", 5)
+            .unwrap();
+        let sister = memory.relevant("birthday card for my sister", 5).unwrap();
+        let nothing = memory.relevant("", 5).unwrap();
+        assert_eq!(facts.len(), 1);
+        assert_eq!(facts[0].id, 1);
+        assert_eq!(sister[0].id, 2);
+        assert!(nothing.is_empty());
+    }
+
+    #[test]
+    fn large_stores_still_give_facts_with_a_request() {
+        let dir = ScratchDir::new("memory-request");
+        let path = dir.join("memory.jsonl");
+        let memory = Memory::new(&path);
+        memory
+            .remember("The user is allergic to peanuts", "2024-01-02")
+            .unwrap();
+        for i in 0..MEMORY_FACTS * 2 {
+            memory
+                .remember(
+                    &format!("The user's meeting number {} went well", i),
+                    "2024-02-01",
+                )
+                .unwrap();
+        }
+        let matching = memory.for_request("math #peanut", MEMORY_FACTS).unwrap();
+        let recent = memory.for_request("", MEMORY_FACTS).unwrap();
+        assert_eq!(matching.len(), 1);
+        assert_eq!(matching[0].id, 1);
+        assert_eq!(recent.len(), MEMORY_FACTS);
+        assert_eq!(recent[0].id, MEMORY_FACTS as u64 * 2 + 1);
+        assert!(recent.iter().all(|fact| fact.id != 1));
+    }
+
+    #[test]
+    fn the_words_on_the_page_pick_older_facts_over_newer_ones() {
+        let dir = ScratchDir::new("memory-page");
+        let memory = Memory::new(dir.join("memory.jsonl"));
+        memory
+            .remember("The user is allergic to peanuts", "2024-01-02")
+            .unwrap();
+        memory
+            .remember("The user bakes bread on Sundays", "2024-01-03")
+            .unwrap();
+        for i in 0..MEMORY_FACTS * 2 {
+            memory
+                .remember(&format!("Project {} is due in spring", i), "2024-03-01")
+                .unwrap();
+        }
+        let page = "Shopping list\n- peanut butter\n- milk";
+        let facts = memory.for_request(page, MEMORY_FACTS).unwrap();
+        assert_eq!(facts.len(), 1);
+        assert_eq!(facts[0].id, 1);
+    }
+
+    #[test]
+    fn small_stores_give_every_fact() {
+        let dir = ScratchDir::new("memory-small");
+        let memory = Memory::new(dir.join("memory.jsonl"));
+        memory
+            .remember("The user is allergic to peanuts", "2024-01-02")
+            .unwrap();
+        memory
+            .remember("The user's sister Anna lives in Berlin", "2024-01-03")
+            .unwrap();
+        let facts = memory.for_request("peanut butter", MEMORY_FACTS).unwrap();
+        let ids: Vec<u64> = facts.iter().map(|fact| fact.id).collect();
+        assert_eq!(ids, [2, 1]);
+    }
+}
@@ -0,0 +1,509 @@
+use anyhow::{anyhow, bail, Result};
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{SmtpTransport, Transport as _};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(20);
+/// How often queued messages are tried again
+const RETRY_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Where messages may be sent, read from a JSON file like
+///
+/// ```json
+/// {
+///   "smtp": { "host": "smtp.example.com", "username": "me", "password": "...", "from": "me@example.com" },
+///   "recipients": {
+///     "me": { "transport": "email", "address": "me@example.com" },
+///     "team": { "transport": "webhook", "url": "https://hooks.slack.com/...", "format": "text" },
+///     "archive": { "transport": "outbox" }
+///   }
+/// }
+/// ```
+///
+/// The model only ever picks one of the recipient names.
+#[derive(Deserialize, Default)]
+pub struct MessageConfig {
+    pub smtp: Option<SmtpConfig>,
+    #[serde(default)]
+    pub recipients: BTreeMap<String, Recipient>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    #[serde(default)]
+    pub security: Security,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    /// Upgrade a plain connection, usually on port 587
+    #[default]
+    StartTls,
+    /// TLS from the start, usually on port 465
+    Tls,
+    /// Plain text, only for local test servers
+    None,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "transport", rename_all = "lowercase")]
+pub enum Recipient {
+    Email {
+        address: String,
+    },
+    Webhook {
+        url: String,
+        #[serde(default)]
+        format: WebhookFormat,
+    },
+    /// Only write to the outbox directory, for another program to pick up
+    Outbox,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// {"to", "subject", "text"}
+    #[default]
+    Json,
+    /// {"text"}, as Slack, Mattermost and Matrix hookshot incoming hooks take
+    Text,
+    /// {"content"}, for Discord
+    Discord,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OutgoingMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    pub created: String,
+}
+
+impl OutgoingMessage {
+    pub fn new(to: &str, subject: &str, body: &str) -> Result<OutgoingMessage> {
+        if body.trim().is_empty() {
+            bail!("The message is empty");
+        }
+        Ok(OutgoingMessage {
+            to: to.trim().to_string(),
+            subject: subject.trim().to_string(),
+            body: body.to_string(),
+            created: chrono::Local::now().to_rfc3339(),
+        })
+    }
+}
+
+pub enum Sent {
+    Delivered,
+    /// Could not be delivered right now, saved in the outbox to retry
+    Queued(String),
+    Saved,
+}
+
+/// Whether a failure is worth retrying later, like when there is no network
+enum SendError {
+    Retry(anyhow::Error),
+    Fail(anyhow::Error),
+}
+
+pub struct Messenger {
+    config: MessageConfig,
+    outbox: PathBuf,
+}
+
+impl Messenger {
+    /// A missing config file means there is nobody to send to
+    pub fn load(config: impl AsRef<Path>, outbox: impl AsRef<Path>) -> Result<Messenger> {
+        let config = config.as_ref();
+        let config = if config.exists() {
+            serde_json::from_str(&fs::read_to_string(config)?)
+                .map_err(|e| anyhow!("Bad message config {}: {}", config.display(), e))?
+        } else {
+            MessageConfig::default()
+        };
+        Ok(Messenger {
+            config,
+            outbox: outbox.as_ref().to_path_buf(),
+        })
+    }
+
+    pub fn recipients(&self) -> Vec<String> {
+        self.config.recipients.keys().cloned().collect()
+    }
+
+    pub fn send(&self, message: &OutgoingMessage) -> Result<Sent> {
+        let recipient = self.config.recipients.get(&message.to).ok_or_else(|| {
+            anyhow!(
+                "Unknown recipient '{}'; messages can go to: {}",
+                message.to,
+                self.recipients().join(", ")
+            )
+        })?;
+        if let Recipient::Outbox = recipient {
+            self.save_to_outbox(message)?;
+            return Ok(Sent::Saved);
+        }
+        match self.deliver(recipient, message) {
+            Ok(()) => Ok(Sent::Delivered),
+            Err(SendError::Fail(e)) => Err(e),
+            Err(SendError::Retry(e)) => {
+                self.save_to_outbox(message)?;
+                Ok(Sent::Queued(e.to_string()))
+            }
+        }
+    }
+
+    /// Try again to deliver messages that were queued while offline.
+    /// Returns how many went out.
+    pub fn flush_outbox(&self) -> Result<usize> {
+        if !self.outbox.exists() {
+            return Ok(0);
+        }
+        let mut paths: Vec<PathBuf> = fs::read_dir(&self.outbox)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|e| e == "json"))
+            .collect();
+        paths.sort();
+
+        let mut sent = 0;
+        for path in paths {
+            let message: OutgoingMessage = serde_json::from_str(&fs::read_to_string(&path)?)?;
+            let recipient = match self.config.recipients.get(&message.to) {
+                Some(Recipient::Outbox) | None => continue,
+                Some(recipient) => recipient,
+            };
+            match self.deliver(recipient, &message) {
+                Ok(()) => {
+                    fs::remove_file(&path)?;
+                    sent += 1;
+                }
+                // Still offline, so don't try the rest now
+                Err(SendError::Retry(_)) => break,
+                Err(SendError::Fail(e)) => {
+                    println!("Dropping queued message {}: {}", path.display(), e);
+                    fs::rename(&path, path.with_extension("failed"))?;
+                }
+            }
+        }
+        Ok(sent)
+    }
+
+    fn save_to_outbox(&self, message: &OutgoingMessage) -> Result<()> {
+        fs::create_dir_all(&self.outbox)?;
+        let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%S%.6f");
+        let path = self.outbox.join(format!("{}-{}.json", stamp, message.to));
+        // Written under another name first so a flush running at the same
+        // time never reads half a message
+        let partial = path.with_extension("partial");
+        fs::write(&partial, serde_json::to_string_pretty(message)?)?;
+        fs::rename(partial, path)?;
+        Ok(())
+    }
+
+    fn deliver(&self, recipient: &Recipient, message: &OutgoingMessage) -> Result<(), SendError> {
+        match recipient {
+            Recipient::Email { address } => self.send_email(address, message),
+            Recipient::Webhook { url, format } => send_webhook(url, *format, message),
+            Recipient::Outbox => Ok(()),
+        }
+    }
+
+    fn send_email(&self, address: &str, message: &OutgoingMessage) -> Result<(), SendError> {
+        let fail = |e: anyhow::Error| SendError::Fail(e);
+        let smtp = self.config.smtp.as_ref().ok_or_else(|| {
+            fail(anyhow!(
+                "Email recipients need an smtp section in the config"
+            ))
+        })?;
+        let email = lettre::Message::builder()
+            .from(
+                smtp.from
+                    .parse()
+                    .map_err(|e| fail(anyhow!("Bad from address: {}", e)))?,
+            )
+            .to(address
+                .parse()
+                .map_err(|e| fail(anyhow!("Bad address for {}: {}", message.to, e)))?)
+            .subject(message.subject.as_str())
+            .header(ContentType::TEXT_PLAIN)
+            .body(message.body.clone())
+            .map_err(|e| fail(e.into()))?;
+
+        let builder = match smtp.security {
+            Security::StartTls => SmtpTransport::starttls_relay(&smtp.host),
+            Security::Tls => SmtpTransport::relay(&smtp.host),
+            Security::None => Ok(SmtpTransport::builder_dangerous(&smtp.host)),
+        }
+        .map_err(|e| fail(e.into()))?;
+        let mut builder = builder.timeout(Some(SEND_TIMEOUT));
+        if let Some(port) = smtp.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        builder.build().send(&email).map(|_| ()).map_err(|e| {
+            if e.is_permanent() {
+                SendError::Fail(e.into())
+            } else {
+                SendError::Retry(e.into())
+            }
+        })
+    }
+}
+
+/// Keep trying to send queued messages in the background, so sending a new
+/// message never waits on old ones
+pub fn retry_outbox(messenger: Arc<Messenger>) {
+    std::thread::spawn(move || loop {
+        match messenger.flush_outbox() {
+            Ok(0) => {}
+            Ok(sent) => println!("Sent {} queued messages", sent),
+            Err(e) => println!("Could not send queued messages: {}", e),
+        }
+        std::thread::sleep(RETRY_INTERVAL);
+    });
+}
+
+fn send_webhook(
+    url: &str,
+    format: WebhookFormat,
+    message: &OutgoingMessage,
+) -> Result<(), SendError> {
+    let text = if message.subject.is_empty() {
+        message.body.clone()
+    } else {
+        format!("{}\n\n{}", message.subject, message.body)
+    };
+    let body = match format {
+        WebhookFormat::Json => json!({
+            "to": message.to,
+            "subject": message.subject,
+            "text": message.body,
+        }),
+        WebhookFormat::Text => json!({ "text": text }),
+        WebhookFormat::Discord => json!({ "content": text }),
+    };
+    match ureq::post(url).timeout(SEND_TIMEOUT).send_json(&body) {
+        Ok(_) => Ok(()),
+        // The server answered, so trying again later won't help, except
+        // when it is overloaded
+        Err(ureq::Error::Status(code, response)) if code != 429 && code < 500 => {
+            let detail = response.into_string().unwrap_or_default();
+            Err(SendError::Fail(anyhow!(
+                "Webhook returned {}: {}",
+                code,
+                detail
+            )))
+        }
+        Err(e) => Err(SendError::Retry(e.into())),
+    }
+}
+
+impl std::fmt::Display for Sent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Sent::Delivered => write!(f, "sent"),
+            Sent::Queued(error) => write!(
+                f,
+                "could not be sent now ({}), so it was saved to the outbox and will be retried",
+                error
+            ),
+            Sent::Saved => write!(f, "saved to the outbox"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    use crate::scratch::ScratchDir;
+
+    fn messenger(name: &str, config: serde_json::Value) -> (ScratchDir, Messenger) {
+        let dir = ScratchDir::new(&format!("messages-{}", name));
+        let messenger = Messenger {
+            config: serde_json::from_value(config).unwrap(),
+            outbox: dir.join("outbox"),
+        };
+        (dir, messenger)
+    }
+
+    fn queued(messenger: &Messenger) -> usize {
+        fs::read_dir(&messenger.outbox).map_or(0, |entries| entries.count())
+    }
+
+    /// An HTTP server that answers one request per status and returns the
+    /// request bodies
+    fn http_server(statuses: Vec<u16>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+            bodies
+        });
+        (url, server)
+    }
+
+    /// An SMTP server that accepts one message and returns what was sent
+    fn smtp_server() -> (u16, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut stream = stream;
+            let mut transcript = String::new();
+            write!(stream, "220 localhost ready\r\n").unwrap();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                transcript.push_str(&line);
+                let command = line.to_uppercase();
+                let reply = if command.starts_with("EHLO") {
+                    "250 localhost"
+                } else if command.starts_with("DATA") {
+                    write!(stream, "354 go ahead\r\n").unwrap();
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line == ".\r\n" {
+                            break;
+                        }
+                        transcript.push_str(&line);
+                    }
+                    "250 queued"
+                } else if command.starts_with("QUIT") {
+                    write!(stream, "221 bye\r\n").unwrap();
+                    break;
+                } else {
+                    "250 ok"
+                };
+                write!(stream, "{}\r\n", reply).unwrap();
+            }
+            transcript
+        });
+        (port, server)
+    }
+
+    #[test]
+    fn sends_email() {
+        let (port, server) = smtp_server();
+        let (_dir, messenger) = messenger(
+            "email",
+            json!({
+                "smtp": { "host": "127.0.0.1", "port": port, "from": "pen@example.com", "security": "none" },
+                "recipients": { "me": { "transport": "email", "address": "me@example.com" } }
+            }),
+        );
+        let message = OutgoingMessage::new("me", "Notes", "Buy milk").unwrap();
+        assert!(matches!(messenger.send(&message).unwrap(), Sent::Delivered));
+        let transcript = server.join().unwrap();
+        assert!(transcript.contains("RCPT TO:<me@example.com>"));
+        assert!(transcript.contains("Subject: Notes"));
+        assert!(transcript.contains("Buy milk"));
+        assert_eq!(queued(&messenger), 0);
+    }
+
+    #[test]
+    fn sends_webhook() {
+        let (url, server) = http_server(vec![200]);
+        let (_dir, messenger) = messenger(
+            "webhook",
+            json!({ "recipients": { "team": { "transport": "webhook", "url": url, "format": "text" } } }),
+        );
+        let message = OutgoingMessage::new("team", "Notes", "Buy milk").unwrap();
+        assert!(matches!(messenger.send(&message).unwrap(), Sent::Delivered));
+        let body: serde_json::Value = serde_json::from_str(&server.join().unwrap()[0]).unwrap();
+        assert_eq!(body, json!({ "text": "Notes\n\nBuy milk" }));
+    }
+
+    #[test]
+    fn rejected_webhook_is_an_error() {
+        let (url, server) = http_server(vec![400]);
+        let (_dir, messenger) = messenger(
+            "rejected",
+            json!({ "recipients": { "team": { "transport": "webhook", "url": url } } }),
+        );
+        let message = OutgoingMessage::new("team", "", "Buy milk").unwrap();
+        assert!(messenger.send(&message).is_err());
+        server.join().unwrap();
+        assert_eq!(queued(&messenger), 0);
+    }
+
+    #[test]
+    fn queues_when_offline_and_flushes_later() {
+        let (url, server) = http_server(vec![503, 200]);
+        let (_dir, messenger) = messenger(
+            "outbox",
+            json!({ "recipients": { "team": { "transport": "webhook", "url": url } } }),
+        );
+        let message = OutgoingMessage::new("team", "", "Buy milk").unwrap();
+        assert!(matches!(messenger.send(&message).unwrap(), Sent::Queued(_)));
+        assert_eq!(queued(&messenger), 1);
+
+        assert_eq!(messenger.flush_outbox().unwrap(), 1);
+        assert_eq!(queued(&messenger), 0);
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies[0], bodies[1]);
+    }
+
+    #[test]
+    fn saves_outbox_recipients() {
+        let (_dir, messenger) = messenger(
+            "archive",
+            json!({ "recipients": { "archive": { "transport": "outbox" } } }),
+        );
+        let message = OutgoingMessage::new("archive", "", "Buy milk").unwrap();
+        assert!(matches!(messenger.send(&message).unwrap(), Sent::Saved));
+        // Outbox recipients are left for another program, not flushed
+        assert_eq!(messenger.flush_outbox().unwrap(), 0);
+        assert_eq!(queued(&messenger), 1);
+    }
+}
@@ -0,0 +1,257 @@
+use anyhow::{bail, Result};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A directory of Markdown notes that the assistant can keep between runs
+pub struct NoteStore {
+    dir: PathBuf,
+}
+
+pub struct NoteSummary {
+    pub name: String,
+    pub lines: usize,
+    pub first_line: String,
+}
+
+/// The file name a note is stored under, without ".md"
+fn slug(name: &str) -> Result<String> {
+    let name = name.trim().trim_end_matches(".md").trim();
+    let slug: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_whitespace() || c == '_' {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect();
+    if slug.is_empty()
+        || slug.starts_with('.')
+        || !slug
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '.')
+    {
+        bail!(
+            "Invalid note name '{}'; use letters, numbers, spaces and dashes",
+            name
+        );
+    }
+    Ok(slug)
+}
+
+impl NoteStore {
+    pub fn new(dir: impl AsRef<Path>) -> NoteStore {
+        NoteStore {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Notes are named by the model, so only allow plain file names. Names
+    /// are matched case-insensitively, so a note made by hand as
+    /// "Groceries.md" is found as "groceries"; new notes are stored
+    /// lowercase with dashes.
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        let wanted = slug(name)?;
+        let existing = fs::read_dir(&self.dir).ok().and_then(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .find(|path| {
+                    path.extension().and_then(|e| e.to_str()) == Some("md")
+                        && path
+                            .file_stem()
+                            .and_then(|stem| stem.to_str())
+                            .and_then(|stem| slug(stem).ok())
+                            .is_some_and(|stem| stem == wanted)
+                })
+        });
+        Ok(existing.unwrap_or_else(|| self.dir.join(format!("{}.md", wanted))))
+    }
+
+    pub fn read(&self, name: &str) -> Result<String> {
+        let path = self.path(name)?;
+        if !path.exists() {
+            let names: Vec<String> = self.list()?.into_iter().map(|note| note.name).collect();
+            if names.is_empty() {
+                bail!("There is no note named '{}' and no notes exist yet", name);
+            }
+            bail!(
+                "There is no note named '{}'. Existing notes: {}",
+                name,
+                names.join(", ")
+            );
+        }
+        Ok(fs::read_to_string(path)?)
+    }
+
+    pub fn write(&self, name: &str, content: &str) -> Result<()> {
+        let path = self.path(name)?;
+        fs::create_dir_all(&self.dir)?;
+        let mut content = content.to_string();
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Add text to the end of a note, creating it if needed
+    pub fn append(&self, name: &str, content: &str) -> Result<()> {
+        let path = self.path(name)?;
+        fs::create_dir_all(&self.dir)?;
+        let existing = fs::read_to_string(&path).unwrap_or_default();
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        if !existing.is_empty() && !existing.ends_with('\n') {
+            writeln!(file)?;
+        }
+        write!(file, "{}", content)?;
+        if !content.ends_with('\n') {
+            writeln!(file)?;
+        }
+        Ok(())
+    }
+
+    pub fn list(&self) -> Result<Vec<NoteSummary>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut notes = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("md") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let content = fs::read_to_string(&path).unwrap_or_default();
+            notes.push(NoteSummary {
+                name: name.to_string(),
+                lines: content
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .count(),
+                first_line: content
+                    .lines()
+                    .find(|line| !line.trim().is_empty())
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+            });
+        }
+        notes.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(notes)
+    }
+
+    /// The note list as text for the model, limited to notes whose name or
+    /// content contains `search` when it is not empty
+    pub fn describe(&self, search: &str) -> Result<String> {
+        let search = search.trim().to_lowercase();
+        let mut notes = self.list()?;
+        if !search.is_empty() {
+            notes.retain(|note| {
+                note.name.to_lowercase().contains(&search)
+                    || self
+                        .read(&note.name)
+                        .is_ok_and(|content| content.to_lowercase().contains(&search))
+            });
+        }
+        if notes.is_empty() {
+            return Ok(if search.is_empty() {
+                "There are no notes yet.".to_string()
+            } else {
+                format!("No notes mention '{}'.", search)
+            });
+        }
+        Ok(notes
+            .iter()
+            .map(|note| format!("{} ({} lines): {}", note.name, note.lines, note.first_line))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    // The notes directory itself doesn't exist until a note is written
+    fn store(name: &str) -> (ScratchDir, NoteStore) {
+        let dir = ScratchDir::new(&format!("notes-{}", name));
+        let notes = NoteStore::new(dir.join("notes"));
+        (dir, notes)
+    }
+
+    #[test]
+    fn names_are_slugged() {
+        assert_eq!(slug("Shopping List").unwrap(), "shopping-list");
+        assert_eq!(slug(" reading_log.md ").unwrap(), "reading-log");
+        assert_eq!(slug("Café 2024").unwrap(), "café-2024");
+        assert_eq!(slug("v1.2").unwrap(), "v1.2");
+    }
+
+    #[test]
+    fn names_that_leave_the_directory_are_rejected() {
+        for name in [
+            "../secrets",
+            "a/b",
+            "/etc/passwd",
+            "..",
+            ".hidden",
+            "",
+            "  ",
+            "a\\b",
+        ] {
+            assert!(slug(name).is_err(), "{:?} was allowed", name);
+        }
+        let (_dir, notes) = store("reject");
+        assert!(notes.write("../escape", "no").is_err());
+        assert!(notes.read("a/b").is_err());
+    }
+
+    #[test]
+    fn notes_round_trip() {
+        let (_dir, notes) = store("round-trip");
+        assert_eq!(notes.describe("").unwrap(), "There are no notes yet.");
+        notes.write("Shopping List", "# Shopping\n- milk").unwrap();
+        notes.append("shopping list", "- bread").unwrap();
+        notes.append("shopping_list.md", "- eggs\n").unwrap();
+        let content = notes.read("SHOPPING LIST").unwrap();
+        let list = notes.list().unwrap();
+        let described = notes.describe("bread").unwrap();
+        let missing = notes.read("todo").unwrap_err().to_string();
+        assert_eq!(content, "# Shopping\n- milk\n- bread\n- eggs\n");
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].name, "shopping-list");
+        assert_eq!(list[0].lines, 4);
+        assert_eq!(list[0].first_line, "# Shopping");
+        assert_eq!(described, "shopping-list (4 lines): # Shopping");
+        assert_eq!(
+            missing,
+            "There is no note named 'todo'. Existing notes: shopping-list"
+        );
+    }
+
+    #[test]
+    fn notes_made_by_hand_are_found_whatever_their_case() {
+        let (_dir, notes) = store("case");
+        fs::create_dir_all(&notes.dir).unwrap();
+        fs::write(notes.dir.join("Groceries.md"), "- apples\n").unwrap();
+        let content = notes.read("groceries").unwrap();
+        notes.append("Groceries", "- pears").unwrap();
+        let files = fs::read_dir(&notes.dir).unwrap().count();
+        let updated = fs::read_to_string(notes.dir.join("Groceries.md")).unwrap();
+        let described = notes.describe("groc").unwrap();
+        assert_eq!(content, "- apples\n");
+        assert_eq!(files, 1);
+        assert_eq!(updated, "- apples\n- pears\n");
+        assert_eq!(described, "Groceries (2 lines): - apples");
+    }
+}
@@ -0,0 +1,461 @@
+//! Put strokes in an order that keeps the pen from jumping around the page,
+//! and trim them down to the points that matter, so a drawing sends fewer
+//! pen events.
+
+use std::collections::HashMap;
+
+use crate::util::Polyline;
+
+/// Points closer than this to the line through their neighbours are dropped
+const SIMPLIFY_TOLERANCE: f32 = 0.5;
+/// Strokes whose ends are this close are drawn as one; only ends that are
+/// the same point up to rounding, so no line is drawn that wasn't there
+const MERGE_EPSILON: f32 = 1e-3;
+/// How far ahead 2-opt looks for a better order, to bound the time it takes
+/// on drawings with thousands of strokes
+const TWO_OPT_WINDOW: usize = 50;
+const TWO_OPT_PASSES: usize = 8;
+
+/// Reorder, join and simplify strokes. The result draws the same lines,
+/// with the pen never travelling further than in the order given.
+pub fn optimize_strokes(strokes: &[Polyline]) -> Vec<Polyline> {
+    let strokes: Vec<Polyline> = strokes.iter().filter(|s| !s.is_empty()).cloned().collect();
+    let mut ordered = nearest_neighbour_order(strokes.clone());
+    // Greedy ordering can lose to an order that was already good
+    if pen_up_travel(&ordered) > pen_up_travel(&strokes) {
+        ordered = strokes;
+    }
+    two_opt(&mut ordered);
+    merge_touching(ordered)
+        .iter()
+        .map(|stroke| simplify(stroke, SIMPLIFY_TOLERANCE))
+        .collect()
+}
+
+/// Total distance the pen moves with its tip lifted
+pub fn pen_up_travel(strokes: &[Polyline]) -> f32 {
+    strokes
+        .windows(2)
+        .map(|pair| distance(end(&pair[0]), start(&pair[1])))
+        .sum()
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+}
+
+fn start(stroke: &Polyline) -> (f32, f32) {
+    stroke[0]
+}
+
+fn end(stroke: &Polyline) -> (f32, f32) {
+    stroke[stroke.len() - 1]
+}
+
+/// Stroke ends bucketed into square cells, so the closest end to a point
+/// can be found by looking at nearby cells only
+struct EndGrid {
+    cell: f32,
+    min: (i32, i32),
+    max: (i32, i32),
+    /// Stroke index, and whether the entry is the stroke's far end
+    buckets: HashMap<(i32, i32), Vec<(usize, bool)>>,
+}
+
+impl EndGrid {
+    fn new(strokes: &[Polyline]) -> EndGrid {
+        let ends = || {
+            strokes
+                .iter()
+                .flat_map(|stroke| [start(stroke), end(stroke)])
+        };
+        let (low, high) = ends().fold(
+            ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN)),
+            |(low, high), p| {
+                (
+                    (low.0.min(p.0), low.1.min(p.1)),
+                    (high.0.max(p.0), high.1.max(p.1)),
+                )
+            },
+        );
+        // About one stroke per cell
+        let side = (high.0 - low.0).max(high.1 - low.1);
+        let cell = (side / (strokes.len() as f32).sqrt().ceil()).max(1.0);
+        let mut grid = EndGrid {
+            cell,
+            min: (i32::MAX, i32::MAX),
+            max: (i32::MIN, i32::MIN),
+            buckets: HashMap::new(),
+        };
+        for (i, stroke) in strokes.iter().enumerate() {
+            for (point, reversed) in [(start(stroke), false), (end(stroke), true)] {
+                let key = grid.key(point);
+                grid.min = (grid.min.0.min(key.0), grid.min.1.min(key.1));
+                grid.max = (grid.max.0.max(key.0), grid.max.1.max(key.1));
+                grid.buckets.entry(key).or_default().push((i, reversed));
+            }
+        }
+        grid
+    }
+
+    fn key(&self, point: (f32, f32)) -> (i32, i32) {
+        (
+            (point.0 / self.cell).floor() as i32,
+            (point.1 / self.cell).floor() as i32,
+        )
+    }
+
+    /// Forget both ends of stroke `i`
+    fn remove(&mut self, i: usize, stroke: &Polyline) {
+        for point in [start(stroke), end(stroke)] {
+            let key = self.key(point);
+            if let Some(bucket) = self.buckets.get_mut(&key) {
+                bucket.retain(|&(j, _)| j != i);
+                if bucket.is_empty() {
+                    self.buckets.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// The closest end of a stroke not yet taken, searching rings of cells
+    /// outwards until no unsearched cell can hold anything closer
+    fn closest(&self, strokes: &[Option<Polyline>], position: (f32, f32)) -> Option<(usize, bool)> {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let centre = self.key(position);
+        let last_ring = [
+            centre.0 - self.min.0,
+            self.max.0 - centre.0,
+            centre.1 - self.min.1,
+            self.max.1 - centre.1,
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(0);
+        let mut best = None;
+        let mut searched = 0;
+        for ring in 0..=last_ring {
+            // Once the rings cover more cells than are left, it is quicker
+            // to look at every stroke end that is left
+            searched += (8 * ring as usize).max(1);
+            if searched > self.buckets.len() {
+                best = None;
+                for bucket in self.buckets.values() {
+                    closest_in(bucket, strokes, position, &mut best);
+                }
+                break;
+            }
+            for key in ring_cells(centre, ring) {
+                if let Some(bucket) = self.buckets.get(&key) {
+                    closest_in(bucket, strokes, position, &mut best);
+                }
+            }
+            // Anything outside this ring is at least this far away
+            if best.is_some_and(|(_, _, d)| d <= ring as f32 * self.cell) {
+                break;
+            }
+        }
+        best.map(|(i, reversed, _)| (i, reversed))
+    }
+}
+
+fn closest_in(
+    bucket: &[(usize, bool)],
+    strokes: &[Option<Polyline>],
+    position: (f32, f32),
+    best: &mut Option<(usize, bool, f32)>,
+) {
+    for &(i, reversed) in bucket {
+        let Some(stroke) = &strokes[i] else {
+            continue;
+        };
+        let point = if reversed { end(stroke) } else { start(stroke) };
+        let d = distance(position, point);
+        if best.is_none_or(|(_, _, best)| d < best) {
+            *best = Some((i, reversed, d));
+        }
+    }
+}
+
+/// The cells on the square ring `ring` cells out from `centre`
+fn ring_cells(centre: (i32, i32), ring: i32) -> impl Iterator<Item = (i32, i32)> {
+    (-ring..=ring).flat_map(move |dx| {
+        // Only the top and bottom rows of the ring span its full width
+        let dys: Vec<i32> = if dx.abs() == ring {
+            (-ring..=ring).collect()
+        } else {
+            vec![-ring, ring]
+        };
+        dys.into_iter()
+            .map(move |dy| (centre.0 + dx, centre.1 + dy))
+    })
+}
+
+/// Always go to the closest unvisited stroke end next, drawing the stroke
+/// backwards if its far end is the closer one
+fn nearest_neighbour_order(strokes: Vec<Polyline>) -> Vec<Polyline> {
+    let mut grid = EndGrid::new(&strokes);
+    let mut strokes: Vec<Option<Polyline>> = strokes.into_iter().map(Some).collect();
+    let mut ordered = Vec::with_capacity(strokes.len());
+    let mut position = (0.0, 0.0);
+    while let Some((i, reversed)) = grid.closest(&strokes, position) {
+        let mut stroke = strokes[i].take().unwrap();
+        grid.remove(i, &stroke);
+        if reversed {
+            stroke.reverse();
+        }
+        position = end(&stroke);
+        ordered.push(stroke);
+    }
+    ordered
+}
+
+/// Reverse runs of strokes (and the direction of each stroke in them)
+/// wherever that shortens the jumps at both ends of the run
+fn two_opt(strokes: &mut [Polyline]) {
+    let count = strokes.len();
+    for _ in 0..TWO_OPT_PASSES {
+        let mut improved = false;
+        for i in 1..count.saturating_sub(1) {
+            for j in i + 1..count.min(i + TWO_OPT_WINDOW) {
+                let before = end(&strokes[i - 1]);
+                let after = strokes.get(j + 1).map(start);
+                let old = distance(before, start(&strokes[i]))
+                    + after.map_or(0.0, |after| distance(end(&strokes[j]), after));
+                let new = distance(before, end(&strokes[j]))
+                    + after.map_or(0.0, |after| distance(start(&strokes[i]), after));
+                if new + 0.01 < old {
+                    strokes[i..=j].reverse();
+                    for stroke in &mut strokes[i..=j] {
+                        stroke.reverse();
+                    }
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+}
+
+/// Join each stroke onto the previous one when it starts where that ended
+fn merge_touching(strokes: Vec<Polyline>) -> Vec<Polyline> {
+    let mut merged: Vec<Polyline> = Vec::with_capacity(strokes.len());
+    for stroke in strokes {
+        match merged.last_mut() {
+            Some(previous) if distance(end(previous), start(&stroke)) <= MERGE_EPSILON => {
+                previous.extend(stroke.into_iter().skip(1));
+            }
+            _ => merged.push(stroke),
+        }
+    }
+    merged
+}
+
+/// Douglas-Peucker: keep the point furthest from the line between the ends
+/// if it is off by more than `tolerance`, then do the same on each side
+pub fn simplify(stroke: &Polyline, tolerance: f32) -> Polyline {
+    if stroke.len() < 3 {
+        return stroke.clone();
+    }
+    let mut keep = vec![false; stroke.len()];
+    keep[0] = true;
+    keep[stroke.len() - 1] = true;
+    let mut ranges = vec![(0, stroke.len() - 1)];
+    while let Some((first, last)) = ranges.pop() {
+        if last <= first + 1 {
+            continue;
+        }
+        let (a, b) = (stroke[first], stroke[last]);
+        let (furthest, offset) = (first + 1..last)
+            .map(|i| (i, distance_to_segment(stroke[i], a, b)))
+            .fold((first, 0.0), |best, candidate| {
+                if candidate.1 > best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            });
+        if offset > tolerance {
+            keep[furthest] = true;
+            ranges.push((first, furthest));
+            ranges.push((furthest, last));
+        }
+    }
+    stroke
+        .iter()
+        .zip(keep)
+        .filter_map(|(&point, keep)| keep.then_some(point))
+        .collect()
+}
+
+fn distance_to_segment(point: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    if length_squared == 0.0 {
+        return distance(point, a);
+    }
+    let t = (((point.0 - a.0) * dx + (point.1 - a.1) * dy) / length_squared).clamp(0.0, 1.0);
+    distance(point, (a.0 + t * dx, a.1 + t * dy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_order_always_jumps_to_the_closest_end() {
+        // A small linear congruential generator keeps the strokes repeatable
+        let mut seed = 12345u32;
+        let mut next = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as f32 % 768.0
+        };
+        let strokes: Vec<Polyline> = (0..500)
+            .map(|_| vec![(next(), next()), (next(), next())])
+            .collect();
+
+        let ordered = nearest_neighbour_order(strokes.clone());
+        assert_eq!(ordered.len(), strokes.len());
+
+        let mut left = strokes;
+        let mut position = (0.0, 0.0);
+        for stroke in &ordered {
+            let closest = left
+                .iter()
+                .flat_map(|s| [distance(position, start(s)), distance(position, end(s))])
+                .fold(f32::MAX, f32::min);
+            assert_eq!(distance(position, start(stroke)), closest);
+            let i = left
+                .iter()
+                .position(|s| {
+                    s == stroke || s.iter().rev().copied().collect::<Polyline>() == *stroke
+                })
+                .unwrap();
+            left.swap_remove(i);
+            position = end(stroke);
+        }
+    }
+
+    // A small linear congruential generator keeps the strokes repeatable
+    fn random_strokes(count: usize, seed: u32) -> Vec<Polyline> {
+        let mut seed = seed;
+        let mut next = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as f32 % 768.0
+        };
+        (0..count)
+            .map(|_| vec![(next(), next()), (next(), next()), (next(), next())])
+            .collect()
+    }
+
+    fn segments(strokes: &[Polyline]) -> Vec<((f32, f32), (f32, f32))> {
+        strokes
+            .iter()
+            .flat_map(|stroke| {
+                if stroke.len() == 1 {
+                    vec![(stroke[0], stroke[0])]
+                } else {
+                    stroke.windows(2).map(|pair| (pair[0], pair[1])).collect()
+                }
+            })
+            .collect()
+    }
+
+    // Every point along `from`'s segments is on one of `to`'s, up to `tolerance`
+    fn covered(from: &[Polyline], to: &[Polyline], tolerance: f32) -> bool {
+        let to = segments(to);
+        segments(from).iter().all(|&(a, b)| {
+            (0..=10).all(|step| {
+                let t = step as f32 / 10.0;
+                let point = (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1));
+                to.iter()
+                    .any(|&(c, d)| distance_to_segment(point, c, d) <= tolerance)
+            })
+        })
+    }
+
+    #[test]
+    fn optimized_strokes_draw_the_same_lines() {
+        let mut strokes = random_strokes(200, 777);
+        // A square drawn as four strokes that meet exactly, and two strokes
+        // that nearly meet, which must not be joined by a connector
+        strokes.extend([
+            vec![(100.0, 100.0), (200.0, 100.0)],
+            vec![(200.0, 100.0), (200.0, 200.0)],
+            vec![(200.0, 200.0), (100.0, 200.0)],
+            vec![(100.0, 200.0), (100.0, 100.0)],
+            vec![(300.0, 300.0), (400.0, 300.0)],
+            vec![(403.0, 300.0), (403.0, 400.0)],
+            vec![(500.0, 500.0)],
+        ]);
+        let optimized = optimize_strokes(&strokes);
+        assert!(covered(&strokes, &optimized, SIMPLIFY_TOLERANCE + 1e-3));
+        assert!(covered(&optimized, &strokes, SIMPLIFY_TOLERANCE + 1e-3));
+    }
+
+    #[test]
+    fn only_coinciding_ends_are_merged() {
+        let merged = merge_touching(vec![
+            vec![(0.0, 0.0), (10.0, 0.0)],
+            vec![(10.0, 0.0), (10.0, 10.0)],
+            vec![(11.0, 10.0), (20.0, 10.0)],
+        ]);
+        assert_eq!(
+            merged,
+            vec![
+                vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)],
+                vec![(11.0, 10.0), (20.0, 10.0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn travel_never_increases() {
+        for seed in 0..20 {
+            let strokes = random_strokes(60, seed);
+            let travel = pen_up_travel(&strokes);
+            assert!(pen_up_travel(&optimize_strokes(&strokes)) <= travel);
+
+            let mut reordered = strokes.clone();
+            two_opt(&mut reordered);
+            assert!(pen_up_travel(&reordered) <= travel);
+        }
+        // Strokes already drawn in a good order stay in it
+        let row: Vec<Polyline> = (0..10)
+            .map(|i| {
+                vec![
+                    (700.0 - i as f32 * 20.0, 10.0),
+                    (690.0 - i as f32 * 20.0, 10.0),
+                ]
+            })
+            .collect();
+        assert!(pen_up_travel(&optimize_strokes(&row)) <= pen_up_travel(&row));
+    }
+
+    #[test]
+    fn simplify_keeps_the_ends() {
+        let line: Polyline = (0..=10).map(|i| (i as f32, 0.1 * (i % 2) as f32)).collect();
+        assert_eq!(simplify(&line, 0.5), vec![(0.0, 0.0), (10.0, 0.0)]);
+
+        let corner = vec![
+            (0.0, 0.0),
+            (5.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 5.0),
+            (10.0, 10.0),
+        ];
+        assert_eq!(
+            simplify(&corner, 0.5),
+            vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]
+        );
+
+        let closed = vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 0.0)];
+        assert_eq!(simplify(&closed, 0.5), closed);
+
+        assert_eq!(simplify(&vec![(1.0, 1.0)], 0.5), vec![(1.0, 1.0)]);
+    }
+}
@@ -0,0 +1,502 @@
+//! How fast the pen sends events. Sending too fast makes xochitl fall behind
+//! and can lock up the tablet on big drawings, so the pacer caps the event
+//! rate and backs off when writes get slow or xochitl is maxing out the CPU,
+//! then speeds up again once it has caught up.
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+/// Rate never drops below this, however far behind things get
+pub const MIN_EVENTS_PER_SECOND: f64 = 200.0;
+/// Fast writes in a row before the rate creeps back up
+const SPEED_UP_AFTER: u32 = 500;
+/// How often to look at xochitl's CPU use
+const CPU_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Kernel clock ticks per second for /proc CPU times
+const CLOCK_TICKS: f64 = 100.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Pacing {
+    /// Most input events to send each second
+    pub events_per_second: f64,
+    /// Longer strokes are split, since xochitl redraws a stroke as it grows
+    pub max_stroke_points: usize,
+    /// Pause after the pen goes down or comes up
+    pub pen_pause: Duration,
+    /// Pause after each row of a bitmap
+    pub row_pause: Duration,
+    /// Distance between points of a straight line, in digitizer units
+    pub line_step: f32,
+    /// A write that takes longer than this means the input queue is backing up
+    pub slow_write: Duration,
+    /// Share of one core above which xochitl counts as falling behind. Set
+    /// close to a full core, since xochitl gets near one whenever it renders
+    /// and a lower limit would hold the rate at the minimum.
+    pub busy_cpu: Option<f64>,
+    /// Print what the pacer is doing
+    pub verbose: bool,
+}
+
+impl Pacing {
+    /// As quick as the tablet keeps up with for small drawings
+    pub fn fast() -> Pacing {
+        Pacing {
+            events_per_second: 20_000.0,
+            max_stroke_points: 2_000,
+            pen_pause: Duration::ZERO,
+            row_pause: Duration::from_millis(1),
+            line_step: 10.0,
+            slow_write: Duration::from_millis(2),
+            busy_cpu: None,
+            verbose: false,
+        }
+    }
+
+    /// The default, with room to spare for large drawings
+    pub fn safe() -> Pacing {
+        Pacing {
+            events_per_second: 5_000.0,
+            max_stroke_points: 500,
+            pen_pause: Duration::from_millis(1),
+            row_pause: Duration::from_millis(5),
+            line_step: 5.0,
+            slow_write: Duration::from_millis(2),
+            busy_cpu: Some(0.95),
+            verbose: false,
+        }
+    }
+
+    /// Slow enough to watch each stroke go down, with logging
+    pub fn debug() -> Pacing {
+        Pacing {
+            events_per_second: 500.0,
+            max_stroke_points: 200,
+            pen_pause: Duration::from_millis(20),
+            row_pause: Duration::from_millis(20),
+            line_step: 5.0,
+            slow_write: Duration::from_millis(2),
+            busy_cpu: None,
+            verbose: true,
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Pacing> {
+        match name.to_lowercase().as_str() {
+            "fast" => Some(Pacing::fast()),
+            "safe" => Some(Pacing::safe()),
+            "debug" => Some(Pacing::debug()),
+            _ => None,
+        }
+    }
+}
+
+/// Where the pacer gets the time and how it waits, so the timing can be
+/// tested without sleeping
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+pub struct Pacer {
+    pub pacing: Pacing,
+    clock: Box<dyn Clock>,
+    rate: f64,
+    window_start: Instant,
+    window_events: f64,
+    fast_writes: u32,
+    /// Started by the first write, so dry runs never scan `/proc`
+    xochitl: Option<CpuWatch>,
+    sent: u64,
+}
+
+struct CpuWatch {
+    name: &'static str,
+    /// The process's stat file, while it is running
+    stat_path: Option<String>,
+    last_ticks: u64,
+    last_check: Instant,
+}
+
+impl Pacer {
+    pub fn new(pacing: Pacing) -> Pacer {
+        Pacer::with_clock(pacing, Box::new(SystemClock))
+    }
+
+    pub fn with_clock(mut pacing: Pacing, clock: Box<dyn Clock>) -> Pacer {
+        pacing.events_per_second = pacing.events_per_second.max(MIN_EVENTS_PER_SECOND);
+        Pacer {
+            pacing,
+            rate: pacing.events_per_second,
+            window_start: clock.now(),
+            clock,
+            window_events: 0.0,
+            fast_writes: 0,
+            xochitl: None,
+            sent: 0,
+        }
+    }
+
+    /// Wait until `count` more events fit within the current rate
+    pub fn wait(&mut self, count: usize) {
+        self.window_events += count as f64;
+        let due = Duration::from_secs_f64(self.window_events / self.rate);
+        let elapsed = self.clock.now() - self.window_start;
+        if due > elapsed {
+            self.clock.sleep(due - elapsed);
+        } else if elapsed - due > Duration::from_millis(100) {
+            // Don't let idle time build up into a burst later
+            self.restart_window();
+        }
+    }
+
+    /// Note how long a write took, backing off if the tablet is struggling
+    pub fn sent(&mut self, count: usize, took: Duration) {
+        self.sent += count as u64;
+        if took > self.pacing.slow_write {
+            self.back_off(&format!("a write took {:?}", took));
+        } else {
+            self.fast_writes += 1;
+            if self.fast_writes >= SPEED_UP_AFTER && self.rate < self.pacing.events_per_second {
+                self.rate = (self.rate * 1.25).min(self.pacing.events_per_second);
+                self.fast_writes = 0;
+                self.restart_window();
+                if self.pacing.verbose {
+                    println!("Pacing: speeding up to {:.0} events/s", self.rate);
+                }
+            }
+        }
+        let Some(limit) = self.pacing.busy_cpu else {
+            return;
+        };
+        let watch = self.xochitl.get_or_insert_with(|| CpuWatch::new("xochitl"));
+        if let Some(busy) = watch.check() {
+            if busy > limit {
+                self.back_off(&format!("xochitl is at {:.0}% CPU", busy * 100.0));
+            }
+        }
+    }
+
+    pub fn pen_pause(&self) {
+        if !self.pacing.pen_pause.is_zero() {
+            self.clock.sleep(self.pacing.pen_pause);
+        }
+    }
+
+    pub fn row_pause(&self) {
+        self.clock.sleep(self.pacing.row_pause);
+    }
+
+    /// Events sent so far, for reporting
+    pub fn events_sent(&self) -> u64 {
+        self.sent
+    }
+
+    fn back_off(&mut self, reason: &str) {
+        self.fast_writes = 0;
+        let rate = (self.rate / 2.0).max(MIN_EVENTS_PER_SECOND);
+        if rate < self.rate && self.pacing.verbose {
+            println!("Pacing: {}, slowing to {:.0} events/s", reason, rate);
+        }
+        self.rate = rate;
+        self.restart_window();
+    }
+
+    fn restart_window(&mut self) {
+        self.window_start = self.clock.now();
+        self.window_events = 0.0;
+    }
+}
+
+impl CpuWatch {
+    fn new(name: &'static str) -> CpuWatch {
+        let mut watch = CpuWatch {
+            name,
+            stat_path: None,
+            last_ticks: 0,
+            last_check: Instant::now(),
+        };
+        watch.find();
+        watch
+    }
+
+    /// Look the process up, again after it restarts with a new pid
+    fn find(&mut self) {
+        self.stat_path = None;
+        let Ok(entries) = fs::read_dir("/proc") else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if entry
+                .file_name()
+                .to_str()
+                .and_then(|pid| pid.parse::<u32>().ok())
+                .is_none()
+            {
+                continue;
+            }
+            let stat_path = format!("{}/stat", entry.path().display());
+            if let Some(ticks) = cpu_ticks(&stat_path, self.name) {
+                self.last_ticks = ticks;
+                self.stat_path = Some(stat_path);
+                return;
+            }
+        }
+    }
+
+    /// Share of a core used since the last check, once per interval
+    fn check(&mut self) -> Option<f64> {
+        let elapsed = self.last_check.elapsed();
+        if elapsed < CPU_CHECK_INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+        let Some(ticks) = self
+            .stat_path
+            .as_deref()
+            .and_then(|stat_path| cpu_ticks(stat_path, self.name))
+        else {
+            // Gone, or its pid now belongs to something else
+            self.find();
+            return None;
+        };
+        let busy =
+            (ticks.saturating_sub(self.last_ticks)) as f64 / CLOCK_TICKS / elapsed.as_secs_f64();
+        self.last_ticks = ticks;
+        Some(busy)
+    }
+}
+
+/// User plus system time from /proc/<pid>/stat, if the process is called
+/// `name`. The name can have spaces, so count fields from after its closing
+/// parenthesis.
+fn cpu_ticks(stat_path: &str, name: &str) -> Option<u64> {
+    let stat = fs::read_to_string(stat_path).ok()?;
+    let (open, close) = (stat.find('(')?, stat.rfind(')')?);
+    if stat.get(open + 1..close)? != name {
+        return None;
+    }
+    let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some(utime + stime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Moves on by however long the pacer sleeps, adding it up
+    #[derive(Clone)]
+    struct FakeClock {
+        now: Arc<Mutex<Instant>>,
+        slept: Arc<Mutex<Duration>>,
+    }
+
+    impl FakeClock {
+        fn new() -> FakeClock {
+            FakeClock {
+                now: Arc::new(Mutex::new(Instant::now())),
+                slept: Arc::new(Mutex::new(Duration::ZERO)),
+            }
+        }
+
+        fn advance(&self, duration: Duration) {
+            *self.now.lock().unwrap() += duration;
+        }
+
+        fn take_slept(&self) -> Duration {
+            std::mem::take(&mut *self.slept.lock().unwrap())
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.advance(duration);
+            *self.slept.lock().unwrap() += duration;
+        }
+    }
+
+    fn pacer(events_per_second: f64) -> (Pacer, FakeClock) {
+        let clock = FakeClock::new();
+        let pacing = Pacing {
+            events_per_second,
+            busy_cpu: None,
+            ..Pacing::safe()
+        };
+        (Pacer::with_clock(pacing, Box::new(clock.clone())), clock)
+    }
+
+    fn assert_close(actual: Duration, expected: Duration) {
+        let diff = actual.abs_diff(expected);
+        assert!(
+            diff < Duration::from_micros(10),
+            "{:?} is not {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn events_are_sent_no_faster_than_the_rate() {
+        let (mut pacer, clock) = pacer(1_000.0);
+        for _ in 0..10 {
+            pacer.wait(100);
+        }
+        assert_close(clock.take_slept(), Duration::from_secs(1));
+
+        // Time spent writing counts towards the wait
+        pacer.wait(100);
+        clock.take_slept();
+        clock.advance(Duration::from_millis(60));
+        pacer.wait(100);
+        assert_close(clock.take_slept(), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn idle_time_does_not_build_up_into_a_burst() {
+        let (mut pacer, clock) = pacer(1_000.0);
+        pacer.wait(100);
+        clock.take_slept();
+        clock.advance(Duration::from_secs(5));
+        pacer.wait(100);
+        assert_eq!(clock.take_slept(), Duration::ZERO);
+        pacer.wait(100);
+        assert_close(clock.take_slept(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn slow_writes_back_off_and_fast_ones_speed_up_again() {
+        let (mut pacer, clock) = pacer(1_000.0);
+        pacer.sent(10, Duration::from_millis(50));
+        assert_eq!(pacer.rate, 500.0);
+        pacer.wait(100);
+        assert_close(clock.take_slept(), Duration::from_millis(200));
+
+        // Never slower than the minimum
+        for _ in 0..5 {
+            pacer.sent(10, Duration::from_millis(50));
+        }
+        assert_eq!(pacer.rate, MIN_EVENTS_PER_SECOND);
+
+        for _ in 0..SPEED_UP_AFTER - 1 {
+            pacer.sent(10, Duration::ZERO);
+        }
+        assert_eq!(pacer.rate, MIN_EVENTS_PER_SECOND);
+        pacer.sent(10, Duration::ZERO);
+        assert_eq!(pacer.rate, MIN_EVENTS_PER_SECOND * 1.25);
+
+        // And never faster than the pacing allows
+        for _ in 0..SPEED_UP_AFTER * 20 {
+            pacer.sent(10, Duration::ZERO);
+        }
+        assert_eq!(pacer.rate, 1_000.0);
+        assert_eq!(pacer.events_sent(), 10 * (6 + SPEED_UP_AFTER as u64 * 21));
+    }
+
+    #[test]
+    fn a_busy_xochitl_backs_off() {
+        let comm = fs::read_to_string(format!("/proc/{}/comm", std::process::id())).unwrap();
+        let name: &'static str = Box::leak(comm.trim().to_string().into_boxed_str());
+        let stat_path = format!("/proc/{}/stat", std::process::id());
+        // Use some CPU so there is something to measure
+        let start = Instant::now();
+        while cpu_ticks(&stat_path, name).unwrap() == 0 && start.elapsed().as_secs() < 5 {
+            std::hint::black_box((0..1_000).sum::<u64>());
+        }
+
+        let (mut pacer, _clock) = pacer(1_000.0);
+        pacer.pacing.busy_cpu = Some(0.0);
+        let mut watch = CpuWatch::new(name);
+        watch.last_ticks = 0;
+        watch.last_check -= CPU_CHECK_INTERVAL;
+        pacer.xochitl = Some(watch);
+        pacer.sent(10, Duration::ZERO);
+        assert_eq!(pacer.rate, 500.0);
+
+        // Not checked again until the interval has passed
+        pacer.sent(10, Duration::ZERO);
+        assert_eq!(pacer.rate, 500.0);
+    }
+
+    #[test]
+    fn xochitl_is_looked_for_once_events_are_sent() {
+        let mut safe = Pacer::with_clock(Pacing::safe(), Box::new(FakeClock::new()));
+        assert!(safe.xochitl.is_none());
+        safe.wait(10);
+        assert!(safe.xochitl.is_none());
+        safe.sent(10, Duration::ZERO);
+        assert!(safe.xochitl.is_some());
+
+        // Not at all when CPU use is ignored
+        let (mut pacer, _clock) = pacer(1_000.0);
+        pacer.sent(10, Duration::ZERO);
+        assert!(pacer.xochitl.is_none());
+    }
+
+    #[test]
+    fn pauses_wait_on_the_clock() {
+        let (pacer, clock) = pacer(1_000.0);
+        pacer.pen_pause();
+        assert_eq!(clock.take_slept(), Pacing::safe().pen_pause);
+        pacer.row_pause();
+        assert_eq!(clock.take_slept(), Pacing::safe().row_pause);
+
+        let clock = FakeClock::new();
+        Pacer::with_clock(Pacing::fast(), Box::new(clock.clone())).pen_pause();
+        assert_eq!(clock.take_slept(), Duration::ZERO);
+    }
+
+    #[test]
+    fn presets() {
+        assert_eq!(Pacing::preset("SAFE").unwrap().events_per_second, 5_000.0);
+        assert!(Pacing::preset("slow").is_none());
+        let (fast, safe, debug) = (Pacing::fast(), Pacing::safe(), Pacing::debug());
+        assert!(fast.events_per_second > safe.events_per_second);
+        assert!(safe.events_per_second > debug.events_per_second);
+        assert!(fast.max_stroke_points > safe.max_stroke_points);
+        assert!(safe.max_stroke_points > debug.max_stroke_points);
+        assert!(safe.busy_cpu.is_some_and(|busy| busy > 0.5 && busy < 1.0));
+        assert!(debug.verbose && !safe.verbose && !fast.verbose);
+
+        let (pacer, _clock) = pacer(10.0);
+        assert_eq!(pacer.rate, MIN_EVENTS_PER_SECOND);
+    }
+
+    #[test]
+    fn cpu_ticks_only_for_the_named_process() {
+        let stat_path = format!("/proc/{}/stat", std::process::id());
+        let comm = fs::read_to_string(format!("/proc/{}/comm", std::process::id())).unwrap();
+        assert!(cpu_ticks(&stat_path, comm.trim()).is_some());
+        assert!(cpu_ticks(&stat_path, "xochitl").is_none());
+    }
+
+    #[test]
+    fn watch_finds_the_process_again() {
+        let comm = fs::read_to_string(format!("/proc/{}/comm", std::process::id())).unwrap();
+        let name: &'static str = Box::leak(comm.trim().to_string().into_boxed_str());
+        let mut watch = CpuWatch::new(name);
+        assert!(watch.stat_path.is_some());
+        // As if it had restarted under another pid
+        watch.stat_path = Some("/proc/0/stat".to_string());
+        watch.last_check -= CPU_CHECK_INTERVAL;
+        assert_eq!(watch.check(), None);
+        assert!(watch.stat_path.is_some_and(|path| path != "/proc/0/stat"));
+    }
+}
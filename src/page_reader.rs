@@ -0,0 +1,135 @@
+use anyhow::Result;
+use base64::prelude::*;
+use image::{GrayImage, ImageFormat};
+use serde_json::{json, Value as json};
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use crate::llm_engine::LLMEngine;
+
+/// Reads the words written on a page, to look up the remembered facts that
+/// go with it. There is no OCR on the tablet, so a model reads them; a
+/// cheaper one than the main model will do.
+pub struct PageReader {
+    engine: Box<dyn LLMEngine>,
+    words: Arc<Mutex<Option<String>>>,
+}
+
+impl PageReader {
+    pub fn new(mut engine: Box<dyn LLMEngine>) -> PageReader {
+        let words = Arc::new(Mutex::new(None));
+
+        let callback_words = Arc::clone(&words);
+        engine.register_tool(
+            "report_words",
+            json!({
+                "name": "report_words",
+                "description": "Report the words on the page",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "words": {
+                            "type": "string",
+                            "description": "The words written on the page, plus a word or two for each drawing"
+                        }
+                    },
+                    "required": ["words"]
+                }
+            }),
+            Box::new(move |arguments: json| {
+                *callback_words.lock().unwrap() = arguments["words"].as_str().map(str::to_string);
+                Ok(String::new())
+            }),
+        );
+
+        PageReader { engine, words }
+    }
+
+    /// The words on the page. If the model can't be asked, say without a
+    /// network, there are none and facts are picked without them.
+    pub fn read(&mut self, screen: &GrayImage) -> String {
+        match self.read_words(screen) {
+            Ok(words) => words,
+            Err(e) => {
+                println!("Could not read the page to look up facts: {}", e);
+                String::new()
+            }
+        }
+    }
+
+    fn read_words(&mut self, screen: &GrayImage) -> Result<String> {
+        let mut png = Vec::new();
+        screen.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+
+        *self.words.lock().unwrap() = None;
+        self.engine.clear_content();
+        self.engine.add_text_content(
+            "This is a handwritten page. Call report_words with the words written on it, and a word or two for anything drawn.",
+        );
+        self.engine.add_image_content(&BASE64_STANDARD.encode(&png));
+        self.engine.execute()?;
+
+        Ok(self.words.lock().unwrap().take().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_engine::ToolCallback;
+    use anyhow::bail;
+    use std::collections::HashMap;
+
+    // Reports fixed words, or fails like a call without a network when
+    // there are none
+    struct FakeEngine {
+        words: Option<&'static str>,
+        tools: Vec<ToolCallback>,
+    }
+
+    impl LLMEngine for FakeEngine {
+        fn new(_options: &HashMap<String, String>) -> Self {
+            FakeEngine {
+                words: None,
+                tools: Vec::new(),
+            }
+        }
+        fn register_tool(&mut self, _name: &str, _definition: json, callback: ToolCallback) {
+            self.tools.push(callback);
+        }
+        fn select_tools(&mut self, _names: Option<Vec<String>>) -> Result<()> {
+            Ok(())
+        }
+        fn add_text_content(&mut self, _text: &str) {}
+        fn add_image_content(&mut self, _base64_image: &str) {}
+        fn clear_content(&mut self) {}
+        fn execute(&mut self) -> Result<()> {
+            let Some(words) = self.words else {
+                bail!("No network");
+            };
+            (self.tools[0])(json!({ "words": words }))?;
+            Ok(())
+        }
+    }
+
+    fn read(words: Option<&'static str>) -> String {
+        let engine = FakeEngine {
+            words,
+            tools: Vec::new(),
+        };
+        PageReader::new(Box::new(engine)).read(&GrayImage::new(768, 1024))
+    }
+
+    #[test]
+    fn reads_the_words_on_the_page() {
+        assert_eq!(
+            read(Some("Shopping: peanut butter, bread")),
+            "Shopping: peanut butter, bread"
+        );
+    }
+
+    #[test]
+    fn failed_calls_read_nothing() {
+        assert_eq!(read(None), "");
+    }
+}
@@ -1,7 +1,10 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use evdev::{Device, EventType, InputEvent};
-use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use crate::cancel::{CancelFlag, Cancelled};
+use crate::pacing::{Pacer, Pacing};
+use crate::util::Polyline;
 
 const INPUT_WIDTH: usize = 15725;
 const INPUT_HEIGHT: usize = 20966;
@@ -9,8 +12,167 @@ const INPUT_HEIGHT: usize = 20966;
 const REMARKABLE_WIDTH: u32 = 768;
 const REMARKABLE_HEIGHT: u32 = 1024;
 
+// ABS_PRESSURE when pressing as hard as the pen goes
+const MAX_PRESSURE: f32 = 2630.0;
+// ABS_TILT_X and ABS_TILT_Y range from minus this to this
+const MAX_TILT: f32 = 9000.0;
+// Distance in screen px between side-by-side passes of a wide line
+const PASS_SPACING: f32 = 1.5;
+// Pressure at the very ends of a tapered stroke, relative to the middle
+const TAPER_START: f32 = 0.3;
+
+// Distance in screen px between eraser passes, a bit under the eraser width
+const ERASER_SPACING: f32 = 6.0;
+// Longest jump in screen px between eraser positions along a path
+const ERASER_STEP: f32 = 2.0;
+
 pub struct Pen {
     device: Option<Device>,
+    pacer: Pacer,
+    cancel: CancelFlag,
+    // Where the pen was last sent, in digitizer coordinates
+    position: (i32, i32),
+    // The stroke the pen is in the middle of, and the ones finished so far
+    stroke: Option<Polyline>,
+    drawn: Vec<Polyline>,
+    // Paths the eraser was rubbed along
+    erased: Vec<Polyline>,
+    // Events counted instead of sent, while working out what strokes cost
+    counting: Option<u64>,
+}
+
+/// How the pen is held for a stroke
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PenStyle {
+    /// From 0 to 1 of full pressure; lighter lines are thinner and fainter
+    /// with pressure sensitive tools like the ballpoint and pencil
+    pub pressure: f32,
+    /// Lean of the pen along x and y, from -1 to 1, which shades the
+    /// pencil and marker tools
+    pub tilt: (f32, f32),
+    /// Distance in screen px over which the pressure builds up at the start
+    /// of a stroke and eases off at the end, like a hand would
+    pub taper: f32,
+    /// Side-by-side passes, for lines wider than the pen draws
+    pub passes: u32,
+}
+
+impl Default for PenStyle {
+    fn default() -> Self {
+        PenStyle {
+            pressure: 1.0,
+            tilt: (0.0, 0.0),
+            taper: 0.0,
+            passes: 1,
+        }
+    }
+}
+
+impl PenStyle {
+    /// A style for an SVG `stroke-width` in screen px: hairlines are drawn
+    /// light, ordinary lines as before, and wide lines with extra passes
+    pub fn for_width(width: f32) -> PenStyle {
+        if width < 1.0 {
+            PenStyle {
+                pressure: 0.4,
+                ..PenStyle::default()
+            }
+        } else if width < 3.0 {
+            PenStyle::default()
+        } else {
+            PenStyle {
+                passes: (((width - 2.0) / PASS_SPACING).ceil() as u32 + 1).min(8),
+                ..PenStyle::default()
+            }
+        }
+    }
+
+    /// Tapered strokes from a slightly leaning pen, for handwriting
+    pub fn handwriting() -> PenStyle {
+        PenStyle {
+            pressure: 0.85,
+            tilt: (0.25, -0.15),
+            taper: 6.0,
+            passes: 1,
+        }
+    }
+
+    /// About how wide the line comes out, in screen px, for previews
+    pub fn line_width(&self) -> f32 {
+        1.0 + self.pressure + (self.passes - 1) as f32 * PASS_SPACING
+    }
+
+    /// The stroke once for each pass, side by side, alternating direction
+    /// so each pass starts where the last one ended
+    pub fn passes(&self, stroke: &[(f32, f32)]) -> Vec<Polyline> {
+        if self.passes <= 1 || stroke.len() < 2 {
+            return vec![stroke.to_vec()];
+        }
+        let middle = (self.passes - 1) as f32 / 2.0;
+        (0..self.passes)
+            .map(|pass| {
+                let mut offset = offset_polyline(stroke, (pass as f32 - middle) * PASS_SPACING);
+                if pass % 2 == 1 {
+                    offset.reverse();
+                }
+                offset
+            })
+            .collect()
+    }
+
+    /// Pressure at each point of a stroke, from 0 to 1
+    fn pressures(&self, stroke: &[(f32, f32)]) -> Vec<f32> {
+        let mut along = Vec::with_capacity(stroke.len());
+        let mut total = 0.0;
+        for (i, &point) in stroke.iter().enumerate() {
+            if i > 0 {
+                let previous = stroke[i - 1];
+                total += ((point.0 - previous.0).powi(2) + (point.1 - previous.1).powi(2)).sqrt();
+            }
+            along.push(total);
+        }
+        along
+            .iter()
+            .map(|&distance| {
+                if self.taper <= 0.0 {
+                    return self.pressure;
+                }
+                let edge = distance.min(total - distance).max(0.0);
+                let ramp = (edge / self.taper).min(1.0);
+                self.pressure * (TAPER_START + (1.0 - TAPER_START) * ramp)
+            })
+            .collect()
+    }
+}
+
+// Shift each point of a polyline sideways, along the average normal of the
+// segments on either side of it
+fn offset_polyline(stroke: &[(f32, f32)], distance: f32) -> Polyline {
+    let normal = |a: (f32, f32), b: (f32, f32)| {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let length = (dx * dx + dy * dy).sqrt().max(f32::EPSILON);
+        (-dy / length, dx / length)
+    };
+    (0..stroke.len())
+        .map(|i| {
+            let before = if i > 0 {
+                normal(stroke[i - 1], stroke[i])
+            } else {
+                normal(stroke[0], stroke[1])
+            };
+            let after = if i + 1 < stroke.len() {
+                normal(stroke[i], stroke[i + 1])
+            } else {
+                before
+            };
+            let (nx, ny) = (before.0 + after.0, before.1 + after.1);
+            let length = (nx * nx + ny * ny).sqrt().max(f32::EPSILON);
+            (
+                stroke[i].0 + nx / length * distance,
+                stroke[i].1 + ny / length * distance,
+            )
+        })
+        .collect()
 }
 
 impl Pen {
@@ -21,7 +183,131 @@ impl Pen {
             Some(Device::open("/dev/input/touchscreen0").unwrap())
         };
 
-        Self { device }
+        Self {
+            device,
+            pacer: Pacer::new(Pacing::safe()),
+            cancel: CancelFlag::new(),
+            position: (0, 0),
+            stroke: None,
+            drawn: Vec::new(),
+            erased: Vec::new(),
+            counting: None,
+        }
+    }
+
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacer = Pacer::new(pacing);
+    }
+
+    /// Stop drawing, with the pen lifted, once this flag is cancelled
+    pub fn set_cancel(&mut self, cancel: CancelFlag) {
+        self.cancel = cancel;
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Run `f` without stopping when the user cancels, to clean up what a
+    /// cancelled answer left behind
+    pub fn ignoring_cancel<T>(&mut self, f: impl FnOnce(&mut Pen) -> Result<T>) -> Result<T> {
+        let cancel = std::mem::take(&mut self.cancel);
+        let result = f(self);
+        self.cancel = cancel;
+        result
+    }
+
+    /// Every stroke drawn since the last call, in screen coordinates, so
+    /// they can be journaled and erased again
+    pub fn take_drawn(&mut self) -> Vec<Polyline> {
+        self.finish_stroke();
+        std::mem::take(&mut self.drawn)
+    }
+
+    /// Every path the eraser was rubbed along since the last call, in
+    /// screen coordinates
+    pub fn take_erased(&mut self) -> Vec<Polyline> {
+        std::mem::take(&mut self.erased)
+    }
+
+    /// How many strokes are recorded so far, to forget back to later
+    pub fn strokes_drawn(&mut self) -> usize {
+        self.finish_stroke();
+        self.drawn.len()
+    }
+
+    /// Drop the strokes recorded after the first `count`, for marks that
+    /// were erased again and aren't part of the output
+    pub fn forget_strokes_after(&mut self, count: usize) {
+        self.finish_stroke();
+        self.drawn.truncate(count);
+    }
+
+    fn finish_stroke(&mut self) {
+        if let Some(stroke) = self.stroke.take() {
+            self.drawn.push(stroke);
+        }
+    }
+
+    // Follow the pen while it is down, to record the stroke it leaves
+    fn moved_to(&mut self, position: (i32, i32)) {
+        self.position = position;
+        if let Some(stroke) = &mut self.stroke {
+            stroke.push(input_to_screen_point(position));
+        }
+    }
+
+    /// Send events to the digitizer, no faster than the pacing allows
+    fn send(&mut self, events: &[InputEvent]) -> Result<()> {
+        if let Some(counted) = &mut self.counting {
+            *counted += events.len() as u64;
+            return Ok(());
+        }
+        if self.cancel.is_cancelled() {
+            self.lift()?;
+            return Err(Cancelled.into());
+        }
+        if let Some(device) = &mut self.device {
+            self.pacer.wait(events.len());
+            let start = Instant::now();
+            device.send_events(events)?;
+            self.pacer.sent(events.len(), start.elapsed());
+        }
+        Ok(())
+    }
+
+    /// Take the pen and eraser off the screen straight away, without pacing,
+    /// so a cancelled stroke doesn't leave the tool pressed down
+    fn lift(&mut self) -> Result<()> {
+        self.finish_stroke();
+        if let Some(device) = &mut self.device {
+            device.send_events(&[
+                InputEvent::new(EventType::ABSOLUTE, 24, 0), // ABS_PRESSURE
+                InputEvent::new(EventType::ABSOLUTE, 25, 100), // ABS_DISTANCE
+                InputEvent::new(EventType::KEY, 330, 0),     // BTN_TOUCH
+                InputEvent::new(EventType::KEY, 320, 0),     // BTN_TOOL_PEN
+                InputEvent::new(EventType::KEY, 321, 0),     // BTN_TOOL_RUBBER
+                InputEvent::new(EventType::SYNCHRONIZATION, 0, 0), // SYN_REPORT
+            ])?;
+        }
+        Ok(())
+    }
+
+    /// Cut strokes longer than the pacing allows into pieces that share
+    /// their end points, so the line stays unbroken
+    fn split_stroke<'a>(&self, stroke: &'a [(f32, f32)]) -> Vec<&'a [(f32, f32)]> {
+        let max = self.pacer.pacing.max_stroke_points.max(2);
+        if stroke.len() <= max {
+            return vec![stroke];
+        }
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        while start + 1 < stroke.len() {
+            let end = (start + max).min(stroke.len());
+            pieces.push(&stroke[start..end]);
+            start = end - 1;
+        }
+        pieces
     }
 
     pub fn draw_line_screen(&mut self, p1: (i32, i32), p2: (i32, i32)) -> Result<()> {
@@ -38,9 +324,8 @@ impl Pen {
         // And then for each step add the right amount to x and y
 
         let length = ((x2 as f32 - x1 as f32).powf(2.0) + (y2 as f32 - y1 as f32).powf(2.0)).sqrt();
-        // 5.0 is the maximum distance between points
-        // If this is too small
-        let steps = (length / 5.0).ceil() as i32;
+        // The pacing sets the maximum distance between points
+        let steps = (length / self.pacer.pacing.line_step).ceil() as i32;
         let dx = (x2 - x1) / steps;
         let dy = (y2 - y1) / steps;
         // println!(
@@ -64,7 +349,167 @@ impl Pen {
         Ok(())
     }
 
-    pub fn draw_bitmap(&mut self, bitmap: &Vec<Vec<bool>>) -> Result<()> {
+    /// Draw each polyline (in screen coordinates) as a single pen stroke
+    pub fn draw_strokes(&mut self, strokes: &[Polyline]) -> Result<()> {
+        for stroke in strokes {
+            for piece in self.split_stroke(stroke) {
+                self.draw_polyline_screen(piece)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Draw each polyline as a pen stroke held in the given style
+    pub fn draw_strokes_styled(&mut self, strokes: &[Polyline], style: &PenStyle) -> Result<()> {
+        for stroke in strokes {
+            for pass in style.passes(stroke) {
+                for piece in self.split_stroke(&pass) {
+                    self.draw_polyline_styled(piece, style)?;
+                }
+            }
+        }
+        if self.pacer.pacing.verbose {
+            println!(
+                "Pen: drew {} strokes, {} events sent so far",
+                strokes.len(),
+                self.pacer.events_sent()
+            );
+        }
+        Ok(())
+    }
+
+    /// How many input events `draw_strokes_styled` sends for these strokes,
+    /// found by going through the drawing without sending or pausing
+    pub fn stroke_events(&mut self, strokes: &[Polyline], style: &PenStyle) -> u64 {
+        let pacing = Pacing {
+            pen_pause: Duration::ZERO,
+            busy_cpu: None,
+            verbose: false,
+            ..self.pacer.pacing
+        };
+        let pacer = std::mem::replace(&mut self.pacer, Pacer::new(pacing));
+        let cancel = std::mem::take(&mut self.cancel);
+        let position = self.position;
+        let stroke = self.stroke.take();
+        let drawn = std::mem::take(&mut self.drawn);
+
+        self.counting = Some(0);
+        // Nothing is sent, so this can't fail
+        let _ = self.draw_strokes_styled(strokes, style);
+        let count = self.counting.take().unwrap_or(0);
+
+        self.pacer = pacer;
+        self.cancel = cancel;
+        self.position = position;
+        self.stroke = stroke;
+        self.drawn = drawn;
+        count
+    }
+
+    /// Input events sent to the digitizer so far
+    pub fn events_sent(&self) -> u64 {
+        self.pacer.events_sent()
+    }
+
+    fn draw_polyline_styled(&mut self, points: &[(f32, f32)], style: &PenStyle) -> Result<()> {
+        if *style == PenStyle::default() {
+            return self.draw_polyline_screen(points);
+        }
+        let Some(&first) = points.first() else {
+            return Ok(());
+        };
+        let pressures = style.pressures(points);
+        self.goto_xy(screen_point_to_input(first))?;
+        self.pen_down_styled(pressures[0], style.tilt)?;
+        self.pacer.pen_pause();
+        for (&point, &pressure) in points.iter().zip(&pressures).skip(1) {
+            if style.taper > 0.0 {
+                self.goto_xy_pressure(screen_point_to_input(point), pressure)?;
+            } else {
+                self.goto_xy(screen_point_to_input(point))?;
+            }
+        }
+        if points.len() == 1 {
+            self.goto_xy(screen_point_to_input((first.0 + 0.5, first.1)))?;
+        }
+        self.pen_up()?;
+        self.pacer.pen_pause();
+        Ok(())
+    }
+
+    pub fn draw_polyline_screen(&mut self, points: &[(f32, f32)]) -> Result<()> {
+        let Some(&first) = points.first() else {
+            return Ok(());
+        };
+        self.goto_xy(screen_point_to_input(first))?;
+        self.pen_down()?;
+        self.pacer.pen_pause();
+        for &point in &points[1..] {
+            self.goto_xy(screen_point_to_input(point))?;
+        }
+        if points.len() == 1 {
+            // A lone point needs a little movement to leave a dot
+            self.goto_xy(screen_point_to_input((first.0 + 0.5, first.1)))?;
+        }
+        self.pen_up()?;
+        self.pacer.pen_pause();
+        Ok(())
+    }
+
+    /// Rub the eraser along a path in screen coordinates
+    pub fn erase_stroke(&mut self, points: &[(f32, f32)]) -> Result<()> {
+        let Some(&first) = points.first() else {
+            return Ok(());
+        };
+        self.erased.push(points.to_vec());
+        self.goto_xy(screen_point_to_input(first))?;
+        self.eraser_down()?;
+        self.pacer.pen_pause();
+        // Fill in long segments so the eraser doesn't skip over ink
+        let mut previous = first;
+        for &point in &points[1..] {
+            let length = ((point.0 - previous.0).powi(2) + (point.1 - previous.1).powi(2)).sqrt();
+            let steps = (length / ERASER_STEP).ceil().max(1.0) as usize;
+            for i in 1..=steps {
+                let t = i as f32 / steps as f32;
+                self.goto_xy(screen_point_to_input((
+                    previous.0 + (point.0 - previous.0) * t,
+                    previous.1 + (point.1 - previous.1) * t,
+                )))?;
+            }
+            previous = point;
+        }
+        if points.len() == 1 {
+            self.goto_xy(screen_point_to_input((first.0 + 0.5, first.1)))?;
+        }
+        self.eraser_up()?;
+        self.pacer.pen_pause();
+        Ok(())
+    }
+
+    /// Erase everything inside a polygon given in screen coordinates
+    pub fn erase_polygon(&mut self, polygon: &[(f32, f32)]) -> Result<()> {
+        let paths = eraser_paths(scanline_spans(polygon, ERASER_SPACING));
+        if paths.is_empty() {
+            bail!("The area to erase is empty");
+        }
+        for path in paths {
+            self.erase_stroke(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Erase everything inside a rectangle given in screen coordinates
+    pub fn erase_rect(&mut self, (x, y): (f32, f32), (width, height): (f32, f32)) -> Result<()> {
+        self.erase_polygon(&[
+            (x, y),
+            (x + width, y),
+            (x + width, y + height),
+            (x, y + height),
+        ])
+    }
+
+    pub fn draw_bitmap(&mut self, bitmap: &[Vec<bool>]) -> Result<()> {
         let mut is_pen_down = false;
         for (y, row) in bitmap.iter().enumerate() {
             for (x, &pixel) in row.iter().enumerate() {
@@ -73,19 +518,19 @@ impl Pen {
                         self.goto_xy_screen((x as i32, y as i32))?;
                         self.pen_down()?;
                         is_pen_down = true;
-                        sleep(Duration::from_millis(1));
+                        self.pacer.pen_pause();
                     }
                     self.goto_xy_screen((x as i32, y as i32))?;
                     self.goto_xy_screen((x as i32 + 1, y as i32))?;
                 } else if is_pen_down {
                     self.pen_up()?;
                     is_pen_down = false;
-                    sleep(Duration::from_millis(1));
+                    self.pacer.pen_pause();
                 }
             }
             self.pen_up()?;
             is_pen_down = false;
-            sleep(Duration::from_millis(5));
+            self.pacer.row_pause();
         }
         Ok(())
     }
@@ -109,28 +554,78 @@ impl Pen {
     // }
 
     pub fn pen_down(&mut self) -> Result<()> {
-        if let Some(device) = &mut self.device {
-            device.send_events(&[
-                InputEvent::new(EventType::KEY, 320, 1), // BTN_TOOL_PEN
-                InputEvent::new(EventType::KEY, 330, 1), // BTN_TOUCH
-                InputEvent::new(EventType::ABSOLUTE, 24, 2630), // ABS_PRESSURE (max pressure)
-                InputEvent::new(EventType::ABSOLUTE, 25, 0), // ABS_DISTANCE
-                InputEvent::new(EventType::SYNCHRONIZATION, 0, 0), // SYN_REPORT
-            ])?;
-        }
+        // Level, so a lean from an earlier stroke doesn't carry over
+        self.pen_down_styled(1.0, (0.0, 0.0))
+    }
+
+    /// Put the pen down with part of full pressure and a lean
+    pub fn pen_down_styled(&mut self, pressure: f32, (tilt_x, tilt_y): (f32, f32)) -> Result<()> {
+        self.send(&[
+            InputEvent::new(EventType::KEY, 320, 1), // BTN_TOOL_PEN
+            InputEvent::new(EventType::KEY, 330, 1), // BTN_TOUCH
+            InputEvent::new(EventType::ABSOLUTE, 24, to_pressure(pressure)), // ABS_PRESSURE
+            InputEvent::new(EventType::ABSOLUTE, 25, 0), // ABS_DISTANCE
+            InputEvent::new(EventType::ABSOLUTE, 26, to_tilt(tilt_x)), // ABS_TILT_X
+            InputEvent::new(EventType::ABSOLUTE, 27, to_tilt(tilt_y)), // ABS_TILT_Y
+            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0), // SYN_REPORT
+        ])?;
+        self.finish_stroke();
+        self.stroke = Some(vec![input_to_screen_point(self.position)]);
         Ok(())
     }
 
     pub fn pen_up(&mut self) -> Result<()> {
-        if let Some(device) = &mut self.device {
-            device.send_events(&[
-                InputEvent::new(EventType::ABSOLUTE, 24, 0), // ABS_PRESSURE
-                InputEvent::new(EventType::ABSOLUTE, 25, 100), // ABS_DISTANCE
-                InputEvent::new(EventType::KEY, 330, 0),     // BTN_TOUCH
-                InputEvent::new(EventType::KEY, 320, 0),     // BTN_TOOL_PEN
-                InputEvent::new(EventType::SYNCHRONIZATION, 0, 0), // SYN_REPORT
-            ])?;
-        }
+        self.send(&[
+            InputEvent::new(EventType::ABSOLUTE, 24, 0), // ABS_PRESSURE
+            InputEvent::new(EventType::ABSOLUTE, 25, 100), // ABS_DISTANCE
+            InputEvent::new(EventType::KEY, 330, 0),     // BTN_TOUCH
+            InputEvent::new(EventType::KEY, 320, 0),     // BTN_TOOL_PEN
+            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0), // SYN_REPORT
+        ])?;
+        self.finish_stroke();
+        Ok(())
+    }
+
+    pub fn eraser_down(&mut self) -> Result<()> {
+        // Bring the eraser end into range first, like flipping the marker
+        self.send(&[
+            InputEvent::new(EventType::KEY, 321, 1), // BTN_TOOL_RUBBER
+            InputEvent::new(EventType::ABSOLUTE, 25, 10), // ABS_DISTANCE (hovering)
+            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0), // SYN_REPORT
+        ])?;
+        self.send(&[
+            InputEvent::new(EventType::KEY, 330, 1), // BTN_TOUCH
+            InputEvent::new(EventType::ABSOLUTE, 24, to_pressure(1.0)), // ABS_PRESSURE (max pressure)
+            InputEvent::new(EventType::ABSOLUTE, 25, 0),                // ABS_DISTANCE
+            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),          // SYN_REPORT
+        ])?;
+        Ok(())
+    }
+
+    pub fn eraser_up(&mut self) -> Result<()> {
+        self.send(&[
+            InputEvent::new(EventType::ABSOLUTE, 24, 0), // ABS_PRESSURE
+            InputEvent::new(EventType::ABSOLUTE, 25, 10), // ABS_DISTANCE (hovering)
+            InputEvent::new(EventType::KEY, 330, 0),     // BTN_TOUCH
+            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0), // SYN_REPORT
+        ])?;
+        self.send(&[
+            InputEvent::new(EventType::ABSOLUTE, 25, 100), // ABS_DISTANCE
+            InputEvent::new(EventType::KEY, 321, 0),       // BTN_TOOL_RUBBER
+            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0), // SYN_REPORT
+        ])?;
+        Ok(())
+    }
+
+    /// Move while pressing with part of full pressure
+    pub fn goto_xy_pressure(&mut self, (x, y): (i32, i32), pressure: f32) -> Result<()> {
+        self.send(&[
+            InputEvent::new(EventType::ABSOLUTE, 0, x), // ABS_X
+            InputEvent::new(EventType::ABSOLUTE, 1, y), // ABS_Y
+            InputEvent::new(EventType::ABSOLUTE, 24, to_pressure(pressure)), // ABS_PRESSURE
+            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0), // SYN_REPORT
+        ])?;
+        self.moved_to((x, y));
         Ok(())
     }
 
@@ -139,22 +634,163 @@ impl Pen {
     }
 
     pub fn goto_xy(&mut self, (x, y): (i32, i32)) -> Result<()> {
-        if let Some(device) = &mut self.device {
-            device.send_events(&[
-                InputEvent::new(EventType::ABSOLUTE, 0, x),        // ABS_X
-                InputEvent::new(EventType::ABSOLUTE, 1, y),        // ABS_Y
-                InputEvent::new(EventType::SYNCHRONIZATION, 0, 0), // SYN_REPORT
-            ])?;
-        }
+        self.send(&[
+            InputEvent::new(EventType::ABSOLUTE, 0, x),        // ABS_X
+            InputEvent::new(EventType::ABSOLUTE, 1, y),        // ABS_Y
+            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0), // SYN_REPORT
+        ])?;
+        self.moved_to((x, y));
         Ok(())
     }
 }
+fn to_pressure(pressure: f32) -> i32 {
+    (pressure.clamp(0.0, 1.0) * MAX_PRESSURE) as i32
+}
+
+fn to_tilt(tilt: f32) -> i32 {
+    (tilt.clamp(-1.0, 1.0) * MAX_TILT) as i32
+}
+
 fn screen_to_input((x, y): (i32, i32)) -> (i32, i32) {
+    screen_point_to_input((x as f32, y as f32))
+}
+
+// The pen digitizer is much finer than the screen, so keep sub-pixel positions
+fn screen_point_to_input((x, y): (f32, f32)) -> (i32, i32) {
     // Swap and normalize the coordinates
-    let x_normalized = x as f32 / REMARKABLE_WIDTH as f32;
-    let y_normalized = y as f32 / REMARKABLE_HEIGHT as f32;
+    let x_normalized = x / REMARKABLE_WIDTH as f32;
+    let y_normalized = y / REMARKABLE_HEIGHT as f32;
 
     let x_input = ((1.0 - y_normalized) * INPUT_HEIGHT as f32) as i32;
     let y_input = (x_normalized * INPUT_WIDTH as f32) as i32;
     (x_input, y_input)
 }
+
+// The other way round, for recording where the pen went
+fn input_to_screen_point((x, y): (i32, i32)) -> (f32, f32) {
+    let x_screen = y as f32 / INPUT_WIDTH as f32 * REMARKABLE_WIDTH as f32;
+    let y_screen = (1.0 - x as f32 / INPUT_HEIGHT as f32) * REMARKABLE_HEIGHT as f32;
+    (x_screen, y_screen)
+}
+
+// The start and end of a horizontal eraser pass
+type Span = ((f32, f32), (f32, f32));
+
+// Eraser strokes along the rows of spans. A row with one span is joined to
+// the one above in a zigzag when they overlap, so the eraser stays inside the
+// polygon between them; otherwise it is lifted.
+fn eraser_paths(rows: Vec<Vec<Span>>) -> Vec<Polyline> {
+    let mut paths = Vec::new();
+    let mut path: Polyline = Vec::new();
+    for row in rows {
+        let joins = match (row.as_slice(), path.as_slice()) {
+            ([(start, end)], [.., before, last]) => {
+                let (left, right) = (before.0.min(last.0), before.0.max(last.0));
+                start.0 <= right && end.0 >= left && start.1 - last.1 <= ERASER_SPACING * 1.5
+            }
+            _ => false,
+        };
+        if !joins && !path.is_empty() {
+            paths.push(std::mem::take(&mut path));
+        }
+        match row.as_slice() {
+            [(start, end)] => {
+                // Carry on from whichever end the last pass finished at
+                if path.last().is_some_and(|last| last.0 > start.0) {
+                    path.extend([*end, *start]);
+                } else {
+                    path.extend([*start, *end]);
+                }
+            }
+            spans => paths.extend(spans.iter().map(|(start, end)| vec![*start, *end])),
+        }
+    }
+    if !path.is_empty() {
+        paths.push(path);
+    }
+    paths
+}
+
+// Horizontal passes covering a polygon, one row every `spacing` px, each row
+// being the (start, end) spans that fall inside the polygon
+fn scanline_spans(polygon: &[(f32, f32)], spacing: f32) -> Vec<Vec<Span>> {
+    if polygon.len() < 3 {
+        return Vec::new();
+    }
+    let min_y = polygon.iter().map(|p| p.1).fold(f32::MAX, f32::min);
+    let max_y = polygon.iter().map(|p| p.1).fold(f32::MIN, f32::max);
+    let mut rows = Vec::new();
+    // Shapes thinner than the spacing still get a pass through the middle
+    let mut y = min_y + (spacing / 2.0).min((max_y - min_y) / 2.0);
+    while y < max_y {
+        let mut crossings: Vec<f32> = polygon
+            .iter()
+            .zip(polygon.iter().cycle().skip(1))
+            .filter(|(a, b)| (a.1 <= y) != (b.1 <= y))
+            .map(|(a, b)| a.0 + (y - a.1) / (b.1 - a.1) * (b.0 - a.0))
+            .collect();
+        crossings.sort_by(f32::total_cmp);
+        let row: Vec<_> = crossings
+            .chunks_exact(2)
+            .map(|span| ((span[0], y), (span[1], y)))
+            .collect();
+        if !row.is_empty() {
+            rows.push(row);
+        }
+        y += spacing;
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(polygon: &[(f32, f32)]) -> Vec<Polyline> {
+        eraser_paths(scanline_spans(polygon, ERASER_SPACING))
+    }
+
+    #[test]
+    fn rectangle_is_one_zigzag() {
+        let paths = paths(&[(0.0, 0.0), (20.0, 0.0), (20.0, 30.0), (0.0, 30.0)]);
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].len(), 10);
+        assert!(paths[0]
+            .iter()
+            .all(|&(x, y)| (0.0..=20.0).contains(&x) && y < 30.0));
+    }
+
+    #[test]
+    fn rows_that_do_not_overlap_are_not_joined() {
+        // A steep slant, where each row is off to the side of the one above
+        let paths = paths(&[(0.0, 0.0), (10.0, 0.0), (110.0, 20.0), (100.0, 20.0)]);
+        assert_eq!(paths.len(), 3);
+        assert!(paths.iter().all(|path| path.len() == 2));
+    }
+
+    #[test]
+    fn thin_rectangle_still_gets_a_pass() {
+        let paths = paths(&[(0.0, 10.0), (20.0, 10.0), (20.0, 12.0), (0.0, 12.0)]);
+        assert_eq!(paths, vec![vec![(0.0, 11.0), (20.0, 11.0)]]);
+        assert!(eraser_paths(scanline_spans(
+            &[(0.0, 10.0), (20.0, 10.0), (20.0, 10.0)],
+            ERASER_SPACING
+        ))
+        .is_empty());
+    }
+
+    #[test]
+    fn counted_events_include_split_pieces() {
+        let mut pen = Pen::new(true);
+        let stroke: Polyline = (0..30).map(|i| (i as f32 * 2.0, 100.0)).collect();
+        let whole = pen.stroke_events(std::slice::from_ref(&stroke), &PenStyle::default());
+        pen.set_pacing(Pacing {
+            max_stroke_points: 10,
+            ..Pacing::safe()
+        });
+        let split = pen.stroke_events(&[stroke], &PenStyle::default());
+        assert!(split > whole);
+        assert_eq!(pen.events_sent(), 0);
+        assert!(pen.take_drawn().is_empty());
+    }
+}
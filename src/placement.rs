@@ -0,0 +1,390 @@
+use image::GrayImage;
+
+use crate::handwriting::text_bounds;
+
+const REMARKABLE_WIDTH: u32 = 768;
+const REMARKABLE_HEIGHT: u32 = 1024;
+
+// Keep text at least this far from existing ink and the screen edge
+const PADDING: f32 = 6.0;
+// Space between the anchor and the side of the text facing it
+const ANCHOR_GAP: f32 = 10.0;
+// How far in px text may be nudged away from where it was asked to go
+const MAX_NUDGE: i32 = 240;
+const NUDGE_STEP: i32 = 4;
+// Smallest size text shrinks to, relative to the requested size
+const MIN_SIZE_RATIO: f32 = 0.5;
+const MIN_SIZE: f32 = 14.0;
+// A few stray pixels of noise don't count as a collision
+const INK_TOLERANCE: u32 = 3;
+
+/// Counts of dark pixels, summed so any rectangle can be checked quickly
+pub struct InkMap {
+    width: usize,
+    height: usize,
+    sums: Vec<u32>,
+}
+
+impl InkMap {
+    pub fn new(image: &GrayImage) -> InkMap {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let mut sums = vec![0u32; (width + 1) * (height + 1)];
+        for y in 0..height {
+            let mut row = 0;
+            for x in 0..width {
+                if image.get_pixel(x as u32, y as u32)[0] < 128 {
+                    row += 1;
+                }
+                sums[(y + 1) * (width + 1) + x + 1] = sums[y * (width + 1) + x + 1] + row;
+            }
+        }
+        InkMap {
+            width,
+            height,
+            sums,
+        }
+    }
+
+    /// Number of ink pixels in the rectangle from (x0, y0) to (x1, y1)
+    pub fn ink_in(&self, (x0, y0): (f32, f32), (x1, y1): (f32, f32)) -> u32 {
+        let clamp_x = |x: f32| (x.max(0.0) as usize).min(self.width);
+        let clamp_y = |y: f32| (y.max(0.0) as usize).min(self.height);
+        let (x0, x1) = (clamp_x(x0), clamp_x(x1));
+        let (y0, y1) = (clamp_y(y0), clamp_y(y1));
+        if x1 <= x0 || y1 <= y0 {
+            return 0;
+        }
+        let at = |x: usize, y: usize| self.sums[y * (self.width + 1) + x];
+        at(x1, y1) + at(x0, y0) - at(x0, y1) - at(x1, y0)
+    }
+}
+
+/// Which side of the anchor point the text goes on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Right,
+    Left,
+    Above,
+    Below,
+    Center,
+}
+
+impl Side {
+    pub fn parse(side: &str) -> Option<Side> {
+        match side.to_lowercase().as_str() {
+            "right" => Some(Side::Right),
+            "left" => Some(Side::Left),
+            "above" => Some(Side::Above),
+            "below" => Some(Side::Below),
+            "center" => Some(Side::Center),
+            _ => None,
+        }
+    }
+
+    // Top-left corner of a box of the given size placed on this side
+    fn origin(&self, (x, y): (f32, f32), (width, height): (f32, f32)) -> (f32, f32) {
+        match self {
+            Side::Right => (x + ANCHOR_GAP, y - height / 2.0),
+            Side::Left => (x - ANCHOR_GAP - width, y - height / 2.0),
+            Side::Above => (x - width / 2.0, y - ANCHOR_GAP - height),
+            Side::Below => (x - width / 2.0, y + ANCHOR_GAP),
+            Side::Center => (x - width / 2.0, y - height / 2.0),
+        }
+    }
+
+    // Width available for wrapping before the text reaches the screen edge
+    fn available_width(&self, (x, _): (f32, f32)) -> f32 {
+        match self {
+            Side::Right => REMARKABLE_WIDTH as f32 - PADDING - x - ANCHOR_GAP,
+            Side::Left => x - ANCHOR_GAP - PADDING,
+            _ => REMARKABLE_WIDTH as f32 - 2.0 * PADDING,
+        }
+    }
+
+    // Width of the ink-free space one line high where the text would go,
+    // reaching away from the anchor, or both ways when centred on it
+    fn free_width(&self, ink: &InkMap, (x, y): (f32, f32), line_height: f32) -> f32 {
+        let (top, bottom) = match self {
+            Side::Above => (y - ANCHOR_GAP - line_height, y - ANCHOR_GAP),
+            Side::Below => (y + ANCHOR_GAP, y + ANCHOR_GAP + line_height),
+            _ => (y - line_height / 2.0, y + line_height / 2.0),
+        };
+        let (top, bottom) = (top - PADDING, bottom + PADDING);
+        // Ink only grows with the span, so the widest free one is found by
+        // bisection
+        let widest = |limit: f32, span: &dyn Fn(f32) -> (f32, f32)| {
+            let is_free = |extent: f32| {
+                let (left, right) = span(extent);
+                ink.ink_in((left, top), (right, bottom)) <= INK_TOLERANCE
+            };
+            let limit = limit.max(0.0);
+            if is_free(limit) {
+                return limit;
+            }
+            let (mut free, mut inked) = (0.0, limit);
+            while inked - free > 1.0 {
+                let middle = (free + inked) / 2.0;
+                if is_free(middle) {
+                    free = middle;
+                } else {
+                    inked = middle;
+                }
+            }
+            free
+        };
+        let screen = REMARKABLE_WIDTH as f32;
+        let width = match self {
+            Side::Right => {
+                let left = x + ANCHOR_GAP - PADDING;
+                widest(screen - left, &|width| (left, left + width))
+            }
+            Side::Left => {
+                let right = x - ANCHOR_GAP + PADDING;
+                widest(right, &|width| (right - width, right))
+            }
+            _ => 2.0 * widest(x.min(screen - x), &|half| (x - half, x + half)),
+        };
+        width - 2.0 * PADDING
+    }
+
+    // Moving further away from the anchor reads better than moving across
+    // it, and moving off the line reads worst
+    fn nudge_cost(&self, (dx, dy): (f32, f32)) -> f32 {
+        let (along, across) = match self {
+            Side::Right => (dx, dy),
+            Side::Left => (-dx, dy),
+            Side::Above => (-dy, dx),
+            Side::Below => (dy, dx),
+            Side::Center => return (dx * dx + dy * dy).sqrt(),
+        };
+        let along = if along >= 0.0 {
+            along * 0.5
+        } else {
+            -along * 2.0
+        };
+        along + across.abs() * 1.5
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    /// Top-left corner of the text, as taken by `text_to_strokes`
+    pub origin: (f32, f32),
+    pub size: f32,
+    pub max_width: f32,
+    pub bounds: (f32, f32),
+    /// No free spot was found, so the text is at the anchor regardless
+    pub overlaps: bool,
+}
+
+/// Find where to write `text` near `anchor` without touching existing ink.
+/// The text is written as large as fits on one line in the free space
+/// there, up to `size`, and only wraps below half that; it shrinks further
+/// if no spot fits.
+pub fn place_text(
+    ink: &InkMap,
+    text: &str,
+    anchor: (f32, f32),
+    side: Side,
+    size: f32,
+    max_width: Option<f32>,
+) -> Placement {
+    let min_size = (size * MIN_SIZE_RATIO).max(MIN_SIZE).min(size);
+    let line_height = text_bounds("", size, f32::INFINITY).1;
+    // Ink right at the anchor is left for the nudging below to get around
+    let free_width = side.free_width(ink, anchor, line_height);
+    let max_width = max_width
+        .unwrap_or_else(|| side.available_width(anchor))
+        .min(if free_width >= min_size * 2.0 {
+            free_width
+        } else {
+            f32::INFINITY
+        })
+        .max(min_size * 2.0);
+
+    // Text width grows with its size, so scale it down to the free width,
+    // then make sure rounding didn't leave it a hair too wide
+    let line_width = text_bounds(text, size, f32::INFINITY).0;
+    let mut fitted_size = size;
+    if line_width > max_width {
+        fitted_size = (size * max_width / line_width).max(min_size);
+        while fitted_size > min_size && text_bounds(text, fitted_size, f32::INFINITY).0 > max_width
+        {
+            fitted_size = (fitted_size * 0.99).max(min_size);
+        }
+    }
+
+    let mut nudges: Vec<(f32, f32)> = (-MAX_NUDGE..=MAX_NUDGE)
+        .step_by(NUDGE_STEP as usize)
+        .flat_map(|dx| {
+            (-MAX_NUDGE..=MAX_NUDGE)
+                .step_by(NUDGE_STEP as usize)
+                .map(move |dy| (dx as f32, dy as f32))
+        })
+        .filter(|(dx, dy)| dx * dx + dy * dy <= (MAX_NUDGE * MAX_NUDGE) as f32)
+        .collect();
+    nudges.sort_by(|a, b| side.nudge_cost(*a).total_cmp(&side.nudge_cost(*b)));
+
+    let mut candidate_size = fitted_size;
+    loop {
+        let bounds = text_bounds(text, candidate_size, max_width);
+        let (x, y) = side.origin(anchor, bounds);
+        let free = nudges.iter().find_map(|(dx, dy)| {
+            let top_left = (x + dx - PADDING, y + dy - PADDING);
+            let bottom_right = (x + dx + bounds.0 + PADDING, y + dy + bounds.1 + PADDING);
+            let on_screen = top_left.0 >= 0.0
+                && top_left.1 >= 0.0
+                && bottom_right.0 <= REMARKABLE_WIDTH as f32
+                && bottom_right.1 <= REMARKABLE_HEIGHT as f32;
+            (on_screen && ink.ink_in(top_left, bottom_right) <= INK_TOLERANCE)
+                .then_some((x + dx, y + dy))
+        });
+        if let Some(origin) = free {
+            return Placement {
+                origin,
+                size: candidate_size,
+                max_width,
+                bounds,
+                overlaps: false,
+            };
+        }
+        if candidate_size <= min_size {
+            break;
+        }
+        candidate_size = (candidate_size * 0.9).max(min_size);
+    }
+
+    let bounds = text_bounds(text, fitted_size, max_width);
+    let (x, y) = side.origin(anchor, bounds);
+    Placement {
+        origin: (
+            x.clamp(0.0, (REMARKABLE_WIDTH as f32 - bounds.0).max(0.0)),
+            y.clamp(0.0, (REMARKABLE_HEIGHT as f32 - bounds.1).max(0.0)),
+        ),
+        size: fitted_size,
+        max_width,
+        bounds,
+        overlaps: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    // A blank screen with the given rectangles, as (x, y, width, height),
+    // filled with ink
+    fn screen(inked: &[(u32, u32, u32, u32)]) -> InkMap {
+        let mut image = GrayImage::from_pixel(REMARKABLE_WIDTH, REMARKABLE_HEIGHT, Luma([255]));
+        for &(left, top, width, height) in inked {
+            for y in top..top + height {
+                for x in left..left + width {
+                    image.put_pixel(x, y, Luma([0]));
+                }
+            }
+        }
+        InkMap::new(&image)
+    }
+
+    fn overlaps_ink(ink: &InkMap, placement: &Placement) -> bool {
+        let (x, y) = placement.origin;
+        let (width, height) = placement.bounds;
+        ink.ink_in((x, y), (x + width, y + height)) > 0
+    }
+
+    #[test]
+    fn ink_is_counted_in_any_rectangle() {
+        let ink = screen(&[(10, 20, 5, 4), (100, 100, 1, 1)]);
+        assert_eq!(ink.ink_in((0.0, 0.0), (768.0, 1024.0)), 21);
+        assert_eq!(ink.ink_in((10.0, 20.0), (15.0, 24.0)), 20);
+        assert_eq!(ink.ink_in((12.0, 22.0), (13.0, 23.0)), 1);
+        assert_eq!(ink.ink_in((11.5, 20.0), (14.0, 21.0)), 3);
+        assert_eq!(ink.ink_in((15.0, 0.0), (100.0, 100.0)), 0);
+        assert_eq!(ink.ink_in((-50.0, -50.0), (2000.0, 2000.0)), 21);
+        assert_eq!(ink.ink_in((20.0, 20.0), (10.0, 30.0)), 0);
+    }
+
+    #[test]
+    fn text_goes_beside_the_anchor_on_a_blank_page() {
+        let ink = screen(&[]);
+        let placement = place_text(&ink, "42", (300.0, 400.0), Side::Right, 32.0, None);
+        assert!(!placement.overlaps);
+        assert_eq!(placement.size, 32.0);
+        assert_eq!(placement.origin.0, 300.0 + ANCHOR_GAP);
+        let middle = placement.origin.1 + placement.bounds.1 / 2.0;
+        assert!((middle - 400.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn text_is_nudged_off_ink() {
+        let ink = screen(&[(300, 370, 200, 60)]);
+        let placement = place_text(&ink, "42", (290.0, 400.0), Side::Right, 32.0, None);
+        assert!(!placement.overlaps);
+        assert!(!overlaps_ink(&ink, &placement));
+        assert_eq!(placement.size, 32.0);
+        let (dx, dy) = (
+            placement.origin.0 - (290.0 + ANCHOR_GAP),
+            placement.origin.1 + placement.bounds.1 / 2.0 - 400.0,
+        );
+        assert!(dx * dx + dy * dy <= (MAX_NUDGE * MAX_NUDGE) as f32);
+    }
+
+    #[test]
+    fn text_is_not_nudged_further_than_the_limit() {
+        // Everything within well over 240 px of the anchor is inked
+        let ink = screen(&[(0, 100, 768, 600)]);
+        let placement = place_text(&ink, "42", (300.0, 400.0), Side::Right, 32.0, None);
+        assert!(placement.overlaps);
+        assert_eq!(placement.origin.0, 300.0 + ANCHOR_GAP);
+    }
+
+    #[test]
+    fn text_shrinks_into_a_small_gap() {
+        // The only free spot nearby is a gap 30 px high, too low for 32 px
+        // text but tall enough at about half the size
+        let ink = screen(&[(0, 0, 768, 385), (0, 415, 768, 609)]);
+        let placement = place_text(&ink, "42", (100.0, 400.0), Side::Right, 32.0, None);
+        assert!(!placement.overlaps);
+        assert!(!overlaps_ink(&ink, &placement));
+        assert!(placement.size < 32.0);
+        assert!(placement.size >= 32.0 * MIN_SIZE_RATIO);
+    }
+
+    #[test]
+    fn text_does_not_shrink_below_half_size() {
+        let ink = screen(&[(0, 0, 768, 395), (0, 405, 768, 619)]);
+        let placement = place_text(&ink, "42", (100.0, 400.0), Side::Right, 40.0, None);
+        assert!(placement.overlaps);
+        assert_eq!(placement.size, 40.0);
+    }
+
+    #[test]
+    fn text_is_sized_to_the_free_width_before_wrapping() {
+        let text = "the answer is forty two";
+        let full_width = text_bounds(text, 32.0, f32::INFINITY).0;
+        // Ink to the right leaves about three quarters of the line free
+        let wall = (100.0 + ANCHOR_GAP + 2.0 * PADDING + full_width * 0.75) as u32;
+        let ink = screen(&[(wall, 0, 768 - wall, 1024)]);
+        let placement = place_text(&ink, text, (100.0, 400.0), Side::Right, 32.0, None);
+        let one_line = text_bounds("", placement.size, f32::INFINITY).1;
+        assert!(!placement.overlaps);
+        assert!(!overlaps_ink(&ink, &placement));
+        assert!(placement.size < 32.0 && placement.size > 32.0 * 0.7);
+        assert_eq!(placement.bounds.1, one_line);
+    }
+
+    #[test]
+    fn text_wraps_when_half_size_is_still_too_wide() {
+        let text = "the answer is forty two";
+        let half_width = text_bounds(text, 16.0, f32::INFINITY).0;
+        let wall = (100.0 + ANCHOR_GAP + 2.0 * PADDING + half_width * 0.6) as u32;
+        let ink = screen(&[(wall, 0, 768 - wall, 1024)]);
+        let placement = place_text(&ink, text, (100.0, 400.0), Side::Right, 32.0, None);
+        let one_line = text_bounds("", placement.size, f32::INFINITY).1;
+        assert!(!placement.overlaps);
+        assert!(!overlaps_ink(&ink, &placement));
+        assert_eq!(placement.size, 16.0);
+        assert!(placement.bounds.1 > one_line);
+    }
+}
@@ -0,0 +1,383 @@
+//! Tools written as WebAssembly modules. A plugin is a `<name>.wasm` file
+//! next to a `<name>.json` tool definition in the plugins directory, in the
+//! same format as the built-in tool files. Its name can't be one that a
+//! built-in tool or another plugin already uses. The definition may also
+//! set limits:
+//!
+//! ```json
+//! "limits": { "fuel": 100000000, "timeout_ms": 5000, "memory_mb": 64 }
+//! ```
+//!
+//! Limits above the host's maximums are lowered to them. A call that times
+//! out can't be stopped, but runs out of fuel soon after; until it does the
+//! plugin turns down new calls.
+//!
+//! The module must export its `memory` and two functions:
+//!
+//! * `alloc(len: i32) -> i32` returns space for the input
+//! * `run(ptr: i32, len: i32) -> i64` gets the tool arguments as UTF-8 JSON
+//!   and returns where its output is, as `ptr << 32 | len`
+//!
+//! The output is JSON: `{"result": ...}` is handed to the model and
+//! `{"error": "..."}` reports a failure. The only import available is
+//! `env.log(ptr: i32, len: i32)`, which prints a message; there is no access
+//! to files, the network or the clock. Plugins loop back to the model with
+//! their result unless the definition sets another `next_action`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value as json;
+use std::fs;
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use wasmi::{
+    Caller, Config, Engine, Extern, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+const DEFAULT_FUEL: u64 = 100_000_000;
+const DEFAULT_TIMEOUT_MS: u64 = 5_000;
+const DEFAULT_MEMORY_MB: u64 = 64;
+// The most a plugin may ask for
+const MAX_FUEL: u64 = 1_000_000_000;
+const MAX_TIMEOUT_MS: u64 = 30_000;
+const MAX_MEMORY_MB: u64 = 256;
+
+#[derive(Clone, Copy)]
+struct Limits {
+    fuel: u64,
+    timeout: Duration,
+    memory: usize,
+}
+
+pub struct Plugin {
+    pub name: String,
+    pub definition: json,
+    engine: Engine,
+    module: Arc<Module>,
+    limits: Limits,
+    /// The thread of the last call, which may still be running if it timed out
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct HostState {
+    name: String,
+    limits: StoreLimits,
+}
+
+/// Load every plugin in `dir`. A plugin that fails to load, or whose name
+/// is in `taken` or already used by another plugin, is reported and skipped
+/// so it can't stop the others.
+pub fn load_plugins(dir: &Path, taken: &[String]) -> Vec<Plugin> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let engine = Engine::new(Config::default().consume_fuel(true));
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .collect();
+    paths.sort();
+
+    let mut plugins: Vec<Plugin> = Vec::new();
+    for path in paths {
+        let plugin = Plugin::load(&engine, &path).and_then(|plugin| {
+            if taken.contains(&plugin.name) {
+                bail!("{} is the name of a built-in tool", plugin.name);
+            }
+            if plugins.iter().any(|other| other.name == plugin.name) {
+                bail!("another plugin is already called {}", plugin.name);
+            }
+            Ok(plugin)
+        });
+        match plugin {
+            Ok(plugin) => {
+                println!("Loaded plugin {}", plugin.name);
+                plugins.push(plugin);
+            }
+            Err(e) => println!("Skipping plugin {}: {:#}", path.display(), e),
+        }
+    }
+    plugins
+}
+
+impl Plugin {
+    fn load(engine: &Engine, definition_path: &Path) -> Result<Plugin> {
+        let mut definition: json = serde_json::from_str(&fs::read_to_string(definition_path)?)?;
+        let name = definition["name"]
+            .as_str()
+            .ok_or_else(|| anyhow!("The tool definition has no name"))?
+            .to_string();
+        if definition["next_action"].is_null() {
+            definition["next_action"] = json::from("loop");
+        }
+
+        let wasm_path = definition_path.with_extension("wasm");
+        let wasm =
+            fs::read(&wasm_path).with_context(|| format!("Reading {}", wasm_path.display()))?;
+        let module = Module::new(engine, &wasm[..])?;
+
+        let limits = &definition["limits"];
+        let memory_mb = limits["memory_mb"]
+            .as_u64()
+            .unwrap_or(DEFAULT_MEMORY_MB)
+            .min(MAX_MEMORY_MB);
+        let limits = Limits {
+            fuel: limits["fuel"]
+                .as_u64()
+                .unwrap_or(DEFAULT_FUEL)
+                .min(MAX_FUEL),
+            timeout: Duration::from_millis(
+                limits["timeout_ms"]
+                    .as_u64()
+                    .unwrap_or(DEFAULT_TIMEOUT_MS)
+                    .min(MAX_TIMEOUT_MS),
+            ),
+            memory: usize::try_from(memory_mb)
+                .ok()
+                .and_then(|mb| mb.checked_mul(1024 * 1024))
+                .ok_or_else(|| anyhow!("memory_mb is too large"))?,
+        };
+
+        Ok(Plugin {
+            name,
+            definition,
+            engine: engine.clone(),
+            module: Arc::new(module),
+            limits,
+            worker: Mutex::new(None),
+        })
+    }
+
+    /// Run the plugin on a fresh instance, so no state carries over between
+    /// calls. Fuel bounds how much work a call does in total and the timeout
+    /// how long we wait for it.
+    pub fn call(&self, arguments: &json) -> Result<String> {
+        let mut worker = self.worker.lock().unwrap();
+        if worker.as_ref().is_some_and(|thread| !thread.is_finished()) {
+            bail!(
+                "Plugin {} is still running a call that timed out, try again later",
+                self.name
+            );
+        }
+        if let Some(thread) = worker.take() {
+            let _ = thread.join();
+        }
+        let (sender, receiver) = mpsc::channel();
+        let (engine, module, limits) = (self.engine.clone(), self.module.clone(), self.limits);
+        let name = self.name.clone();
+        let input = arguments.to_string();
+        *worker = Some(std::thread::spawn(move || {
+            let _ = sender.send(run(&engine, &module, limits, name, &input));
+        }));
+        let output = receiver
+            .recv_timeout(self.limits.timeout)
+            .map_err(|_| {
+                anyhow!(
+                    "Plugin {} took longer than {:?}",
+                    self.name,
+                    self.limits.timeout
+                )
+            })?
+            .map_err(|e| anyhow!("Plugin {} failed: {:#}", self.name, e))?;
+
+        let output: json = serde_json::from_str(&output)
+            .map_err(|e| anyhow!("Plugin {} returned invalid JSON: {}", self.name, e))?;
+        if let Some(error) = output.get("error") {
+            bail!(
+                "{}",
+                error
+                    .as_str()
+                    .map(str::to_string)
+                    .unwrap_or(error.to_string())
+            );
+        }
+        Ok(match output.get("result") {
+            Some(json::String(result)) => result.clone(),
+            Some(result) => result.to_string(),
+            None => output.to_string(),
+        })
+    }
+}
+
+fn run(
+    engine: &Engine,
+    module: &Module,
+    limits: Limits,
+    name: String,
+    input: &str,
+) -> Result<String> {
+    let mut store = Store::new(
+        engine,
+        HostState {
+            name,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(limits.memory)
+                .instances(1)
+                .build(),
+        },
+    );
+    store.limiter(|state| &mut state.limits);
+    store
+        .set_fuel(limits.fuel)
+        .map_err(|e| anyhow!("Can not set fuel: {:?}", e))?;
+
+    let mut linker = <Linker<HostState>>::new(engine);
+    linker.func_wrap(
+        "env",
+        "log",
+        |caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+            if let Some(Extern::Memory(memory)) = caller.get_export("memory") {
+                let (start, len) = (ptr as u32 as usize, len as u32 as usize);
+                let message = start
+                    .checked_add(len)
+                    .and_then(|end| memory.data(&caller).get(start..end));
+                if let Some(message) = message {
+                    println!(
+                        "Plugin {}: {}",
+                        caller.data().name,
+                        String::from_utf8_lossy(message)
+                    );
+                }
+            }
+        },
+    )?;
+    let instance = linker.instantiate(&mut store, module)?.start(&mut store)?;
+
+    let memory = instance
+        .get_memory(&store, "memory")
+        .ok_or_else(|| anyhow!("The module does not export its memory"))?;
+    let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+    let run = instance.get_typed_func::<(i32, i32), i64>(&store, "run")?;
+
+    let input = input.as_bytes();
+    let input_ptr = alloc.call(&mut store, input.len() as i32)?;
+    let input_start = input_ptr as u32 as usize;
+    input_start
+        .checked_add(input.len())
+        .and_then(|input_end| memory.data_mut(&mut store).get_mut(input_start..input_end))
+        .ok_or_else(|| anyhow!("alloc returned space outside of the module's memory"))?
+        .copy_from_slice(input);
+    let output = run.call(&mut store, (input_ptr, input.len() as i32))? as u64;
+
+    let (output_ptr, output_len) = ((output >> 32) as usize, (output & 0xffff_ffff) as usize);
+    let bytes = output_ptr
+        .checked_add(output_len)
+        .and_then(|output_end| memory.data(&store).get(output_ptr..output_end))
+        .ok_or_else(|| anyhow!("The output is outside of the module's memory"))?;
+    Ok(String::from_utf8(bytes.to_vec())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    // A module whose run does `run_body`; returning 15 points at {"result":"ok"}
+    fn module(run_body: &str) -> String {
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{{\"result\":\"ok\"}}")
+                (func (export "alloc") (param i32) (result i32) i32.const 1024)
+                (func (export "run") (param i32 i32) (result i64) {}))"#,
+            run_body
+        )
+    }
+
+    fn plugin_dir(test: &str) -> ScratchDir {
+        ScratchDir::new(&format!("plugins-{}", test))
+    }
+
+    fn write_plugin(dir: &Path, file: &str, name: &str, limits: json, wat: &str) {
+        let definition = serde_json::json!({ "name": name, "limits": limits });
+        fs::write(dir.join(format!("{}.json", file)), definition.to_string()).unwrap();
+        fs::write(
+            dir.join(format!("{}.wasm", file)),
+            wat::parse_str(wat).unwrap(),
+        )
+        .unwrap();
+    }
+
+    fn load_one(test: &str, limits: json, wat: &str) -> Plugin {
+        let dir = plugin_dir(test);
+        write_plugin(dir.path(), "plugin", "plugin", limits, wat);
+        load_plugins(dir.path(), &[]).pop().unwrap()
+    }
+
+    #[test]
+    fn echoes_a_result() {
+        let plugin = load_one("echo", json::Null, &module("i64.const 15"));
+        assert_eq!(plugin.call(&serde_json::json!({})).unwrap(), "ok");
+    }
+
+    #[test]
+    fn names_must_be_unique() {
+        let dir = plugin_dir("names");
+        for (file, name) in [("a", "lookup"), ("b", "lookup"), ("c", "draw_text")] {
+            write_plugin(dir.path(), file, name, json::Null, &module("i64.const 15"));
+        }
+        let plugins = load_plugins(dir.path(), &["draw_text".to_string()]);
+        let names: Vec<&str> = plugins.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["lookup"]);
+    }
+
+    #[test]
+    fn runs_out_of_fuel() {
+        let plugin = load_one(
+            "fuel",
+            serde_json::json!({ "fuel": 10_000 }),
+            &module("(loop $forever (br $forever)) i64.const 15"),
+        );
+        let e = plugin.call(&serde_json::json!({})).unwrap_err();
+        assert!(format!("{:#}", e).contains("failed"), "{:#}", e);
+    }
+
+    #[test]
+    fn times_out_and_refuses_calls_until_done() {
+        let plugin = load_one(
+            "timeout",
+            serde_json::json!({ "fuel": MAX_FUEL, "timeout_ms": 20 }),
+            &module("(loop $forever (br $forever)) i64.const 15"),
+        );
+        let e = plugin.call(&serde_json::json!({})).unwrap_err();
+        assert!(e.to_string().contains("took longer than"), "{}", e);
+        let e = plugin.call(&serde_json::json!({})).unwrap_err();
+        assert!(e.to_string().contains("still running"), "{}", e);
+    }
+
+    #[test]
+    fn memory_is_capped() {
+        // 100 pages are 6.4 MB, over the 1 MB allowed
+        let grow = "(if (i32.eq (memory.grow (i32.const 100)) (i32.const -1))
+                        (then unreachable))
+                    i64.const 15";
+        let plugin = load_one(
+            "memory",
+            serde_json::json!({ "memory_mb": 1 }),
+            &module(grow),
+        );
+        assert!(plugin.call(&serde_json::json!({})).is_err());
+        let plugin = load_one(
+            "memory-ok",
+            serde_json::json!({ "memory_mb": 8 }),
+            &module(grow),
+        );
+        assert_eq!(plugin.call(&serde_json::json!({})).unwrap(), "ok");
+    }
+
+    #[test]
+    fn results_outside_memory_are_errors() {
+        // A pointer just past the single page of memory
+        let plugin = load_one(
+            "bounds",
+            json::Null,
+            &module("i64.const 0x0001000000000010"),
+        );
+        let e = plugin.call(&serde_json::json!({})).unwrap_err();
+        assert!(format!("{:#}", e).contains("outside of the module's memory"));
+        // The largest pointer and length there are
+        let plugin = load_one("wrap", json::Null, &module("i64.const 0xffffffffffffffff"));
+        assert!(plugin.call(&serde_json::json!({})).is_err());
+    }
+}
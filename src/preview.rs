@@ -0,0 +1,290 @@
+//! Showing where an answer will land before drawing it. A light dotted box
+//! goes round the spot, and the user taps inside it to go ahead or outside
+//! it to turn the answer down, which is cheaper than erasing a misplaced
+//! drawing afterwards.
+
+use image::GrayImage;
+use std::time::Duration;
+
+use crate::pen::PenStyle;
+use crate::util::Polyline;
+
+/// Space left between the output and the box around it, in screen px
+pub const PADDING: f32 = 8.0;
+/// How long to wait for a tap before taking the answer as turned down
+pub const TAP_TIMEOUT: Duration = Duration::from_secs(30);
+const DASH: f32 = 4.0;
+const GAP: f32 = 8.0;
+
+// Typed text, roughly: where the text area starts and ends across the page,
+// how tall a line is, and how many characters fit on one
+const TEXT_LEFT: f32 = 104.0;
+const TEXT_RIGHT: f32 = 664.0;
+const TEXT_LINE_HEIGHT: f32 = 32.0;
+const TEXT_LINE_CHARS: usize = 48;
+/// Darker pixels than this count as ink when looking for free space
+const INK_LEVEL: u8 = 128;
+/// How far the eraser reaches either side of a dash it rubs out
+const ERASER_REACH: f32 = 4.0;
+
+/// A rectangle in screen px
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Bounds {
+    /// The smallest rectangle round all the points of these strokes
+    pub fn of_strokes(strokes: &[Polyline]) -> Option<Bounds> {
+        strokes
+            .iter()
+            .flatten()
+            .map(|&(x, y)| Bounds {
+                left: x,
+                top: y,
+                right: x,
+                bottom: y,
+            })
+            .reduce(Bounds::union)
+    }
+
+    /// The smallest rectangle round the set pixels of a bitmap
+    pub fn of_bitmap(bitmap: &[Vec<bool>]) -> Option<Bounds> {
+        bitmap
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.iter()
+                    .enumerate()
+                    .filter(|(_, &pixel)| pixel)
+                    .map(move |(x, _)| Bounds {
+                        left: x as f32,
+                        top: y as f32,
+                        right: x as f32 + 1.0,
+                        bottom: y as f32 + 1.0,
+                    })
+            })
+            .reduce(Bounds::union)
+    }
+
+    /// A guess at where typed text goes: below the lowest ink on the page,
+    /// across the text area, a line for each wrapped line of the text
+    pub fn of_text(text: &str, screen: &GrayImage) -> Bounds {
+        let lowest_ink = screen
+            .rows()
+            .enumerate()
+            .rev()
+            .find(|(_, row)| row.clone().any(|pixel| pixel.0[0] < INK_LEVEL))
+            .map_or(0.0, |(y, _)| y as f32 + 1.0);
+        let lines: usize = text
+            .lines()
+            .map(|line| line.chars().count().div_ceil(TEXT_LINE_CHARS).max(1))
+            .sum();
+        let top =
+            (lowest_ink + TEXT_LINE_HEIGHT / 2.0).min(screen.height() as f32 - TEXT_LINE_HEIGHT);
+        Bounds {
+            left: TEXT_LEFT,
+            top,
+            right: TEXT_RIGHT,
+            bottom: (top + lines.max(1) as f32 * TEXT_LINE_HEIGHT).min(screen.height() as f32),
+        }
+    }
+
+    pub fn union(self, other: Bounds) -> Bounds {
+        Bounds {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+
+    pub fn contains(&self, (x, y): (f32, f32)) -> bool {
+        x >= self.left && x <= self.right && y >= self.top && y <= self.bottom
+    }
+
+    /// Grown on every side, but kept on a screen of this size
+    pub fn padded(&self, padding: f32, (width, height): (u32, u32)) -> Bounds {
+        Bounds {
+            left: (self.left - padding).max(0.0),
+            top: (self.top - padding).max(0.0),
+            right: (self.right + padding).min(width as f32 - 1.0),
+            bottom: (self.bottom + padding).min(height as f32 - 1.0),
+        }
+    }
+}
+
+impl std::fmt::Display for Bounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "x {:.0} to {:.0}, y {:.0} to {:.0}",
+            self.left, self.right, self.top, self.bottom
+        )
+    }
+}
+
+/// The error a tool stops with when the user turns down its preview
+#[derive(Debug)]
+pub struct Rejected {
+    pub tool: String,
+    pub bounds: Bounds,
+}
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "The user rejected the {} output at {}",
+            self.tool, self.bounds
+        )
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Light pressure, so the box is faint and quick to erase
+pub fn preview_style() -> PenStyle {
+    PenStyle {
+        pressure: 0.3,
+        ..PenStyle::default()
+    }
+}
+
+/// Short dashes along the edges of a box, one stroke per dash. Dashes that
+/// would touch ink on the screen are left out, since erasing the box again
+/// would rub out that ink as well.
+pub fn dotted_box(b: &Bounds, screen: &GrayImage) -> Vec<Polyline> {
+    let corners = [
+        (b.left, b.top),
+        (b.right, b.top),
+        (b.right, b.bottom),
+        (b.left, b.bottom),
+        (b.left, b.top),
+    ];
+    let mut dashes = Vec::new();
+    for side in corners.windows(2) {
+        let (start, end) = (side[0], side[1]);
+        let length = ((end.0 - start.0).powi(2) + (end.1 - start.1).powi(2)).sqrt();
+        let at = |t: f32| {
+            let t = (t / length).min(1.0);
+            (
+                start.0 + (end.0 - start.0) * t,
+                start.1 + (end.1 - start.1) * t,
+            )
+        };
+        let mut along = 0.0;
+        while along < length {
+            dashes.push(vec![at(along), at(along + DASH)]);
+            along += DASH + GAP;
+        }
+    }
+    dashes.retain(|dash| !touches_ink(dash, screen));
+    dashes
+}
+
+fn touches_ink(dash: &Polyline, screen: &GrayImage) -> bool {
+    let Some(b) = Bounds::of_strokes(std::slice::from_ref(dash)) else {
+        return false;
+    };
+    let area = b.padded(ERASER_REACH, screen.dimensions());
+    (area.top.max(0.0) as u32..=area.bottom as u32).any(|y| {
+        (area.left.max(0.0) as u32..=area.right as u32)
+            .any(|x| screen.get_pixel(x, y).0[0] < INK_LEVEL)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    fn blank() -> GrayImage {
+        GrayImage::from_pixel(768, 1024, Luma([255]))
+    }
+
+    fn frame() -> Bounds {
+        Bounds {
+            left: 100.0,
+            top: 100.0,
+            right: 220.0,
+            bottom: 160.0,
+        }
+    }
+
+    #[test]
+    fn dashes_run_round_the_box() {
+        let dashes = dotted_box(&frame(), &blank());
+        // 10 dashes along each long side and 5 along each short one
+        assert_eq!(dashes.len(), 30);
+        for dash in &dashes {
+            let (a, b) = (dash[0], dash[1]);
+            assert!(((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt() <= DASH + 0.01);
+            let on_edge = |(x, y): (f32, f32)| x == 100.0 || x == 220.0 || y == 100.0 || y == 160.0;
+            assert!(on_edge(a) && on_edge(b));
+        }
+    }
+
+    #[test]
+    fn dashes_leave_out_ink() {
+        let mut screen = blank();
+        // A stroke of the user's crossing the top edge
+        for y in 90..110 {
+            screen.put_pixel(150, y, Luma([0]));
+        }
+        let dashes = dotted_box(&frame(), &screen);
+        assert_eq!(dashes.len(), 29);
+        assert!(dashes
+            .iter()
+            .flatten()
+            .all(|&(x, y)| y != 100.0 || (x - 150.0).abs() > ERASER_REACH));
+    }
+
+    #[test]
+    fn text_goes_below_the_lowest_ink() {
+        let mut screen = blank();
+        screen.put_pixel(300, 199, Luma([0]));
+        let bounds = Bounds::of_text(&"word ".repeat(20), &screen);
+        assert_eq!(bounds.top, 200.0 + TEXT_LINE_HEIGHT / 2.0);
+        // 100 characters wrap onto 3 lines
+        assert_eq!(bounds.bottom, bounds.top + 3.0 * TEXT_LINE_HEIGHT);
+        assert_eq!((bounds.left, bounds.right), (TEXT_LEFT, TEXT_RIGHT));
+
+        let empty = Bounds::of_text("hi", &blank());
+        assert_eq!(empty.top, TEXT_LINE_HEIGHT / 2.0);
+    }
+
+    #[test]
+    fn text_stays_on_a_full_page() {
+        let mut screen = blank();
+        screen.put_pixel(300, 1023, Luma([0]));
+        let bounds = Bounds::of_text("hi", &screen);
+        assert_eq!(bounds.top, 1024.0 - TEXT_LINE_HEIGHT);
+        assert_eq!(bounds.bottom, 1024.0);
+    }
+
+    #[test]
+    fn padding_stays_on_screen() {
+        let bounds = Bounds {
+            left: 4.0,
+            top: 500.0,
+            right: 765.0,
+            bottom: 1020.0,
+        };
+        let padded = bounds.padded(PADDING, (768, 1024));
+        assert_eq!(
+            padded,
+            Bounds {
+                left: 0.0,
+                top: 492.0,
+                right: 767.0,
+                bottom: 1023.0,
+            }
+        );
+        assert!(padded.contains((0.0, 1023.0)));
+        assert!(!padded.contains((0.0, 1023.5)));
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::Result;
+use base64::prelude::*;
+use image::{GrayImage, ImageFormat};
+use serde::Deserialize;
+use serde_json::{json, Value as json};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use crate::llm_engine::LLMEngine;
+
+/// A prompt file with `routes` instead of a `prompt`, which picks the real
+/// prompt from a handwritten tag on the page:
+///
+/// ```json
+/// {
+///   "routes": { "todo": "todo.json", "math": "math.json" },
+///   "default": "general.json",
+///   "region": { "x": 0, "y": 0, "width": 320, "height": 160 },
+///   "model": "claude-3-5-haiku-latest"
+/// }
+/// ```
+#[derive(Deserialize, Clone)]
+pub struct RouterConfig {
+    pub routes: BTreeMap<String, String>,
+    #[serde(default = "default_prompt")]
+    pub default: String,
+    #[serde(default)]
+    pub region: Region,
+    /// A cheaper model for reading the tag; the main model when not given
+    pub model: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Region {
+            x: 0,
+            y: 0,
+            width: 320,
+            height: 160,
+        }
+    }
+}
+
+fn default_prompt() -> String {
+    "general.json".to_string()
+}
+
+pub struct Route {
+    pub tag: Option<String>,
+    pub prompt: String,
+}
+
+pub struct Router {
+    config: RouterConfig,
+    engine: Box<dyn LLMEngine>,
+    choice: Arc<Mutex<Option<String>>>,
+}
+
+impl RouterConfig {
+    /// The router config in a prompt file, if it is one
+    pub fn from_prompt(prompt: &json) -> Result<Option<RouterConfig>> {
+        if prompt.get("routes").is_none() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_value(prompt.clone())?))
+    }
+}
+
+impl Router {
+    pub fn new(config: RouterConfig, mut engine: Box<dyn LLMEngine>) -> Router {
+        let choice = Arc::new(Mutex::new(None));
+        let mut tags: Vec<String> = config.routes.keys().cloned().collect();
+        tags.push("none".to_string());
+
+        let callback_choice = Arc::clone(&choice);
+        engine.register_tool(
+            "choose_prompt",
+            json!({
+                "name": "choose_prompt",
+                "description": "Report which tag is written on the page",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "tag": {
+                            "type": "string",
+                            "enum": tags,
+                            "description": "The tag written on the page without the #, or none if there is no tag"
+                        }
+                    },
+                    "required": ["tag"]
+                }
+            }),
+            Box::new(move |arguments: json| {
+                *callback_choice.lock().unwrap() = arguments["tag"].as_str().map(str::to_string);
+                Ok(String::new())
+            }),
+        );
+
+        Router {
+            config,
+            engine,
+            choice,
+        }
+    }
+
+    /// The prompt for the tag in the corner of the screen. If the model
+    /// can't be asked, say without a network, the default prompt is used.
+    pub fn route(&mut self, screen: &GrayImage) -> Route {
+        let tag = match self.read_tag(screen) {
+            Ok(tag) => tag,
+            Err(e) => {
+                println!(
+                    "Could not read the page tag, using the default prompt: {}",
+                    e
+                );
+                None
+            }
+        };
+        let prompt = match &tag {
+            Some(tag) => self.config.routes[tag].clone(),
+            None => self.config.default.clone(),
+        };
+        Route { tag, prompt }
+    }
+
+    /// Ask the model which tag is in the corner of the screen, sending only
+    /// that part of the screen to keep the call cheap
+    fn read_tag(&mut self, screen: &GrayImage) -> Result<Option<String>> {
+        let region = self.config.region;
+        let x = region.x.min(screen.width().saturating_sub(1));
+        let y = region.y.min(screen.height().saturating_sub(1));
+        let corner = image::imageops::crop_imm(
+            screen,
+            x,
+            y,
+            region.width.min(screen.width() - x),
+            region.height.min(screen.height() - y),
+        )
+        .to_image();
+        let mut png = Vec::new();
+        corner.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+
+        let tags = self
+            .config
+            .routes
+            .keys()
+            .map(|tag| format!("#{}", tag))
+            .collect::<Vec<_>>()
+            .join(", ");
+        *self.choice.lock().unwrap() = None;
+        self.engine.clear_content();
+        self.engine.add_text_content(
+            format!("This is the top corner of a handwritten page. The user may have written one of these tags there: {}. Call choose_prompt with the tag you see, or none if there is no tag.", tags).as_str(),
+        );
+        self.engine.add_image_content(&BASE64_STANDARD.encode(&png));
+        self.engine.execute()?;
+
+        Ok(self
+            .choice
+            .lock()
+            .unwrap()
+            .take()
+            .filter(|tag| self.config.routes.contains_key(tag)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_engine::ToolCallback;
+    use anyhow::bail;
+    use std::collections::HashMap;
+
+    // Answers choose_prompt with a fixed tag, or fails like a call without
+    // a network when there is none
+    struct FakeEngine {
+        tag: Option<&'static str>,
+        tools: Vec<ToolCallback>,
+    }
+
+    impl LLMEngine for FakeEngine {
+        fn new(_options: &HashMap<String, String>) -> Self {
+            FakeEngine {
+                tag: None,
+                tools: Vec::new(),
+            }
+        }
+        fn register_tool(&mut self, _name: &str, _definition: json, callback: ToolCallback) {
+            self.tools.push(callback);
+        }
+        fn select_tools(&mut self, _names: Option<Vec<String>>) -> Result<()> {
+            Ok(())
+        }
+        fn add_text_content(&mut self, _text: &str) {}
+        fn add_image_content(&mut self, _base64_image: &str) {}
+        fn clear_content(&mut self) {}
+        fn execute(&mut self) -> Result<()> {
+            let Some(tag) = self.tag else {
+                bail!("No network");
+            };
+            (self.tools[0])(json!({ "tag": tag }))?;
+            Ok(())
+        }
+    }
+
+    fn route(tag: Option<&'static str>) -> Route {
+        let config = RouterConfig::from_prompt(&json!({
+            "routes": { "todo": "todo.json", "math": "math.json" },
+            "default": "general.json"
+        }))
+        .unwrap()
+        .unwrap();
+        let engine = FakeEngine {
+            tag,
+            tools: Vec::new(),
+        };
+        let mut router = Router::new(config, Box::new(engine));
+        router.route(&GrayImage::new(768, 1024))
+    }
+
+    #[test]
+    fn parses_router_configs() {
+        let config = RouterConfig::from_prompt(&json!({ "routes": { "todo": "todo.json" } }))
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.routes["todo"], "todo.json");
+        assert_eq!(config.default, "general.json");
+        assert_eq!(config.region.width, 320);
+        assert!(config.model.is_none());
+
+        let plain = json!({ "prompt": "You are a helpful assistant." });
+        assert!(RouterConfig::from_prompt(&plain).unwrap().is_none());
+
+        let broken = json!({ "routes": ["todo.json"] });
+        assert!(RouterConfig::from_prompt(&broken).is_err());
+    }
+
+    #[test]
+    fn routes_a_known_tag() {
+        let route = route(Some("math"));
+        assert_eq!(route.tag.as_deref(), Some("math"));
+        assert_eq!(route.prompt, "math.json");
+    }
+
+    #[test]
+    fn unknown_tags_use_the_default() {
+        let unknown = route(Some("shopping"));
+        assert_eq!(unknown.tag, None);
+        assert_eq!(unknown.prompt, "general.json");
+        assert_eq!(route(Some("none")).prompt, "general.json");
+    }
+
+    #[test]
+    fn failed_calls_use_the_default() {
+        let route = route(None);
+        assert_eq!(route.tag, None);
+        assert_eq!(route.prompt, "general.json");
+    }
+}
@@ -0,0 +1,36 @@
+//! Scratch directories for tests that read and write files
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// An empty directory under the system temp dir, removed along with
+/// everything in it when dropped
+pub struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    /// `name` keeps tests running at the same time apart, so give each test
+    /// its own
+    pub fn new(name: &str) -> ScratchDir {
+        let path =
+            std::env::temp_dir().join(format!("ghostwriter-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        ScratchDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -52,7 +52,7 @@ impl Screenshot {
         let pids = String::from_utf8(output.stdout)?;
         for pid in pids.split_whitespace() {
             let has_fb = process::Command::new("grep")
-                .args(&["-C1", "/dev/fb0", &format!("/proc/{}/maps", pid)])
+                .args(["-C1", "/dev/fb0", &format!("/proc/{}/maps", pid)])
                 .output()?;
             if !has_fb.stdout.is_empty() {
                 return Ok(pid.to_string());
@@ -124,15 +124,15 @@ impl Screenshot {
             }
         }
 
-        let img = GrayImage::from_raw(REMARKABLE_WIDTH as u32, REMARKABLE_HEIGHT as u32, processed)
+        let img = GrayImage::from_raw(REMARKABLE_WIDTH, REMARKABLE_HEIGHT, processed)
             .ok_or_else(|| anyhow::anyhow!("Failed to create image from raw data"))?;
 
         let mut png_data = Vec::new();
         let encoder = image::codecs::png::PngEncoder::new(&mut png_data);
         encoder.write_image(
             img.as_raw(),
-            REMARKABLE_WIDTH as u32,
-            REMARKABLE_HEIGHT as u32,
+            REMARKABLE_WIDTH,
+            REMARKABLE_HEIGHT,
             image::ExtendedColorType::L8,
         )?;
 
@@ -158,6 +158,10 @@ impl Screenshot {
         Ok(())
     }
 
+    pub fn png_data(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn base64(&self) -> Result<String> {
         let base64_image = general_purpose::STANDARD.encode(&self.data);
         Ok(base64_image)
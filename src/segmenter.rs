@@ -228,3 +228,592 @@ pub fn analyze_image(image_path: &str) -> Result<String, Box<dyn std::error::Err
 
     Ok(result)
 }
+
+// Grid and table detection. Hand-drawn grids have wobbly, slightly tilted
+// lines, so lines are found with a coarse Hough transform over nearly
+// horizontal and nearly vertical angles, then grouped by where they cross.
+
+const SCREEN_WIDTH: u32 = 768;
+const SCREEN_HEIGHT: u32 = 1024;
+
+// Shortest stroke in px that counts as a grid line
+const MIN_LINE_LENGTH: f32 = 50.0;
+// Half the height of the band around a line that its ink must stay in
+const LINE_BAND: f32 = 8.0;
+// Largest break in px along a hand-drawn line
+const MAX_LINE_GAP: f32 = 15.0;
+// How far lines may stop short of each other and still count as crossing
+const CROSSING_TOLERANCE: f32 = 12.0;
+const MAX_LINE_TILT_DEGREES: i32 = 12;
+// Handwriting also produces line candidates, so look at plenty of them
+const MAX_LINE_CANDIDATES: usize = 100;
+
+/// Load a screenshot as a grayscale image in screen coordinates
+pub fn load_screen(png_data: &[u8]) -> image::ImageResult<GrayImage> {
+    let image = image::load_from_memory(png_data)?.to_luma8();
+    if image.dimensions() == (SCREEN_WIDTH, SCREEN_HEIGHT) {
+        return Ok(image);
+    }
+    Ok(image::imageops::resize(
+        &image,
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT,
+        image::imageops::FilterType::Triangle,
+    ))
+}
+
+/// A straight line fitted to a hand-drawn stroke. Horizontal lines are
+/// y = slope * x + intercept for x in start..end, and vertical lines are
+/// x = slope * y + intercept for y in start..end.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct GridLine {
+    pub horizontal: bool,
+    pub slope: f32,
+    pub intercept: f32,
+    pub start: f32,
+    pub end: f32,
+}
+
+impl GridLine {
+    // Position across the line (y for horizontal lines) at a point along it
+    fn at(&self, along: f32) -> f32 {
+        self.slope * along + self.intercept
+    }
+
+    fn shifted(&self, offset: f32) -> GridLine {
+        GridLine {
+            intercept: self.intercept + offset,
+            ..*self
+        }
+    }
+}
+
+// Where a horizontal and a vertical line meet, extending them as needed
+fn intersection(horizontal: &GridLine, vertical: &GridLine) -> (f32, f32) {
+    let x = (vertical.slope * horizontal.intercept + vertical.intercept)
+        / (1.0 - vertical.slope * horizontal.slope);
+    (x, horizontal.at(x))
+}
+
+fn lines_cross(horizontal: &GridLine, vertical: &GridLine) -> bool {
+    let (x, y) = intersection(horizontal, vertical);
+    x >= horizontal.start - CROSSING_TOLERANCE
+        && x <= horizontal.end + CROSSING_TOLERANCE
+        && y >= vertical.start - CROSSING_TOLERANCE
+        && y <= vertical.end + CROSSING_TOLERANCE
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Cell {
+    pub center: (f32, f32),
+    pub width: f32,
+    pub height: f32,
+}
+
+/// A grid of cells bounded by lines, including the open outer cells of a
+/// tic-tac-toe board. Rows and columns are numbered from 1 at the top left.
+#[derive(Debug, Clone, Serialize)]
+pub struct Grid {
+    pub rows: usize,
+    pub columns: usize,
+    pub bounds: (u32, u32, u32, u32), // x, y, width, height
+    pub cells_with_ink: Vec<(usize, usize)>,
+    // Boundaries between and around the rows and columns, in order
+    horizontals: Vec<GridLine>,
+    verticals: Vec<GridLine>,
+}
+
+impl Grid {
+    pub fn cell(&self, row: usize, column: usize) -> Option<Cell> {
+        if row == 0 || row > self.rows || column == 0 || column > self.columns {
+            return None;
+        }
+        let top_left = intersection(&self.horizontals[row - 1], &self.verticals[column - 1]);
+        let top_right = intersection(&self.horizontals[row - 1], &self.verticals[column]);
+        let bottom_left = intersection(&self.horizontals[row], &self.verticals[column - 1]);
+        let bottom_right = intersection(&self.horizontals[row], &self.verticals[column]);
+        let corners = [top_left, top_right, bottom_left, bottom_right];
+        Some(Cell {
+            center: (
+                corners.iter().map(|c| c.0).sum::<f32>() / 4.0,
+                corners.iter().map(|c| c.1).sum::<f32>() / 4.0,
+            ),
+            width: ((top_right.0 - top_left.0) + (bottom_right.0 - bottom_left.0)) / 2.0,
+            height: ((bottom_left.1 - top_left.1) + (bottom_right.1 - top_right.1)) / 2.0,
+        })
+    }
+}
+
+// Ink pixels of a thresholded image, listed column by column for horizontal
+// lines, or with x and y swapped for vertical lines
+fn ink_points(image: &GrayImage, horizontal: bool) -> Vec<(f32, f32)> {
+    let (width, height) = image.dimensions();
+    let mut points = Vec::new();
+    for x in 0..width {
+        for y in 0..height {
+            if image.get_pixel(x, y)[0] < 128 {
+                points.push((x as f32, y as f32));
+            }
+        }
+    }
+    if !horizontal {
+        points = points.into_iter().map(|(x, y)| (y, x)).collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    }
+    points
+}
+
+// Find nearly horizontal lines in `points` (given as (along, across) pairs
+// sorted by `along`). Vertical lines are found by passing swapped points.
+fn find_lines(mut points: Vec<(f32, f32)>, length: f32, horizontal: bool) -> Vec<GridLine> {
+    let bin_size = LINE_BAND / 2.0;
+    // Tilted lines can have intercepts a little outside the image
+    let offset = length;
+    let bins = ((length * 3.0) / bin_size) as usize + 1;
+    let angles: Vec<f32> = (-MAX_LINE_TILT_DEGREES..=MAX_LINE_TILT_DEGREES)
+        .map(|degrees| (degrees as f32).to_radians().tan())
+        .collect();
+    let mut lines = Vec::new();
+
+    for _ in 0..MAX_LINE_CANDIDATES {
+        // Count the distinct columns with ink near each candidate line
+        let mut votes = vec![vec![0u32; bins]; angles.len()];
+        let mut last_along = vec![vec![-1.0f32; bins]; angles.len()];
+        for &(along, across) in &points {
+            for (a, slope) in angles.iter().enumerate() {
+                let intercept = across - slope * along + offset;
+                let bin = (intercept / bin_size) as isize;
+                for b in (bin - 1)..=(bin + 1) {
+                    if b < 0 || b as usize >= bins {
+                        continue;
+                    }
+                    let b = b as usize;
+                    if last_along[a][b] != along {
+                        last_along[a][b] = along;
+                        votes[a][b] += 1;
+                    }
+                }
+            }
+        }
+        let Some((a, b, count)) = votes
+            .iter()
+            .enumerate()
+            .flat_map(|(a, row)| row.iter().enumerate().map(move |(b, count)| (a, b, *count)))
+            .max_by_key(|(_, _, count)| *count)
+        else {
+            break;
+        };
+        if (count as f32) < MIN_LINE_LENGTH {
+            break;
+        }
+        let mut slope = angles[a];
+        let mut intercept = (b as f32 + 0.5) * bin_size - offset;
+
+        // Refit the line to its ink, twice, then find its longest run
+        let mut run = Vec::new();
+        for _ in 0..2 {
+            run = longest_run(&points, slope, intercept);
+            if run.len() < 2 {
+                break;
+            }
+            (slope, intercept) = fit_line(&run);
+        }
+        let is_line = run.len() >= 2 && {
+            let start = run[0].0;
+            let end = run[run.len() - 1].0;
+            end - start >= MIN_LINE_LENGTH && is_thin(&points, slope, intercept, start, end)
+        };
+
+        // Remove the ink of this candidate so the next best one shows up
+        let (start, end) = match (run.first(), run.last()) {
+            (Some(first), Some(last)) => (first.0, last.0),
+            _ => (0.0, length),
+        };
+        let band = LINE_BAND * 1.5;
+        let before = points.len();
+        points.retain(|&(along, across)| {
+            along < start || along > end || (across - (slope * along + intercept)).abs() > band
+        });
+        if is_line {
+            lines.push(GridLine {
+                horizontal,
+                slope,
+                intercept,
+                start,
+                end,
+            });
+        } else if points.len() == before {
+            // Nothing left to remove near the best candidate
+            break;
+        }
+    }
+    lines
+}
+
+// The mean ink position per column near a line, over its longest stretch
+// without big gaps
+fn longest_run(points: &[(f32, f32)], slope: f32, intercept: f32) -> Vec<(f32, f32)> {
+    let mut columns: Vec<(f32, f32, u32)> = Vec::new();
+    for &(along, across) in points {
+        if (across - (slope * along + intercept)).abs() > LINE_BAND {
+            continue;
+        }
+        match columns.last_mut() {
+            Some(column) if column.0 == along => {
+                column.1 += across;
+                column.2 += 1;
+            }
+            _ => columns.push((along, across, 1)),
+        }
+    }
+    let mut best: Vec<(f32, f32)> = Vec::new();
+    let mut current: Vec<(f32, f32)> = Vec::new();
+    for (along, sum, count) in columns {
+        if current
+            .last()
+            .is_some_and(|last| along - last.0 > MAX_LINE_GAP)
+        {
+            if current.len() > best.len() {
+                best = std::mem::take(&mut current);
+            }
+            current.clear();
+        }
+        current.push((along, sum / count as f32));
+    }
+    if current.len() > best.len() {
+        best = current;
+    }
+    best
+}
+
+fn fit_line(points: &[(f32, f32)]) -> (f32, f32) {
+    let n = points.len() as f32;
+    let mean_x = points.iter().map(|p| p.0).sum::<f32>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f32>() / n;
+    let covariance: f32 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    let variance: f32 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    let slope = if variance > 0.0 {
+        covariance / variance
+    } else {
+        0.0
+    };
+    (slope, mean_y - slope * mean_x)
+}
+
+// Lines have their ink close to them, unlike a row of handwriting. Crossing
+// lines also leave ink beside the line, but only in a few columns.
+fn is_thin(points: &[(f32, f32)], slope: f32, intercept: f32, start: f32, end: f32) -> bool {
+    let mut messy_columns = 0;
+    let mut last_messy = None;
+    for &(along, across) in points {
+        if along < start || along > end || last_messy == Some(along) {
+            continue;
+        }
+        let distance = (across - (slope * along + intercept)).abs();
+        if distance > LINE_BAND && distance <= LINE_BAND * 4.0 {
+            messy_columns += 1;
+            last_messy = Some(along);
+        }
+    }
+    messy_columns as f32 <= (end - start) * 0.3
+}
+
+/// Find grids and tables, including single boxes, in a screen image
+pub fn detect_grids(image: &GrayImage) -> Vec<Grid> {
+    let (width, height) = image.dimensions();
+    let horizontals = find_lines(ink_points(image, true), height as f32, true);
+    let verticals = find_lines(ink_points(image, false), width as f32, false);
+
+    // Group lines that cross each other into connected sets
+    let mut group_of_h: Vec<Option<usize>> = vec![None; horizontals.len()];
+    let mut group_of_v: Vec<Option<usize>> = vec![None; verticals.len()];
+    let mut groups = 0;
+    for start in 0..horizontals.len() {
+        if group_of_h[start].is_some() {
+            continue;
+        }
+        group_of_h[start] = Some(groups);
+        let mut queue = vec![(true, start)];
+        while let Some((is_h, i)) = queue.pop() {
+            if is_h {
+                for (j, v) in verticals.iter().enumerate() {
+                    if group_of_v[j].is_none() && lines_cross(&horizontals[i], v) {
+                        group_of_v[j] = Some(groups);
+                        queue.push((false, j));
+                    }
+                }
+            } else {
+                for (j, h) in horizontals.iter().enumerate() {
+                    if group_of_h[j].is_none() && lines_cross(h, &verticals[i]) {
+                        group_of_h[j] = Some(groups);
+                        queue.push((true, j));
+                    }
+                }
+            }
+        }
+        groups += 1;
+    }
+
+    let mut grids: Vec<Grid> = (0..groups)
+        .filter_map(|group| {
+            let hs: Vec<GridLine> = horizontals
+                .iter()
+                .zip(&group_of_h)
+                .filter(|(_, g)| **g == Some(group))
+                .map(|(line, _)| *line)
+                .collect();
+            let vs: Vec<GridLine> = verticals
+                .iter()
+                .zip(&group_of_v)
+                .filter(|(_, g)| **g == Some(group))
+                .map(|(line, _)| *line)
+                .collect();
+            build_grid(hs, vs, image)
+        })
+        .collect();
+    grids.sort_by_key(|grid| (grid.bounds.1 / 20, grid.bounds.0));
+    grids
+}
+
+fn median(mut values: Vec<f32>) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(f32::total_cmp);
+    values[values.len() / 2]
+}
+
+fn build_grid(mut hs: Vec<GridLine>, mut vs: Vec<GridLine>, image: &GrayImage) -> Option<Grid> {
+    if hs.len() < 2 || vs.len() < 2 {
+        return None;
+    }
+    let center_x = median(vs.iter().map(|v| v.intercept).collect());
+    let center_y = median(hs.iter().map(|h| h.at(center_x)).collect());
+    hs.sort_by(|a, b| a.at(center_x).total_cmp(&b.at(center_x)));
+    vs.sort_by(|a, b| a.at(center_y).total_cmp(&b.at(center_y)));
+
+    // Lines that run well past the outermost crossing line mark open outer
+    // cells, as on a tic-tac-toe board
+    let row_size = median(
+        hs.windows(2)
+            .map(|w| w[1].at(center_x) - w[0].at(center_x))
+            .collect(),
+    );
+    let column_size = median(
+        vs.windows(2)
+            .map(|w| w[1].at(center_y) - w[0].at(center_y))
+            .collect(),
+    );
+    let overhang = |lines: &[GridLine], edge: &GridLine, before: bool| {
+        median(
+            lines
+                .iter()
+                .map(|line| {
+                    let (along, _) = if edge.horizontal {
+                        let (x, y) = intersection(edge, line);
+                        (y, x)
+                    } else {
+                        intersection(line, edge)
+                    };
+                    if before {
+                        along - line.start
+                    } else {
+                        line.end - along
+                    }
+                })
+                .collect(),
+        )
+    };
+    let top = overhang(&vs, &hs[0], true);
+    if top > row_size * 0.4 {
+        hs.insert(0, hs[0].shifted(-top));
+    }
+    let bottom = overhang(&vs, &hs[hs.len() - 1], false);
+    if bottom > row_size * 0.4 {
+        hs.push(hs[hs.len() - 1].shifted(bottom));
+    }
+    let left = overhang(&hs, &vs[0], true);
+    if left > column_size * 0.4 {
+        vs.insert(0, vs[0].shifted(-left));
+    }
+    let right = overhang(&hs, &vs[vs.len() - 1], false);
+    if right > column_size * 0.4 {
+        vs.push(vs[vs.len() - 1].shifted(right));
+    }
+
+    let corners = [
+        intersection(&hs[0], &vs[0]),
+        intersection(&hs[0], &vs[vs.len() - 1]),
+        intersection(&hs[hs.len() - 1], &vs[0]),
+        intersection(&hs[hs.len() - 1], &vs[vs.len() - 1]),
+    ];
+    let x_min = corners
+        .iter()
+        .map(|c| c.0)
+        .fold(f32::MAX, f32::min)
+        .max(0.0);
+    let y_min = corners
+        .iter()
+        .map(|c| c.1)
+        .fold(f32::MAX, f32::min)
+        .max(0.0);
+    let x_max = corners.iter().map(|c| c.0).fold(f32::MIN, f32::max);
+    let y_max = corners.iter().map(|c| c.1).fold(f32::MIN, f32::max);
+
+    let mut grid = Grid {
+        rows: hs.len() - 1,
+        columns: vs.len() - 1,
+        bounds: (
+            x_min as u32,
+            y_min as u32,
+            (x_max - x_min).max(0.0) as u32,
+            (y_max - y_min).max(0.0) as u32,
+        ),
+        cells_with_ink: Vec::new(),
+        horizontals: hs,
+        verticals: vs,
+    };
+    grid.cells_with_ink = (1..=grid.rows)
+        .flat_map(|row| (1..=grid.columns).map(move |column| (row, column)))
+        .filter(|&(row, column)| {
+            grid.cell(row, column)
+                .is_some_and(|cell| cell_has_ink(image, &cell))
+        })
+        .collect();
+    Some(grid)
+}
+
+// Look inside the middle of a cell, away from its border lines
+fn cell_has_ink(image: &GrayImage, cell: &Cell) -> bool {
+    let (width, height) = image.dimensions();
+    let half_width = cell.width * 0.35;
+    let half_height = cell.height * 0.35;
+    let x0 = (cell.center.0 - half_width).max(0.0) as u32;
+    let y0 = (cell.center.1 - half_height).max(0.0) as u32;
+    let x1 = ((cell.center.0 + half_width) as u32).min(width);
+    let y1 = ((cell.center.1 + half_height) as u32).min(height);
+    let ink = (y0..y1)
+        .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+        .filter(|&(x, y)| image.get_pixel(x, y)[0] < 128)
+        .count();
+    ink > 20
+}
+
+/// Describe detected grids for the prompt
+pub fn describe_grids(grids: &[Grid]) -> String {
+    let mut description = String::new();
+    for (i, grid) in grids.iter().enumerate() {
+        let (x, y, width, height) = grid.bounds;
+        description.push_str(&format!(
+            "Grid {}: {} rows x {} columns at x={}, y={}, width={}, height={}",
+            i + 1,
+            grid.rows,
+            grid.columns,
+            x,
+            y,
+            width,
+            height
+        ));
+        if grid.cells_with_ink.is_empty() {
+            description.push_str("; all cells are empty\n");
+        } else {
+            let cells: Vec<String> = grid
+                .cells_with_ink
+                .iter()
+                .map(|(row, column)| format!("(row {}, column {})", row, column))
+                .collect();
+            description.push_str(&format!("; cells with marks: {}\n", cells.join(", ")));
+        }
+    }
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    fn blank() -> GrayImage {
+        GrayImage::from_pixel(SCREEN_WIDTH, SCREEN_HEIGHT, Luma([255]))
+    }
+
+    // A 3 px wide pen line
+    fn line(image: &mut GrayImage, from: (f32, f32), to: (f32, f32)) {
+        let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil() as usize;
+        for i in 0..=steps {
+            let t = i as f32 / steps as f32;
+            let (x, y) = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    image.put_pixel((x as i32 + dx) as u32, (y as i32 + dy) as u32, Luma([0]));
+                }
+            }
+        }
+    }
+
+    // A hand-drawn board: two slightly tilted lines each way, with an X in
+    // the middle cell
+    fn tic_tac_toe() -> GrayImage {
+        let mut image = blank();
+        line(&mut image, (300.0, 200.0), (304.0, 500.0));
+        line(&mut image, (400.0, 202.0), (397.0, 498.0));
+        line(&mut image, (200.0, 300.0), (500.0, 305.0));
+        line(&mut image, (201.0, 400.0), (499.0, 397.0));
+        line(&mut image, (330.0, 330.0), (370.0, 370.0));
+        line(&mut image, (370.0, 330.0), (330.0, 370.0));
+        image
+    }
+
+    #[test]
+    fn finds_a_tic_tac_toe_board() {
+        let grids = detect_grids(&tic_tac_toe());
+        assert_eq!(grids.len(), 1);
+        let grid = &grids[0];
+        assert_eq!((grid.rows, grid.columns), (3, 3));
+        assert_eq!(grid.cells_with_ink, vec![(2, 2)]);
+    }
+
+    #[test]
+    fn outer_cells_of_an_open_board() {
+        let grid = &detect_grids(&tic_tac_toe())[0];
+        let corner = grid.cell(1, 1).unwrap();
+        assert!((corner.center.0 - 250.0).abs() < 10.0);
+        assert!((corner.center.1 - 250.0).abs() < 10.0);
+        assert!((corner.width - 100.0).abs() < 10.0);
+        assert!((corner.height - 100.0).abs() < 10.0);
+        let last = grid.cell(3, 3).unwrap();
+        assert!((last.center.0 - 450.0).abs() < 10.0);
+        assert!((last.center.1 - 450.0).abs() < 10.0);
+        assert!(grid.cell(0, 1).is_none());
+        assert!(grid.cell(4, 1).is_none());
+        assert!(grid.cell(1, 4).is_none());
+    }
+
+    #[test]
+    fn finds_a_closed_table() {
+        let mut image = blank();
+        for y in [100.0, 160.0, 220.0, 280.0] {
+            line(&mut image, (100.0, y), (600.0, y));
+        }
+        for x in [100.0, 300.0, 600.0] {
+            line(&mut image, (x, 100.0), (x, 280.0));
+        }
+        let grids = detect_grids(&image);
+        assert_eq!(grids.len(), 1);
+        let grid = &grids[0];
+        assert_eq!((grid.rows, grid.columns), (3, 2));
+        let (x, y, width, height) = grid.bounds;
+        assert!(x.abs_diff(100) < 5 && y.abs_diff(100) < 5);
+        assert!(width.abs_diff(500) < 5 && height.abs_diff(180) < 5);
+        assert!(grid.cells_with_ink.is_empty());
+        let cell = grid.cell(2, 2).unwrap();
+        assert!((cell.center.0 - 450.0).abs() < 5.0);
+        assert!((cell.center.1 - 190.0).abs() < 5.0);
+    }
+
+    #[test]
+    fn blank_page_has_no_grids() {
+        assert!(detect_grids(&blank()).is_empty());
+        assert_eq!(describe_grids(&[]), "");
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::util::Polyline;
+
+// Glyph coordinates are in font units. The baseline is at y=0 and capitals
+// reach y=-21, so one em is a little taller than the cap height.
+pub const UNITS_PER_EM: f32 = 32.0;
+pub const CAP_HEIGHT: f32 = 21.0;
+pub const X_HEIGHT: f32 = 14.0;
+pub const DESCENT: f32 = 7.0;
+
+const SIMPLEX_DATA: &str = include_str!("../fonts/simplex.txt");
+
+pub struct Glyph {
+    pub advance: f32,
+    pub strokes: Vec<Polyline>,
+}
+
+pub struct StrokeFont {
+    glyphs: HashMap<char, Glyph>,
+}
+
+impl StrokeFont {
+    /// The built-in single-line font, parsed once on first use
+    pub fn simplex() -> &'static StrokeFont {
+        static FONT: OnceLock<StrokeFont> = OnceLock::new();
+        FONT.get_or_init(|| StrokeFont::parse(SIMPLEX_DATA))
+    }
+
+    pub fn parse(data: &str) -> StrokeFont {
+        let mut glyphs = HashMap::new();
+        for line in data.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(3, ' ');
+            let codepoint = parts.next().unwrap_or_default().trim_start_matches("U+");
+            let advance = parts.next().and_then(|a| a.parse::<f32>().ok());
+            let c = u32::from_str_radix(codepoint, 16)
+                .ok()
+                .and_then(char::from_u32);
+            let (Some(c), Some(advance)) = (c, advance) else {
+                println!("Skipping bad stroke font line: {}", line);
+                continue;
+            };
+            let strokes = parts
+                .next()
+                .unwrap_or_default()
+                .split(';')
+                .map(|stroke| {
+                    stroke
+                        .split_whitespace()
+                        .filter_map(|point| {
+                            let (x, y) = point.split_once(',')?;
+                            Some((x.parse().ok()?, y.parse().ok()?))
+                        })
+                        .collect::<Polyline>()
+                })
+                .filter(|stroke| !stroke.is_empty())
+                .collect();
+            glyphs.insert(c, Glyph { advance, strokes });
+        }
+        StrokeFont { glyphs }
+    }
+
+    /// Look up a glyph, falling back to '?' for characters the font lacks
+    pub fn glyph(&self, c: char) -> &Glyph {
+        self.glyphs
+            .get(&c)
+            .or_else(|| self.glyphs.get(&'?'))
+            .expect("stroke font is missing the '?' fallback glyph")
+    }
+
+    pub fn has_glyph(&self, c: char) -> bool {
+        self.glyphs.contains_key(&c)
+    }
+
+    /// Width in pixels of `text` at the given font size (pixels per em)
+    pub fn text_width(&self, text: &str, size: f32) -> f32 {
+        let scale = size / UNITS_PER_EM;
+        text.chars().map(|c| self.glyph(c).advance * scale).sum()
+    }
+
+    /// Strokes for `text` with the left end of its baseline at `origin`
+    pub fn text_strokes(&self, text: &str, origin: (f32, f32), size: f32) -> Vec<Polyline> {
+        let scale = size / UNITS_PER_EM;
+        let mut x = origin.0;
+        let mut strokes = Vec::new();
+        for c in text.chars() {
+            let glyph = self.glyph(c);
+            for stroke in &glyph.strokes {
+                strokes.push(
+                    stroke
+                        .iter()
+                        .map(|(gx, gy)| (x + gx * scale, origin.1 + gy * scale))
+                        .collect(),
+                );
+            }
+            x += glyph.advance * scale;
+        }
+        strokes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_glyphs_and_skips_bad_lines() {
+        let font = StrokeFont::parse(
+            "# comment\nU+0041 18 1,0 9,-21 17,0; 4,-7 14,-7\nnot a glyph\nU+0020 10\nU+003F 18 9,-1 9,0\n",
+        );
+        assert_eq!(font.glyphs.len(), 3);
+        let a = font.glyph('A');
+        assert_eq!(a.advance, 18.0);
+        assert_eq!(
+            a.strokes,
+            vec![
+                vec![(1.0, 0.0), (9.0, -21.0), (17.0, 0.0)],
+                vec![(4.0, -7.0), (14.0, -7.0)]
+            ]
+        );
+        assert!(font.glyph(' ').strokes.is_empty());
+    }
+
+    #[test]
+    fn missing_characters_fall_back_to_a_question_mark() {
+        let font = StrokeFont::simplex();
+        for c in ['é', '☃', '中', '\u{7f}'] {
+            assert!(!font.has_glyph(c), "{:?}", c);
+            assert!(std::ptr::eq(font.glyph(c), font.glyph('?')), "{:?}", c);
+        }
+        assert_eq!(font.text_width("é", 32.0), font.text_width("?", 32.0));
+    }
+
+    #[test]
+    fn the_simplex_font_covers_printable_ascii() {
+        let font = StrokeFont::simplex();
+        for c in ' '..='~' {
+            assert!(font.has_glyph(c), "{:?}", c);
+        }
+        assert!(!font.glyph('.').strokes.is_empty());
+    }
+
+    #[test]
+    fn text_is_scaled_from_font_units() {
+        let font = StrokeFont::parse("U+0041 18 1,0 9,-21 17,0\nU+003F 18 9,-1 9,0\n");
+        assert_eq!(font.text_width("AA", 64.0), 72.0);
+        assert_eq!(
+            font.text_strokes("AA", (100.0, 200.0), 64.0),
+            vec![
+                vec![(102.0, 200.0), (118.0, 158.0), (134.0, 200.0)],
+                vec![(138.0, 200.0), (154.0, 158.0), (170.0, 200.0)]
+            ]
+        );
+    }
+}
@@ -0,0 +1,421 @@
+//! Fitting a model's SVG onto the screen. Models often declare the size of
+//! the device (1404x1872) or some other canvas instead of the screenshot
+//! they were shown, so marks would land in the wrong place if the SVG were
+//! drawn as is. This works out which coordinates the SVG is really in and
+//! rewrites its root element to scale them onto the screen.
+
+use serde::Serialize;
+
+use crate::util::parse_svg;
+
+/// Sizes this close count as the same, to allow for rounding
+const SAME_SIZE: f32 = 1.0;
+/// How far across or down the screenshot content has to reach before a
+/// mismatched declared size is put down to the model, as a fraction
+const SHOWN_REACH: f32 = 0.5;
+const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+
+/// A rectangle as x, y, width and height
+pub type Area = (f32, f32, f32, f32);
+
+/// How an SVG was mapped onto the screen, for logging and the model output
+/// file
+#[derive(Debug, Clone, Serialize)]
+pub struct SvgFit {
+    /// The `width` and `height` of the root element, if given in pixels
+    pub declared: Option<(f32, f32)>,
+    /// The root `viewBox`, as x, y, width and height
+    pub view_box: Option<Area>,
+    /// The size of the screenshot the model was shown
+    pub shown: (u32, u32),
+    /// The area of SVG coordinates that is fitted to the screen
+    pub source: Area,
+    /// Screen pixels per SVG unit. The source keeps its shape and is
+    /// centered on the screen, unless it is the screenshot itself.
+    pub scale: f32,
+    /// Whether the source is the screenshot, stretched over the whole
+    /// screen so each of its points lands where it was shown; `scale` is
+    /// then the one across
+    pub stretched: bool,
+    /// Anything about the sizes that suggests the model got them wrong
+    pub warnings: Vec<String>,
+}
+
+impl std::fmt::Display for SvgFit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (x, y, width, height) = self.source;
+        write!(
+            f,
+            "{} x {} at ({}, {}) scaled by {:.3}{}",
+            width,
+            height,
+            x,
+            y,
+            self.scale,
+            if self.stretched { ", stretched" } else { "" }
+        )
+    }
+}
+
+/// Rewrite the root `<svg>` element so the coordinates the model drew in
+/// fill the screen, keeping their shape. A `viewBox` is taken at its word. Without one the
+/// declared size is used, unless it differs from the screenshot and the
+/// content fills out the screenshot without going past it, which means the
+/// model drew in screenshot coordinates and only got the size wrong. With
+/// neither the SVG is taken to be in screenshot coordinates.
+///
+/// Content in a corner is ambiguous: a few marks in the top left of a
+/// 1404x1872 SVG fit either reading. The declared size is kept then, with
+/// a warning, as switching would move a genuine full-size drawing.
+pub fn fit_svg(svg_data: &str, shown: (u32, u32), screen: (u32, u32)) -> (String, SvgFit) {
+    let shown_size = (shown.0 as f32, shown.1 as f32);
+    let Some((start, end)) = root_tag(svg_data) else {
+        let source = (0.0, 0.0, shown_size.0, shown_size.1);
+        let fit = fit_to(source, None, None, shown, screen, Vec::new());
+        return (svg_data.to_string(), fit);
+    };
+    let attributes = parse_attributes(&svg_data[start + 4..end]);
+    let attribute = |name: &str| {
+        attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let declared = attribute("width")
+        .and_then(parse_length)
+        .zip(attribute("height").and_then(parse_length));
+    let view_box = attribute("viewBox").and_then(parse_view_box);
+    let rewrite = |fit: Option<Area>| {
+        rewrite_root(
+            svg_data,
+            (start, end),
+            &attributes,
+            fit.map(|source| (screen, source, is_screenshot(source, shown_size))),
+        )
+    };
+
+    let mut warnings = Vec::new();
+    let source = if let Some(view_box) = view_box {
+        if !same_size((view_box.2, view_box.3), shown_size) {
+            warnings.push(format!(
+                "viewBox is {} x {} but the screenshot was {} x {}",
+                view_box.2, view_box.3, shown.0, shown.1
+            ));
+        }
+        view_box
+    } else if let Some(declared) = declared {
+        if same_size(declared, shown_size) {
+            (0.0, 0.0, declared.0, declared.1)
+        } else if let Some((right, bottom)) =
+            content_extent(&rewrite(None)).filter(|(right, bottom)| {
+                *right <= shown_size.0 + SAME_SIZE && *bottom <= shown_size.1 + SAME_SIZE
+            })
+        {
+            if right >= shown_size.0 * SHOWN_REACH || bottom >= shown_size.1 * SHOWN_REACH {
+                warnings.push(format!(
+                    "declared size is {} x {} but everything is drawn within the {} x {} screenshot, so using screenshot coordinates",
+                    declared.0, declared.1, shown.0, shown.1
+                ));
+                (0.0, 0.0, shown_size.0, shown_size.1)
+            } else {
+                warnings.push(format!(
+                    "declared size is {} x {} but the screenshot was {} x {}, and the content is too small to tell which was meant, so using the declared size",
+                    declared.0, declared.1, shown.0, shown.1
+                ));
+                (0.0, 0.0, declared.0, declared.1)
+            }
+        } else {
+            warnings.push(format!(
+                "declared size is {} x {} but the screenshot was {} x {}",
+                declared.0, declared.1, shown.0, shown.1
+            ));
+            (0.0, 0.0, declared.0, declared.1)
+        }
+    } else {
+        (0.0, 0.0, shown_size.0, shown_size.1)
+    };
+
+    let fit = fit_to(source, declared, view_box, shown, screen, warnings);
+    (rewrite(Some(source)), fit)
+}
+
+/// The SVG with its root element rebuilt, adding the SVG namespace if it is
+/// missing (the parser finds nothing without it) and, given a screen size
+/// and source area, replacing the size and `viewBox` to map one onto the
+/// other. The default `preserveAspectRatio` keeps the source's shape; only
+/// a source that is the screenshot is stretched.
+fn rewrite_root(
+    svg_data: &str,
+    (start, end): (usize, usize),
+    attributes: &[(String, String)],
+    fit: Option<((u32, u32), Area, bool)>,
+) -> String {
+    let mut tag = String::from("<svg");
+    if !attributes.iter().any(|(key, _)| key == "xmlns") {
+        tag.push_str(&format!(" xmlns=\"{}\"", SVG_NAMESPACE));
+    }
+    for (key, value) in attributes {
+        if fit.is_none()
+            || !matches!(
+                key.as_str(),
+                "width" | "height" | "viewBox" | "preserveAspectRatio"
+            )
+        {
+            tag.push_str(&format!(" {}=\"{}\"", key, value.replace('"', "&quot;")));
+        }
+    }
+    if let Some((screen, source, stretched)) = fit {
+        tag.push_str(&format!(
+            " width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\"",
+            screen.0, screen.1, source.0, source.1, source.2, source.3
+        ));
+        if stretched {
+            tag.push_str(" preserveAspectRatio=\"none\"");
+        }
+    }
+    if svg_data[..end].ends_with('/') {
+        tag.push('/');
+    }
+    format!("{}{}{}", &svg_data[..start], tag, &svg_data[end..])
+}
+
+fn fit_to(
+    source: Area,
+    declared: Option<(f32, f32)>,
+    view_box: Option<Area>,
+    shown: (u32, u32),
+    screen: (u32, u32),
+    warnings: Vec<String>,
+) -> SvgFit {
+    let across = screen.0 as f32 / source.2;
+    let down = screen.1 as f32 / source.3;
+    let stretched = is_screenshot(source, (shown.0 as f32, shown.1 as f32));
+    SvgFit {
+        declared,
+        view_box,
+        shown,
+        source,
+        scale: if stretched { across } else { across.min(down) },
+        stretched,
+        warnings,
+    }
+}
+
+/// Whether a source area is the screenshot the model was shown, whose
+/// points map straight onto the screen even if its shape differs a little
+fn is_screenshot(source: Area, shown: (f32, f32)) -> bool {
+    same_size((source.2, source.3), shown)
+}
+
+fn same_size(a: (f32, f32), b: (f32, f32)) -> bool {
+    (a.0 - b.0).abs() <= SAME_SIZE && (a.1 - b.1).abs() <= SAME_SIZE
+}
+
+/// The right and bottom edges of everything the SVG draws, in its own
+/// units, or none if it draws nothing
+fn content_extent(svg_data: &str) -> Option<(f32, f32)> {
+    let tree = parse_svg(svg_data).ok()?;
+    if !tree.root().has_children() {
+        return None;
+    }
+    let bounds = tree.root().abs_stroke_bounding_box();
+    Some((bounds.right(), bounds.bottom()))
+}
+
+/// Where the root element's attributes are: just after `<svg` up to the `>`
+fn root_tag(svg_data: &str) -> Option<(usize, usize)> {
+    let mut from = 0;
+    loop {
+        let start = from + svg_data[from..].find("<svg")?;
+        let after = svg_data[start + 4..].chars().next()?;
+        if after.is_whitespace() || after == '>' || after == '/' {
+            let mut quote = None;
+            for (i, c) in svg_data[start..].char_indices() {
+                match (quote, c) {
+                    (None, '"' | '\'') => quote = Some(c),
+                    (Some(q), _) if c == q => quote = None,
+                    (None, '>') => return Some((start, start + i)),
+                    _ => {}
+                }
+            }
+            return None;
+        }
+        from = start + 4;
+    }
+}
+
+/// `name="value"` pairs, with either kind of quote
+fn parse_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag.trim_start();
+    while let Some(equals) = rest.find('=') {
+        let name = rest[..equals].trim().to_string();
+        let value_start = rest[equals + 1..].trim_start();
+        let Some(quote) = value_start
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+        else {
+            break;
+        };
+        let Some(length) = value_start[1..].find(quote) else {
+            break;
+        };
+        attributes.push((name, value_start[1..1 + length].to_string()));
+        rest = value_start[length + 2..].trim_start();
+    }
+    attributes
+}
+
+/// A length in pixels, like "768" or "768px". Percentages and physical
+/// units don't say anything about the model's coordinates.
+fn parse_length(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let number = value.strip_suffix("px").unwrap_or(value).trim();
+    number.parse::<f32>().ok().filter(|length| *length > 0.0)
+}
+
+fn parse_view_box(value: &str) -> Option<Area> {
+    let numbers: Vec<f32> = value
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    match numbers[..] {
+        [x, y, width, height] if width > 0.0 && height > 0.0 => Some((x, y, width, height)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_square_view_box_stays_square() {
+        let svg = r#"<svg viewBox="0 0 100 100" preserveAspectRatio="none"><circle cx="50" cy="50" r="50" stroke="black" stroke-width="0.01"/></svg>"#;
+        let (fitted, fit) = fit_svg(svg, (768, 1024), (768, 1024));
+        assert_eq!(fit.scale, 7.68);
+        assert!(!fit.stretched);
+        assert!(!fitted.contains("preserveAspectRatio"));
+        // As wide as the screen and centered down it
+        let bounds = parse_svg(&fitted).unwrap().root().abs_bounding_box();
+        assert!((bounds.width() - 768.0).abs() < 0.5, "{:?}", bounds);
+        assert!((bounds.height() - 768.0).abs() < 0.5, "{:?}", bounds);
+        assert!((bounds.y() - 128.0).abs() < 0.5, "{:?}", bounds);
+    }
+
+    #[test]
+    fn the_screenshot_is_stretched_over_the_screen() {
+        let svg = r#"<svg viewBox="0 0 800 1000" preserveAspectRatio="xMidYMid meet"><rect x="0" y="0" width="800" height="1000" stroke="black" stroke-width="0.01"/></svg>"#;
+        let (fitted, fit) = fit_svg(svg, (800, 1000), (768, 1024));
+        assert!(fit.stretched);
+        assert_eq!(fit.scale, 0.96);
+        assert_eq!(fitted.matches("preserveAspectRatio").count(), 1);
+        let bounds = parse_svg(&fitted).unwrap().root().abs_bounding_box();
+        assert!((bounds.width() - 768.0).abs() < 0.5, "{:?}", bounds);
+        assert!((bounds.height() - 1024.0).abs() < 0.5, "{:?}", bounds);
+    }
+
+    fn declared_device_size(content: &str) -> String {
+        format!(
+            r#"<svg width="1404" height="1872" xmlns="http://www.w3.org/2000/svg">{}</svg>"#,
+            content
+        )
+    }
+
+    #[test]
+    fn device_size_with_content_filling_the_screenshot_uses_screenshot_coordinates() {
+        let svg = declared_device_size(
+            r#"<path d="M 100 100 L 700 950" stroke="black" stroke-width="2"/>"#,
+        );
+        let (fitted, fit) = fit_svg(&svg, (768, 1024), (768, 1024));
+        assert_eq!(fit.declared, Some((1404.0, 1872.0)));
+        assert_eq!(fit.source, (0.0, 0.0, 768.0, 1024.0));
+        assert_eq!(fit.scale, 1.0);
+        assert_eq!(fit.warnings.len(), 1);
+        assert!(fit.warnings[0].contains("so using screenshot coordinates"));
+        assert!(fitted.contains(r#"viewBox="0 0 768 1024""#));
+        assert!(!fitted.contains("1404"));
+    }
+
+    #[test]
+    fn device_size_with_content_past_the_screenshot_uses_the_declared_size() {
+        let svg = declared_device_size(
+            r#"<path d="M 100 100 L 1300 1800" stroke="black" stroke-width="2"/>"#,
+        );
+        let (fitted, fit) = fit_svg(&svg, (768, 1024), (768, 1024));
+        assert_eq!(fit.source, (0.0, 0.0, 1404.0, 1872.0));
+        assert!((fit.scale - 768.0 / 1404.0).abs() < 1e-6);
+        assert!(!fit.stretched);
+        assert_eq!(
+            fit.warnings,
+            vec!["declared size is 1404 x 1872 but the screenshot was 768 x 1024"]
+        );
+        assert!(fitted.contains(r#"viewBox="0 0 1404 1872""#));
+    }
+
+    #[test]
+    fn device_size_with_content_in_a_corner_keeps_the_declared_size() {
+        let svg = declared_device_size(
+            r#"<path d="M 20 20 L 200 300" stroke="black" stroke-width="2"/>"#,
+        );
+        let (_, fit) = fit_svg(&svg, (768, 1024), (768, 1024));
+        assert_eq!(fit.source, (0.0, 0.0, 1404.0, 1872.0));
+        assert_eq!(fit.warnings.len(), 1);
+        assert!(fit.warnings[0].contains("too small to tell"));
+    }
+
+    #[test]
+    fn matching_sizes_have_no_warnings() {
+        let svg =
+            r#"<svg width="768px" height="1024"><path d="M 0 0 L 10 10" stroke="black"/></svg>"#;
+        let (_, fit) = fit_svg(svg, (768, 1024), (768, 1024));
+        assert_eq!(fit.source, (0.0, 0.0, 768.0, 1024.0));
+        assert!(fit.warnings.is_empty());
+
+        // No size at all is taken as screenshot coordinates, scaled up to
+        // a bigger screen
+        let svg = r#"<svg><path d="M 0 0 L 10 10" stroke="black"/></svg>"#;
+        let (fitted, fit) = fit_svg(svg, (768, 1024), (1404, 1872));
+        assert_eq!(fit.declared, None);
+        assert_eq!(fit.source, (0.0, 0.0, 768.0, 1024.0));
+        assert!(fit.warnings.is_empty());
+        assert!(fitted.contains(r#"width="1404" height="1872" viewBox="0 0 768 1024""#));
+    }
+
+    #[test]
+    fn mismatched_view_box_is_used_with_a_warning() {
+        let svg = r#"<svg viewBox="0, 0, 100, 100"><path d="M 0 0 L 10 10" stroke="black"/></svg>"#;
+        let (_, fit) = fit_svg(svg, (768, 1024), (768, 1024));
+        assert_eq!(fit.view_box, Some((0.0, 0.0, 100.0, 100.0)));
+        assert_eq!(fit.source, (0.0, 0.0, 100.0, 100.0));
+        assert_eq!(
+            fit.warnings,
+            vec!["viewBox is 100 x 100 but the screenshot was 768 x 1024"]
+        );
+    }
+
+    #[test]
+    fn root_is_rewritten_in_place() {
+        // An existing namespace isn't added twice, other attributes and the
+        // rest of the document are kept
+        let svg = r#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg" width="800" height="600" fill='none' data-note="a &quot;b&quot;"><g/></svg>"#;
+        let (fitted, _) = fit_svg(svg, (768, 1024), (768, 1024));
+        assert_eq!(
+            fitted,
+            r#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg" fill="none" data-note="a &quot;b&quot;" width="768" height="1024" viewBox="0 0 800 600"><g/></svg>"#
+        );
+
+        let (fitted, fit) = fit_svg("<svg/>", (768, 1024), (768, 1024));
+        assert_eq!(
+            fitted,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="768" height="1024" viewBox="0 0 768 1024" preserveAspectRatio="none"/>"#
+        );
+        assert!(fit.warnings.is_empty());
+
+        // Not an SVG root at all, like <svgfoo>, is left alone
+        let (fitted, _) = fit_svg("<svgfoo/>", (768, 1024), (768, 1024));
+        assert_eq!(fitted, "<svgfoo/>");
+    }
+}
@@ -0,0 +1,727 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{Duration, NaiveDate, NaiveDateTime, Weekday};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as json;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::dates::{self, day_count, weekday};
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const TASKWARRIOR_DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// One line of a todo.txt file, see https://github.com/todotxt/todo.txt
+#[derive(Debug, Clone, PartialEq)]
+pub struct TodoItem {
+    pub done: bool,
+    pub priority: Option<char>,
+    pub completed: Option<NaiveDate>,
+    pub created: Option<NaiveDate>,
+    /// Everything after the dates, including +project, @context and key:value tags
+    pub text: String,
+}
+
+fn parse_date(word: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(word, DATE_FORMAT).ok()
+}
+
+impl TodoItem {
+    pub fn parse(line: &str) -> TodoItem {
+        let mut words = line.split_whitespace().peekable();
+
+        let done = words.next_if_eq(&"x").is_some();
+        let priority = words
+            .next_if(|word| matches!(word.as_bytes(), [b'(', b'A'..=b'Z', b')']))
+            .map(|word| word.as_bytes()[1] as char);
+        let mut dates = Vec::new();
+        while dates.len() < if done { 2 } else { 1 } {
+            match words.peek().and_then(|word| parse_date(word)) {
+                Some(date) => {
+                    dates.push(date);
+                    words.next();
+                }
+                None => break,
+            }
+        }
+        // A completed item has its completion date first
+        let (completed, created) = match (done, dates.as_slice()) {
+            (true, [completed, created]) => (Some(*completed), Some(*created)),
+            (true, [completed]) => (Some(*completed), None),
+            (false, [created]) => (None, Some(*created)),
+            _ => (None, None),
+        };
+
+        TodoItem {
+            done,
+            priority,
+            completed,
+            created,
+            text: words.collect::<Vec<_>>().join(" "),
+        }
+    }
+
+    fn words_with_prefix(&self, prefix: char) -> Vec<String> {
+        self.text
+            .split_whitespace()
+            .filter(|word| word.len() > 1 && word.starts_with(prefix))
+            .map(|word| word[1..].to_string())
+            .collect()
+    }
+
+    pub fn projects(&self) -> Vec<String> {
+        self.words_with_prefix('+')
+    }
+
+    pub fn contexts(&self) -> Vec<String> {
+        self.words_with_prefix('@')
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.text.split_whitespace().find_map(|word| {
+            word.split_once(':')
+                .filter(|(k, v)| *k == key && !v.is_empty())
+                .map(|(_, v)| v)
+        })
+    }
+
+    pub fn due(&self) -> Option<NaiveDate> {
+        self.tag("due").and_then(parse_date)
+    }
+
+    /// The text without projects, contexts and tags
+    pub fn description(&self) -> String {
+        self.text
+            .split_whitespace()
+            .filter(|word| {
+                !(word.len() > 1 && (word.starts_with('+') || word.starts_with('@')))
+                    && !word
+                        .split_once(':')
+                        .is_some_and(|(k, v)| !k.is_empty() && !v.is_empty() && !v.starts_with('/'))
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn complete(&mut self, today: NaiveDate) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        self.completed = Some(today);
+        // todo.txt keeps the priority of finished items as a tag
+        if let Some(priority) = self.priority.take() {
+            self.text = format!("{} pri:{}", self.text, priority);
+        }
+    }
+}
+
+impl fmt::Display for TodoItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut words = Vec::new();
+        if self.done {
+            words.push("x".to_string());
+        }
+        if let Some(priority) = self.priority {
+            words.push(format!("({})", priority));
+        }
+        if let Some(completed) = self.completed {
+            words.push(completed.format(DATE_FORMAT).to_string());
+        }
+        if let Some(created) = self.created {
+            words.push(created.format(DATE_FORMAT).to_string());
+        }
+        words.push(self.text.clone());
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+/// The date this many days after today, or an error if that is past the
+/// dates chrono can hold
+fn days_after(today: NaiveDate, days: i64) -> Result<NaiveDate> {
+    Duration::try_days(days)
+        .and_then(|days| today.checked_add_signed(days))
+        .ok_or_else(|| anyhow!("{} days from {} is out of range", days, today))
+}
+
+/// Turn a date the model or user wrote into a date: 2024-10-23, today,
+/// tomorrow, a weekday name (the next one after today), next week or "in 3 days"
+pub fn parse_due(due: &str, today: NaiveDate) -> Result<NaiveDate> {
+    let due = due.trim().to_lowercase();
+    if let Some(date) = parse_date(&due) {
+        return Ok(date);
+    }
+    // A bare weekday is never today, since a task due today says "today"
+    let days_until = |day: Weekday| match dates::days_until(today, day) {
+        0 => 7,
+        days => days,
+    };
+
+    let words: Vec<&str> = due.split_whitespace().collect();
+    let date = match words.as_slice() {
+        ["today"] => today,
+        ["tomorrow"] => days_after(today, 1)?,
+        ["next", "week"] => days_after(today, days_until(Weekday::Mon))?,
+        ["next", "month"] => today
+            .checked_add_months(chrono::Months::new(1))
+            .ok_or_else(|| anyhow!("Date out of range"))?,
+        ["in", count, unit] => match day_count(count, unit) {
+            Some(days) => days_after(today, days)?,
+            None => bail!("Can not understand the due date '{}'", due),
+        },
+        [name] | ["this", name] | ["on", name] if weekday(name).is_some() => {
+            days_after(today, days_until(weekday(name).unwrap()))?
+        }
+        ["next", name] if weekday(name).is_some() => {
+            days_after(today, days_until(weekday(name).unwrap()) + 7)?
+        }
+        _ => bail!("Can not understand the due date '{}'; use YYYY-MM-DD", due),
+    };
+    Ok(date)
+}
+
+/// A todo.txt file. Items are numbered by line, starting at 1, which stays
+/// stable because finished items are marked done rather than removed.
+pub struct TodoList {
+    path: PathBuf,
+}
+
+impl TodoList {
+    pub fn new(path: impl AsRef<Path>) -> TodoList {
+        TodoList {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn load(&self) -> Result<Vec<TodoItem>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        Ok(fs::read_to_string(&self.path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(TodoItem::parse)
+            .collect())
+    }
+
+    pub fn save(&self, items: &[TodoItem]) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content: String = items.iter().map(|item| format!("{}\n", item)).collect();
+        fs::write(&self.path, content)?;
+        Ok(())
+    }
+
+    /// Add an item and return its number
+    pub fn add(
+        &self,
+        text: &str,
+        priority: Option<char>,
+        due: Option<&str>,
+        today: NaiveDate,
+    ) -> Result<(usize, TodoItem)> {
+        let text = text.trim();
+        if text.is_empty() {
+            bail!("The todo item has no text");
+        }
+        let priority = match priority.map(|p| p.to_ascii_uppercase()) {
+            Some(p) if !p.is_ascii_uppercase() => bail!("Priority must be a letter from A to Z"),
+            priority => priority,
+        };
+        // The text is taken as it is, not as todo.txt syntax, so "x ray" is
+        // not a finished item. The creation date always goes before it,
+        // which keeps it from being read back that way from the file.
+        let mut item = TodoItem {
+            done: false,
+            priority,
+            completed: None,
+            created: Some(today),
+            text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+        };
+        if let Some(due) = due.filter(|due| !due.trim().is_empty()) {
+            if item.tag("due").is_some() {
+                bail!("The text already has a due: tag");
+            }
+            let due = parse_due(due, today)?;
+            item.text = format!("{} due:{}", item.text, due.format(DATE_FORMAT));
+        }
+
+        let mut items = self.load()?;
+        items.push(item.clone());
+        self.save(&items)?;
+        Ok((items.len(), item))
+    }
+
+    /// Mark items done by number, or the one open item whose text contains `text`
+    pub fn complete(&self, ids: &[usize], text: Option<&str>, today: NaiveDate) -> Result<String> {
+        let mut items = self.load()?;
+        let mut ids = ids.to_vec();
+        if let Some(text) = text.filter(|text| !text.trim().is_empty()) {
+            let needle = text.trim().to_lowercase();
+            let matches: Vec<usize> = items
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| !item.done && item.text.to_lowercase().contains(&needle))
+                .map(|(i, _)| i + 1)
+                .collect();
+            match matches.as_slice() {
+                [] => bail!("No open todo item contains '{}'", text),
+                [id] => ids.push(*id),
+                _ => bail!(
+                    "Several open items contain '{}', pass the number instead:\n{}",
+                    text,
+                    matches
+                        .iter()
+                        .map(|id| format_item(*id, &items[id - 1], today))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                ),
+            }
+        }
+        if ids.is_empty() {
+            bail!("Give the number or text of the item to complete");
+        }
+
+        let mut lines = Vec::new();
+        for id in ids {
+            let item = items
+                .get_mut(id.wrapping_sub(1))
+                .ok_or_else(|| anyhow!("There is no todo item number {}", id))?;
+            if item.done {
+                lines.push(format!("{} was already done", id));
+            } else {
+                item.complete(today);
+                lines.push(format!("Completed {}", format_item(id, item, today)));
+            }
+        }
+        self.save(&items)?;
+        Ok(lines.join("\n"))
+    }
+
+    /// Numbered items, open ones by priority then due date. A filter matches
+    /// +project, @context or any words in the text.
+    pub fn list(&self, filter: &str, include_done: bool, today: NaiveDate) -> Result<String> {
+        let filter = filter.trim().to_lowercase();
+        let mut items: Vec<(usize, TodoItem)> = self
+            .load()?
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| (i + 1, item))
+            .filter(|(_, item)| include_done || !item.done)
+            .filter(|(_, item)| {
+                filter.is_empty()
+                    || filter
+                        .split_whitespace()
+                        .all(|word| item.text.to_lowercase().contains(word))
+            })
+            .collect();
+        items.sort_by_key(|(id, item)| {
+            (
+                item.done,
+                item.priority.unwrap_or('~'),
+                item.due().unwrap_or(NaiveDate::MAX),
+                *id,
+            )
+        });
+        if items.is_empty() {
+            return Ok("No todo items.".to_string());
+        }
+        Ok(items
+            .iter()
+            .map(|(id, item)| format_item(*id, item, today))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// Open items that are overdue or due within `days` days, soonest first
+    pub fn due(&self, days: i64, today: NaiveDate) -> Result<String> {
+        let until = days_after(today, days)?;
+        let mut items: Vec<(usize, TodoItem)> = self
+            .load()?
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| (i + 1, item))
+            .filter(|(_, item)| !item.done && item.due().is_some_and(|due| due <= until))
+            .collect();
+        items.sort_by_key(|(id, item)| (item.due(), item.priority.unwrap_or('~'), *id));
+        if items.is_empty() {
+            return Ok(format!("Nothing is due in the next {} days.", days));
+        }
+        Ok(items
+            .iter()
+            .map(|(id, item)| format_item(*id, item, today))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// Add tasks from a Taskwarrior `task export`, skipping deleted ones
+    /// and ones already in the list, by uuid or by the same line. Returns
+    /// how many were added.
+    pub fn import_taskwarrior(&self, data: &str) -> Result<usize> {
+        let tasks: Vec<TaskwarriorTask> = serde_json::from_str(data)?;
+        let mut items = self.load()?;
+        let existing: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+        let uuids: Vec<String> = items
+            .iter()
+            .filter_map(|item| item.tag("uuid"))
+            .map(str::to_string)
+            .collect();
+        let mut added = 0;
+        for task in tasks.iter().filter(|task| task.status != "deleted") {
+            if task.uuid.as_ref().is_some_and(|uuid| uuids.contains(uuid)) {
+                continue;
+            }
+            let item = task.to_todo()?;
+            if !existing.contains(&item.to_string()) {
+                items.push(item);
+                added += 1;
+            }
+        }
+        self.save(&items)?;
+        Ok(added)
+    }
+
+    /// All items as a Taskwarrior JSON array, for `task import`
+    pub fn export_taskwarrior(&self) -> Result<String> {
+        let tasks = self
+            .load()?
+            .iter()
+            .map(TaskwarriorTask::from_todo)
+            .collect::<Vec<_>>();
+        Ok(serde_json::to_string_pretty(&tasks)?)
+    }
+}
+
+fn format_item(id: usize, item: &TodoItem, today: NaiveDate) -> String {
+    let mut line = format!("{}. ", id);
+    if item.done {
+        line.push_str("[done] ");
+    }
+    if let Some(priority) = item.priority {
+        line.push_str(&format!("({}) ", priority));
+    }
+    line.push_str(&item.text);
+    if let Some(due) = item.due().filter(|_| !item.done) {
+        let note = match (due - today).num_days() {
+            days if days < 0 => format!("overdue by {} days", -days),
+            0 => "due today".to_string(),
+            1 => "due tomorrow".to_string(),
+            days if days < 7 => format!("due {}", due.format("%A")),
+            days => format!("due in {} days", days),
+        };
+        line.push_str(&format!(" ({})", note));
+    }
+    line
+}
+
+#[derive(Serialize, Deserialize)]
+struct TaskwarriorTask {
+    /// Kept as a uuid: tag, so a task exported back updates the original
+    /// instead of being imported again as a new one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uuid: Option<String>,
+    description: String,
+    #[serde(default = "pending")]
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entry: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    due: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    project: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// Fields we don't map, like urgency and annotations, are read but
+    /// lost on import, so they are not exported again
+    #[serde(flatten, skip_serializing)]
+    _other: serde_json::Map<String, json>,
+}
+
+fn pending() -> String {
+    "pending".to_string()
+}
+
+fn taskwarrior_date(date: &str) -> Result<NaiveDate> {
+    Ok(NaiveDateTime::parse_from_str(date, TASKWARRIOR_DATE_FORMAT)
+        .map_err(|e| anyhow!("Bad Taskwarrior date '{}': {}", date, e))?
+        .date())
+}
+
+fn to_taskwarrior_date(date: NaiveDate) -> String {
+    date.and_hms_opt(0, 0, 0)
+        .unwrap()
+        .format(TASKWARRIOR_DATE_FORMAT)
+        .to_string()
+}
+
+impl TaskwarriorTask {
+    fn to_todo(&self) -> Result<TodoItem> {
+        let done = self.status == "completed";
+        let mut words = vec![self.description.clone()];
+        if let Some(project) = &self.project {
+            words.push(format!("+{}", project.replace(' ', "-")));
+        }
+        words.extend(self.tags.iter().map(|tag| format!("@{}", tag)));
+        if let Some(uuid) = &self.uuid {
+            words.push(format!("uuid:{}", uuid));
+        }
+        if let Some(due) = &self.due {
+            words.push(format!(
+                "due:{}",
+                taskwarrior_date(due)?.format(DATE_FORMAT)
+            ));
+        }
+        let priority = match self.priority.as_deref() {
+            Some("H") => Some('A'),
+            Some("M") => Some('B'),
+            Some("L") => Some('C'),
+            _ => None,
+        };
+        let mut item = TodoItem {
+            done: false,
+            priority,
+            completed: None,
+            created: self.entry.as_deref().map(taskwarrior_date).transpose()?,
+            text: words.join(" "),
+        };
+        if done {
+            let end = self.end.as_deref().map(taskwarrior_date).transpose()?;
+            item.complete(NaiveDate::default());
+            item.completed = end.or(item.created);
+        }
+        Ok(item)
+    }
+
+    fn from_todo(item: &TodoItem) -> TaskwarriorTask {
+        let priority = item
+            .priority
+            .or_else(|| item.tag("pri").and_then(|p| p.chars().next()));
+        TaskwarriorTask {
+            uuid: item.tag("uuid").map(str::to_string),
+            description: item.description(),
+            status: if item.done { "completed" } else { "pending" }.to_string(),
+            entry: item.created.map(to_taskwarrior_date),
+            end: item.completed.map(to_taskwarrior_date),
+            due: item.due().map(to_taskwarrior_date),
+            priority: match priority {
+                Some('A') => Some("H".to_string()),
+                Some('B') => Some("M".to_string()),
+                Some(_) => Some("L".to_string()),
+                None => None,
+            },
+            // Taskwarrior has a single project, so any others stay as tags
+            project: item.projects().first().cloned(),
+            tags: item
+                .contexts()
+                .into_iter()
+                .chain(item.projects().into_iter().skip(1))
+                .collect(),
+            _other: serde_json::Map::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 10, 21).unwrap()
+    }
+
+    /// A list in its own file, removed when the test is done with it
+    struct Scratch(TodoList, ScratchDir);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let dir = ScratchDir::new(&format!("todo-{}", name));
+            Scratch(TodoList::new(dir.join("todo.txt")), dir)
+        }
+
+        fn lines(&self) -> Vec<String> {
+            fs::read_to_string(self.1.join("todo.txt"))
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn added_text_is_not_todo_txt_syntax() {
+        let list = Scratch::new("syntax");
+        let (_, item) = list
+            .0
+            .add("x ray results pickup", None, None, today())
+            .unwrap();
+        let items = list.0.load().unwrap();
+        assert!(!item.done);
+        assert_eq!(items, vec![item]);
+        assert_eq!(items[0].text, "x ray results pickup");
+    }
+
+    #[test]
+    fn far_off_due_dates_are_errors() {
+        assert!(parse_due("in 999999999999 days", today()).is_err());
+        assert!(parse_due("in 999999999999 weeks", today()).is_err());
+        assert_eq!(
+            parse_due("in 3 days", today()).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 10, 24).unwrap()
+        );
+    }
+
+    #[test]
+    fn weekdays_are_due_after_today() {
+        let date = |day| NaiveDate::from_ymd_opt(2024, 10, day).unwrap();
+        assert_eq!(parse_due("monday", today()).unwrap(), date(28));
+        assert_eq!(parse_due("wed", today()).unwrap(), date(23));
+        assert_eq!(parse_due("next wed", today()).unwrap(), date(30));
+    }
+
+    #[test]
+    fn completing_by_number_or_text() {
+        let list = Scratch::new("complete");
+        let todo = &list.0;
+        todo.add("buy milk", Some('a'), None, today()).unwrap();
+        todo.add("call mum", None, None, today()).unwrap();
+        todo.add("call the bank", None, None, today()).unwrap();
+
+        assert_eq!(
+            todo.complete(&[1], None, today()).unwrap(),
+            "Completed 1. [done] buy milk pri:A"
+        );
+        assert_eq!(
+            todo.complete(&[1], None, today()).unwrap(),
+            "1 was already done"
+        );
+        assert!(todo.complete(&[], Some("call"), today()).is_err());
+        assert!(todo.complete(&[9], None, today()).is_err());
+        assert_eq!(
+            todo.complete(&[], Some("MUM"), today()).unwrap(),
+            "Completed 2. [done] call mum"
+        );
+        assert_eq!(
+            list.lines(),
+            [
+                "x 2024-10-21 2024-10-21 buy milk pri:A",
+                "x 2024-10-21 2024-10-21 call mum",
+                "2024-10-21 call the bank",
+            ]
+        );
+    }
+
+    #[test]
+    fn list_orders_by_priority_then_due() {
+        let list = Scratch::new("list");
+        let todo = &list.0;
+        todo.add("plain", None, None, today()).unwrap();
+        todo.add("later +home", Some('B'), Some("friday"), today())
+            .unwrap();
+        todo.add("sooner", Some('B'), Some("tomorrow"), today())
+            .unwrap();
+        todo.add("top +home", Some('A'), None, today()).unwrap();
+        todo.add("finished", Some('A'), None, today()).unwrap();
+        todo.complete(&[5], None, today()).unwrap();
+
+        assert_eq!(
+            todo.list("", false, today()).unwrap(),
+            "4. (A) top +home\n\
+             3. (B) sooner due:2024-10-22 (due tomorrow)\n\
+             2. (B) later +home due:2024-10-25 (due Friday)\n\
+             1. plain"
+        );
+        assert_eq!(
+            todo.list("+HOME", true, today()).unwrap(),
+            "4. (A) top +home\n2. (B) later +home due:2024-10-25 (due Friday)"
+        );
+        assert!(todo
+            .list("", true, today())
+            .unwrap()
+            .ends_with("5. [done] finished pri:A"));
+        assert_eq!(
+            todo.list("nothing", true, today()).unwrap(),
+            "No todo items."
+        );
+    }
+
+    #[test]
+    fn due_lists_overdue_and_upcoming_items() {
+        let list = Scratch::new("due");
+        let todo = &list.0;
+        todo.add("far off", None, Some("in 2 weeks"), today())
+            .unwrap();
+        todo.add("soon", None, Some("wednesday"), today()).unwrap();
+        todo.add("late due:2024-10-19", None, None, today())
+            .unwrap();
+        todo.add("done", None, Some("today"), today()).unwrap();
+        todo.add("whenever", None, None, today()).unwrap();
+        todo.complete(&[4], None, today()).unwrap();
+
+        assert_eq!(
+            todo.due(3, today()).unwrap(),
+            "3. late due:2024-10-19 (overdue by 2 days)\n\
+             2. soon due:2024-10-23 (due Wednesday)"
+        );
+        assert!(todo.due(14, today()).unwrap().ends_with("(due in 14 days)"));
+        assert_eq!(
+            Scratch::new("due-empty").0.due(7, today()).unwrap(),
+            "Nothing is due in the next 7 days."
+        );
+    }
+
+    #[test]
+    fn taskwarrior_round_trip() {
+        let list = Scratch::new("taskwarrior");
+        let export = r#"[
+            {"uuid": "a1", "description": "Write report", "status": "pending",
+             "entry": "20241020T090000Z", "due": "20241030T170000Z", "priority": "H",
+             "project": "work stuff", "tags": ["office"], "urgency": 9.2},
+            {"uuid": "b2", "description": "Old chore", "status": "completed",
+             "entry": "20241001T090000Z", "end": "20241005T090000Z"},
+            {"uuid": "c3", "description": "Gone", "status": "deleted"},
+            {"description": "No uuid"}
+        ]"#;
+        assert_eq!(list.0.import_taskwarrior(export).unwrap(), 3);
+        assert_eq!(
+            list.lines(),
+            [
+                "(A) 2024-10-20 Write report +work-stuff @office uuid:a1 due:2024-10-30",
+                "x 2024-10-05 2024-10-01 Old chore uuid:b2",
+                "No uuid",
+            ]
+        );
+
+        // The same tasks again, even with edited text, are not added twice
+        let edited = export.replace("Write report", "Write the report");
+        assert_eq!(list.0.import_taskwarrior(&edited).unwrap(), 0);
+        assert_eq!(list.lines().len(), 3);
+
+        let tasks: Vec<json> = serde_json::from_str(&list.0.export_taskwarrior().unwrap()).unwrap();
+        assert_eq!(
+            tasks[0],
+            serde_json::json!({
+                "uuid": "a1",
+                "description": "Write report",
+                "status": "pending",
+                "entry": "20241020T000000Z",
+                "due": "20241030T000000Z",
+                "priority": "H",
+                "project": "work-stuff",
+                "tags": ["office"],
+            })
+        );
+        assert_eq!(tasks[1]["status"], "completed");
+        assert_eq!(tasks[1]["end"], "20241005T000000Z");
+        assert!(tasks[2].get("uuid").is_none());
+
+        // Exported tasks import back onto the same items
+        let again = list.0.export_taskwarrior().unwrap();
+        assert_eq!(list.0.import_taskwarrior(&again).unwrap(), 0);
+    }
+}
@@ -1,8 +1,14 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use evdev::{Device, EventType, InputEvent};
 
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::cancel::CancelFlag;
+
+const TOUCH_DEVICE: &str = "/dev/input/by-path/platform-30a40000.i2c-event";
 
 // Device to virtual coordinate conversion
 const INPUT_WIDTH: u16 = 1404;
@@ -21,43 +27,166 @@ const ABS_MT_POSITION_Y: u16 = 54;
 const ABS_MT_TRACKING_ID: u16 = 57;
 const ABS_MT_PRESSURE: u16 = 58;
 
+// A contact this big is a palm rather than a finger, which is about 17
+const PALM_TOUCH_MAJOR: i32 = 40;
+// As many fingers at once as a hand laid flat puts down
+const PALM_CONTACTS: usize = 3;
+
+// Holding the corner at least this long undoes instead of answering
+const UNDO_HOLD: Duration = Duration::from_millis(1000);
+
+/// What the user asked for by touching the upper-right corner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// A tap: answer what is on the screen
+    Respond,
+    /// A long press: take the last answer back off the page
+    Undo,
+}
+
+/// One finger on the screen, in touchscreen coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Contact {
+    x: i32,
+    y: i32,
+    touched_at: SystemTime,
+    down: bool,
+}
+
+/// Where each finger is. The screen reports every finger in its own slot
+/// and only says which slot when that changes, so a position belongs to
+/// the slot named last, not to whichever finger lifts next.
+#[derive(Default)]
+struct Contacts {
+    slot: i32,
+    slots: HashMap<i32, Contact>,
+}
+
+impl Contacts {
+    /// Follow one event, returning the contact it lifted, if any
+    fn update(&mut self, event: &InputEvent) -> Option<Contact> {
+        if event.code() == ABS_MT_SLOT {
+            self.slot = event.value();
+            return None;
+        }
+        let contact = self.slots.entry(self.slot).or_insert(Contact {
+            x: 0,
+            y: 0,
+            touched_at: UNIX_EPOCH,
+            down: false,
+        });
+        match event.code() {
+            ABS_MT_POSITION_X => contact.x = event.value(),
+            ABS_MT_POSITION_Y => contact.y = event.value(),
+            ABS_MT_TRACKING_ID if event.value() == -1 => {
+                contact.down = false;
+                return Some(*contact);
+            }
+            ABS_MT_TRACKING_ID => {
+                contact.down = true;
+                contact.touched_at = event.timestamp();
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Fingers on the screen right now
+    fn down(&self) -> usize {
+        self.slots.values().filter(|contact| contact.down).count()
+    }
+}
+
 pub struct Touch {
     device: Option<Device>,
+    // Events read from the touchscreen in the background, so a wait can
+    // skip whatever happened before it started
+    events: Option<Receiver<InputEvent>>,
 }
 
 impl Touch {
     pub fn new(no_touch: bool) -> Self {
-        let device = if no_touch {
-            None
-        } else {
-            Some(Device::open("/dev/input/by-path/platform-30a40000.i2c-event").unwrap())
-        };
+        if no_touch {
+            return Self {
+                device: None,
+                events: None,
+            };
+        }
+        let device = Device::open(TOUCH_DEVICE).unwrap();
+        let mut reader = Device::open(TOUCH_DEVICE).unwrap();
+        let (sender, events) = channel();
+        std::thread::spawn(move || loop {
+            let Ok(events) = reader.fetch_events() else {
+                return;
+            };
+            for event in events {
+                if sender.send(event).is_err() {
+                    return;
+                }
+            }
+        });
+        Self {
+            device: Some(device),
+            events: Some(events),
+        }
+    }
 
-        Self { device }
+    /// Events from now on, until the timeout if there is one. Earlier ones
+    /// are dropped: the release of a tap that stopped the last answer, or
+    /// touches ghostwriter made itself, must not count as a new touch.
+    fn fresh_events(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<impl Iterator<Item = InputEvent> + '_> {
+        let events = self
+            .events
+            .as_ref()
+            .ok_or_else(|| anyhow!("The touchscreen is not open"))?;
+        while events.try_recv().is_ok() {}
+        let since = SystemTime::now();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        Ok(std::iter::from_fn(move || match deadline {
+            Some(deadline) => events
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .ok(),
+            None => events.recv().ok(),
+        })
+        .filter(move |event| event.timestamp() >= since))
     }
 
-    pub fn wait_for_trigger(&mut self) -> Result<()> {
-        let mut position_x = 0;
-        let mut position_y = 0;
-        loop {
-            for event in self.device.as_mut().unwrap().fetch_events().unwrap() {
-                if event.code() == ABS_MT_POSITION_X {
-                    position_x = event.value();
-                }
-                if event.code() == ABS_MT_POSITION_Y {
-                    position_y = event.value();
-                }
-                if event.code() == ABS_MT_TRACKING_ID {
-                    if event.value() == -1 {
-                        println!("Touch release detected at ({}, {})", position_x, position_y);
-                        if position_x > 1360 && position_y > 1810 {
-                            println!("Touch release in target zone!");
-                            return Ok(());
-                        }
-                    }
+    pub fn wait_for_trigger(&mut self) -> Result<Trigger> {
+        let mut contacts = Contacts::default();
+        for event in self.fresh_events(None)? {
+            let Some(lifted) = contacts.update(&event) else {
+                continue;
+            };
+            println!("Touch release detected at ({}, {})", lifted.x, lifted.y);
+            if in_corner(lifted.x, lifted.y) {
+                let held = event
+                    .timestamp()
+                    .duration_since(lifted.touched_at)
+                    .unwrap_or_default();
+                if held >= UNDO_HOLD {
+                    println!("Long press in target zone!");
+                    return Ok(Trigger::Undo);
                 }
+                println!("Touch release in target zone!");
+                return Ok(Trigger::Respond);
             }
         }
+        Err(anyhow!("The touchscreen stopped sending events"))
+    }
+
+    /// Wait for the next finger to lift, and where it was in screen px, or
+    /// None if nobody taps in time
+    pub fn wait_for_tap(&mut self, timeout: Duration) -> Result<Option<(f32, f32)>> {
+        let mut contacts = Contacts::default();
+        for event in self.fresh_events(Some(timeout))? {
+            if let Some(lifted) = contacts.update(&event) {
+                return Ok(Some(input_to_screen((lifted.x, lifted.y))));
+            }
+        }
+        Ok(None)
     }
 
     pub fn touch_start(&mut self, xy: (i32, i32)) -> Result<()> {
@@ -107,6 +236,53 @@ impl Touch {
     }
 }
 
+/// Watch the touchscreen in the background, cancelling the flag when the
+/// user taps the upper-right corner (the same spot that starts an answer)
+/// or lays a palm on the screen. The watcher has its own handle on the
+/// device, so it runs alongside `wait_for_trigger` and the drawing.
+pub fn watch_for_cancel(cancel: CancelFlag) {
+    let Ok(mut device) = Device::open(TOUCH_DEVICE) else {
+        println!("Can not open the touchscreen, so drawing can't be stopped by touch");
+        return;
+    };
+    std::thread::spawn(move || {
+        let mut contacts = Contacts::default();
+        loop {
+            let Ok(events) = device.fetch_events() else {
+                return;
+            };
+            for event in events {
+                let lifted = contacts.update(&event);
+                match event.code() {
+                    ABS_MT_TOUCH_MAJOR if event.value() >= PALM_TOUCH_MAJOR => {
+                        cancel.cancel();
+                    }
+                    ABS_MT_TRACKING_ID
+                        if lifted.is_some_and(|lifted| in_corner(lifted.x, lifted.y)) =>
+                    {
+                        cancel.cancel();
+                    }
+                    ABS_MT_TRACKING_ID if lifted.is_none() && contacts.down() >= PALM_CONTACTS => {
+                        cancel.cancel();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    });
+}
+
+// The upper-right corner, in touchscreen coordinates
+fn in_corner(x: i32, y: i32) -> bool {
+    x > 1360 && y > 1810
+}
+
+fn input_to_screen((x, y): (i32, i32)) -> (f32, f32) {
+    let x_screen = x as f32 / INPUT_WIDTH as f32 * REMARKABLE_WIDTH as f32;
+    let y_screen = (1.0 - y as f32 / INPUT_HEIGHT as f32) * REMARKABLE_HEIGHT as f32;
+    (x_screen, y_screen)
+}
+
 fn screen_to_input((x, y): (i32, i32)) -> (i32, i32) {
     // Swap and normalize the coordinates
     let x_normalized = x as f32 / REMARKABLE_WIDTH as f32;
@@ -116,3 +292,47 @@ fn screen_to_input((x, y): (i32, i32)) -> (i32, i32) {
     let y_input = ((1.0 - y_normalized) * INPUT_HEIGHT as f32) as i32;
     (x_input, y_input)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn abs(code: u16, value: i32) -> InputEvent {
+        InputEvent::new(EventType::ABSOLUTE, code, value)
+    }
+
+    #[test]
+    fn fingers_keep_their_own_positions() {
+        let mut contacts = Contacts::default();
+        let events = [
+            // A finger goes down in the corner
+            abs(ABS_MT_SLOT, 0),
+            abs(ABS_MT_TRACKING_ID, 1),
+            abs(ABS_MT_POSITION_X, 1380),
+            abs(ABS_MT_POSITION_Y, 1850),
+            // A second one lands far from it
+            abs(ABS_MT_SLOT, 1),
+            abs(ABS_MT_TRACKING_ID, 2),
+            abs(ABS_MT_POSITION_X, 100),
+            abs(ABS_MT_POSITION_Y, 200),
+        ];
+        for event in &events {
+            assert_eq!(contacts.update(event), None);
+        }
+        assert_eq!(contacts.down(), 2);
+
+        // The first moves, naming its slot; the second doesn't
+        contacts.update(&abs(ABS_MT_SLOT, 0));
+        contacts.update(&abs(ABS_MT_POSITION_Y, 1860));
+        let lifted = contacts.update(&abs(ABS_MT_TRACKING_ID, -1)).unwrap();
+        assert_eq!((lifted.x, lifted.y), (1380, 1860));
+        assert!(in_corner(lifted.x, lifted.y));
+        assert_eq!(contacts.down(), 1);
+
+        contacts.update(&abs(ABS_MT_SLOT, 1));
+        let lifted = contacts.update(&abs(ABS_MT_TRACKING_ID, -1)).unwrap();
+        assert_eq!((lifted.x, lifted.y), (100, 200));
+        assert!(!in_corner(lifted.x, lifted.y));
+        assert_eq!(contacts.down(), 0);
+    }
+}
@@ -0,0 +1,420 @@
+//! Turn thin filled shapes, like text glyphs and thick lines, into the
+//! strokes down their middle, so the pen draws a letter in a few strokes
+//! instead of dozens of scanline segments. Each stroke keeps the width of
+//! the shape it came from, and specks too small to have a middle, like the
+//! dot of an "i", become dots. Shapes too wide to be a line are left in the
+//! bitmap to be filled.
+
+use crate::util::Polyline;
+
+/// Shapes up to about twice this many pixels across are traced
+const MAX_HALF_WIDTH: u32 = 4;
+
+pub struct Traced {
+    /// Each stroke with the width in px of the shape it runs down
+    pub strokes: Vec<(Polyline, f32)>,
+    /// What was too wide to trace
+    pub rest: Vec<Vec<bool>>,
+}
+
+const NEIGHBOURS: [(i32, i32); 8] = [
+    // The four sides come first so walks don't cut corners and strand pixels
+    (1, 0),
+    (0, 1),
+    (-1, 0),
+    (0, -1),
+    (1, 1),
+    (-1, 1),
+    (-1, -1),
+    (1, -1),
+];
+
+struct Grid {
+    width: usize,
+    height: usize,
+    cells: Vec<bool>,
+}
+
+impl Grid {
+    fn from_bitmap(bitmap: &[Vec<bool>]) -> Grid {
+        let height = bitmap.len();
+        let width = bitmap.first().map_or(0, |row| row.len());
+        Grid {
+            width,
+            height,
+            cells: bitmap.iter().flatten().copied().collect(),
+        }
+    }
+
+    fn get(&self, x: i32, y: i32) -> bool {
+        x >= 0
+            && y >= 0
+            && (x as usize) < self.width
+            && (y as usize) < self.height
+            && self.cells[y as usize * self.width + x as usize]
+    }
+
+    fn set(&mut self, x: usize, y: usize, value: bool) {
+        self.cells[y * self.width + x] = value;
+    }
+
+    fn neighbours(&self, (x, y): (usize, usize)) -> impl Iterator<Item = (usize, usize)> + '_ {
+        NEIGHBOURS.iter().filter_map(move |&(dx, dy)| {
+            let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+            self.get(nx, ny).then_some((nx as usize, ny as usize))
+        })
+    }
+
+    fn to_bitmap(&self) -> Vec<Vec<bool>> {
+        self.cells
+            .chunks(self.width)
+            .map(|row| row.to_vec())
+            .collect()
+    }
+}
+
+pub fn trace_centerlines(bitmap: &[Vec<bool>]) -> Traced {
+    let mut ink = Grid::from_bitmap(bitmap);
+    if ink.width == 0 {
+        return Traced {
+            strokes: Vec::new(),
+            rest: bitmap.to_vec(),
+        };
+    }
+
+    // Move the thin shapes out of the bitmap
+    let depth = distance_to_background(&ink);
+    let mut thin = Grid {
+        width: ink.width,
+        height: ink.height,
+        cells: vec![false; ink.cells.len()],
+    };
+    let mut traced = Vec::new();
+    for component in components(&ink) {
+        let deepest = component
+            .iter()
+            .map(|&(x, y)| depth[y * ink.width + x])
+            .max()
+            .unwrap_or(0);
+        if deepest <= MAX_HALF_WIDTH {
+            for &(x, y) in &component {
+                ink.set(x, y, false);
+                thin.set(x, y, true);
+            }
+            traced.push((component, deepest));
+        }
+    }
+
+    thin_to_skeleton(&mut thin);
+    let mut strokes: Vec<(Polyline, f32)> = walk_skeleton(&thin)
+        .into_iter()
+        .map(|stroke| {
+            let width = stroke_width(&stroke, &depth, ink.width, ink.height);
+            (stroke, width)
+        })
+        .collect();
+    // Thinning can peel a small blob away completely
+    for (component, deepest) in traced {
+        if component
+            .iter()
+            .all(|&(x, y)| !thin.get(x as i32, y as i32))
+        {
+            let count = component.len() as f32;
+            let center = component.iter().fold((0.0, 0.0), |(cx, cy), &(x, y)| {
+                (cx + (x as f32 + 0.5) / count, cy + (y as f32 + 0.5) / count)
+            });
+            strokes.push((vec![center], (2 * deepest) as f32 - 1.0));
+        }
+    }
+    Traced {
+        strokes,
+        rest: ink.to_bitmap(),
+    }
+}
+
+/// How wide the shape is along a stroke down its middle, from how deep the
+/// middle lies. The skeleton can sit a pixel off the true middle, so each
+/// point takes the deepest of its neighbours; ends of a stroke lie shallower,
+/// so the median is used.
+fn stroke_width(stroke: &Polyline, depth: &[u32], width: usize, height: usize) -> f32 {
+    let mut depths: Vec<u32> = stroke
+        .iter()
+        .map(|&(x, y)| {
+            let (x, y) = (x as usize, y as usize);
+            let xs = x.saturating_sub(1)..=(x + 1).min(width - 1);
+            (y.saturating_sub(1)..=(y + 1).min(height - 1))
+                .flat_map(|ny| xs.clone().map(move |nx| depth[ny * width + nx]))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    depths.sort_unstable();
+    let middle = depths.get(depths.len() / 2).copied().unwrap_or(1).max(1);
+    (2 * middle) as f32 - 1.0
+}
+
+/// How many steps each ink pixel is from the nearest blank one, counting
+/// sideways and diagonal steps alike
+fn distance_to_background(ink: &Grid) -> Vec<u32> {
+    let (width, height) = (ink.width, ink.height);
+    let far = (width + height) as u32;
+    let mut depth: Vec<u32> = ink.cells.iter().map(|&i| if i { far } else { 0 }).collect();
+    let at = |depth: &Vec<u32>, x: i32, y: i32| {
+        if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+            0
+        } else {
+            depth[y as usize * width + x as usize]
+        }
+    };
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let i = y as usize * width + x as usize;
+            if depth[i] > 0 {
+                let nearest = [(-1, -1), (0, -1), (1, -1), (-1, 0)]
+                    .iter()
+                    .map(|&(dx, dy)| at(&depth, x + dx, y + dy))
+                    .min()
+                    .unwrap();
+                depth[i] = depth[i].min(nearest + 1);
+            }
+        }
+    }
+    for y in (0..height as i32).rev() {
+        for x in (0..width as i32).rev() {
+            let i = y as usize * width + x as usize;
+            if depth[i] > 0 {
+                let nearest = [(1, 1), (0, 1), (-1, 1), (1, 0)]
+                    .iter()
+                    .map(|&(dx, dy)| at(&depth, x + dx, y + dy))
+                    .min()
+                    .unwrap();
+                depth[i] = depth[i].min(nearest + 1);
+            }
+        }
+    }
+    depth
+}
+
+/// Groups of touching ink pixels
+fn components(ink: &Grid) -> Vec<Vec<(usize, usize)>> {
+    let mut seen = vec![false; ink.cells.len()];
+    let mut found = Vec::new();
+    for start in 0..ink.cells.len() {
+        if !ink.cells[start] || seen[start] {
+            continue;
+        }
+        seen[start] = true;
+        let mut component = Vec::new();
+        let mut stack = vec![(start % ink.width, start / ink.width)];
+        while let Some(pixel) = stack.pop() {
+            component.push(pixel);
+            for (x, y) in ink.neighbours(pixel) {
+                if !seen[y * ink.width + x] {
+                    seen[y * ink.width + x] = true;
+                    stack.push((x, y));
+                }
+            }
+        }
+        found.push(component);
+    }
+    found
+}
+
+/// Zhang-Suen thinning: peel pixels off the edges until only a one pixel
+/// wide line down the middle of each shape is left
+fn thin_to_skeleton(grid: &mut Grid) {
+    let mut ink: Vec<(usize, usize)> = (0..grid.cells.len())
+        .filter(|&i| grid.cells[i])
+        .map(|i| (i % grid.width, i / grid.width))
+        .collect();
+    loop {
+        let mut changed = false;
+        for pass in 0..2 {
+            let remove: Vec<(usize, usize)> = ink
+                .iter()
+                .copied()
+                .filter(|&(x, y)| {
+                    let (x, y) = (x as i32, y as i32);
+                    // Clockwise from the pixel above
+                    let p = [
+                        grid.get(x, y - 1),
+                        grid.get(x + 1, y - 1),
+                        grid.get(x + 1, y),
+                        grid.get(x + 1, y + 1),
+                        grid.get(x, y + 1),
+                        grid.get(x - 1, y + 1),
+                        grid.get(x - 1, y),
+                        grid.get(x - 1, y - 1),
+                    ];
+                    let count = p.iter().filter(|&&v| v).count();
+                    let transitions = (0..8).filter(|&i| !p[i] && p[(i + 1) % 8]).count();
+                    let (up, right, down, left) = (p[0], p[2], p[4], p[6]);
+                    let sides = if pass == 0 {
+                        !(right && down && (up || left))
+                    } else {
+                        !(up && left && (right || down))
+                    };
+                    (2..=6).contains(&count) && transitions == 1 && sides
+                })
+                .collect();
+            for &(x, y) in &remove {
+                grid.set(x, y, false);
+            }
+            changed |= !remove.is_empty();
+            ink.retain(|&(x, y)| grid.get(x as i32, y as i32));
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Follow the skeleton from its line ends, then its junctions, then whatever
+/// is left (closed loops like an "o"), so every pixel is drawn once
+fn walk_skeleton(skeleton: &Grid) -> Vec<Polyline> {
+    let width = skeleton.width;
+    let pixels: Vec<(usize, usize)> = (0..skeleton.cells.len())
+        .filter(|&i| skeleton.cells[i])
+        .map(|i| (i % width, i / width))
+        .collect();
+    let degree = |pixel| skeleton.neighbours(pixel).count();
+    let mut starts: Vec<(usize, usize)> =
+        pixels.iter().copied().filter(|&p| degree(p) == 1).collect();
+    starts.extend(pixels.iter().copied().filter(|&p| degree(p) > 2));
+    starts.extend(pixels.iter().copied());
+
+    let mut visited = vec![false; skeleton.cells.len()];
+    let mut strokes = Vec::new();
+    for start in starts {
+        let isolated = !visited[start.1 * width + start.0] && degree(start) == 0;
+        visited[start.1 * width + start.0] = true;
+        if isolated {
+            strokes.push(vec![(start.0 as f32 + 0.5, start.1 as f32 + 0.5)]);
+            continue;
+        }
+        while let Some(next) = skeleton
+            .neighbours(start)
+            .find(|&(x, y)| !visited[y * width + x])
+        {
+            let mut stroke = vec![start, next];
+            visited[next.1 * width + next.0] = true;
+            let mut current = next;
+            loop {
+                if let Some(next) = skeleton
+                    .neighbours(current)
+                    .find(|&(x, y)| !visited[y * width + x])
+                {
+                    visited[next.1 * width + next.0] = true;
+                    stroke.push(next);
+                    current = next;
+                } else {
+                    // Join up with a junction or the start of a loop
+                    // rather than stopping a pixel short of it
+                    let previous = stroke[stroke.len() - 2];
+                    if let Some(end) = skeleton
+                        .neighbours(current)
+                        .find(|&p| p != previous && (degree(p) > 2 || p == start))
+                    {
+                        stroke.push(end);
+                    }
+                    break;
+                }
+            }
+            strokes.push(
+                stroke
+                    .into_iter()
+                    .map(|(x, y)| (x as f32 + 0.5, y as f32 + 0.5))
+                    .collect(),
+            );
+        }
+    }
+    strokes
+}
+
+/// The edges of every filled shape as strokes, for drawing a shape without
+/// filling it in
+pub fn trace_outlines(bitmap: &[Vec<bool>]) -> Vec<Polyline> {
+    let ink = Grid::from_bitmap(bitmap);
+    let mut edges = Grid {
+        width: ink.width,
+        height: ink.height,
+        cells: vec![false; ink.cells.len()],
+    };
+    for y in 0..ink.height {
+        for x in 0..ink.width {
+            let (sx, sy) = (x as i32, y as i32);
+            if ink.get(sx, sy)
+                && NEIGHBOURS[..4]
+                    .iter()
+                    .any(|&(dx, dy)| !ink.get(sx + dx, sy + dy))
+            {
+                edges.set(x, y, true);
+            }
+        }
+    }
+    walk_skeleton(&edges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pen::PenStyle;
+
+    fn bitmap(width: usize, height: usize, ink: impl Fn(usize, usize) -> bool) -> Vec<Vec<bool>> {
+        (0..height)
+            .map(|y| (0..width).map(|x| ink(x, y)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn specks_become_dots() {
+        let traced = trace_centerlines(&bitmap(30, 30, |x, y| {
+            (10..12).contains(&x) && (10..12).contains(&y)
+        }));
+        assert_eq!(traced.strokes, vec![(vec![(11.0, 11.0)], 1.0)]);
+        assert!(traced.rest.iter().flatten().all(|&pixel| !pixel));
+    }
+
+    #[test]
+    fn thick_lines_keep_their_width() {
+        let traced = trace_centerlines(&bitmap(80, 40, |x, y| {
+            (10..70).contains(&x) && (20..27).contains(&y)
+        }));
+        let (line, width) = traced
+            .strokes
+            .iter()
+            .max_by_key(|(stroke, _)| stroke.len())
+            .unwrap();
+        assert_eq!(*width, 7.0);
+        assert!(line.len() >= 50);
+        assert!(line[2..line.len() - 2]
+            .iter()
+            .all(|&(_, y)| (y - 23.5).abs() <= 1.0));
+        assert!(PenStyle::for_width(*width).passes > 1);
+    }
+
+    #[test]
+    fn rings_become_closed_loops() {
+        let traced = trace_centerlines(&bitmap(60, 60, |x, y| {
+            let r = ((x as f32 - 30.0).powi(2) + (y as f32 - 30.0).powi(2)).sqrt();
+            (15.0..18.0).contains(&r)
+        }));
+        assert_eq!(traced.strokes.len(), 1);
+        let (ring, width) = &traced.strokes[0];
+        assert!((2.0..=4.0).contains(width), "{width}");
+        let (first, last) = (ring[0], ring[ring.len() - 1]);
+        assert!((first.0 - last.0).abs() <= 1.0 && (first.1 - last.1).abs() <= 1.0);
+        assert!(ring.iter().all(|&(x, y)| {
+            let r = ((x - 30.5).powi(2) + (y - 30.5).powi(2)).sqrt();
+            (14.0..19.0).contains(&r)
+        }));
+    }
+
+    #[test]
+    fn wide_shapes_are_left_to_fill() {
+        let square = bitmap(40, 40, |x, y| (5..35).contains(&x) && (5..35).contains(&y));
+        let traced = trace_centerlines(&square);
+        assert!(traced.strokes.is_empty());
+        assert_eq!(traced.rest, square);
+    }
+}
@@ -9,7 +9,12 @@ use std::sync::Arc;
 
 pub type OptionMap = HashMap<String, String>;
 
-pub fn svg_to_bitmap(svg_data: &str, width: u32, height: u32) -> Result<Vec<Vec<bool>>> {
+/// A connected run of points in screen coordinates, drawn as one pen stroke
+pub type Polyline = Vec<(f32, f32)>;
+
+/// Parse an SVG with the system fonts, falling back to an error message on
+/// the page if the SVG is broken
+pub fn parse_svg(svg_data: &str) -> Result<Tree> {
     let mut opt = Options::default();
     let mut fontdb = fontdb::Database::new();
     fontdb.load_system_fonts();
@@ -24,7 +29,11 @@ pub fn svg_to_bitmap(svg_data: &str, width: u32, height: u32) -> Result<Vec<Vec<
             Tree::from_str(fallback_svg, &opt)?
         }
     };
+    Ok(tree)
+}
 
+pub fn svg_to_bitmap(svg_data: &str, width: u32, height: u32) -> Result<Vec<Vec<bool>>> {
+    let tree = parse_svg(svg_data)?;
     let mut pixmap = Pixmap::new(width, height).unwrap();
     render(&tree, usvg::Transform::default(), &mut pixmap.as_mut());
 
@@ -37,7 +46,45 @@ pub fn svg_to_bitmap(svg_data: &str, width: u32, height: u32) -> Result<Vec<Vec<
     Ok(bitmap)
 }
 
-pub fn write_bitmap_to_file(bitmap: &Vec<Vec<bool>>, filename: &str) -> Result<()> {
+/// Render polylines as an SVG path `d` attribute
+pub fn strokes_to_path_data(strokes: &[Polyline]) -> String {
+    let mut d = String::new();
+    for stroke in strokes {
+        for (i, (x, y)) in stroke.iter().enumerate() {
+            let command = if i == 0 { 'M' } else { 'L' };
+            d.push_str(&format!("{}{:.1} {:.1} ", command, x, y));
+        }
+        // A single point would not show up as a path, so nudge it into a dot
+        if stroke.len() == 1 {
+            let (x, y) = stroke[0];
+            d.push_str(&format!("L{:.1} {:.1} ", x + 0.5, y));
+        }
+    }
+    d.trim_end().to_string()
+}
+
+/// Rasterize strokes the same way an SVG would be, for saving evaluation output
+pub fn strokes_to_bitmap(strokes: &[Polyline], width: u32, height: u32) -> Result<Vec<Vec<bool>>> {
+    strokes_to_bitmap_with_width(strokes, 2.0, width, height)
+}
+
+pub fn strokes_to_bitmap_with_width(
+    strokes: &[Polyline],
+    stroke_width: f32,
+    width: u32,
+    height: u32,
+) -> Result<Vec<Vec<bool>>> {
+    let svg_data = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}"><path d="{}" fill="none" stroke="black" stroke-width="{:.1}" stroke-linecap="round" stroke-linejoin="round"/></svg>"#,
+        width,
+        height,
+        strokes_to_path_data(strokes),
+        stroke_width
+    );
+    svg_to_bitmap(&svg_data, width, height)
+}
+
+pub fn write_bitmap_to_file(bitmap: &[Vec<bool>], filename: &str) -> Result<()> {
     let width = bitmap[0].len();
     let height = bitmap.len();
     let mut img = GrayImage::new(width as u32, height as u32);
@@ -58,11 +105,10 @@ pub fn write_bitmap_to_file(bitmap: &Vec<Vec<bool>>, filename: &str) -> Result<(
 }
 
 pub fn option_or_env(options: &OptionMap, key: &str, env_key: &str) -> String {
-    let option = options.get(key);
-    if option.is_some() {
-        option.unwrap().to_string()
+    if let Some(option) = options.get(key) {
+        option.to_string()
     } else {
-        std::env::var(env_key.to_string()).unwrap().to_string()
+        std::env::var(env_key).unwrap().to_string()
     }
 }
 
@@ -72,11 +118,10 @@ pub fn option_or_env_fallback(
     env_key: &str,
     fallback: &str,
 ) -> String {
-    let option = options.get(key);
-    if option.is_some() {
-        option.unwrap().to_string()
+    if let Some(option) = options.get(key) {
+        option.to_string()
     } else {
-        std::env::var(env_key.to_string())
+        std::env::var(env_key)
             .unwrap_or(fallback.to_string())
             .to_string()
     }
@@ -0,0 +1,489 @@
+//! Turn an SVG into pen strokes. Stroked paths are flattened into polylines
+//! in screen space and drawn as one pen stroke each, which is quick and looks
+//! like real pen lines. Thin fills, like text, are traced down their middle
+//! into strokes too; only wide fills (and embedded images) are left to be
+//! drawn row by row.
+
+use anyhow::Result;
+use resvg::tiny_skia::{self, FillRule, Paint, PathSegment, Pixmap, Point, Rect, Transform};
+use resvg::usvg::{self, Group, Node};
+
+use crate::fill::{fill_strokes, FillSettings, FillStyle};
+use crate::pen::PenStyle;
+use crate::trace::trace_centerlines;
+use crate::util::{parse_svg, strokes_to_bitmap_with_width, Polyline};
+
+/// Curves are split into pieces about this long, in screen pixels
+const CURVE_STEP: f32 = 4.0;
+const MAX_CURVE_PIECES: usize = 64;
+
+/// Strokes that are all drawn holding the pen the same way
+pub struct Layer {
+    pub style: PenStyle,
+    pub strokes: Vec<Polyline>,
+}
+
+pub struct SvgDrawing {
+    /// From the lightest lines to the heaviest, the order they are drawn in
+    pub layers: Vec<Layer>,
+    /// Filled areas too wide to trace, in the same row-major form as
+    /// `svg_to_bitmap`
+    pub fills: Vec<Vec<bool>>,
+    width: u32,
+    height: u32,
+}
+
+impl SvgDrawing {
+    pub fn has_fills(&self) -> bool {
+        self.fills.iter().any(|row| row.iter().any(|&pixel| pixel))
+    }
+
+    /// Draw the fills with strokes in the given style instead of row by row,
+    /// unless the style is solid
+    pub fn set_fill(&mut self, fill: FillSettings) {
+        if fill.style == FillStyle::Solid || !self.has_fills() {
+            return;
+        }
+        let strokes = fill_strokes(&self.fills, fill.style, fill.spacing);
+        add_strokes(&mut self.layers, PenStyle::default(), strokes);
+        for row in &mut self.fills {
+            row.fill(false);
+        }
+    }
+
+    /// What the page will look like once both the strokes and the fills are
+    /// drawn, for saving evaluation output
+    pub fn to_bitmap(&self) -> Result<Vec<Vec<bool>>> {
+        let mut bitmap = self.fills.clone();
+        for layer in &self.layers {
+            let passes: Vec<Polyline> = layer
+                .strokes
+                .iter()
+                .flat_map(|stroke| layer.style.passes(stroke))
+                .collect();
+            let drawn = strokes_to_bitmap_with_width(
+                &passes,
+                layer.style.line_width() / layer.style.passes as f32,
+                self.width,
+                self.height,
+            )?;
+            for (row, drawn_row) in bitmap.iter_mut().zip(drawn) {
+                for (pixel, drawn) in row.iter_mut().zip(drawn_row) {
+                    *pixel |= drawn;
+                }
+            }
+        }
+        Ok(bitmap)
+    }
+}
+
+pub fn svg_to_drawing(svg_data: &str, width: u32, height: u32) -> Result<SvgDrawing> {
+    let tree = parse_svg(svg_data)?;
+    let mut pixmap = Pixmap::new(width, height).unwrap();
+    let mut layers = Vec::new();
+    let bounds = (width as f32, height as f32);
+    collect_group(tree.root(), bounds, &mut layers, &mut pixmap);
+
+    let fills: Vec<Vec<bool>> = pixmap
+        .pixels()
+        .chunks(width as usize)
+        .map(|row| row.iter().map(|p| p.alpha() > 128).collect())
+        .collect();
+    let traced = trace_centerlines(&fills);
+    for (stroke, width) in traced.strokes {
+        add_strokes(&mut layers, PenStyle::for_width(width), vec![stroke]);
+    }
+    layers.sort_by(|a, b| a.style.line_width().total_cmp(&b.style.line_width()));
+    Ok(SvgDrawing {
+        layers,
+        fills: traced.rest,
+        width,
+        height,
+    })
+}
+
+fn add_strokes(layers: &mut Vec<Layer>, style: PenStyle, strokes: Vec<Polyline>) {
+    if strokes.is_empty() {
+        return;
+    }
+    match layers.iter_mut().find(|layer| layer.style == style) {
+        Some(layer) => layer.strokes.extend(strokes),
+        None => layers.push(Layer { style, strokes }),
+    }
+}
+
+fn collect_group(group: &Group, bounds: (f32, f32), layers: &mut Vec<Layer>, fills: &mut Pixmap) {
+    for node in group.children() {
+        match node {
+            Node::Group(group) => collect_group(group, bounds, layers, fills),
+            Node::Path(path) => {
+                if !path.is_visible() {
+                    continue;
+                }
+                let transform = path.abs_transform();
+                if let Some(fill) = path.fill() {
+                    if is_ink(fill.paint()) {
+                        let mut paint = Paint::default();
+                        paint.set_color_rgba8(0, 0, 0, 255);
+                        let rule = match fill.rule() {
+                            usvg::FillRule::NonZero => FillRule::Winding,
+                            usvg::FillRule::EvenOdd => FillRule::EvenOdd,
+                        };
+                        fills.fill_path(path.data(), &paint, rule, transform, None);
+                    }
+                }
+                if let Some(stroke) = path.stroke() {
+                    if is_ink(stroke.paint()) {
+                        let scale = transform.get_scale();
+                        let scale = ((scale.0 + scale.1) / 2.0).max(f32::EPSILON);
+                        let style = PenStyle::for_width(stroke.width().get() * scale);
+                        let mut strokes = Vec::new();
+                        for line in flatten(path.data(), transform) {
+                            let dashed = match stroke.dasharray() {
+                                Some(dashes) => dash(&line, dashes, stroke.dashoffset(), scale),
+                                None => vec![line],
+                            };
+                            for line in dashed {
+                                strokes.extend(clip(&line, bounds));
+                            }
+                        }
+                        add_strokes(layers, style, strokes);
+                    }
+                }
+            }
+            Node::Text(text) => collect_group(text.flattened(), bounds, layers, fills),
+            Node::Image(_) => {
+                resvg::render_node(node, Transform::default(), &mut fills.as_mut());
+            }
+        }
+    }
+}
+
+/// The pen only has black ink, so white (or nearly white) paint, like a
+/// background rectangle, is left blank rather than drawn
+fn is_ink(paint: &usvg::Paint) -> bool {
+    match paint {
+        usvg::Paint::Color(color) => color.red < 240 || color.green < 240 || color.blue < 240,
+        _ => true,
+    }
+}
+
+/// Split a path into polylines in screen space, one per subpath
+fn flatten(path: &tiny_skia::Path, transform: Transform) -> Vec<Polyline> {
+    let map = |mut point: Point| {
+        transform.map_point(&mut point);
+        point
+    };
+    let mut lines: Vec<Polyline> = Vec::new();
+    let mut current: Polyline = Vec::new();
+    let mut last = Point::zero();
+    let mut start = Point::zero();
+
+    for segment in path.segments() {
+        match segment {
+            PathSegment::MoveTo(p) => {
+                if current.len() > 1 {
+                    lines.push(std::mem::take(&mut current));
+                }
+                current.clear();
+                last = map(p);
+                start = last;
+                current.push((last.x, last.y));
+            }
+            PathSegment::LineTo(p) => {
+                last = map(p);
+                current.push((last.x, last.y));
+            }
+            PathSegment::QuadTo(c, p) => {
+                let (c, p) = (map(c), map(p));
+                let pieces = curve_pieces(&[last, c, p]);
+                for i in 1..=pieces {
+                    let t = i as f32 / pieces as f32;
+                    let u = 1.0 - t;
+                    current.push((
+                        u * u * last.x + 2.0 * u * t * c.x + t * t * p.x,
+                        u * u * last.y + 2.0 * u * t * c.y + t * t * p.y,
+                    ));
+                }
+                last = p;
+            }
+            PathSegment::CubicTo(c1, c2, p) => {
+                let (c1, c2, p) = (map(c1), map(c2), map(p));
+                let pieces = curve_pieces(&[last, c1, c2, p]);
+                for i in 1..=pieces {
+                    let t = i as f32 / pieces as f32;
+                    let u = 1.0 - t;
+                    let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+                    current.push((
+                        a * last.x + b * c1.x + c * c2.x + d * p.x,
+                        a * last.y + b * c1.y + c * c2.y + d * p.y,
+                    ));
+                }
+                last = p;
+            }
+            PathSegment::Close => {
+                current.push((start.x, start.y));
+                last = start;
+            }
+        }
+    }
+    if current.len() > 1 {
+        lines.push(current);
+    }
+    lines
+}
+
+/// How many straight pieces a curve needs, from the length of its control
+/// polygon, which is never shorter than the curve
+fn curve_pieces(points: &[Point]) -> usize {
+    let length: f32 = points.windows(2).map(|w| w[0].distance(w[1])).sum();
+    ((length / CURVE_STEP).ceil() as usize).clamp(1, MAX_CURVE_PIECES)
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+}
+
+/// Cut a polyline into the "on" parts of a dash pattern, given in user units
+/// and scaled to the screen
+fn dash(line: &Polyline, dashes: &[f32], offset: f32, scale: f32) -> Vec<Polyline> {
+    let dashes: Vec<f32> = dashes.iter().map(|d| d * scale).collect();
+    let period: f32 = dashes.iter().sum();
+    if period <= 0.0 {
+        return vec![line.clone()];
+    }
+
+    // Find where in the pattern the line starts
+    let mut index = 0;
+    let mut left = dashes[0];
+    let mut skip = (offset * scale).rem_euclid(period);
+    while skip > 0.0 {
+        if skip >= left {
+            skip -= left;
+            index = (index + 1) % dashes.len();
+            left = dashes[index];
+        } else {
+            left -= skip;
+            skip = 0.0;
+        }
+    }
+
+    let mut pieces = Vec::new();
+    let mut current: Polyline = Vec::new();
+    if index % 2 == 0 {
+        current.push(line[0]);
+    }
+    for pair in line.windows(2) {
+        let (mut from, to) = (pair[0], pair[1]);
+        let mut length = distance(from, to);
+        while length > 0.0 {
+            let step = left.min(length);
+            let t = step / length;
+            let point = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+            if index % 2 == 0 {
+                current.push(point);
+            }
+            left -= step;
+            length -= step;
+            from = point;
+            if left <= 0.0 {
+                if index % 2 == 0 && current.len() > 1 {
+                    pieces.push(std::mem::take(&mut current));
+                }
+                current.clear();
+                index = (index + 1) % dashes.len();
+                left = dashes[index];
+                if index % 2 == 0 {
+                    current.push(point);
+                }
+            }
+        }
+    }
+    if current.len() > 1 {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// Keep only the parts of a polyline that are on the screen, since the pen
+/// can't go past its edges
+fn clip(line: &Polyline, (width, height): (f32, f32)) -> Vec<Polyline> {
+    let screen = Rect::from_ltrb(0.0, 0.0, width - 1.0, height - 1.0).unwrap();
+    let inside = |p: (f32, f32)| {
+        p.0 >= screen.left()
+            && p.0 <= screen.right()
+            && p.1 >= screen.top()
+            && p.1 <= screen.bottom()
+    };
+    if line.iter().all(|&p| inside(p)) {
+        return vec![line.clone()];
+    }
+
+    let mut pieces = Vec::new();
+    let mut current: Polyline = Vec::new();
+    for pair in line.windows(2) {
+        let Some((from, to)) = clip_segment(pair[0], pair[1], &screen) else {
+            if current.len() > 1 {
+                pieces.push(std::mem::take(&mut current));
+            }
+            current.clear();
+            continue;
+        };
+        if current.last() != Some(&from) {
+            if current.len() > 1 {
+                pieces.push(std::mem::take(&mut current));
+            }
+            current.clear();
+            current.push(from);
+        }
+        current.push(to);
+        if to != pair[1] {
+            pieces.push(std::mem::take(&mut current));
+        }
+    }
+    if current.len() > 1 {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// Liang-Barsky clipping of one segment to a rectangle
+fn clip_segment(from: (f32, f32), to: (f32, f32), rect: &Rect) -> Option<((f32, f32), (f32, f32))> {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    for (p, q) in [
+        (-dx, from.0 - rect.left()),
+        (dx, rect.right() - from.0),
+        (-dy, from.1 - rect.top()),
+        (dy, rect.bottom() - from.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    let at = |t: f32| (from.0 + dx * t, from.1 + dy * t);
+    Some((
+        if t0 > 0.0 { at(t0) } else { from },
+        if t1 < 1.0 { at(t1) } else { to },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resvg::tiny_skia::PathBuilder;
+
+    fn close(a: &[Polyline], b: &[Polyline]) -> bool {
+        a.len() == b.len()
+            && a.iter().zip(b).all(|(a, b)| {
+                a.len() == b.len() && a.iter().zip(b).all(|(&p, &q)| distance(p, q) < 1e-3)
+            })
+    }
+
+    #[test]
+    fn curves_stay_near_the_true_shape() {
+        let circle = PathBuilder::from_circle(50.0, 50.0, 40.0).unwrap();
+        let lines = flatten(&circle, Transform::identity());
+        assert_eq!(lines.len(), 1);
+        let ring = &lines[0];
+        assert!(ring.len() > 40);
+        assert!(ring
+            .iter()
+            .all(|&p| (distance(p, (50.0, 50.0)) - 40.0).abs() < 0.5));
+        assert!(ring.windows(2).all(|w| distance(w[0], w[1]) <= CURVE_STEP));
+        assert!(distance(ring[0], ring[ring.len() - 1]) < 1e-3);
+    }
+
+    #[test]
+    fn curves_are_split_in_screen_space() {
+        let mut path = PathBuilder::new();
+        path.move_to(0.0, 0.0);
+        path.quad_to(10.0, 20.0, 20.0, 0.0);
+        let path = path.finish().unwrap();
+        let small = flatten(&path, Transform::identity());
+        let large = flatten(&path, Transform::from_scale(4.0, 4.0));
+        assert!(large[0].len() > small[0].len());
+        assert_eq!(large[0].last(), Some(&(80.0, 0.0)));
+    }
+
+    #[test]
+    fn subpaths_become_separate_lines() {
+        let mut path = PathBuilder::new();
+        path.move_to(0.0, 0.0);
+        path.line_to(10.0, 0.0);
+        path.line_to(10.0, 10.0);
+        path.close();
+        path.move_to(20.0, 20.0);
+        path.line_to(30.0, 20.0);
+        let lines = flatten(&path.finish().unwrap(), Transform::identity());
+        assert_eq!(
+            lines,
+            vec![
+                vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 0.0)],
+                vec![(20.0, 20.0), (30.0, 20.0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn dashes_carry_across_corners() {
+        let corner = vec![(0.0, 0.0), (3.0, 0.0), (3.0, 5.0)];
+        assert!(close(
+            &dash(&corner, &[4.0, 2.0], 0.0, 1.0),
+            &[
+                vec![(0.0, 0.0), (3.0, 0.0), (3.0, 1.0)],
+                vec![(3.0, 3.0), (3.0, 5.0)],
+            ]
+        ));
+    }
+
+    #[test]
+    fn dash_offset_shifts_the_pattern() {
+        let line = vec![(0.0, 0.0), (10.0, 0.0)];
+        let expected = [vec![(1.0, 0.0), (5.0, 0.0)], vec![(7.0, 0.0), (10.0, 0.0)]];
+        assert!(close(&dash(&line, &[4.0, 2.0], 5.0, 1.0), &expected));
+        assert!(close(&dash(&line, &[4.0, 2.0], -1.0, 1.0), &expected));
+        assert!(close(&dash(&line, &[2.0, 1.0], 2.5, 2.0), &expected));
+    }
+
+    #[test]
+    fn empty_dash_patterns_draw_solid() {
+        let line = vec![(0.0, 0.0), (10.0, 0.0)];
+        assert_eq!(dash(&line, &[0.0, 0.0], 3.0, 1.0), vec![line]);
+    }
+
+    #[test]
+    fn strokes_are_clipped_to_the_screen() {
+        let screen = (100.0, 100.0);
+        let through = vec![(-10.0, 50.0), (50.0, 50.0), (50.0, 150.0)];
+        assert!(close(
+            &clip(&through, screen),
+            &[vec![(0.0, 50.0), (50.0, 50.0), (50.0, 99.0)]]
+        ));
+
+        let out_and_back = vec![(50.0, 50.0), (150.0, 50.0), (150.0, 60.0), (50.0, 60.0)];
+        assert!(close(
+            &clip(&out_and_back, screen),
+            &[
+                vec![(50.0, 50.0), (99.0, 50.0)],
+                vec![(99.0, 60.0), (50.0, 60.0)],
+            ]
+        ));
+
+        assert!(clip(&vec![(-5.0, -5.0), (-5.0, 200.0)], screen).is_empty());
+        let inside = vec![(1.0, 1.0), (98.0, 98.0)];
+        assert_eq!(clip(&inside, screen), vec![inside]);
+    }
+}